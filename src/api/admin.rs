@@ -0,0 +1,81 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Json},
+    routing::{get, post},
+    Router,
+};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::pricing::PricingStore;
+use super::{ApiError, ApiResponse, ApiResult, AppState};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/pricing", get(get_pricing))
+        .route("/pricing/reload", post(reload_pricing))
+}
+
+// GET /api/config - Effective configuration with secrets redacted
+pub(super) async fn get_config(State(config): State<Arc<Config>>) -> ApiResult<impl IntoResponse> {
+    Ok(Json(ApiResponse::success(config.redacted())))
+}
+
+// GET /api/admin/pricing - Pricing table currently in effect
+async fn get_pricing(State(pricing): State<Arc<PricingStore>>) -> ApiResult<impl IntoResponse> {
+    Ok(Json(ApiResponse::success(pricing.current())))
+}
+
+// POST /api/admin/pricing/reload - Re-read the configured pricing file
+async fn reload_pricing(State(pricing): State<Arc<PricingStore>>) -> ApiResult<impl IntoResponse> {
+    let table = pricing
+        .reload()
+        .map_err(|e| ApiError::InvalidQuery(e.to_string()))?;
+    Ok(Json(ApiResponse::success(table)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use tower::ServiceExt;
+
+    use crate::{api::test_state, storage::sqlite::test_database};
+
+    #[tokio::test]
+    async fn test_config_dump_redacts_secrets() {
+        let (_dir, db) = test_database().await;
+        let mut state = test_state(db);
+        state.config = Arc::new(Config {
+            http_port: 4100,
+            otel_port: 4317,
+            database_path: "/var/lib/claude-lens/lens.db".to_string(),
+            cors_origins: vec!["https://lens.example.com".to_string()],
+            log_level: "debug".to_string(),
+            max_connections: 25,
+            retention_days: Some(30),
+            api_keys: vec!["read-key".to_string()],
+            ingest_token: Some("ingest-secret".to_string()),
+            ..Config::default()
+        });
+        let app = Router::new().route("/config", get(get_config)).with_state(state);
+
+        let response = app.oneshot(Request::builder().uri("/config").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let config = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+
+        assert_eq!(config["http_port"], 4100);
+        assert_eq!(config["otel_port"], 4317);
+        assert_eq!(config["database_path"], "/var/lib/claude-lens/lens.db");
+        assert_eq!(config["cors_origins"], serde_json::json!(["https://lens.example.com"]));
+        assert_eq!(config["log_level"], "debug");
+        assert_eq!(config["max_connections"], 25);
+        assert_eq!(config["retention_days"], 30);
+        assert_eq!(config["api_keys"], serde_json::json!(["***"]));
+        assert_eq!(config["ingest_token"], "***");
+        // Unset secrets stay null rather than suggesting one is configured
+        assert!(config["privacy_salt"].is_null());
+        assert!(!body.windows(b"secret".len()).any(|window| window == b"secret"));
+    }
+}
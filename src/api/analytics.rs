@@ -4,20 +4,50 @@ use axum::{
     routing::get,
     Router,
 };
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveTime, Timelike, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
-
-use crate::storage::Database;
-use super::{ApiError, ApiResponse, ApiResult};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::forecast::linear_forecast;
+use crate::otel::metrics::{cost_or_derived, EnhancedClaudeMetric, MetricClassifier};
+use crate::otel::{classify_event, classify_metric, CodeChangeType, EventType, MetricType, TokenType};
+use crate::pricing::{PricingStore, PricingTable};
+use crate::privacy::Privacy;
+use crate::storage::{
+    CostBucket, Database, LogRecord, MetricRecord, MetricScope, SessionFilter, SessionRecord, SessionSort, SessionSortKey, SortOrder,
+    TimeBucket, TraceRecord,
+};
+use crate::util::parse_range;
+use super::{ApiError, ApiResponse, ApiResult, AppState};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct AnalyticsQuery {
     pub start_time: Option<DateTime<Utc>>,
     pub end_time: Option<DateTime<Utc>>,
     pub user_email: Option<String>,
     pub organization_id: Option<String>,
-    pub range: Option<String>, // "24h", "7d", "30d"
+    pub range: Option<String>, // "<n>h", "<n>d" or "<n>w", e.g. "24h", "7d", "2w"
+    pub tz: Option<String>,    // IANA timezone name, e.g. "Europe/Berlin"
+    pub bucket: Option<String>, // "hour", "day", "week"; defaults from the range
+    pub service: Option<String>, // `service.name` of the exporter
+}
+
+impl AnalyticsQuery {
+    /// The user, organization and service filters, ignoring blank values
+    pub fn scope(&self) -> MetricScope {
+        let filter = |value: &Option<String>| value.as_deref().map(str::trim).filter(|value| !value.is_empty()).map(str::to_string);
+        MetricScope {
+            user_email: filter(&self.user_email),
+            organization_id: filter(&self.organization_id),
+            service: filter(&self.service),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -63,6 +93,18 @@ pub struct CostAnalytics {
     pub top_users_by_cost: Vec<UserCostStats>,
 }
 
+/// Longest window a query may span, so a stray start_time can't scan every table in full
+pub(super) const MAX_WINDOW_DAYS: i64 = 365;
+
+/// Upper bound on `cost_trend` points, e.g. hourly buckets over ~14 months
+const MAX_TREND_POINTS: i64 = 10_000;
+
+/// Rows in the per-user rankings
+const TOP_USERS: usize = 10;
+
+/// Changes within this many percent either way count as `TrendDirection::Stable`
+const STABLE_TREND_PCT: f64 = 2.0;
+
 #[derive(Debug, Serialize)]
 pub struct CostPoint {
     pub timestamp: DateTime<Utc>,
@@ -73,6 +115,12 @@ pub struct CostPoint {
     pub cache_read_tokens: u64,
 }
 
+impl CostPoint {
+    fn has_tokens(&self) -> bool {
+        self.input_tokens + self.output_tokens + self.cache_creation_tokens + self.cache_read_tokens > 0
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ModelCostBreakdown {
     pub model_name: String,
@@ -114,9 +162,13 @@ pub struct ToolEfficiencyStats {
 
 #[derive(Debug, Serialize)]
 pub struct TimeToProductivityPoint {
+    /// When the session started
     pub timestamp: DateTime<Utc>,
-    pub session_start_to_first_commit_minutes: f64,
-    pub session_start_to_first_edit_minutes: f64,
+    pub session_id: Uuid,
+    /// Null when the session made no commit in the window
+    pub session_start_to_first_commit_minutes: Option<f64>,
+    /// Null when the session added no lines in the window
+    pub session_start_to_first_edit_minutes: Option<f64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -137,7 +189,7 @@ pub struct ProductivityForecast {
     pub lines_of_code: u64,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 pub enum TrendDirection {
     Increasing(f64), // percentage increase
     Decreasing(f64), // percentage decrease
@@ -148,7 +200,7 @@ pub enum TrendDirection {
 #[derive(Debug, Serialize)]
 pub struct DashboardKPIs {
     pub today_sessions: u64,
-    pub today_sessions_change: f64, // percentage change from yesterday
+    pub today_sessions_change: f64, // % change from the equally long range before; 0 when that was 0
     pub total_tokens: u64,
     pub total_tokens_change: f64,
     pub total_cost: f64,
@@ -186,6 +238,8 @@ pub struct ToolUsageStats {
     pub usage_count: u64,
     pub success_rate: f64,
     pub avg_duration_ms: f64,
+    /// False when no trace spans recorded this tool, leaving `avg_duration_ms` at 0
+    pub duration_available: bool,
     pub percentage: f64,
     pub color: String, // for chart coloring
 }
@@ -205,6 +259,15 @@ pub struct HeatmapCell {
     pub token_count: u64,
 }
 
+/// The four dashboard panels, computed from one fetch of the range
+#[derive(Debug, Serialize)]
+pub struct AnalyticsOverview {
+    pub kpis: DashboardKPIs,
+    pub token_trend: TokenTrendData,
+    pub tool_usage: ToolUsageData,
+    pub heatmap: UsageHeatmapData,
+}
+
 // Advanced analytics data structures
 #[derive(Debug, Serialize)]
 pub struct ModelCostComparison {
@@ -330,12 +393,29 @@ pub struct CodeQualityMetrics {
     pub readability_score: f64,
 }
 
-pub fn routes() -> Router<Arc<dyn Database>> {
+#[derive(Debug, Serialize)]
+pub struct CostMatrix {
+    pub range: String,
+    pub timezone: String,
+    pub dates: Vec<NaiveDate>,
+    pub models: Vec<ModelDailyCosts>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelDailyCosts {
+    pub model_name: String,
+    pub total_cost_usd: f64,
+    pub daily_costs: Vec<f64>, // aligned with `CostMatrix::dates`
+}
+
+pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/productivity", get(get_productivity_metrics))
         .route("/costs", get(get_cost_analytics))
         .route("/efficiency", get(get_efficiency_metrics))
         .route("/trends", get(get_trend_analysis))
+        .route("/cost-matrix", get(get_cost_matrix))
+        .route("/overview", get(get_analytics_overview))
         .route("/dashboard/kpis", get(get_dashboard_kpis))
         .route("/dashboard/token-trend", get(get_token_trend))
         .route("/dashboard/tool-usage", get(get_tool_usage))
@@ -350,40 +430,67 @@ pub fn routes() -> Router<Arc<dyn Database>> {
 // GET /api/analytics/productivity - Productivity metrics and trends
 async fn get_productivity_metrics(
     State(db): State<Arc<dyn Database>>,
+    State(privacy): State<Privacy>,
     Query(params): Query<AnalyticsQuery>,
 ) -> ApiResult<impl IntoResponse> {
     let (start_time, end_time) = parse_time_range(&params)?;
-    
-    // TODO: Implement actual database queries for productivity metrics
-    // This is a mock implementation showing the expected structure
-    
+    let bucket = parse_bucket(params.bucket.as_deref(), start_time, end_time)?;
+    check_trend_points(start_time, end_time, bucket)?;
+    let metrics = code_metrics(&*db, start_time, end_time, &params.scope()).await?;
+
+    let mut totals = CodeTotals::default();
+    let mut by_bucket: HashMap<DateTime<Utc>, CodeTotals> = HashMap::new();
+    let mut by_user: HashMap<String, CodeTotals> = HashMap::new();
+    for metric in &metrics {
+        totals.add(metric);
+        by_bucket.entry(bucket.truncate(metric.timestamp)).or_default().add(metric);
+        if let Some(user) = metric_user(metric) {
+            by_user.entry(user).or_default().add(metric);
+        }
+    }
+
+    let mut productivity_trend = Vec::new();
+    let mut timestamp = bucket.truncate(start_time);
+    while timestamp <= end_time {
+        let sums = by_bucket.remove(&timestamp).unwrap_or_default();
+        productivity_trend.push(ProductivityPoint {
+            timestamp,
+            commits: sums.commits,
+            pull_requests: sums.pull_requests,
+            lines_added: sums.lines_added,
+            lines_removed: sums.lines_removed,
+        });
+        timestamp += bucket.duration();
+    }
+
+    let mut top_contributors: Vec<ContributorStats> = by_user
+        .into_iter()
+        .map(|(user_email, sums)| ContributorStats {
+            user_email,
+            commits: sums.commits,
+            pull_requests: sums.pull_requests,
+            lines_added: sums.lines_added,
+            lines_removed: sums.lines_removed,
+        })
+        .collect();
+    top_contributors.sort_by(|a, b| {
+        (b.commits, b.lines_added).cmp(&(a.commits, a.lines_added)).then_with(|| a.user_email.cmp(&b.user_email))
+    });
+    top_contributors.truncate(TOP_USERS);
+    for contributor in &mut top_contributors {
+        contributor.user_email = privacy.mask(&contributor.user_email);
+    }
+
+    // Claude Code reports no per-file or per-repository metrics, so those stay empty
     let productivity = ProductivityMetrics {
-        total_commits: 42,
-        total_pull_requests: 8,
-        total_lines_added: 1247,
-        total_lines_removed: 389,
-        files_changed: 156,
-        active_repositories: vec![
-            "claude-scope".to_string(),
-            "other-project".to_string(),
-        ],
-        productivity_trend: generate_mock_productivity_trend(start_time, end_time),
-        top_contributors: vec![
-            ContributorStats {
-                user_email: "developer@example.com".to_string(),
-                commits: 25,
-                pull_requests: 5,
-                lines_added: 800,
-                lines_removed: 200,
-            },
-            ContributorStats {
-                user_email: "engineer@example.com".to_string(),
-                commits: 17,
-                pull_requests: 3,
-                lines_added: 447,
-                lines_removed: 189,
-            },
-        ],
+        total_commits: totals.commits,
+        total_pull_requests: totals.pull_requests,
+        total_lines_added: totals.lines_added,
+        total_lines_removed: totals.lines_removed,
+        files_changed: 0,
+        active_repositories: Vec::new(),
+        productivity_trend,
+        top_contributors,
     };
 
     Ok(Json(ApiResponse::success(productivity)))
@@ -392,275 +499,645 @@ async fn get_productivity_metrics(
 // GET /api/analytics/costs - Cost analysis and token usage
 async fn get_cost_analytics(
     State(db): State<Arc<dyn Database>>,
+    State(privacy): State<Privacy>,
+    State(pricing): State<Arc<PricingStore>>,
     Query(params): Query<AnalyticsQuery>,
 ) -> ApiResult<impl IntoResponse> {
     let (start_time, end_time) = parse_time_range(&params)?;
-    
-    // TODO: Implement actual database queries for cost metrics
-    // This is a mock implementation showing the expected structure
-    
-    let costs = CostAnalytics {
-        total_cost_usd: 23.47,
-        total_input_tokens: 145_892,
-        total_output_tokens: 89_347,
-        total_cache_creation_tokens: 12_445,
-        total_cache_read_tokens: 78_923,
-        average_cost_per_session: 1.84,
-        cost_trend: generate_mock_cost_trend(start_time, end_time),
-        model_breakdown: vec![
-            ModelCostBreakdown {
-                model_name: "claude-3-5-sonnet-20241022".to_string(),
-                total_cost_usd: 18.32,
-                input_tokens: 120_445,
-                output_tokens: 67_234,
-                sessions: 45,
-                percentage_of_total: 78.1,
-            },
-            ModelCostBreakdown {
-                model_name: "claude-3-haiku-20240307".to_string(),
-                total_cost_usd: 5.15,
-                input_tokens: 25_447,
-                output_tokens: 22_113,
-                sessions: 12,
-                percentage_of_total: 21.9,
-            },
-        ],
-        top_users_by_cost: vec![
-            UserCostStats {
-                user_email: "developer@example.com".to_string(),
-                total_cost_usd: 15.23,
-                total_tokens: 189_445,
-                sessions: 32,
-                avg_cost_per_session: 0.48,
-            },
-            UserCostStats {
-                user_email: "engineer@example.com".to_string(),
-                total_cost_usd: 8.24,
-                total_tokens: 67_234,
-                sessions: 25,
-                avg_cost_per_session: 0.33,
-            },
-        ],
+    let bucket = parse_bucket(params.bucket.as_deref(), start_time, end_time)?;
+    let scope = params.scope();
+    let pricing = pricing.current();
+    let mut cost_trend = cost_trend(&*db, start_time, end_time, bucket, &scope).await?;
+    let (costs, tokens) = tokio::try_join!(
+        db.get_scoped_metrics(start_time, end_time, Some("claude_code.cost.usage"), &scope),
+        db.get_scoped_metrics(start_time, end_time, Some("claude_code.token.usage"), &scope),
+    )?;
+    fill_missing_costs(&pricing, &mut cost_trend, &tokens, bucket);
+
+    let metrics: Vec<MetricRecord> = costs.into_iter().chain(tokens).collect();
+    let sessions = metrics.iter().filter_map(|metric| metric.session_id).collect::<HashSet<_>>().len();
+    let total_cost_usd: f64 = cost_trend.iter().map(|point| point.cost_usd).sum();
+
+    let mut costs = CostAnalytics {
+        total_cost_usd,
+        total_input_tokens: cost_trend.iter().map(|point| point.input_tokens).sum(),
+        total_output_tokens: cost_trend.iter().map(|point| point.output_tokens).sum(),
+        total_cache_creation_tokens: cost_trend.iter().map(|point| point.cache_creation_tokens).sum(),
+        total_cache_read_tokens: cost_trend.iter().map(|point| point.cache_read_tokens).sum(),
+        average_cost_per_session: if sessions == 0 { 0.0 } else { total_cost_usd / sessions as f64 },
+        cost_trend,
+        model_breakdown: model_breakdown(&metrics, &pricing),
+        top_users_by_cost: top_users_by_cost(&metrics, &pricing),
     };
 
+    for user in &mut costs.top_users_by_cost {
+        user.user_email = privacy.mask(&user.user_email);
+    }
+
     Ok(Json(ApiResponse::success(costs)))
 }
 
 // GET /api/analytics/efficiency - Usage efficiency metrics
+//
+// Over the window's token, cost and code metrics:
+// - tokens_per_commit = tokens / commits, cost_per_commit = cost / commits
+// - tokens_per_line_of_code = tokens / lines added, cost_per_line_of_code = cost / lines added
+// - session_productivity_score: see `productivity_score`
+// Each ratio is 0 when its denominator is. `tool_efficiency` is the tool usage breakdown,
+// with each tool's `productivity_correlation` from `tool_efficiency`. `time_to_productivity`
+// has a point per session that started in the window, see `time_to_productivity`.
 async fn get_efficiency_metrics(
     State(db): State<Arc<dyn Database>>,
+    State(pricing): State<Arc<PricingStore>>,
     Query(params): Query<AnalyticsQuery>,
 ) -> ApiResult<impl IntoResponse> {
     let (start_time, end_time) = parse_time_range(&params)?;
-    
-    // TODO: Implement actual efficiency calculations
-    // This is a mock implementation showing the expected structure
-    
+    let scope = params.scope();
+    let (usage, code) = tokio::try_join!(
+        usage_metrics(&*db, start_time, end_time, &scope),
+        code_metrics(&*db, start_time, end_time, &scope),
+    )?;
+    let started = SessionFilter { start_time: Some(start_time), end_time: Some(end_time), ..SessionFilter::default() };
+    let oldest_first = SessionSort { key: SessionSortKey::StartTime, order: SortOrder::Asc };
+    let (logs, spans, started) = tokio::try_join!(
+        db.get_logs(Some(start_time), Some(end_time), None),
+        db.get_traces(Some(start_time), Some(end_time), None),
+        db.list_sessions_sorted(&started, oldest_first, PRODUCTIVITY_SESSIONS, 0),
+    )?;
+    let (logs, spans) = scope_tool_calls(&scope, usage.iter().chain(&code), logs, spans);
+
+    let sessions = usage.iter().chain(&code).filter_map(|metric| metric.session_id).collect::<HashSet<_>>().len();
+    let usage: Vec<EnhancedClaudeMetric> = usage
+        .into_iter()
+        .map(|metric| EnhancedClaudeMetric::from_basic_metric(metric.name, metric.value, metric.timestamp, metric.labels))
+        .collect();
+    let tokens: f64 = usage.iter().filter(|metric| metric.is_token_metric()).map(|metric| metric.value).sum();
+    let cost = cost_or_derived(&usage, &pricing.current());
+    let mut code_totals = CodeTotals::default();
+    code.iter().for_each(|metric| code_totals.add(metric));
+    let commits = code_totals.commits as f64;
+    let lines = code_totals.lines_added as f64;
+    let ratio = |total: f64, count: f64| if count > 0.0 { total / count } else { 0.0 };
+
     let efficiency = EfficiencyMetrics {
-        tokens_per_commit: 3_472.5,
-        cost_per_commit: 0.56,
-        tokens_per_line_of_code: 143.2,
-        cost_per_line_of_code: 0.019,
-        session_productivity_score: 8.2, // out of 10
-        tool_efficiency: vec![
-            ToolEfficiencyStats {
-                tool_name: "Edit".to_string(),
-                usage_count: 234,
-                success_rate: 97.4,
-                avg_duration_ms: 1_250.0,
-                productivity_correlation: 0.89,
-            },
-            ToolEfficiencyStats {
-                tool_name: "Read".to_string(),
-                usage_count: 456,
-                success_rate: 99.1,
-                avg_duration_ms: 580.0,
-                productivity_correlation: 0.72,
-            },
-            ToolEfficiencyStats {
-                tool_name: "Bash".to_string(),
-                usage_count: 123,
-                success_rate: 94.3,
-                avg_duration_ms: 2_840.0,
-                productivity_correlation: 0.65,
-            },
-        ],
-        time_to_productivity: generate_mock_time_to_productivity(start_time, end_time),
+        tokens_per_commit: ratio(tokens, commits),
+        cost_per_commit: ratio(cost, commits),
+        tokens_per_line_of_code: ratio(tokens, lines),
+        cost_per_line_of_code: ratio(cost, lines),
+        session_productivity_score: productivity_score(commits, lines, cost, sessions),
+        tool_efficiency: tool_efficiency(&logs, &spans, &code),
+        time_to_productivity: time_to_productivity(&started, &code),
     };
 
     Ok(Json(ApiResponse::success(efficiency)))
 }
 
+// Sessions considered for time-to-productivity, oldest first
+const PRODUCTIVITY_SESSIONS: u32 = 1000;
+
+// When a session first committed and first added lines
+#[derive(Default)]
+struct FirstOutput {
+    commit: Option<DateTime<Utc>>,
+    edit: Option<DateTime<Utc>>,
+}
+
+/// Minutes from each session's start to its first commit and its first added lines, for the
+/// sessions in `sessions` that did either in `code`. Sessions with neither are left out, and
+/// a point reported before its session's start counts as at the start.
+fn time_to_productivity(sessions: &[SessionRecord], code: &[MetricRecord]) -> Vec<TimeToProductivityPoint> {
+    let mut firsts: HashMap<Uuid, FirstOutput> = HashMap::new();
+    for metric in code {
+        let Some(session_id) = metric.session_id else { continue };
+        if metric.value <= 0.0 {
+            continue;
+        }
+        let firsts = firsts.entry(session_id).or_default();
+        let first = match classify_metric(&metric.name, &metric.labels) {
+            MetricType::CommitCount => &mut firsts.commit,
+            MetricType::LinesOfCode { change_type: CodeChangeType::Added } => &mut firsts.edit,
+            _ => continue,
+        };
+        *first = Some(first.map_or(metric.timestamp, |first| first.min(metric.timestamp)));
+    }
+
+    sessions
+        .iter()
+        .filter_map(|session| {
+            let firsts = firsts.get(&session.id)?;
+            if firsts.commit.is_none() && firsts.edit.is_none() {
+                return None;
+            }
+            let minutes = |at: DateTime<Utc>| (at - session.start_time).num_seconds().max(0) as f64 / 60.0;
+            Some(TimeToProductivityPoint {
+                timestamp: session.start_time,
+                session_id: session.id,
+                session_start_to_first_commit_minutes: firsts.commit.map(minutes),
+                session_start_to_first_edit_minutes: firsts.edit.map(minutes),
+            })
+        })
+        .collect()
+}
+
+// What a session needs for full marks in the productivity score, and the spend that halves it
+const SCORE_COMMITS_PER_SESSION: f64 = 2.0;
+const SCORE_LINES_PER_SESSION: f64 = 200.0;
+const SCORE_HALVING_COST_USD: f64 = 5.0;
+
+/// Productivity out of 10, from per-session averages:
+/// `10 * (min(commits / 2, 1) + min(lines / 200, 1)) / 2 / (1 + cost / $5)`.
+/// Output is what a session delivers relative to 2 commits and 200 lines added; spending
+/// $5 a session halves the score. 0 without sessions.
+fn productivity_score(commits: f64, lines: f64, cost: f64, sessions: usize) -> f64 {
+    if sessions == 0 {
+        return 0.0;
+    }
+    let sessions = sessions as f64;
+    let output = ((commits / sessions / SCORE_COMMITS_PER_SESSION).min(1.0)
+        + (lines / sessions / SCORE_LINES_PER_SESSION).min(1.0))
+        / 2.0;
+    10.0 * output / (1.0 + cost / sessions / SCORE_HALVING_COST_USD)
+}
+
+// The tool usage breakdown, each row with the Pearson correlation, across the sessions
+// that called any tool, between a session's calls of the tool and the lines it added.
+// The correlation is 0 with fewer than two such sessions or when either side is constant.
+fn tool_efficiency(logs: &[LogRecord], spans: &[TraceRecord], code: &[MetricRecord]) -> Vec<ToolEfficiencyStats> {
+    let mut lines: HashMap<Uuid, f64> = HashMap::new();
+    for metric in code {
+        if let (Some(session_id), MetricType::LinesOfCode { change_type: CodeChangeType::Added }) =
+            (metric.session_id, classify_metric(&metric.name, &metric.labels))
+        {
+            *lines.entry(session_id).or_default() += metric.value;
+        }
+    }
+    let mut calls: HashMap<Uuid, HashMap<String, f64>> = HashMap::new();
+    for log in logs {
+        let Some(session_id) = log.session_id else { continue };
+        if let EventType::ToolResult { tool_name } = classify_event(&log.message, &log.attributes) {
+            *calls.entry(session_id).or_default().entry(tool_name).or_default() += 1.0;
+        }
+    }
+
+    let tools = tool_usage(logs, spans);
+    // Rows past the named tools are the "Other" row, which covers every remaining tool
+    let named: HashSet<&str> = tools.iter().take(TOP_TOOL_COUNT).map(|tool| tool.tool_name.as_str()).collect();
+    let correlations: Vec<f64> = tools
+        .iter()
+        .enumerate()
+        .map(|(row, tool)| {
+            let pairs: Vec<(f64, f64)> = calls
+                .iter()
+                .map(|(session_id, by_tool)| {
+                    let count = if row < TOP_TOOL_COUNT {
+                        by_tool.get(&tool.tool_name).copied().unwrap_or(0.0)
+                    } else {
+                        by_tool.iter().filter(|(name, _)| !named.contains(name.as_str())).map(|(_, count)| count).sum()
+                    };
+                    (count, lines.get(session_id).copied().unwrap_or(0.0))
+                })
+                .collect();
+            correlation(&pairs)
+        })
+        .collect();
+
+    tools
+        .into_iter()
+        .zip(correlations)
+        .map(|(tool, productivity_correlation)| ToolEfficiencyStats {
+            tool_name: tool.tool_name,
+            usage_count: tool.usage_count,
+            success_rate: tool.success_rate,
+            avg_duration_ms: tool.avg_duration_ms,
+            productivity_correlation,
+        })
+        .collect()
+}
+
+// Pearson correlation coefficient; 0 when it is undefined
+fn correlation(pairs: &[(f64, f64)]) -> f64 {
+    if pairs.len() < 2 {
+        return 0.0;
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let (mut covariance, mut variance_x, mut variance_y) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x).powi(2);
+        variance_y += (y - mean_y).powi(2);
+    }
+    if variance_x == 0.0 || variance_y == 0.0 {
+        0.0
+    } else {
+        covariance / (variance_x * variance_y).sqrt()
+    }
+}
+
 // GET /api/analytics/trends - Historical trend analysis
+// Each trend compares the range with the equally long range right before it
 async fn get_trend_analysis(
     State(db): State<Arc<dyn Database>>,
-    Query(params): Query<AnalyticsQuery>,
+    State(pricing): State<Arc<PricingStore>>,
+    Query(mut params): Query<AnalyticsQuery>,
 ) -> ApiResult<impl IntoResponse> {
-    let range = params.range.as_deref().unwrap_or("30d");
-    
-    // TODO: Implement actual trend calculations
-    // This is a mock implementation showing the expected structure
-    
+    let range = params.range.get_or_insert_with(|| "30d".to_string()).clone();
+    let (start_time, end_time) = parse_time_range(&params)?;
+    let previous_start = start_time - (end_time - start_time);
+    let scope = params.scope();
+    let (usage, code) = tokio::try_join!(
+        usage_metrics(&*db, previous_start, end_time, &scope),
+        code_metrics(&*db, previous_start, end_time, &scope),
+    )?;
+
+    let pricing = pricing.current();
+    let mut daily_costs = cost_trend(&*db, start_time, end_time, TimeBucket::Day, &scope).await?;
+    fill_missing_costs(&pricing, &mut daily_costs, &usage, TimeBucket::Day);
+    let daily_costs: Vec<_> = daily_costs.iter().map(|point| (point.timestamp, point.cost_usd)).collect();
+    let daily_code = daily_code_totals(&code, start_time, end_time);
+    let forecast = |value: fn(&CodeTotals) -> u64| {
+        let series: Vec<_> = daily_code.iter().map(|(day, totals)| (*day, value(totals) as f64)).collect();
+        linear_forecast(&series).round() as u64
+    };
+    let forecasted_monthly_productivity = ProductivityForecast {
+        commits: forecast(|totals| totals.commits),
+        pull_requests: forecast(|totals| totals.pull_requests),
+        lines_of_code: forecast(|totals| totals.lines_added + totals.lines_removed),
+    };
+    let (current, previous): (Vec<MetricRecord>, Vec<MetricRecord>) =
+        usage.into_iter().chain(code).partition(|metric| metric.timestamp >= start_time);
+    let current = WindowTotals::from_metrics(&current, &pricing);
+    let previous = WindowTotals::from_metrics(&previous, &pricing);
+
     let trends = TrendAnalysis {
-        range: range.to_string(),
-        cost_trend: TrendDirection::Increasing(12.3),
-        productivity_trend: TrendDirection::Increasing(8.7),
-        token_efficiency_trend: TrendDirection::Decreasing(3.2),
-        user_adoption_trend: TrendDirection::Increasing(25.1),
-        forecasted_monthly_cost: 67.89,
-        forecasted_monthly_productivity: ProductivityForecast {
-            commits: 180,
-            pull_requests: 35,
-            lines_of_code: 8_450,
-        },
+        range,
+        cost_trend: compute_trend(current.cost, previous.cost),
+        productivity_trend: compute_trend(current.commits, previous.commits),
+        token_efficiency_trend: compute_trend(current.lines_per_token(), previous.lines_per_token()),
+        user_adoption_trend: compute_trend(current.users as f64, previous.users as f64),
+        forecasted_monthly_cost: linear_forecast(&daily_costs),
+        forecasted_monthly_productivity,
     };
 
     Ok(Json(ApiResponse::success(trends)))
 }
 
+// Code totals per day of the range, zero-filled, oldest first
+fn daily_code_totals(metrics: &[MetricRecord], start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<(DateTime<Utc>, CodeTotals)> {
+    let mut by_day: HashMap<DateTime<Utc>, CodeTotals> = HashMap::new();
+    for metric in metrics.iter().filter(|metric| metric.timestamp >= start && metric.timestamp <= end) {
+        by_day.entry(TimeBucket::Day.truncate(metric.timestamp)).or_default().add(metric);
+    }
+
+    let mut days = Vec::new();
+    let mut day = TimeBucket::Day.truncate(start);
+    while day <= end {
+        days.push((day, by_day.remove(&day).unwrap_or_default()));
+        day += TimeBucket::Day.duration();
+    }
+    days
+}
+
+/// Percentage change from `previous` to `current`. Growth from zero counts as 100%,
+/// since there is no base to divide by.
+pub(super) fn compute_trend(current: f64, previous: f64) -> TrendDirection {
+    let change = if previous == 0.0 {
+        if current == 0.0 { 0.0 } else { 100.0_f64.copysign(current) }
+    } else {
+        (current - previous) / previous.abs() * 100.0
+    };
+
+    if change.abs() <= STABLE_TREND_PCT {
+        TrendDirection::Stable
+    } else if change > 0.0 {
+        TrendDirection::Increasing(change)
+    } else {
+        TrendDirection::Decreasing(-change)
+    }
+}
+
+// What the trend analysis and KPIs compare between two windows
+#[derive(Debug, Default)]
+struct WindowTotals {
+    cost: f64,
+    tokens: f64,
+    commits: f64,
+    lines_added: f64,
+    users: usize,
+    sessions: usize,
+}
+
+impl WindowTotals {
+    fn from_metrics(metrics: &[MetricRecord], pricing: &PricingTable) -> Self {
+        let mut code = CodeTotals::default();
+        metrics.iter().for_each(|metric| code.add(metric));
+        let usage: Vec<EnhancedClaudeMetric> = metrics
+            .iter()
+            .map(|metric| EnhancedClaudeMetric::from_basic_metric(metric.name.clone(), metric.value, metric.timestamp, metric.labels.clone()))
+            .collect();
+
+        Self {
+            cost: cost_or_derived(&usage, pricing),
+            tokens: usage.iter().filter(|metric| metric.is_token_metric()).map(|metric| metric.value).sum(),
+            commits: code.commits as f64,
+            lines_added: code.lines_added as f64,
+            users: metrics.iter().filter_map(metric_user).collect::<HashSet<_>>().len(),
+            sessions: metrics.iter().filter_map(|metric| metric.session_id).collect::<HashSet<_>>().len(),
+        }
+    }
+
+    // Token efficiency: lines of code written per token spent
+    fn lines_per_token(&self) -> f64 {
+        if self.tokens > 0.0 { self.lines_added / self.tokens } else { 0.0 }
+    }
+}
+
 // Helper functions
-fn parse_time_range(params: &AnalyticsQuery) -> ApiResult<(DateTime<Utc>, DateTime<Utc>)> {
-    match (&params.start_time, &params.end_time, &params.range) {
-        (Some(start), Some(end), _) => Ok((*start, *end)),
+pub(super) fn parse_time_range(params: &AnalyticsQuery) -> ApiResult<(DateTime<Utc>, DateTime<Utc>)> {
+    let (start_time, end_time) = match (params.start_time, params.end_time, &params.range) {
+        (Some(start), Some(end), _) => (start, end),
         (_, _, Some(range)) => {
+            let length = parse_range(range).ok_or_else(|| ApiError::InvalidQuery(format!("Invalid range: {}", range)))?;
             let end_time = Utc::now();
-            let start_time = match range.as_str() {
-                "1h" => end_time - Duration::hours(1),
-                "24h" => end_time - Duration::hours(24),
-                "7d" => end_time - Duration::days(7),
-                "30d" => end_time - Duration::days(30),
-                "90d" => end_time - Duration::days(90),
-                _ => return Err(ApiError::InvalidQuery(format!("Invalid range: {}", range))),
-            };
-            Ok((start_time, end_time))
+            let start_time = end_time
+                .checked_sub_signed(length)
+                .ok_or_else(|| ApiError::InvalidQuery(format!("Invalid range: {}", range)))?;
+            (start_time, end_time)
         }
-        _ => {
+        // A single bound is open-ended up to now, or reaches back the default 24 hours
+        (Some(start), None, None) => (start, Utc::now()),
+        (None, Some(end), None) => (end - Duration::hours(24), end),
+        (None, None, None) => {
             // Default to last 24 hours
             let end_time = Utc::now();
-            let start_time = end_time - Duration::hours(24);
-            Ok((start_time, end_time))
+            (end_time - Duration::hours(24), end_time)
         }
+    };
+
+    if start_time > end_time {
+        return Err(ApiError::InvalidQuery(format!(
+            "start_time {} is after end_time {}",
+            start_time.to_rfc3339(),
+            end_time.to_rfc3339()
+        )));
     }
+    if end_time - start_time > Duration::days(MAX_WINDOW_DAYS) {
+        return Err(ApiError::InvalidQuery(format!(
+            "Time window exceeds the maximum of {} days",
+            MAX_WINDOW_DAYS
+        )));
+    }
+
+    Ok((start_time, end_time))
 }
 
-// Mock data generators (TODO: Replace with real database queries)
-fn generate_mock_productivity_trend(start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<ProductivityPoint> {
-    let mut points = Vec::new();
-    let duration = end - start;
-    let num_points = 24; // 24 data points regardless of range
-    
-    for i in 0..num_points {
-        let timestamp = start + duration * i as i32 / num_points as i32;
-        points.push(ProductivityPoint {
-            timestamp,
-            commits: (i % 3) as u64,
-            pull_requests: if i % 8 == 0 { 1 } else { 0 },
-            lines_added: (50 + i * 10) as u64,
-            lines_removed: (20 + i * 3) as u64,
-        });
+// Explicit bucket, or one sized to the range: hourly up to a day, daily up to a month, weekly beyond
+fn parse_bucket(bucket: Option<&str>, start: DateTime<Utc>, end: DateTime<Utc>) -> ApiResult<TimeBucket> {
+    match bucket {
+        Some(bucket) => {
+            TimeBucket::parse(bucket).ok_or_else(|| ApiError::InvalidQuery(format!("Invalid bucket: {}", bucket)))
+        }
+        None if end - start <= Duration::days(1) => Ok(TimeBucket::Hour),
+        None if end - start <= Duration::days(31) => Ok(TimeBucket::Day),
+        None => Ok(TimeBucket::Week),
     }
-    
-    points
 }
 
-fn generate_mock_cost_trend(start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<CostPoint> {
+fn check_trend_points(start: DateTime<Utc>, end: DateTime<Utc>, bucket: TimeBucket) -> ApiResult<()> {
+    if (end - start).num_seconds() / bucket.duration().num_seconds() > MAX_TREND_POINTS {
+        return Err(ApiError::InvalidQuery(format!("Range has more than {} {:?} buckets", MAX_TREND_POINTS, bucket)));
+    }
+    Ok(())
+}
+
+// Every bucket from `start` to `end`, with zeros where nothing was recorded
+async fn cost_trend(
+    db: &dyn Database,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    bucket: TimeBucket,
+    scope: &MetricScope,
+) -> ApiResult<Vec<CostPoint>> {
+    check_trend_points(start, end, bucket)?;
+
+    let mut sums: HashMap<DateTime<Utc>, _> = db
+        .cost_buckets(start, end, bucket, scope)
+        .await?
+        .into_iter()
+        .map(|sums| (sums.start, sums))
+        .collect();
+
     let mut points = Vec::new();
-    let duration = end - start;
-    let num_points = 24;
-    
-    for i in 0..num_points {
-        let timestamp = start + duration * i as i32 / num_points as i32;
+    let mut timestamp = bucket.truncate(start);
+    while timestamp <= end {
+        let sums = sums.remove(&timestamp).unwrap_or_default();
         points.push(CostPoint {
             timestamp,
-            cost_usd: 0.5 + (i as f64 * 0.1),
-            input_tokens: (1000 + i * 50) as u64,
-            output_tokens: (600 + i * 30) as u64,
-            cache_creation_tokens: (100 + i * 5) as u64,
-            cache_read_tokens: (200 + i * 10) as u64,
+            cost_usd: sums.cost_usd,
+            input_tokens: sums.input_tokens,
+            output_tokens: sums.output_tokens,
+            cache_creation_tokens: sums.cache_creation_tokens,
+            cache_read_tokens: sums.cache_read_tokens,
         });
+        timestamp += bucket.duration();
     }
-    
-    points
+
+    Ok(points)
 }
 
-fn generate_mock_time_to_productivity(start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<TimeToProductivityPoint> {
-    let mut points = Vec::new();
-    let duration = end - start;
-    let num_points = 10;
-    
-    for i in 0..num_points {
-        let timestamp = start + duration * i as i32 / num_points as i32;
-        points.push(TimeToProductivityPoint {
-            timestamp,
-            session_start_to_first_commit_minutes: 15.5 + (i as f64 * 2.3),
-            session_start_to_first_edit_minutes: 3.2 + (i as f64 * 0.8),
-        });
+// Buckets with token usage but no `cost.usage` points get a cost priced from their tokens
+fn fill_missing_costs(pricing: &PricingTable, points: &mut [CostPoint], tokens: &[MetricRecord], bucket: TimeBucket) {
+    if points.iter().all(|point| point.cost_usd > 0.0 || !point.has_tokens()) {
+        return;
     }
-    
-    points
+
+    let mut by_bucket: HashMap<DateTime<Utc>, Vec<EnhancedClaudeMetric>> = HashMap::new();
+    for metric in tokens {
+        by_bucket
+            .entry(bucket.truncate(metric.timestamp))
+            .or_default()
+            .push(EnhancedClaudeMetric::from_basic_metric(metric.name.clone(), metric.value, metric.timestamp, metric.labels.clone()));
+    }
+
+    for point in points.iter_mut().filter(|point| point.cost_usd == 0.0) {
+        if let Some(metrics) = by_bucket.get(&point.timestamp) {
+            point.cost_usd = cost_or_derived(metrics, pricing);
+        }
+    }
+}
+
+// Cost and token points within `scope`
+async fn usage_metrics(
+    db: &dyn Database,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    scope: &MetricScope,
+) -> ApiResult<Vec<MetricRecord>> {
+    let (costs, tokens) = tokio::try_join!(
+        db.get_scoped_metrics(start, end, Some("claude_code.cost.usage"), scope),
+        db.get_scoped_metrics(start, end, Some("claude_code.token.usage"), scope),
+    )?;
+    Ok(costs.into_iter().chain(tokens).collect())
+}
+
+// Commit, pull request and lines-of-code points within `scope`
+async fn code_metrics(
+    db: &dyn Database,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    scope: &MetricScope,
+) -> ApiResult<Vec<MetricRecord>> {
+    let (commits, pull_requests, lines) = tokio::try_join!(
+        db.get_scoped_metrics(start, end, Some("claude_code.commit.count"), scope),
+        db.get_scoped_metrics(start, end, Some("claude_code.pull_request.count"), scope),
+        db.get_scoped_metrics(start, end, Some("claude_code.lines_of_code.count"), scope),
+    )?;
+    Ok(commits.into_iter().chain(pull_requests).chain(lines).collect())
+}
+
+// The user a point is attributed to: its `user.email` label, else `user.id`
+fn metric_user(metric: &MetricRecord) -> Option<String> {
+    let user = MetricClassifier::extract_user_context(&metric.labels);
+    user.user_email.or(user.user_id)
+}
+
+#[derive(Debug, Default)]
+struct CodeTotals {
+    commits: u64,
+    pull_requests: u64,
+    lines_added: u64,
+    lines_removed: u64,
+}
+
+impl CodeTotals {
+    fn add(&mut self, metric: &MetricRecord) {
+        let value = metric.value as u64;
+        match classify_metric(&metric.name, &metric.labels) {
+            MetricType::CommitCount => self.commits += value,
+            MetricType::PullRequestCount => self.pull_requests += value,
+            MetricType::LinesOfCode { change_type: CodeChangeType::Added } => self.lines_added += value,
+            MetricType::LinesOfCode { change_type: CodeChangeType::Removed } => self.lines_removed += value,
+            _ => {}
+        }
+    }
+}
+
+// Cost and token usage of the points sharing a key, e.g. a model or a user
+#[derive(Default)]
+struct UsageGroup {
+    metrics: Vec<EnhancedClaudeMetric>,
+    sessions: HashSet<Uuid>,
+    input_tokens: f64,
+    output_tokens: f64,
+    total_tokens: f64,
+}
+
+// Points whose key is None are left out
+fn group_usage(
+    metrics: impl IntoIterator<Item = MetricRecord>,
+    key: impl Fn(&MetricRecord) -> Option<String>,
+) -> HashMap<String, UsageGroup> {
+    let mut groups: HashMap<String, UsageGroup> = HashMap::new();
+    for metric in metrics {
+        let Some(key) = key(&metric) else { continue };
+        let group = groups.entry(key).or_default();
+        group.sessions.extend(metric.session_id);
+        let metric = EnhancedClaudeMetric::from_basic_metric(metric.name, metric.value, metric.timestamp, metric.labels);
+        match metric.metric_type {
+            MetricType::TokenUsage { token_type: TokenType::Input } => group.input_tokens += metric.value,
+            MetricType::TokenUsage { token_type: TokenType::Output } => group.output_tokens += metric.value,
+            _ => {}
+        }
+        if metric.is_token_metric() {
+            group.total_tokens += metric.value;
+        }
+        group.metrics.push(metric);
+    }
+    groups
+}
+
+fn model_label(metric: &MetricRecord) -> Option<String> {
+    Some(metric.labels.get("model").cloned().unwrap_or_else(|| "unknown".to_string()))
+}
+
+// Most expensive model first
+fn model_breakdown(metrics: &[MetricRecord], pricing: &PricingTable) -> Vec<ModelCostBreakdown> {
+    let costs: Vec<(String, UsageGroup, f64)> = group_usage(metrics.iter().cloned(), model_label)
+        .into_iter()
+        .map(|(model, group)| {
+            let cost = cost_or_derived(&group.metrics, pricing);
+            (model, group, cost)
+        })
+        .collect();
+    let total: f64 = costs.iter().map(|(_, _, cost)| cost).sum();
+
+    let mut models: Vec<ModelCostBreakdown> = costs
+        .into_iter()
+        .map(|(model_name, group, total_cost_usd)| ModelCostBreakdown {
+            model_name,
+            total_cost_usd,
+            input_tokens: group.input_tokens as u64,
+            output_tokens: group.output_tokens as u64,
+            sessions: group.sessions.len() as u64,
+            percentage_of_total: if total > 0.0 { total_cost_usd / total * 100.0 } else { 0.0 },
+        })
+        .collect();
+    models.sort_by(|a, b| b.total_cost_usd.total_cmp(&a.total_cost_usd).then_with(|| a.model_name.cmp(&b.model_name)));
+    models
+}
+
+// The TOP_USERS biggest spenders, most expensive first
+fn top_users_by_cost(metrics: &[MetricRecord], pricing: &PricingTable) -> Vec<UserCostStats> {
+    let mut users: Vec<UserCostStats> = group_usage(metrics.iter().cloned(), metric_user)
+        .into_iter()
+        .map(|(user_email, group)| {
+            let total_cost_usd = cost_or_derived(&group.metrics, pricing);
+            let sessions = group.sessions.len() as u64;
+            UserCostStats {
+                user_email,
+                total_cost_usd,
+                total_tokens: group.total_tokens as u64,
+                sessions,
+                avg_cost_per_session: if sessions == 0 { 0.0 } else { total_cost_usd / sessions as f64 },
+            }
+        })
+        .collect();
+    users.sort_by(|a, b| b.total_cost_usd.total_cmp(&a.total_cost_usd).then_with(|| a.user_email.cmp(&b.user_email)));
+    users.truncate(TOP_USERS);
+    users
 }
 
 // New dashboard endpoints
 // GET /api/analytics/dashboard/kpis - Dashboard KPI summary
 async fn get_dashboard_kpis(
-    State(_db): State<Arc<dyn Database>>,
+    State(db): State<Arc<dyn Database>>,
+    State(pricing): State<Arc<PricingStore>>,
     Query(params): Query<AnalyticsQuery>,
 ) -> ApiResult<impl IntoResponse> {
-    let range = params.range.as_deref().unwrap_or("24h");
-    
-    // TODO: Implement actual KPI calculations from database
-    let kpis = DashboardKPIs {
-        today_sessions: 24,
-        today_sessions_change: 12.5, // +12.5% from yesterday
-        total_tokens: 145_892,
-        total_tokens_change: -3.2, // -3.2% from previous period
-        total_cost: 23.47,
-        total_cost_change: 8.1, // +8.1% from previous period
-        lines_of_code: 1_247,
-        lines_of_code_change: 15.8, // +15.8% from previous period
-        period: range.to_string(),
-    };
+    let range = params.range.clone().unwrap_or_else(|| "24h".to_string());
+    let windows = KpiWindows::parse(&params, parse_timezone(params.tz.as_deref())?)?;
+    let metrics = db.get_scoped_metrics(windows.previous_start, windows.end, None, &params.scope()).await?;
+    let (current, previous) = windows.split(metrics);
 
+    let kpis = dashboard_kpis(&current, &previous, &pricing.current(), range);
     Ok(Json(ApiResponse::success(kpis)))
 }
 
 // GET /api/analytics/dashboard/token-trend - Token usage trend over time
 async fn get_token_trend(
-    State(_db): State<Arc<dyn Database>>,
+    State(db): State<Arc<dyn Database>>,
     Query(params): Query<AnalyticsQuery>,
 ) -> ApiResult<impl IntoResponse> {
+    let range = params.range.clone().unwrap_or_else(|| "24h".to_string());
     let (start_time, end_time) = parse_time_range(&params)?;
-    let range = params.range.as_deref().unwrap_or("24h");
-    
-    let mut data_points = Vec::new();
-    let duration = end_time - start_time;
-    let num_points = match range {
-        "24h" => 24,
-        "7d" => 7 * 4, // 4 points per day
-        "30d" => 30,
-        _ => 24,
-    };
-    
-    for i in 0..num_points {
-        let timestamp = start_time + duration * i as i32 / num_points as i32;
-        let base_input = 1000 + (i * 50) as u64;
-        let base_output = 600 + (i * 30) as u64;
-        let cache_creation = 50 + (i * 5) as u64;
-        let cache_read = 200 + (i * 10) as u64;
-        
-        data_points.push(TokenTrendPoint {
-            timestamp,
-            input_tokens: base_input,
-            output_tokens: base_output,
-            cache_creation_tokens: cache_creation,
-            cache_read_tokens: cache_read,
-            total_tokens: base_input + base_output + cache_creation + cache_read,
-        });
-    }
-    
+    let bucket = parse_bucket(params.bucket.as_deref(), start_time, end_time)?;
+    check_trend_points(start_time, end_time, bucket)?;
+
+    let tokens = db.get_scoped_metrics(start_time, end_time, Some("claude_code.token.usage"), &params.scope()).await?;
     let trend_data = TokenTrendData {
-        range: range.to_string(),
-        data_points,
+        range,
+        data_points: token_trend(&tokens, start_time, end_time, bucket),
     };
 
     Ok(Json(ApiResponse::success(trend_data)))
@@ -668,204 +1145,442 @@ async fn get_token_trend(
 
 // GET /api/analytics/dashboard/tool-usage - Tool usage statistics
 async fn get_tool_usage(
-    State(_db): State<Arc<dyn Database>>,
-    Query(_params): Query<AnalyticsQuery>,
+    State(db): State<Arc<dyn Database>>,
+    Query(params): Query<AnalyticsQuery>,
 ) -> ApiResult<impl IntoResponse> {
-    // TODO: Implement actual tool usage queries from database
-    let tools = vec![
-        ToolUsageStats {
-            tool_name: "Edit".to_string(),
-            usage_count: 456,
-            success_rate: 97.4,
-            avg_duration_ms: 1_250.0,
-            percentage: 35.2,
-            color: "#8b5cf6".to_string(),
-        },
-        ToolUsageStats {
-            tool_name: "Read".to_string(),
-            usage_count: 324,
-            success_rate: 99.1,
-            avg_duration_ms: 580.0,
-            percentage: 25.0,
-            color: "#06b6d4".to_string(),
-        },
-        ToolUsageStats {
-            tool_name: "Bash".to_string(),
-            usage_count: 189,
-            success_rate: 94.3,
-            avg_duration_ms: 2_840.0,
-            percentage: 14.6,
-            color: "#10b981".to_string(),
-        },
-        ToolUsageStats {
-            tool_name: "Write".to_string(),
-            usage_count: 156,
-            success_rate: 96.8,
-            avg_duration_ms: 1_890.0,
-            percentage: 12.0,
-            color: "#f59e0b".to_string(),
-        },
-        ToolUsageStats {
-            tool_name: "Grep".to_string(),
-            usage_count: 123,
-            success_rate: 98.4,
-            avg_duration_ms: 750.0,
-            percentage: 9.5,
-            color: "#ef4444".to_string(),
-        },
-        ToolUsageStats {
-            tool_name: "Other".to_string(),
-            usage_count: 48,
-            success_rate: 92.1,
-            avg_duration_ms: 1_340.0,
-            percentage: 3.7,
-            color: "#6b7280".to_string(),
-        },
-    ];
-    
-    let total_calls = tools.iter().map(|t| t.usage_count).sum();
-    
+    let (start_time, end_time) = parse_time_range(&params)?;
+    let (logs, spans) = tokio::try_join!(
+        db.get_logs(Some(start_time), Some(end_time), None),
+        db.get_traces(Some(start_time), Some(end_time), None),
+    )?;
+
+    let tools = tool_usage(&logs, &spans);
     let usage_data = ToolUsageData {
-        total_tool_calls: total_calls,
+        total_tool_calls: tools.iter().map(|t| t.usage_count).sum(),
         tools,
     };
 
     Ok(Json(ApiResponse::success(usage_data)))
 }
 
-// GET /api/analytics/dashboard/usage-heatmap - Usage activity heatmap
-async fn get_usage_heatmap(
-    State(_db): State<Arc<dyn Database>>,
-    Query(_params): Query<AnalyticsQuery>,
-) -> ApiResult<impl IntoResponse> {
-    // TODO: Implement actual heatmap data from database
-    let mut heatmap = Vec::new();
-    
-    // Generate 7 days x 24 hours heatmap
-    for day in 0..7 {
-        for hour in 0..24 {
-            // Mock intensity based on typical work patterns
-            let intensity = match (day, hour) {
-                // Weekend
-                (0, _) | (6, _) => 0.1 + ((hour * day + 7) as f64 % 5.0) * 0.04,
-                // Weekday business hours (9-18)
-                (_, h) if h >= 9 && h <= 18 => 0.4 + ((h * day + 13) as f64 % 10.0) * 0.06,
-                // Weekday evening
-                (_, h) if h >= 19 && h <= 23 => 0.2 + ((h + day * 3) as f64 % 7.0) * 0.057,
-                // Night/early morning
-                _ => ((hour + day * 2) as f64 % 11.0) * 0.027,
-            };
-            
-            heatmap.push(HeatmapCell {
-                hour: hour as u8,
-                day_of_week: day,
-                intensity: intensity.min(1.0),
-                session_count: ((intensity * 10.0) as u64).max(1),
-                token_count: ((intensity * 5000.0) as u64).max(100),
-            });
+// Tools listed by name; the rest are summed into one "Other" row
+const TOP_TOOL_COUNT: usize = 5;
+const OTHER_TOOLS_COLOR: &str = "#6b7280";
+
+#[derive(Default)]
+struct ToolCalls {
+    count: u64,
+    failures: u64,
+    duration_ms: f64,
+    timed: u64,
+}
+
+impl ToolCalls {
+    fn add(&mut self, other: &ToolCalls) {
+        self.count += other.count;
+        self.failures += other.failures;
+        self.duration_ms += other.duration_ms;
+        self.timed += other.timed;
+    }
+
+    fn stats(&self, tool_name: String, color: &str, total: u64) -> ToolUsageStats {
+        ToolUsageStats {
+            tool_name,
+            usage_count: self.count,
+            success_rate: (self.count - self.failures) as f64 / self.count as f64 * 100.0,
+            avg_duration_ms: if self.timed == 0 { 0.0 } else { self.duration_ms / self.timed as f64 },
+            duration_available: self.timed > 0,
+            percentage: self.count as f64 / total as f64 * 100.0,
+            color: color.to_string(),
         }
     }
-    
+}
+
+// Per-tool counts of `tool_result` events, most used first. A result fails when its
+// `success` attribute is "false" or it carries an `error`; durations come from spans
+// with a `tool_name` attribute.
+pub(super) fn tool_usage(logs: &[LogRecord], spans: &[TraceRecord]) -> Vec<ToolUsageStats> {
+    let mut by_tool: HashMap<String, ToolCalls> = HashMap::new();
+    for log in logs {
+        let EventType::ToolResult { tool_name } = classify_event(&log.message, &log.attributes) else {
+            continue;
+        };
+        let failed = log.attributes.get("success").is_some_and(|success| success == "false")
+            || log.attributes.get("error").is_some_and(|error| !error.is_empty());
+        let calls = by_tool.entry(tool_name).or_default();
+        calls.count += 1;
+        calls.failures += u64::from(failed);
+    }
+    for span in spans {
+        if let Some(calls) = span.attributes.get("tool_name").and_then(|tool| by_tool.get_mut(tool)) {
+            calls.duration_ms += span.duration_ns as f64 / 1_000_000.0;
+            calls.timed += 1;
+        }
+    }
+
+    let total: u64 = by_tool.values().map(|calls| calls.count).sum();
+    let mut ranked: Vec<(String, ToolCalls)> = by_tool.into_iter().collect();
+    ranked.sort_by(|(a_name, a), (b_name, b)| b.count.cmp(&a.count).then_with(|| a_name.cmp(b_name)));
+
+    let tail = ranked.split_off(ranked.len().min(TOP_TOOL_COUNT));
+    let mut tools: Vec<ToolUsageStats> = ranked
+        .into_iter()
+        .map(|(tool_name, calls)| {
+            let color = chart_color(&tool_name);
+            calls.stats(tool_name, color, total)
+        })
+        .collect();
+    if !tail.is_empty() {
+        let mut other = ToolCalls::default();
+        for (_, calls) in &tail {
+            other.add(calls);
+        }
+        tools.push(other.stats("Other".to_string(), OTHER_TOOLS_COLOR, total));
+    }
+    tools
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HeatmapQuery {
+    /// IANA timezone name the hour-of-week cells are laid out in; `tz` works too
+    pub timezone: Option<String>,
+}
+
+// GET /api/analytics/dashboard/usage-heatmap?timezone= - Usage activity per hour of the week
+async fn get_usage_heatmap(
+    State(db): State<Arc<dyn Database>>,
+    Query(params): Query<AnalyticsQuery>,
+    Query(heatmap): Query<HeatmapQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let (start_time, end_time) = parse_time_range(&params)?;
+    let tz = parse_timezone(heatmap.timezone.as_deref().or(params.tz.as_deref()))?;
+    let metrics = db.get_scoped_metrics(start_time, end_time, None, &params.scope()).await?;
+
     let heatmap_data = UsageHeatmapData {
-        timezone: "UTC".to_string(),
-        heatmap,
+        timezone: tz.name().to_string(),
+        heatmap: usage_heatmap(&metrics, tz),
     };
 
     Ok(Json(ApiResponse::success(heatmap_data)))
 }
 
-// Advanced analytics endpoints for the analytics page
+// All 168 hour-of-week cells in local time, Sunday first. Sessions are those reporting
+// any metric in the hour; intensity is the cell's tokens relative to the busiest cell.
+fn usage_heatmap(metrics: &[MetricRecord], tz: Tz) -> Vec<HeatmapCell> {
+    let mut sessions: Vec<HashSet<Uuid>> = vec![HashSet::new(); 7 * 24];
+    let mut tokens = vec![0u64; 7 * 24];
+    for metric in metrics {
+        let local = metric.timestamp.with_timezone(&tz);
+        let cell = local.weekday().num_days_from_sunday() as usize * 24 + local.hour() as usize;
+        sessions[cell].extend(metric.session_id);
+        if metric.name == "claude_code.token.usage" {
+            tokens[cell] += metric.value as u64;
+        }
+    }
 
-// GET /api/analytics/advanced/model-costs - Model cost comparison
-async fn get_model_cost_comparison(
-    State(_db): State<Arc<dyn Database>>,
+    let busiest = tokens.iter().copied().max().unwrap_or(0);
+    (0..7 * 24)
+        .map(|cell| HeatmapCell {
+            hour: (cell % 24) as u8,
+            day_of_week: (cell / 24) as u8,
+            intensity: if busiest == 0 { 0.0 } else { tokens[cell] as f64 / busiest as f64 },
+            session_count: sessions[cell].len() as u64,
+            token_count: tokens[cell],
+        })
+        .collect()
+}
+
+// Tool calls count when their session reported metrics within the scope
+fn scope_tool_calls<'a>(
+    scope: &MetricScope,
+    metrics: impl IntoIterator<Item = &'a MetricRecord>,
+    logs: Vec<LogRecord>,
+    spans: Vec<TraceRecord>,
+) -> (Vec<LogRecord>, Vec<TraceRecord>) {
+    if *scope == MetricScope::default() {
+        return (logs, spans);
+    }
+    let sessions: HashSet<Uuid> = metrics.into_iter().filter_map(|metric| metric.session_id).collect();
+    let in_scope = |session_id: Option<Uuid>| session_id.is_some_and(|id| sessions.contains(&id));
+    (
+        logs.into_iter().filter(|log| in_scope(log.session_id)).collect(),
+        spans.into_iter().filter(|span| in_scope(span.session_id)).collect(),
+    )
+}
+
+// GET /api/analytics/overview - KPIs, token trend, tool usage and heatmap in one request
+async fn get_analytics_overview(
+    State(db): State<Arc<dyn Database>>,
+    State(pricing): State<Arc<PricingStore>>,
     Query(params): Query<AnalyticsQuery>,
+    Query(heatmap): Query<HeatmapQuery>,
 ) -> ApiResult<impl IntoResponse> {
-    let range = params.range.as_deref().unwrap_or("30d");
-    
-    let models = vec![
-        ModelCostComparisonItem {
-            model_name: "claude-3-5-sonnet-20241022".to_string(),
-            cost_per_session: 1.85,
-            total_sessions: 145,
-            total_cost: 268.25,
-            avg_input_tokens: 2847,
-            avg_output_tokens: 1593,
-            efficiency_score: 0.059, // cost per token
-            color: "#8b5cf6".to_string(),
+    let range = params.range.clone().unwrap_or_else(|| "24h".to_string());
+    let tz = parse_timezone(heatmap.timezone.as_deref().or(params.tz.as_deref()))?;
+    let windows = KpiWindows::parse(&params, tz)?;
+    let (start_time, end_time) = (windows.start, windows.end);
+    let bucket = parse_bucket(params.bucket.as_deref(), start_time, end_time)?;
+    check_trend_points(start_time, end_time, bucket)?;
+
+    // One metrics query covers the range and the window before it, for the KPI changes
+    let scope = params.scope();
+    let (metrics, logs, spans) = tokio::try_join!(
+        db.get_scoped_metrics(windows.previous_start, end_time, None, &scope),
+        db.get_logs(Some(start_time), Some(end_time), None),
+        db.get_traces(Some(start_time), Some(end_time), None),
+    )?;
+    let (current, previous) = windows.split(metrics);
+
+    let (logs, spans) = scope_tool_calls(&scope, &current, logs, spans);
+    let tools = tool_usage(&logs, &spans);
+    let overview = AnalyticsOverview {
+        kpis: dashboard_kpis(&current, &previous, &pricing.current(), range.clone()),
+        token_trend: TokenTrendData {
+            range,
+            data_points: token_trend(&current, start_time, end_time, bucket),
         },
-        ModelCostComparisonItem {
-            model_name: "claude-3-haiku-20240307".to_string(),
-            cost_per_session: 0.42,
-            total_sessions: 89,
-            total_cost: 37.38,
-            avg_input_tokens: 1245,
-            avg_output_tokens: 843,
-            efficiency_score: 0.018,
-            color: "#06b6d4".to_string(),
+        tool_usage: ToolUsageData {
+            total_tool_calls: tools.iter().map(|t| t.usage_count).sum(),
+            tools,
         },
-        ModelCostComparisonItem {
-            model_name: "claude-3-opus-20240229".to_string(),
-            cost_per_session: 3.24,
-            total_sessions: 23,
-            total_cost: 74.52,
-            avg_input_tokens: 3456,
-            avg_output_tokens: 2134,
-            efficiency_score: 0.133,
-            color: "#f59e0b".to_string(),
+        heatmap: UsageHeatmapData {
+            timezone: tz.name().to_string(),
+            heatmap: usage_heatmap(&current, tz),
         },
-    ];
-    
+    };
+
+    Ok(Json(ApiResponse::success(overview)))
+}
+
+// The KPI range and the one it is compared with: `range=today` runs from local midnight
+// in the requested timezone and compares with the same stretch of yesterday, any other
+// range with the equally long range right before it
+struct KpiWindows {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    previous_start: DateTime<Utc>,
+    previous_end: DateTime<Utc>,
+}
+
+impl KpiWindows {
+    fn parse(params: &AnalyticsQuery, tz: Tz) -> ApiResult<Self> {
+        if params.range.as_deref() == Some("today") && params.start_time.is_none() && params.end_time.is_none() {
+            let end = Utc::now();
+            let start = start_of_day(end, tz);
+            let day = Duration::days(1);
+            return Ok(Self { start, end, previous_start: start - day, previous_end: end - day });
+        }
+
+        let (start, end) = parse_time_range(params)?;
+        Ok(Self { start, end, previous_start: start - (end - start), previous_end: start })
+    }
+
+    // Points of the range and of the previous window; anything between the two is dropped
+    fn split(&self, metrics: Vec<MetricRecord>) -> (Vec<MetricRecord>, Vec<MetricRecord>) {
+        let (current, previous): (Vec<_>, Vec<_>) = metrics.into_iter().partition(|metric| metric.timestamp >= self.start);
+        let previous = previous.into_iter().filter(|metric| metric.timestamp <= self.previous_end).collect();
+        (current, previous)
+    }
+}
+
+// Local midnight of `now`'s day in `tz`, or the first hour after it where a DST gap skips midnight
+fn start_of_day(now: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+    let day = now.with_timezone(&tz).date_naive();
+    (0..24)
+        .find_map(|hour| day.and_hms_opt(hour, 0, 0)?.and_local_timezone(tz).earliest())
+        .map_or(now, |start| start.with_timezone(&Utc))
+}
+
+// KPI totals of `current`, each change relative to `previous`
+fn dashboard_kpis(current: &[MetricRecord], previous: &[MetricRecord], pricing: &PricingTable, period: String) -> DashboardKPIs {
+    let now = WindowTotals::from_metrics(current, pricing);
+    let before = WindowTotals::from_metrics(previous, pricing);
+    DashboardKPIs {
+        today_sessions: now.sessions as u64,
+        today_sessions_change: percent_change(now.sessions as f64, before.sessions as f64),
+        total_tokens: now.tokens as u64,
+        total_tokens_change: percent_change(now.tokens, before.tokens),
+        total_cost: now.cost,
+        total_cost_change: percent_change(now.cost, before.cost),
+        lines_of_code: now.lines_added as u64,
+        lines_of_code_change: percent_change(now.lines_added, before.lines_added),
+        period,
+    }
+}
+
+/// Percentage change from `previous` to `current`; 0 when there was nothing before
+fn percent_change(current: f64, previous: f64) -> f64 {
+    if previous == 0.0 { 0.0 } else { (current - previous) / previous.abs() * 100.0 }
+}
+
+// Token usage per bucket from `start` to `end`, split by token type, with zeros where nothing was recorded
+fn token_trend(metrics: &[MetricRecord], start: DateTime<Utc>, end: DateTime<Utc>, bucket: TimeBucket) -> Vec<TokenTrendPoint> {
+    let mut by_bucket: HashMap<DateTime<Utc>, [u64; 4]> = HashMap::new();
+    for metric in metrics {
+        let MetricType::TokenUsage { token_type } = classify_metric(&metric.name, &metric.labels) else {
+            continue;
+        };
+        let index = match token_type {
+            TokenType::Input => 0,
+            TokenType::Output => 1,
+            TokenType::CacheCreation => 2,
+            TokenType::CacheRead => 3,
+        };
+        by_bucket.entry(bucket.truncate(metric.timestamp)).or_default()[index] += metric.value as u64;
+    }
+
+    let mut points = Vec::new();
+    let mut timestamp = bucket.truncate(start);
+    while timestamp <= end {
+        let [input, output, cache_creation, cache_read] = by_bucket.remove(&timestamp).unwrap_or_default();
+        points.push(TokenTrendPoint {
+            timestamp,
+            input_tokens: input,
+            output_tokens: output,
+            cache_creation_tokens: cache_creation,
+            cache_read_tokens: cache_read,
+            total_tokens: input + output + cache_creation + cache_read,
+        });
+        timestamp += bucket.duration();
+    }
+    points
+}
+
+// Advanced analytics endpoints for the analytics page
+
+// GET /api/analytics/advanced/model-costs - Model cost comparison
+async fn get_model_cost_comparison(
+    State(db): State<Arc<dyn Database>>,
+    State(pricing): State<Arc<PricingStore>>,
+    Query(params): Query<AnalyticsQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let (start_time, end_time) = parse_time_range(&params)?;
+    let usage = usage_metrics(&*db, start_time, end_time, &params.scope()).await?;
+
+    let models = compare_model_costs(usage, &pricing.current());
     let total_cost = models.iter().map(|m| m.total_cost).sum();
-    
+
     let comparison = ModelCostComparison {
         models,
         total_cost,
-        period: range.to_string(),
+        period: params.range.clone().unwrap_or_else(|| "24h".to_string()),
     };
 
     Ok(Json(ApiResponse::success(comparison)))
 }
 
-// GET /api/analytics/advanced/budget-progress - Budget tracking
+// Chart colors, indexed by a hash of the series name (model, tool, ...)
+const CHART_COLORS: [&str; 8] = [
+    "#8b5cf6", "#06b6d4", "#f59e0b", "#10b981", "#ef4444", "#3b82f6", "#ec4899", "#84cc16",
+];
+
+// FNV-1a, so a series keeps its color across requests and restarts
+fn chart_color(name: &str) -> &'static str {
+    let hash = name
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3));
+    CHART_COLORS[(hash % CHART_COLORS.len() as u64) as usize]
+}
+
+// Groups cost and token metrics on their `model` label, most expensive model first
+fn compare_model_costs(metrics: impl IntoIterator<Item = MetricRecord>, pricing: &PricingTable) -> Vec<ModelCostComparisonItem> {
+    let mut models: Vec<ModelCostComparisonItem> = group_usage(metrics, model_label)
+        .into_iter()
+        .map(|(model_name, usage)| {
+            let total_cost = cost_or_derived(&usage.metrics, pricing);
+            let sessions = usage.sessions.len() as u64;
+            let per_session = |total: f64| if sessions == 0 { 0.0 } else { total / sessions as f64 };
+            let tokens = usage.input_tokens + usage.output_tokens;
+            ModelCostComparisonItem {
+                color: chart_color(&model_name).to_string(),
+                model_name,
+                cost_per_session: per_session(total_cost),
+                total_sessions: sessions,
+                total_cost,
+                avg_input_tokens: per_session(usage.input_tokens).round() as u64,
+                avg_output_tokens: per_session(usage.output_tokens).round() as u64,
+                efficiency_score: if tokens > 0.0 { total_cost / tokens } else { 0.0 },
+            }
+        })
+        .collect();
+    models.sort_by(|a, b| b.total_cost.total_cmp(&a.total_cost).then_with(|| a.model_name.cmp(&b.model_name)));
+    models
+}
+
+// GET /api/analytics/advanced/budget-progress?user_email= - Month-to-date spend against
+// the user's budget, or against the configured team budget without `user_email`
 async fn get_budget_progress(
-    State(_db): State<Arc<dyn Database>>,
-    Query(_params): Query<AnalyticsQuery>,
+    State(db): State<Arc<dyn Database>>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<AnalyticsQuery>,
 ) -> ApiResult<impl IntoResponse> {
-    let current_cost = 380.15;
-    let budget = 500.0;
-    let days_in_month = 30;
-    let days_passed = 18;
-    let days_remaining = days_in_month - days_passed;
-    
-    // Generate daily breakdown for the current month
-    let mut daily_breakdown = Vec::new();
+    let user = params.user_email.as_deref().map(str::trim).filter(|user| !user.is_empty());
+    let budget = match user {
+        Some(user) => db
+            .get_budget(user)
+            .await?
+            .map(|budget| budget.monthly_budget_usd)
+            .ok_or_else(|| ApiError::NotConfigured(format!("No budget set for {}; add one with POST /api/budgets", user)))?,
+        None => config.monthly_budget_usd.ok_or_else(|| {
+            ApiError::NotConfigured("No monthly budget configured; set CLAUDE_LENS_MONTHLY_BUDGET_USD".to_string())
+        })?,
+    };
+
     let now = Utc::now();
-    
-    for i in 0..days_passed {
-        let date = now - Duration::days(days_passed as i64 - i as i64);
-        let base_cost = 15.0 + (i as f64 * 1.2) + ((i * 7) % 13) as f64 * 0.8;
-        daily_breakdown.push(DailyCostBreakdown {
-            date,
-            cost: base_cost,
-            sessions: 3 + (i % 8) as u64,
-            tokens: ((base_cost * 1500.0) as u64),
-        });
-    }
-    
-    let projected_cost = current_cost / days_passed as f64 * days_in_month as f64;
-    
-    let progress = BudgetProgressData {
-        current_month_cost: current_cost,
+    let scope = MetricScope { user_email: user.map(str::to_string), ..MetricScope::default() };
+    let buckets = db.cost_buckets(month_start(now), now, TimeBucket::Day, &scope).await?;
+
+    Ok(Json(ApiResponse::success(budget_progress(budget, &buckets, now))))
+}
+
+// Midnight UTC on the first of `now`'s month
+fn month_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    bucket_start(now.date_naive().with_day(1).expect("every month has a first day"))
+}
+
+fn bucket_start(day: NaiveDate) -> DateTime<Utc> {
+    day.and_time(NaiveTime::MIN).and_utc()
+}
+
+// Month-to-date spend from daily buckets, projected to month end along the daily trend so far
+fn budget_progress(budget: f64, buckets: &[CostBucket], now: DateTime<Utc>) -> BudgetProgressData {
+    let first_day = month_start(now).date_naive();
+    let today = now.date_naive();
+    let next_month = first_day.checked_add_months(Months::new(1)).expect("date within chrono's range");
+    let days_in_month = (next_month - first_day).num_days() as u32;
+    let days_passed = today.day();
+
+    let by_day: HashMap<DateTime<Utc>, &CostBucket> = buckets.iter().map(|bucket| (bucket.start, bucket)).collect();
+    let daily_breakdown: Vec<DailyCostBreakdown> = first_day
+        .iter_days()
+        .take_while(|day| *day <= today)
+        .map(|day| {
+            let date = bucket_start(day);
+            let bucket = by_day.get(&date);
+            DailyCostBreakdown {
+                date,
+                cost: bucket.map_or(0.0, |b| b.cost_usd),
+                sessions: bucket.map_or(0, |b| b.sessions),
+                tokens: bucket.map_or(0, |b| {
+                    b.input_tokens + b.output_tokens + b.cache_creation_tokens + b.cache_read_tokens
+                }),
+            }
+        })
+        .collect();
+
+    let current_month_cost: f64 = daily_breakdown.iter().map(|day| day.cost).sum();
+    let daily_costs: Vec<_> = daily_breakdown.iter().map(|day| (day.date, day.cost)).collect();
+    let projected_month_end_cost = linear_forecast(&daily_costs);
+
+    BudgetProgressData {
+        current_month_cost,
         monthly_budget: budget,
-        percentage_used: (current_cost / budget * 100.0),
-        days_remaining: days_remaining as u32,
-        projected_month_end_cost: projected_cost,
-        is_over_budget: projected_cost > budget,
+        percentage_used: current_month_cost / budget * 100.0,
+        days_remaining: days_in_month - days_passed,
+        projected_month_end_cost,
+        is_over_budget: current_month_cost > budget,
         daily_breakdown,
-    };
-
-    Ok(Json(ApiResponse::success(progress)))
+    }
 }
 
 // GET /api/analytics/advanced/tool-efficiency - Advanced tool efficiency analysis
@@ -948,83 +1663,109 @@ async fn get_advanced_tool_efficiency(
     Ok(Json(ApiResponse::success(efficiency)))
 }
 
-// GET /api/analytics/advanced/session-duration - Session duration distribution
+// GET /api/analytics/advanced/session-duration - Session duration distribution.
+// Only sessions that started in the window and have ended count: a session without
+// an end_time is still running and has no duration yet.
 async fn get_session_duration_distribution(
-    State(_db): State<Arc<dyn Database>>,
+    State(db): State<Arc<dyn Database>>,
     Query(params): Query<AnalyticsQuery>,
 ) -> ApiResult<impl IntoResponse> {
     let (start_time, end_time) = parse_time_range(&params)?;
-    
-    let buckets = vec![
-        DurationBucket {
-            min_minutes: 0,
-            max_minutes: 5,
-            session_count: 23,
-            percentage: 15.4,
-            label: "0-5 min".to_string(),
-        },
-        DurationBucket {
-            min_minutes: 5,
-            max_minutes: 15,
-            session_count: 45,
-            percentage: 30.2,
-            label: "5-15 min".to_string(),
-        },
-        DurationBucket {
-            min_minutes: 15,
-            max_minutes: 30,
-            session_count: 38,
-            percentage: 25.5,
-            label: "15-30 min".to_string(),
-        },
-        DurationBucket {
-            min_minutes: 30,
-            max_minutes: 60,
-            session_count: 28,
-            percentage: 18.8,
-            label: "30-60 min".to_string(),
-        },
-        DurationBucket {
-            min_minutes: 60,
-            max_minutes: 120,
-            session_count: 12,
-            percentage: 8.1,
-            label: "1-2 hours".to_string(),
-        },
-        DurationBucket {
-            min_minutes: 120,
-            max_minutes: u32::MAX,
-            session_count: 3,
-            percentage: 2.0,
-            label: "2+ hours".to_string(),
-        },
-    ];
-    
-    let total_sessions = buckets.iter().map(|b| b.session_count).sum();
-    
-    // Generate duration over time
-    let mut duration_points = Vec::new();
-    let duration = end_time - start_time;
-    let num_points = 15;
-    
-    for i in 0..num_points {
-        let timestamp = start_time + duration * i as i32 / num_points as i32;
-        duration_points.push(DurationTimePoint {
+    let bucket = parse_bucket(params.bucket.as_deref(), start_time, end_time)?;
+    if (end_time - start_time).num_seconds() / bucket.duration().num_seconds() > MAX_TREND_POINTS {
+        return Err(ApiError::InvalidQuery(format!("Range has more than {} {:?} buckets", MAX_TREND_POINTS, bucket)));
+    }
+
+    let sessions: Vec<(DateTime<Utc>, f64)> = db
+        .list_ended_sessions(start_time, end_time)
+        .await?
+        .into_iter()
+        .filter_map(|session| {
+            let minutes = (session.end_time? - session.start_time).num_seconds().max(0) as f64 / 60.0;
+            Some((session.start_time, minutes))
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(duration_distribution(&sessions, start_time, end_time, bucket))))
+}
+
+// Lower bounds in minutes; each bucket runs up to the next one's bound
+const DURATION_BUCKETS: [(u32, &str); 6] = [
+    (0, "0-5 min"),
+    (5, "5-15 min"),
+    (15, "15-30 min"),
+    (30, "30-60 min"),
+    (60, "1-2 hours"),
+    (120, "2+ hours"),
+];
+
+// `sessions` holds (start time, duration in minutes) pairs
+fn duration_distribution(
+    sessions: &[(DateTime<Utc>, f64)],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    bucket: TimeBucket,
+) -> SessionDurationDistribution {
+    let total_sessions = sessions.len() as u64;
+    let percentage = |count: u64| if total_sessions == 0 { 0.0 } else { count as f64 / total_sessions as f64 * 100.0 };
+
+    let distribution_buckets = DURATION_BUCKETS
+        .iter()
+        .enumerate()
+        .map(|(i, &(min_minutes, label))| {
+            let max_minutes = DURATION_BUCKETS.get(i + 1).map_or(u32::MAX, |&(next, _)| next);
+            let session_count = sessions
+                .iter()
+                .filter(|(_, minutes)| *minutes >= f64::from(min_minutes) && *minutes < f64::from(max_minutes))
+                .count() as u64;
+            DurationBucket {
+                min_minutes,
+                max_minutes,
+                session_count,
+                percentage: percentage(session_count),
+                label: label.to_string(),
+            }
+        })
+        .collect();
+
+    let mut by_bucket: HashMap<DateTime<Utc>, (f64, u64)> = HashMap::new();
+    for (started, minutes) in sessions {
+        let entry = by_bucket.entry(bucket.truncate(*started)).or_default();
+        entry.0 += minutes;
+        entry.1 += 1;
+    }
+    let mut duration_over_time = Vec::new();
+    let mut timestamp = bucket.truncate(start);
+    while timestamp <= end {
+        let (minutes, session_count) = by_bucket.remove(&timestamp).unwrap_or_default();
+        duration_over_time.push(DurationTimePoint {
             timestamp,
-            avg_duration_minutes: 22.5 + ((i * 7) % 11) as f64 * 2.3,
-            session_count: 8 + (i % 6) as u64,
+            avg_duration_minutes: if session_count == 0 { 0.0 } else { minutes / session_count as f64 },
+            session_count,
         });
+        timestamp += bucket.duration();
     }
-    
-    let distribution = SessionDurationDistribution {
+
+    let mut minutes: Vec<f64> = sessions.iter().map(|(_, minutes)| *minutes).collect();
+    minutes.sort_by(f64::total_cmp);
+
+    SessionDurationDistribution {
         total_sessions,
-        avg_duration_minutes: 24.7,
-        median_duration_minutes: 18.3,
-        distribution_buckets: buckets,
-        duration_over_time: duration_points,
-    };
+        avg_duration_minutes: if minutes.is_empty() { 0.0 } else { minutes.iter().sum::<f64>() / minutes.len() as f64 },
+        median_duration_minutes: median(&minutes),
+        distribution_buckets,
+        duration_over_time,
+    }
+}
 
-    Ok(Json(ApiResponse::success(distribution)))
+// Of sorted values; the mean of the middle two for an even count, 0 when empty
+fn median(sorted: &[f64]) -> f64 {
+    let mid = sorted.len() / 2;
+    match sorted.len() {
+        0 => 0.0,
+        len if len % 2 == 0 => (sorted[mid - 1] + sorted[mid]) / 2.0,
+        _ => sorted[mid],
+    }
 }
 
 // GET /api/analytics/advanced/code-generation - Code generation statistics
@@ -1104,4 +1845,1132 @@ async fn get_code_generation_stats(
     };
 
     Ok(Json(ApiResponse::success(stats)))
-}
\ No newline at end of file
+}
+
+// GET /api/analytics/cost-matrix - Per-model daily cost grid on a shared date axis
+async fn get_cost_matrix(
+    State(db): State<Arc<dyn Database>>,
+    Query(params): Query<AnalyticsQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let (start_time, end_time) = parse_time_range(&params)?;
+    let tz = parse_timezone(params.tz.as_deref())?;
+
+    let costs = db.get_scoped_metrics(start_time, end_time, Some("claude_code.cost.usage"), &params.scope()).await?;
+    let points: Vec<(DateTime<Utc>, String, f64)> = costs
+        .into_iter()
+        .map(|m| {
+            let model = m.labels.get("model").cloned().unwrap_or_else(|| "unknown".to_string());
+            (m.timestamp, model, m.value)
+        })
+        .collect();
+
+    let (dates, models) = build_cost_matrix(&points, start_time, end_time, tz);
+
+    let matrix = CostMatrix {
+        range: params.range.clone().unwrap_or_else(|| "24h".to_string()),
+        timezone: tz.name().to_string(),
+        dates,
+        models,
+    };
+
+    Ok(Json(ApiResponse::success(matrix)))
+}
+
+fn parse_timezone(tz: Option<&str>) -> ApiResult<Tz> {
+    match tz {
+        Some(name) => name
+            .parse::<Tz>()
+            .map_err(|_| ApiError::InvalidQuery(format!("Invalid timezone: {}", name))),
+        None => Ok(Tz::UTC),
+    }
+}
+
+// Sums costs per model per local calendar day, zero-filling days without data
+fn build_cost_matrix(
+    points: &[(DateTime<Utc>, String, f64)],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    tz: Tz,
+) -> (Vec<NaiveDate>, Vec<ModelDailyCosts>) {
+    let first_day = start.with_timezone(&tz).date_naive();
+    let last_day = end.with_timezone(&tz).date_naive();
+    let dates: Vec<NaiveDate> = first_day
+        .iter_days()
+        .take_while(|d| *d <= last_day)
+        .collect();
+
+    let mut by_model: HashMap<&str, Vec<f64>> = HashMap::new();
+    for (timestamp, model, cost) in points {
+        let day = timestamp.with_timezone(&tz).date_naive();
+        let Some(index) = dates.iter().position(|d| *d == day) else {
+            continue;
+        };
+        by_model.entry(model.as_str()).or_insert_with(|| vec![0.0; dates.len()])[index] += cost;
+    }
+
+    let mut models: Vec<ModelDailyCosts> = by_model
+        .into_iter()
+        .map(|(model, daily_costs)| ModelDailyCosts {
+            model_name: model.to_string(),
+            total_cost_usd: daily_costs.iter().sum(),
+            daily_costs,
+        })
+        .collect();
+    models.sort_by(|a, b| {
+        b.total_cost_usd
+            .total_cmp(&a.total_cost_usd)
+            .then_with(|| a.model_name.cmp(&b.model_name))
+    });
+
+    (dates, models)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use chrono::TimeZone;
+    use tower::ServiceExt;
+
+    use crate::{api::test_state, storage::sqlite::test_database};
+
+    fn window(start_time: Option<DateTime<Utc>>, end_time: Option<DateTime<Utc>>, range: Option<&str>) -> AnalyticsQuery {
+        AnalyticsQuery { start_time, end_time, range: range.map(str::to_string), ..Default::default() }
+    }
+
+    #[test]
+    fn test_parse_time_range_rejects_inverted_window() {
+        let start = Utc.with_ymd_and_hms(2025, 3, 2, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap();
+        let err = parse_time_range(&window(Some(start), Some(end), None)).unwrap_err();
+        assert!(matches!(err, ApiError::InvalidQuery(msg) if msg.contains("is after")));
+
+        // A lone start in the future lies after the implied end of now
+        let future = Utc::now() + Duration::days(1);
+        assert!(matches!(parse_time_range(&window(Some(future), None, None)), Err(ApiError::InvalidQuery(_))));
+
+        // Equal bounds are an empty but valid window
+        assert_eq!(parse_time_range(&window(Some(start), Some(start), None)).unwrap(), (start, start));
+    }
+
+    #[test]
+    fn test_parse_time_range_caps_window_length() {
+        let end = Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap();
+        let longest = end - Duration::days(MAX_WINDOW_DAYS);
+        assert_eq!(parse_time_range(&window(Some(longest), Some(end), None)).unwrap(), (longest, end));
+
+        let too_long = longest - Duration::seconds(1);
+        let err = parse_time_range(&window(Some(too_long), Some(end), None)).unwrap_err();
+        assert!(matches!(err, ApiError::InvalidQuery(msg) if msg.contains("365 days")));
+        assert!(parse_time_range(&window(Some(end - Duration::days(400)), None, None)).is_err());
+    }
+
+    #[test]
+    fn test_parse_time_range_rejects_unknown_range() {
+        for range in ["5x", "24H", "", "0d"] {
+            let err = parse_time_range(&window(None, None, Some(range))).unwrap_err();
+            assert!(matches!(err, ApiError::InvalidQuery(msg) if msg == format!("Invalid range: {}", range)));
+        }
+
+        for (range, length) in [("12h", Duration::hours(12)), ("3d", Duration::days(3)), ("2w", Duration::weeks(2))] {
+            let (start, end) = parse_time_range(&window(None, None, Some(range))).unwrap();
+            assert_eq!(end - start, length);
+        }
+        // Well-formed, but longer than any query may span
+        assert!(parse_time_range(&window(None, None, Some("53w"))).is_err());
+        // Far enough back to overflow the timestamp, not just the window cap
+        assert!(matches!(parse_time_range(&window(None, None, Some("100000000w"))), Err(ApiError::InvalidQuery(_))));
+        // A lone end_time reaches back the default 24 hours
+        let end = Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap();
+        assert_eq!(parse_time_range(&window(None, Some(end), None)).unwrap(), (end - Duration::hours(24), end));
+    }
+
+    #[test]
+    fn test_build_cost_matrix_aligns_models_and_fills_gaps() {
+        let start = Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2025, 3, 4, 12, 0, 0).unwrap();
+        let at = |day: u32, hour: u32| Utc.with_ymd_and_hms(2025, 3, day, hour, 0, 0).unwrap();
+        let points = vec![
+            (at(1, 9), "sonnet".to_string(), 1.0),
+            (at(1, 15), "sonnet".to_string(), 0.5),
+            (at(3, 10), "sonnet".to_string(), 2.0),
+            (at(2, 8), "haiku".to_string(), 0.25),
+            (at(4, 11), "haiku".to_string(), 0.25),
+        ];
+
+        let (dates, models) = build_cost_matrix(&points, start, end, Tz::UTC);
+
+        assert_eq!(dates.len(), 4);
+        assert_eq!(dates[0], NaiveDate::from_ymd_opt(2025, 3, 1).unwrap());
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].model_name, "sonnet");
+        assert_eq!(models[0].daily_costs, vec![1.5, 0.0, 2.0, 0.0]);
+        assert_eq!(models[0].total_cost_usd, 3.5);
+        assert_eq!(models[1].model_name, "haiku");
+        assert_eq!(models[1].daily_costs, vec![0.0, 0.25, 0.0, 0.25]);
+    }
+
+    #[test]
+    fn test_build_cost_matrix_buckets_by_local_day() {
+        let start = Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2025, 3, 2, 12, 0, 0).unwrap();
+        // 23:30 UTC on the 1st is already the 2nd in Tokyo
+        let points = vec![(Utc.with_ymd_and_hms(2025, 3, 1, 23, 30, 0).unwrap(), "sonnet".to_string(), 1.0)];
+
+        let tz = parse_timezone(Some("Asia/Tokyo")).unwrap();
+        let (dates, models) = build_cost_matrix(&points, start, end, tz);
+
+        assert_eq!(dates, vec![NaiveDate::from_ymd_opt(2025, 3, 1).unwrap(), NaiveDate::from_ymd_opt(2025, 3, 2).unwrap()]);
+        assert_eq!(models[0].daily_costs, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_parse_timezone_rejects_unknown_names() {
+        assert!(matches!(parse_timezone(Some("Mars/Olympus")), Err(ApiError::InvalidQuery(_))));
+        assert_eq!(parse_timezone(None).unwrap(), Tz::UTC);
+    }
+
+    #[tokio::test]
+    async fn test_late_point_corrects_an_earlier_bucket() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db.clone()));
+        let cost = |timestamp: DateTime<Utc>, value: f64| MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            name: "claude_code.cost.usage".to_string(),
+            timestamp,
+            value,
+            labels: HashMap::from([("model".to_string(), "sonnet".to_string())]),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        };
+        let daily_costs = |app: Router| async move {
+            let uri = "/cost-matrix?start_time=2025-03-01T00:00:00Z&end_time=2025-03-03T00:00:00Z";
+            let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            body["data"]["models"][0]["daily_costs"].clone()
+        };
+
+        db.store_metric(&cost(Utc.with_ymd_and_hms(2025, 3, 2, 10, 0, 0).unwrap(), 2.0)).await.unwrap();
+        assert_eq!(daily_costs(app.clone()).await, serde_json::json!([0.0, 2.0, 0.0]));
+
+        // Exported after the 2nd's bucket was already served, but stamped on the 1st
+        db.store_metric(&cost(Utc.with_ymd_and_hms(2025, 3, 1, 18, 0, 0).unwrap(), 1.5)).await.unwrap();
+        assert_eq!(daily_costs(app).await, serde_json::json!([1.5, 2.0, 0.0]));
+    }
+
+    #[tokio::test]
+    async fn test_privacy_mode_hashes_user_emails() {
+        let (_dir, db) = test_database().await;
+        let now = Utc::now();
+        for (email, cost, commits) in [("developer@example.com", 5.0, 3.0), ("other@example.com", 1.0, 1.0)] {
+            for (name, value) in [("claude_code.cost.usage", cost), ("claude_code.commit.count", commits)] {
+                let mut metric = usage(name, None, value, now - Duration::hours(1));
+                metric.labels.insert("user.email".to_string(), email.to_string());
+                db.store_metric(&metric).await.unwrap();
+            }
+        }
+        let mut state = test_state(db);
+        state.privacy = Privacy::new(true, Some("pepper"));
+        let app = routes().with_state(state);
+        let fetch = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        let costs = fetch("/costs").await;
+        let productivity = fetch("/productivity").await;
+
+        let top_user = costs["data"]["top_users_by_cost"][0]["user_email"].as_str().unwrap();
+        let top_contributor = productivity["data"]["top_contributors"][0]["user_email"].as_str().unwrap();
+        assert!(top_user.starts_with("user-"), "{}", top_user);
+        assert!(!costs.to_string().contains('@') && !productivity.to_string().contains('@'));
+        // Both endpoints report developer@example.com first
+        assert_eq!(top_user, top_contributor);
+        assert_eq!(top_user, Privacy::new(true, Some("pepper")).mask("developer@example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_analytics_filter_by_user_and_organization() {
+        let (_dir, db) = test_database().await;
+        let now = Utc::now();
+        for (email, org, cost, lines) in [("alice@example.com", "acme", 4.0, 40.0), ("bob@example.com", "globex", 1.0, 10.0)] {
+            for (name, kind, value) in [
+                ("claude_code.cost.usage", None, cost),
+                ("claude_code.token.usage", Some("input"), cost * 1000.0),
+                ("claude_code.commit.count", None, 2.0),
+                ("claude_code.lines_of_code.count", Some("added"), lines),
+            ] {
+                let mut metric = usage(name, kind, value, now - Duration::hours(1));
+                metric.labels.insert("user.email".to_string(), email.to_string());
+                metric.labels.insert("organization.id".to_string(), org.to_string());
+                db.store_metric(&metric).await.unwrap();
+            }
+        }
+        let app = routes().with_state(test_state(db));
+        let fetch = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"].clone()
+            }
+        };
+
+        let costs = fetch("/costs?user_email=alice@example.com").await;
+        assert_eq!(costs["total_cost_usd"], 4.0);
+        assert_eq!(costs["top_users_by_cost"].as_array().unwrap().len(), 1);
+        assert_eq!(fetch("/costs?organization_id=globex").await["total_cost_usd"], 1.0);
+        assert_eq!(fetch("/costs").await["total_cost_usd"], 5.0);
+
+        let productivity = fetch("/productivity?organization_id=acme").await;
+        assert_eq!(productivity["total_commits"], 2);
+        assert_eq!(productivity["total_lines_added"], 40);
+        let efficiency = fetch("/efficiency?user_email=bob@example.com").await;
+        assert_eq!(efficiency["cost_per_commit"], 0.5);
+        assert_eq!(efficiency["cost_per_line_of_code"], 0.1);
+
+        // A filter that matches nothing yields empty aggregates rather than an error
+        let costs = fetch("/costs?user_email=nobody@example.com").await;
+        assert_eq!(costs["total_cost_usd"], 0.0);
+        assert!(costs["top_users_by_cost"].as_array().unwrap().is_empty());
+        assert!(costs["model_breakdown"].as_array().unwrap().is_empty());
+        let productivity = fetch("/productivity?user_email=nobody@example.com&organization_id=acme").await;
+        assert_eq!(productivity["total_commits"], 0);
+        assert!(productivity["top_contributors"].as_array().unwrap().is_empty());
+        assert_eq!(fetch("/efficiency?organization_id=initech").await["tokens_per_commit"], 0.0);
+    }
+
+    fn usage(name: &str, kind: Option<&str>, value: f64, timestamp: DateTime<Utc>) -> MetricRecord {
+        MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            name: name.to_string(),
+            timestamp,
+            value,
+            labels: kind.map(|kind| HashMap::from([("type".to_string(), kind.to_string())])).unwrap_or_default(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    async fn fetch_cost_trend(app: Router, query: &str) -> Vec<serde_json::Value> {
+        let uri = format!("/costs?{}", query);
+        let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        body["data"]["cost_trend"].as_array().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_cost_trend_hourly_buckets() {
+        let (_dir, db) = test_database().await;
+        let at = |hour: u32, minute: u32| Utc.with_ymd_and_hms(2025, 3, 1, hour, minute, 0).unwrap();
+        db.store_metric(&usage("claude_code.cost.usage", None, 1.0, at(1, 15))).await.unwrap();
+        db.store_metric(&usage("claude_code.cost.usage", None, 0.5, at(1, 45))).await.unwrap();
+        db.store_metric(&usage("claude_code.token.usage", Some("input"), 100.0, at(3, 10))).await.unwrap();
+        db.store_metric(&usage("claude_code.token.usage", Some("cache_read"), 40.0, at(3, 50))).await.unwrap();
+        let app = routes().with_state(test_state(db));
+
+        let trend = fetch_cost_trend(app, "start_time=2025-03-01T00:00:00Z&end_time=2025-03-01T05:59:59Z&bucket=hour").await;
+
+        assert_eq!(trend.len(), 6);
+        assert_eq!(trend[0]["timestamp"], "2025-03-01T00:00:00Z");
+        assert_eq!(trend[1]["timestamp"], "2025-03-01T01:00:00Z");
+        assert_eq!(trend[1]["cost_usd"], 1.5);
+        assert_eq!(trend[3]["input_tokens"], 100);
+        assert_eq!(trend[3]["cache_read_tokens"], 40);
+        // Empty hours keep the series continuous
+        for empty in [0, 2, 4, 5] {
+            assert_eq!(trend[empty]["cost_usd"], 0.0);
+            assert_eq!(trend[empty]["input_tokens"], 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cost_trend_daily_buckets() {
+        let (_dir, db) = test_database().await;
+        let at = |day: u32, hour: u32| Utc.with_ymd_and_hms(2025, 3, day, hour, 0, 0).unwrap();
+        db.store_metric(&usage("claude_code.cost.usage", None, 2.0, at(1, 9))).await.unwrap();
+        db.store_metric(&usage("claude_code.cost.usage", None, 0.25, at(1, 23))).await.unwrap();
+        db.store_metric(&usage("claude_code.token.usage", Some("output"), 300.0, at(3, 12))).await.unwrap();
+        let app = routes().with_state(test_state(db));
+
+        let trend = fetch_cost_trend(app, "start_time=2025-03-01T00:00:00Z&end_time=2025-03-04T23:59:59Z&bucket=day").await;
+
+        let days: Vec<_> = trend.iter().map(|point| point["timestamp"].as_str().unwrap()).collect();
+        assert_eq!(days, ["2025-03-01T00:00:00Z", "2025-03-02T00:00:00Z", "2025-03-03T00:00:00Z", "2025-03-04T00:00:00Z"]);
+        assert_eq!(trend[0]["cost_usd"], 2.25);
+        assert_eq!(trend[1]["cost_usd"], 0.0);
+        assert_eq!(trend[2]["output_tokens"], 300);
+        assert_eq!(trend[3]["output_tokens"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_cost_falls_back_to_priced_tokens() {
+        let (_dir, db) = test_database().await;
+        let at = |day: u32| Utc.with_ymd_and_hms(2025, 3, day, 12, 0, 0).unwrap();
+        let priced = |kind: &str, value: f64, day: u32| {
+            let mut metric = usage("claude_code.token.usage", Some(kind), value, at(day));
+            metric.labels.insert("model".to_string(), "claude-sonnet-4-20250514".to_string());
+            metric
+        };
+        // Day 1 reports cost directly; day 2 only tokens
+        db.store_metric(&usage("claude_code.cost.usage", None, 0.5, at(1))).await.unwrap();
+        db.store_metric(&priced("input", 1_000_000.0, 1)).await.unwrap();
+        db.store_metric(&priced("input", 1_000_000.0, 2)).await.unwrap();
+        db.store_metric(&priced("output", 200_000.0, 2)).await.unwrap();
+        let app = routes().with_state(test_state(db));
+
+        let uri = "/costs?start_time=2025-03-01T00:00:00Z&end_time=2025-03-03T23:59:59Z&bucket=day";
+        let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let costs = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+
+        let trend = costs["cost_trend"].as_array().unwrap();
+        assert_eq!(trend[0]["cost_usd"], 0.5);
+        // 3.00 for input plus 3.00 for output at Sonnet 4 rates
+        assert_eq!(trend[1]["cost_usd"], 6.0);
+        assert_eq!(trend[2]["cost_usd"], 0.0);
+        assert_eq!(costs["total_cost_usd"], 6.5);
+        assert_eq!(costs["total_input_tokens"], 2_000_000);
+        assert_eq!(costs["total_output_tokens"], 200_000);
+    }
+
+    #[tokio::test]
+    async fn test_cost_trend_weekly_buckets_start_on_monday() {
+        let (_dir, db) = test_database().await;
+        let at = |day: u32| Utc.with_ymd_and_hms(2025, 3, day, 12, 0, 0).unwrap();
+        // Sunday 2 March belongs to the week of Monday 24 February
+        db.store_metric(&usage("claude_code.cost.usage", None, 1.0, at(2))).await.unwrap();
+        db.store_metric(&usage("claude_code.cost.usage", None, 3.0, at(10))).await.unwrap();
+        db.store_metric(&usage("claude_code.cost.usage", None, 0.5, at(16))).await.unwrap();
+        let app = routes().with_state(test_state(db));
+
+        let trend = fetch_cost_trend(app, "start_time=2025-03-01T00:00:00Z&end_time=2025-03-20T00:00:00Z&bucket=week").await;
+
+        let weeks: Vec<_> = trend.iter().map(|point| point["timestamp"].as_str().unwrap()).collect();
+        assert_eq!(weeks, ["2025-02-24T00:00:00Z", "2025-03-03T00:00:00Z", "2025-03-10T00:00:00Z", "2025-03-17T00:00:00Z"]);
+        let costs: Vec<_> = trend.iter().map(|point| point["cost_usd"].as_f64().unwrap()).collect();
+        assert_eq!(costs, [1.0, 0.0, 3.5, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn test_model_cost_comparison_groups_on_model() {
+        let (_dir, db) = test_database().await;
+        let first = db.create_session("alice@example.com").await.unwrap();
+        let second = db.create_session("bob@example.com").await.unwrap();
+        let at = Utc::now() - Duration::hours(1);
+        let metric = |session_id: Uuid, name: &str, kind: Option<&str>, model: &str, value: f64| {
+            let mut metric = usage(name, kind, value, at);
+            metric.session_id = Some(session_id);
+            metric.labels.insert("model".to_string(), model.to_string());
+            metric
+        };
+        for record in [
+            metric(first, "claude_code.cost.usage", None, "claude-opus-4", 3.0),
+            metric(first, "claude_code.token.usage", Some("input"), "claude-opus-4", 1000.0),
+            metric(first, "claude_code.token.usage", Some("output"), "claude-opus-4", 500.0),
+            metric(second, "claude_code.cost.usage", None, "claude-opus-4", 1.0),
+            metric(second, "claude_code.token.usage", Some("input"), "claude-opus-4", 3000.0),
+            metric(second, "claude_code.token.usage", Some("cache_read"), "claude-opus-4", 9000.0),
+            // Only tokens, so its cost is priced at Sonnet 4 rates
+            metric(second, "claude_code.token.usage", Some("input"), "claude-sonnet-4-20250514", 100_000.0),
+        ] {
+            db.store_metric(&record).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let response = app
+            .oneshot(Request::builder().uri("/advanced/model-costs?range=24h").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let comparison = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+
+        assert_eq!(comparison["period"], "24h");
+        assert!((comparison["total_cost"].as_f64().unwrap() - 4.3).abs() < 1e-9);
+        let models = comparison["models"].as_array().unwrap();
+        assert_eq!(models.len(), 2);
+
+        let opus = &models[0];
+        assert_eq!(opus["model_name"], "claude-opus-4");
+        assert_eq!(opus["total_cost"], 4.0);
+        assert_eq!(opus["total_sessions"], 2);
+        assert_eq!(opus["cost_per_session"], 2.0);
+        assert_eq!(opus["avg_input_tokens"], 2000);
+        assert_eq!(opus["avg_output_tokens"], 250);
+        assert!((opus["efficiency_score"].as_f64().unwrap() - 4.0 / 4500.0).abs() < 1e-12);
+        assert_eq!(opus["color"], chart_color("claude-opus-4"));
+
+        let sonnet = &models[1];
+        assert_eq!(sonnet["model_name"], "claude-sonnet-4-20250514");
+        assert_eq!(sonnet["total_sessions"], 1);
+        assert!((sonnet["total_cost"].as_f64().unwrap() - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_compute_trend_is_stable_within_two_percent() {
+        assert_eq!(compute_trend(102.0, 100.0), TrendDirection::Stable);
+        assert_eq!(compute_trend(98.0, 100.0), TrendDirection::Stable);
+        assert_eq!(compute_trend(100.0, 100.0), TrendDirection::Stable);
+        assert!(matches!(compute_trend(102.5, 100.0), TrendDirection::Increasing(pct) if (pct - 2.5).abs() < 1e-9));
+        assert!(matches!(compute_trend(97.5, 100.0), TrendDirection::Decreasing(pct) if (pct - 2.5).abs() < 1e-9));
+        assert_eq!(compute_trend(50.0, 200.0), TrendDirection::Decreasing(75.0));
+    }
+
+    #[test]
+    fn test_compute_trend_from_zero() {
+        assert_eq!(compute_trend(0.0, 0.0), TrendDirection::Stable);
+        assert_eq!(compute_trend(5.0, 0.0), TrendDirection::Increasing(100.0));
+        assert_eq!(compute_trend(0.0, 5.0), TrendDirection::Decreasing(100.0));
+    }
+
+    #[tokio::test]
+    async fn test_trends_compare_with_the_previous_window() {
+        let (_dir, db) = test_database().await;
+        let now = Utc::now();
+        // The 7d range against the 7 days before it
+        for (days_ago, cost, commits, user) in [
+            (10, 10.0, 4.0, "alice@example.com"),
+            (2, 15.0, 4.0, "alice@example.com"),
+            (1, 0.0, 0.0, "bob@example.com"),
+        ] {
+            for (name, value) in [("claude_code.cost.usage", cost), ("claude_code.commit.count", commits)] {
+                let mut metric = usage(name, None, value, now - Duration::days(days_ago));
+                metric.labels.insert("user.email".to_string(), user.to_string());
+                db.store_metric(&metric).await.unwrap();
+            }
+        }
+        let app = routes().with_state(test_state(db));
+
+        let response = app.oneshot(Request::builder().uri("/trends?range=7d").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let trends: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let trends = &trends["data"];
+
+        assert_eq!(trends["range"], "7d");
+        assert_eq!(trends["cost_trend"], serde_json::json!({"Increasing": 50.0}));
+        assert_eq!(trends["productivity_trend"], "Stable");
+        assert_eq!(trends["user_adoption_trend"], serde_json::json!({"Increasing": 100.0}));
+        assert_eq!(trends["token_efficiency_trend"], "Stable");
+    }
+
+    #[tokio::test]
+    async fn test_trends_forecast_productivity_from_the_daily_series() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db.clone()));
+        let forecast = |app: Router| async move {
+            let response = app.oneshot(Request::builder().uri("/trends?range=7d").body(Body::empty()).unwrap()).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let trends: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            trends["data"]["forecasted_monthly_productivity"].clone()
+        };
+        assert_eq!(forecast(app.clone()).await, serde_json::json!({"commits": 0, "pull_requests": 0, "lines_of_code": 0}));
+
+        // Three commits on each of the range's eight calendar days is a flat line,
+        // so the forecast adds three a day for the rest of the month
+        let now = Utc::now();
+        for days_ago in 0..=7 {
+            let timestamp = now - Duration::days(days_ago) + if days_ago == 7 { Duration::minutes(1) } else { Duration::zero() };
+            db.store_metric(&usage("claude_code.commit.count", None, 3.0, timestamp)).await.unwrap();
+        }
+        let month_start = now.date_naive().with_day(1).unwrap();
+        let days_in_month = (month_start.checked_add_months(Months::new(1)).unwrap() - month_start).num_days() as u32;
+        let expected = 3 * now.day().min(8) + 3 * (days_in_month - now.day());
+        assert_eq!(forecast(app).await["commits"], expected);
+    }
+
+    #[test]
+    fn test_chart_colors_are_stable() {
+        assert_eq!(chart_color("claude-opus-4"), chart_color("claude-opus-4"));
+        assert!(CHART_COLORS.contains(&chart_color("")));
+        let colors: HashSet<_> = ["claude-opus-4", "claude-sonnet-4", "claude-haiku-3-5", "claude-3-opus"]
+            .into_iter()
+            .map(chart_color)
+            .collect();
+        assert!(colors.len() > 1);
+    }
+
+    #[test]
+    fn test_budget_progress_projects_the_daily_trend() {
+        let now = Utc.with_ymd_and_hms(2025, 4, 10, 15, 0, 0).unwrap();
+        let day = |day: u32, cost_usd: f64, sessions: u64| CostBucket {
+            start: Utc.with_ymd_and_hms(2025, 4, day, 0, 0, 0).unwrap(),
+            cost_usd,
+            input_tokens: 1000,
+            output_tokens: 200,
+            sessions,
+            ..Default::default()
+        };
+
+        let progress = budget_progress(100.0, &[day(1, 20.0, 2), day(9, 10.0, 1)], now);
+
+        assert_eq!(progress.current_month_cost, 30.0);
+        assert_eq!(progress.percentage_used, 30.0);
+        assert_eq!(progress.days_remaining, 20);
+        // Spend falls off after the 1st, so the fitted line reaches zero before month end
+        assert_eq!(progress.projected_month_end_cost, 30.0);
+        assert!(!progress.is_over_budget);
+        assert_eq!(progress.daily_breakdown.len(), 10);
+        assert_eq!(progress.daily_breakdown[0].sessions, 2);
+        assert_eq!(progress.daily_breakdown[0].tokens, 1200);
+        assert_eq!(progress.daily_breakdown[1].cost, 0.0);
+        assert_eq!(progress.daily_breakdown[9].date, Utc.with_ymd_and_hms(2025, 4, 10, 0, 0, 0).unwrap());
+
+        assert!(budget_progress(25.0, &[day(1, 20.0, 2), day(9, 10.0, 1)], now).is_over_budget);
+        // February 2024 has 29 days
+        let leap = Utc.with_ymd_and_hms(2024, 2, 29, 12, 0, 0).unwrap();
+        assert_eq!(budget_progress(100.0, &[], leap).days_remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_budget_progress_requires_a_budget() {
+        let (_dir, db) = test_database().await;
+        let first_of_month = month_start(Utc::now());
+        let mut cost = usage("claude_code.cost.usage", None, 12.5, first_of_month);
+        cost.session_id = Some(db.create_session("alice@example.com").await.unwrap());
+        db.store_metric(&cost).await.unwrap();
+        db.store_metric(&usage("claude_code.cost.usage", None, 99.0, first_of_month - Duration::seconds(1))).await.unwrap();
+        let fetch = |state: AppState| async move {
+            let app = routes().with_state(state);
+            let request = Request::builder().uri("/advanced/budget-progress").body(Body::empty()).unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+        };
+
+        let (status, body) = fetch(test_state(db.clone())).await;
+        assert_eq!(status, axum::http::StatusCode::NOT_FOUND);
+        assert!(body["error"].as_str().unwrap().contains("CLAUDE_LENS_MONTHLY_BUDGET_USD"));
+
+        let mut state = test_state(db);
+        state.config = Arc::new(Config { monthly_budget_usd: Some(50.0), ..Config::default() });
+        let (status, body) = fetch(state).await;
+        assert_eq!(status, axum::http::StatusCode::OK);
+        let progress = &body["data"];
+        // Last month's spend is not counted
+        assert_eq!(progress["current_month_cost"], 12.5);
+        assert_eq!(progress["monthly_budget"], 50.0);
+        assert_eq!(progress["daily_breakdown"][0]["sessions"], 1);
+    }
+
+    fn tool_log(message: &str, attributes: &[(&str, &str)]) -> LogRecord {
+        LogRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            timestamp: Utc::now() - Duration::minutes(10),
+            level: "INFO".to_string(),
+            message: message.to_string(),
+            attributes: attributes.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn tool_span(tool_name: &str, duration_ms: u64) -> TraceRecord {
+        let end_time = Utc::now() - Duration::minutes(10);
+        TraceRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            trace_id: Uuid::new_v4().simple().to_string(),
+            span_id: Uuid::new_v4().simple().to_string()[..16].to_string(),
+            parent_span_id: None,
+            name: "tool".to_string(),
+            start_time: end_time - Duration::milliseconds(duration_ms as i64),
+            end_time,
+            duration_ns: duration_ms * 1_000_000,
+            attributes: HashMap::from([("tool_name".to_string(), tool_name.to_string())]),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_usage_counts_tool_results() {
+        let (_dir, db) = test_database().await;
+        for log in [
+            tool_log("tool_result", &[("tool_name", "Bash"), ("success", "true")]),
+            tool_log("tool_result", &[("tool_name", "Bash"), ("success", "false")]),
+            tool_log("tool_result", &[("tool_name", "Bash")]),
+            tool_log("tool_result", &[("tool_name", "Read"), ("error", "File not found")]),
+            // Not tool results
+            tool_log("tool_decision", &[("tool_name", "Bash")]),
+            tool_log("api_request", &[("model", "claude-sonnet-4")]),
+        ] {
+            db.store_log(&log).await.unwrap();
+        }
+        for span in [tool_span("Bash", 100), tool_span("Bash", 300)] {
+            db.store_trace(&span).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let response = app
+            .oneshot(Request::builder().uri("/dashboard/tool-usage?range=24h").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let usage = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+
+        assert_eq!(usage["total_tool_calls"], 4);
+        let tools = usage["tools"].as_array().unwrap();
+        assert_eq!(tools.len(), 2);
+
+        let bash = &tools[0];
+        assert_eq!(bash["tool_name"], "Bash");
+        assert_eq!(bash["usage_count"], 3);
+        assert_eq!(bash["percentage"], 75.0);
+        assert!((bash["success_rate"].as_f64().unwrap() - 200.0 / 3.0).abs() < 1e-9);
+        assert_eq!(bash["avg_duration_ms"], 200.0);
+        assert_eq!(bash["duration_available"], true);
+        assert_eq!(bash["color"], chart_color("Bash"));
+
+        // No spans for Read, so no duration
+        let read = &tools[1];
+        assert_eq!(read["tool_name"], "Read");
+        assert_eq!(read["percentage"], 25.0);
+        assert_eq!(read["success_rate"], 0.0);
+        assert_eq!(read["avg_duration_ms"], 0.0);
+        assert_eq!(read["duration_available"], false);
+    }
+
+    #[tokio::test]
+    async fn test_efficiency_ratios_score_and_tool_correlation() {
+        let (_dir, db) = test_database().await;
+        let shipping = db.create_session("dev@example.com").await.unwrap();
+        let exploring = db.create_session("dev@example.com").await.unwrap();
+        let at = Utc::now() - Duration::minutes(10);
+        for (session_id, name, kind, value, second) in [
+            (shipping, "claude_code.token.usage", Some("input"), 1000.0, 0),
+            (shipping, "claude_code.cost.usage", None, 2.0, 1),
+            (shipping, "claude_code.commit.count", None, 2.0, 2),
+            (shipping, "claude_code.lines_of_code.count", Some("added"), 100.0, 3),
+            (exploring, "claude_code.token.usage", Some("output"), 500.0, 4),
+            (exploring, "claude_code.cost.usage", None, 1.0, 5),
+        ] {
+            let mut metric = usage(name, kind, value, at + Duration::seconds(second));
+            metric.session_id = Some(session_id);
+            db.store_metric(&metric).await.unwrap();
+        }
+        for (session_id, tool) in [(shipping, "Edit"), (shipping, "Edit"), (shipping, "Edit"), (exploring, "Edit"), (exploring, "Bash"), (exploring, "Bash")] {
+            let mut log = tool_log("tool_result", &[("tool_name", tool)]);
+            log.session_id = Some(session_id);
+            db.store_log(&log).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let response = app.oneshot(Request::builder().uri("/efficiency?range=24h").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let efficiency = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+
+        assert_eq!(efficiency["tokens_per_commit"], 750.0);
+        assert_eq!(efficiency["cost_per_commit"], 1.5);
+        assert_eq!(efficiency["tokens_per_line_of_code"], 15.0);
+        assert_eq!(efficiency["cost_per_line_of_code"], 0.03);
+        // Per session: 1 commit (0.5 of 2), 50 lines (0.25 of 200) and $1.50 spent
+        let score = efficiency["session_productivity_score"].as_f64().unwrap();
+        assert!((score - 10.0 * 0.375 / 1.3).abs() < 1e-9);
+
+        let tools = efficiency["tool_efficiency"].as_array().unwrap();
+        assert_eq!(tools.len(), 2);
+        assert_eq!((tools[0]["tool_name"].as_str(), tools[0]["usage_count"].as_u64()), (Some("Edit"), Some(4)));
+        assert_eq!(tools[0]["success_rate"], 100.0);
+        // The session calling Edit more is the one that wrote code
+        assert!((tools[0]["productivity_correlation"].as_f64().unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(tools[1]["tool_name"], "Bash");
+        assert!((tools[1]["productivity_correlation"].as_f64().unwrap() + 1.0).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_efficiency_correlates_only_the_scoped_sessions_tools() {
+        let (_dir, db) = test_database().await;
+        let at = Utc::now() - Duration::minutes(10);
+        for (user, lines, tools) in [("alice@example.com", 100.0, &["Edit"][..]), ("bob@example.com", 0.0, &["Bash", "Bash"][..])] {
+            let session_id = db.create_session(user).await.unwrap();
+            let mut metric = usage("claude_code.lines_of_code.count", Some("added"), lines, at);
+            metric.session_id = Some(session_id);
+            db.store_metric(&metric).await.unwrap();
+            for tool in tools {
+                let mut log = tool_log("tool_result", &[("tool_name", tool)]);
+                log.session_id = Some(session_id);
+                db.store_log(&log).await.unwrap();
+            }
+        }
+        let app = routes().with_state(test_state(db));
+
+        let uri = "/efficiency?range=24h&user_email=alice@example.com";
+        let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let efficiency = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+
+        let tools = efficiency["tool_efficiency"].as_array().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!((tools[0]["tool_name"].as_str(), tools[0]["usage_count"].as_u64()), (Some("Edit"), Some(1)));
+    }
+
+    #[tokio::test]
+    async fn test_time_to_productivity_measures_from_the_session_start() {
+        let (_dir, db) = test_database().await;
+        let start = Utc::now() - Duration::hours(2);
+        let (committed, reading) = (Uuid::new_v4(), Uuid::new_v4());
+        db.upsert_session(committed, "dev@example.com", start).await.unwrap();
+        db.upsert_session(reading, "dev@example.com", start + Duration::minutes(5)).await.unwrap();
+        for (name, kind, minutes) in [
+            ("claude_code.lines_of_code.count", Some("added"), 4),
+            ("claude_code.lines_of_code.count", Some("added"), 30),
+            ("claude_code.commit.count", None, 45),
+        ] {
+            let mut metric = usage(name, kind, 1.0, start + Duration::minutes(minutes));
+            metric.session_id = Some(committed);
+            db.store_metric(&metric).await.unwrap();
+        }
+        let mut metric = usage("claude_code.token.usage", Some("input"), 100.0, start + Duration::minutes(10));
+        metric.session_id = Some(reading);
+        db.store_metric(&metric).await.unwrap();
+        let app = routes().with_state(test_state(db));
+
+        let response = app.oneshot(Request::builder().uri("/efficiency?range=24h").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let efficiency = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+
+        // The session that never wrote code has no point
+        let points = efficiency["time_to_productivity"].as_array().unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0]["session_id"], committed.to_string());
+        assert_eq!(points[0]["session_start_to_first_edit_minutes"], 4.0);
+        assert_eq!(points[0]["session_start_to_first_commit_minutes"], 45.0);
+    }
+
+    #[test]
+    fn test_efficiency_formulas_without_data() {
+        assert_eq!(productivity_score(0.0, 0.0, 0.0, 0), 0.0);
+        // Output beyond the reference points is capped
+        assert_eq!(productivity_score(10.0, 1000.0, 0.0, 1), 10.0);
+        assert_eq!(productivity_score(2.0, 200.0, 5.0, 1), 5.0);
+        assert_eq!(correlation(&[]), 0.0);
+        assert_eq!(correlation(&[(1.0, 2.0), (1.0, 5.0)]), 0.0);
+    }
+
+    #[test]
+    fn test_tool_usage_collapses_the_long_tail() {
+        let mut logs = Vec::new();
+        for (tool, calls) in [("Edit", 6), ("Read", 5), ("Bash", 4), ("Grep", 3), ("Glob", 2), ("Write", 1), ("Task", 1)] {
+            logs.extend((0..calls).map(|_| tool_log("tool_result", &[("tool_name", tool)])));
+        }
+        let spans = [tool_span("Task", 40), tool_span("Write", 20)];
+
+        let tools = tool_usage(&logs, &spans);
+
+        let names: Vec<_> = tools.iter().map(|tool| tool.tool_name.as_str()).collect();
+        assert_eq!(names, ["Edit", "Read", "Bash", "Grep", "Glob", "Other"]);
+        let other = &tools[5];
+        assert_eq!(other.usage_count, 2);
+        assert_eq!(other.color, OTHER_TOOLS_COLOR);
+        assert_eq!(other.avg_duration_ms, 30.0);
+        assert_eq!(tools.iter().map(|tool| tool.percentage).sum::<f64>().round(), 100.0);
+    }
+
+    #[test]
+    fn test_median_of_odd_and_even_counts() {
+        assert_eq!(median(&[]), 0.0);
+        assert_eq!(median(&[4.0]), 4.0);
+        assert_eq!(median(&[1.0, 2.0, 10.0]), 2.0);
+        assert_eq!(median(&[1.0, 2.0, 4.0, 10.0]), 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_session_duration_distribution_skips_active_sessions() {
+        let (_dir, db) = test_database().await;
+        let at = |hour: u32, minute: u32| Utc.with_ymd_and_hms(2025, 3, 1, hour, minute, 0).unwrap();
+        for (start, minutes) in [(at(1, 0), Some(3)), (at(1, 30), Some(10)), (at(3, 0), Some(45)), (at(3, 10), Some(200)), (at(4, 0), None)] {
+            let id = Uuid::new_v4();
+            db.upsert_session(id, "dev@example.com", start).await.unwrap();
+            if let Some(minutes) = minutes {
+                db.update_session(id, Some(start + Duration::minutes(minutes))).await.unwrap();
+            }
+        }
+        let app = routes().with_state(test_state(db));
+
+        let uri = "/advanced/session-duration?start_time=2025-03-01T00:00:00Z&end_time=2025-03-01T05:59:59Z&bucket=hour";
+        let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let distribution = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+
+        assert_eq!(distribution["total_sessions"], 4);
+        assert_eq!(distribution["avg_duration_minutes"], 64.5);
+        assert_eq!(distribution["median_duration_minutes"], 27.5);
+
+        let counts: Vec<_> = distribution["distribution_buckets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bucket| bucket["session_count"].as_u64().unwrap())
+            .collect();
+        assert_eq!(counts, [1, 1, 0, 1, 0, 1]);
+        assert_eq!(distribution["distribution_buckets"][0]["percentage"], 25.0);
+        assert_eq!(distribution["distribution_buckets"][5]["max_minutes"], u32::MAX);
+
+        let over_time = distribution["duration_over_time"].as_array().unwrap();
+        assert_eq!(over_time.len(), 6);
+        assert_eq!(over_time[1]["session_count"], 2);
+        assert_eq!(over_time[1]["avg_duration_minutes"], 6.5);
+        assert_eq!(over_time[3]["avg_duration_minutes"], 122.5);
+        // The open session at 04:00 is left out
+        assert_eq!(over_time[4]["session_count"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_usage_heatmap_buckets_by_local_hour_of_week() {
+        let (_dir, db) = test_database().await;
+        let session_id = db.create_session("dev@example.com").await.unwrap();
+        // Monday 3 March 2025, 23:30 UTC is Tuesday 00:30 in Berlin
+        let at = Utc.with_ymd_and_hms(2025, 3, 3, 23, 30, 0).unwrap();
+        for (second, value) in [(0, 400.0), (1, 100.0)] {
+            let mut tokens = usage("claude_code.token.usage", Some("input"), value, at + Duration::seconds(second));
+            tokens.session_id = Some(session_id);
+            db.store_metric(&tokens).await.unwrap();
+        }
+        let later = at + Duration::hours(2);
+        db.store_metric(&usage("claude_code.token.usage", Some("output"), 250.0, later)).await.unwrap();
+        let app = routes().with_state(test_state(db));
+        let heatmap = |query: &'static str| {
+            let app = app.clone();
+            async move {
+                let uri = format!("/dashboard/usage-heatmap?start_time=2025-03-03T00:00:00Z&end_time=2025-03-05T00:00:00Z{}", query);
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"].clone()
+            }
+        };
+        let cell = |data: &serde_json::Value, day: u64, hour: u64| {
+            data["heatmap"].as_array().unwrap()[(day * 24 + hour) as usize].clone()
+        };
+
+        let utc = heatmap("").await;
+        assert_eq!(utc["timezone"], "UTC");
+        assert_eq!(utc["heatmap"].as_array().unwrap().len(), 168);
+        let monday = cell(&utc, 1, 23);
+        assert_eq!((monday["day_of_week"].as_u64(), monday["hour"].as_u64()), (Some(1), Some(23)));
+        assert_eq!(monday["token_count"], 500);
+        assert_eq!(monday["session_count"], 1);
+        assert_eq!(monday["intensity"], 1.0);
+        assert_eq!(cell(&utc, 2, 1)["intensity"], 0.5);
+        assert_eq!(cell(&utc, 2, 1)["session_count"], 0);
+        assert_eq!(cell(&utc, 0, 0)["intensity"], 0.0);
+
+        let berlin = heatmap("&timezone=Europe/Berlin").await;
+        assert_eq!(berlin["timezone"], "Europe/Berlin");
+        assert_eq!(cell(&berlin, 2, 0)["token_count"], 500);
+        assert_eq!(cell(&berlin, 1, 23)["token_count"], 0);
+
+        let response = app
+            .oneshot(Request::builder().uri("/dashboard/usage-heatmap?timezone=Mars/Olympus").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_dashboard_kpis_compare_with_the_previous_window() {
+        let (_dir, db) = test_database().await;
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            sessions.push(db.create_session("dev@example.com").await.unwrap());
+        }
+        let at = |day: u32, hour: u32| Utc.with_ymd_and_hms(2025, 3, day, hour, 0, 0).unwrap();
+        for (session, name, kind, value, timestamp) in [
+            // 2 March, the previous window: two sessions
+            (0, "claude_code.token.usage", Some("input"), 1000.0, at(2, 9)),
+            (1, "claude_code.token.usage", Some("output"), 600.0, at(2, 10)),
+            (1, "claude_code.cost.usage", None, 2.0, at(2, 10)),
+            (1, "claude_code.lines_of_code.count", Some("added"), 40.0, at(2, 11)),
+            // 3 March, the requested window: one session
+            (2, "claude_code.token.usage", Some("input"), 1200.0, at(3, 9)),
+            (2, "claude_code.cost.usage", None, 3.0, at(3, 9)),
+            (2, "claude_code.lines_of_code.count", Some("added"), 10.0, at(3, 10)),
+        ] {
+            let mut metric = usage(name, kind, value, timestamp);
+            metric.session_id = Some(sessions[session]);
+            db.store_metric(&metric).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+        let kpis = |query: &'static str| {
+            let app = app.clone();
+            async move {
+                let uri = format!("/dashboard/kpis?{}", query);
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"].clone()
+            }
+        };
+
+        let current = kpis("start_time=2025-03-03T00:00:00Z&end_time=2025-03-04T00:00:00Z").await;
+        assert_eq!(current["today_sessions"], 1);
+        assert_eq!(current["today_sessions_change"], -50.0);
+        assert_eq!(current["total_tokens"], 1200);
+        assert_eq!(current["total_tokens_change"], -25.0);
+        assert_eq!(current["total_cost"], 3.0);
+        assert_eq!(current["total_cost_change"], 50.0);
+        assert_eq!(current["lines_of_code"], 10);
+        assert_eq!(current["lines_of_code_change"], -75.0);
+
+        // Nothing on 1 March, so no change rather than an infinite one
+        let first = kpis("start_time=2025-03-02T00:00:00Z&end_time=2025-03-03T00:00:00Z").await;
+        assert_eq!(first["today_sessions"], 2);
+        assert_eq!(first["total_tokens"], 1600);
+        assert_eq!(first["total_tokens_change"], 0.0);
+        assert_eq!(first["total_cost_change"], 0.0);
+        assert_eq!(first["period"], "24h");
+    }
+
+    #[tokio::test]
+    async fn test_kpis_for_today_compare_with_the_same_time_yesterday() {
+        let (_dir, db) = test_database().await;
+        let now = Utc::now();
+        let midnight = start_of_day(now, Tz::UTC);
+        let elapsed = now - midnight;
+        for (value, timestamp) in [
+            // Today so far
+            (3.0, midnight + elapsed / 2),
+            // Yesterday up to the same time of day
+            (2.0, midnight - Duration::days(1) + elapsed / 2),
+            // Yesterday after that time, which today hasn't reached yet
+            (100.0, now - Duration::days(1) + (midnight + Duration::days(1) - now) / 2),
+        ] {
+            db.store_metric(&usage("claude_code.cost.usage", None, value, timestamp)).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let response = app.oneshot(Request::builder().uri("/dashboard/kpis?range=today").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let kpis = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+        assert_eq!(kpis["period"], "today");
+        assert_eq!(kpis["total_cost"], 3.0);
+        assert_eq!(kpis["total_cost_change"], 50.0);
+    }
+
+    #[test]
+    fn test_start_of_day_is_local_midnight() {
+        let now = Utc.with_ymd_and_hms(2025, 3, 3, 2, 30, 0).unwrap();
+        assert_eq!(start_of_day(now, Tz::UTC), Utc.with_ymd_and_hms(2025, 3, 3, 0, 0, 0).unwrap());
+        // Still 2 March in New York (UTC-5)
+        assert_eq!(start_of_day(now, chrono_tz::America::New_York), Utc.with_ymd_and_hms(2025, 3, 2, 5, 0, 0).unwrap());
+        // Santiago skips from midnight to 1am on 7 September 2025
+        let gap = Utc.with_ymd_and_hms(2025, 9, 7, 12, 0, 0).unwrap();
+        assert_eq!(start_of_day(gap, chrono_tz::America::Santiago), Utc.with_ymd_and_hms(2025, 9, 7, 4, 0, 0).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_overview_panels_agree_with_each_other() {
+        let (_dir, db) = test_database().await;
+        let first = db.create_session("dev@example.com").await.unwrap();
+        let second = db.create_session("dev@example.com").await.unwrap();
+        let at = |day: u32, hour: u32, minute: u32| Utc.with_ymd_and_hms(2025, 3, day, hour, minute, 0).unwrap();
+        for (session_id, name, kind, value, timestamp) in [
+            (first, "claude_code.token.usage", Some("input"), 400.0, at(3, 9, 10)),
+            (first, "claude_code.token.usage", Some("output"), 100.0, at(3, 9, 40)),
+            (second, "claude_code.token.usage", Some("cache_read"), 50.0, at(3, 14, 0)),
+            (second, "claude_code.cost.usage", None, 0.5, at(3, 14, 0)),
+            (second, "claude_code.lines_of_code.count", Some("added"), 30.0, at(3, 14, 5)),
+            // The day before, only counted in the changes
+            (first, "claude_code.token.usage", Some("input"), 250.0, at(2, 9, 0)),
+            (first, "claude_code.cost.usage", None, 0.25, at(2, 9, 0)),
+        ] {
+            let mut metric = usage(name, kind, value, timestamp);
+            metric.session_id = Some(session_id);
+            db.store_metric(&metric).await.unwrap();
+        }
+        for (tool, minute) in [("Bash", 15), ("Bash", 20), ("Read", 25)] {
+            let mut log = tool_log("tool_result", &[("tool_name", tool)]);
+            log.timestamp = at(3, 9, minute);
+            db.store_log(&log).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let uri = "/overview?start_time=2025-03-03T00:00:00Z&end_time=2025-03-04T00:00:00Z";
+        let response = app.clone().oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let overview = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+
+        let kpis = &overview["kpis"];
+        assert_eq!(kpis["today_sessions"], 2);
+        assert_eq!(kpis["total_tokens"], 550);
+        assert_eq!(kpis["total_tokens_change"], 120.0);
+        assert_eq!(kpis["total_cost"], 0.5);
+        assert_eq!(kpis["total_cost_change"], 100.0);
+        assert_eq!(kpis["lines_of_code"], 30);
+        // Nothing written the day before
+        assert_eq!(kpis["lines_of_code_change"], 0.0);
+
+        let points = overview["token_trend"]["data_points"].as_array().unwrap();
+        assert_eq!(points.len(), 25);
+        assert_eq!((points[9]["input_tokens"].as_u64(), points[9]["output_tokens"].as_u64()), (Some(400), Some(100)));
+        assert_eq!(points[14]["cache_read_tokens"], 50);
+        let trend_tokens: u64 = points.iter().map(|point| point["total_tokens"].as_u64().unwrap()).sum();
+        let heatmap = overview["heatmap"]["heatmap"].as_array().unwrap();
+        let heatmap_tokens: u64 = heatmap.iter().map(|cell| cell["token_count"].as_u64().unwrap()).sum();
+        assert_eq!(trend_tokens, 550);
+        assert_eq!(heatmap_tokens, 550);
+        assert_eq!(overview["heatmap"]["timezone"], "UTC");
+
+        let tools = &overview["tool_usage"];
+        assert_eq!(tools["total_tool_calls"], 3);
+        let counted: u64 = tools["tools"].as_array().unwrap().iter().map(|tool| tool["usage_count"].as_u64().unwrap()).sum();
+        assert_eq!(counted, 3);
+
+        // The standalone token trend endpoint reports the same panel
+        let uri = "/dashboard/token-trend?start_time=2025-03-03T00:00:00Z&end_time=2025-03-04T00:00:00Z";
+        let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let trend = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+        assert_eq!(trend["data_points"], overview["token_trend"]["data_points"]);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_overview_counts_only_the_scoped_sessions_tools() {
+        let (_dir, db) = test_database().await;
+        for (user, tools) in [("alice@example.com", &["Bash", "Bash"][..]), ("bob@example.com", &["Read"][..])] {
+            let session_id = db.create_session(user).await.unwrap();
+            let mut metric = usage("claude_code.token.usage", Some("input"), 100.0, Utc::now() - Duration::minutes(10));
+            metric.session_id = Some(session_id);
+            db.store_metric(&metric).await.unwrap();
+            for tool in tools {
+                let mut log = tool_log("tool_result", &[("tool_name", tool)]);
+                log.session_id = Some(session_id);
+                db.store_log(&log).await.unwrap();
+            }
+        }
+        let app = routes().with_state(test_state(db));
+
+        let uri = "/overview?range=24h&user_email=alice@example.com";
+        let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let overview = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+        assert_eq!(overview["kpis"]["total_tokens"], 100);
+        assert_eq!(overview["tool_usage"]["total_tool_calls"], 2);
+        assert_eq!(overview["tool_usage"]["tools"][0]["tool_name"], "Bash");
+    }
+
+    #[test]
+    fn test_bucket_defaults_follow_the_range() {
+        let end = Utc.with_ymd_and_hms(2025, 3, 31, 0, 0, 0).unwrap();
+        assert_eq!(parse_bucket(None, end - Duration::hours(24), end).unwrap(), TimeBucket::Hour);
+        assert_eq!(parse_bucket(None, end - Duration::days(7), end).unwrap(), TimeBucket::Day);
+        assert_eq!(parse_bucket(None, end - Duration::days(30), end).unwrap(), TimeBucket::Day);
+        assert_eq!(parse_bucket(None, end - Duration::days(90), end).unwrap(), TimeBucket::Week);
+        assert_eq!(parse_bucket(Some("week"), end - Duration::hours(24), end).unwrap(), TimeBucket::Week);
+        assert!(matches!(parse_bucket(Some("minute"), end - Duration::hours(1), end), Err(ApiError::InvalidQuery(_))));
+    }
+}
@@ -0,0 +1,161 @@
+use axum::{
+    extract::State,
+    response::{IntoResponse, Json},
+    routing::post,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::storage::{BudgetRecord, Database};
+use super::{ApiError, ApiResponse, ApiResult, AppState};
+
+#[derive(Debug, Deserialize)]
+pub struct SetBudgetRequest {
+    pub user_email: String,
+    pub monthly_budget_usd: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Budget {
+    pub user_email: String,
+    pub monthly_budget_usd: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<BudgetRecord> for Budget {
+    fn from(budget: BudgetRecord) -> Self {
+        Self {
+            user_email: budget.user_id,
+            monthly_budget_usd: budget.monthly_budget_usd,
+            updated_at: budget.updated_at,
+        }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", post(set_budget))
+}
+
+// POST /api/budgets - Set or replace a user's monthly budget
+async fn set_budget(
+    State(db): State<Arc<dyn Database>>,
+    Json(request): Json<SetBudgetRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let user_email = request.user_email.trim();
+    if user_email.is_empty() {
+        return Err(ApiError::InvalidQuery("user_email must not be empty".to_string()));
+    }
+    if !(request.monthly_budget_usd.is_finite() && request.monthly_budget_usd > 0.0) {
+        return Err(ApiError::InvalidQuery("monthly_budget_usd must be a positive amount".to_string()));
+    }
+
+    let budget = db.set_budget(user_email, request.monthly_budget_usd).await?;
+    Ok(Json(ApiResponse::success(Budget::from(budget))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use chrono::{Datelike, TimeZone};
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::{api::{create_routes, test_state}, storage::{sqlite::test_database, MetricRecord}};
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn set(user_email: &str, monthly_budget_usd: f64) -> Request<Body> {
+        let body = serde_json::json!({ "user_email": user_email, "monthly_budget_usd": monthly_budget_usd });
+        Request::post("/budgets")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn progress(user_email: &str) -> Request<Body> {
+        let uri = format!("/analytics/advanced/budget-progress?user_email={}", user_email);
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_budget_progress_per_user() {
+        let (_dir, db) = test_database().await;
+        let now = Utc::now();
+        let first_of_month = Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0).unwrap();
+        for (user, cost) in [("alice@example.com", 12.0), ("bob@example.com", 30.0)] {
+            let session_id = db.create_session(user).await.unwrap();
+            db.store_metric(&MetricRecord {
+                id: Uuid::new_v4(),
+                session_id: Some(session_id),
+                name: "claude_code.cost.usage".to_string(),
+                timestamp: first_of_month,
+                value: cost,
+                labels: HashMap::new(),
+                unit: None,
+                description: None,
+                service: None,
+                created_at: now,
+            })
+            .await
+            .unwrap();
+        }
+        let app = create_routes().with_state(test_state(db));
+
+        let (status, body) = send(&app, progress("alice@example.com")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body["error"].as_str().unwrap().contains("alice@example.com"));
+
+        let (status, body) = send(&app, set("alice@example.com", 1000.0)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["monthly_budget_usd"], 1000.0);
+        send(&app, set("bob@example.com", 20.0)).await;
+
+        // Only alice's sessions count towards her budget
+        let (_, body) = send(&app, progress("alice@example.com")).await;
+        let alice = &body["data"];
+        assert_eq!(alice["current_month_cost"], 12.0);
+        assert_eq!(alice["monthly_budget"], 1000.0);
+        assert_eq!(alice["is_over_budget"], false);
+        // Projected along the trend of her daily costs, never below what is already spent
+        let daily: Vec<(DateTime<Utc>, f64)> = alice["daily_breakdown"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|day| (day["date"].as_str().unwrap().parse().unwrap(), day["cost"].as_f64().unwrap()))
+            .collect();
+        assert_eq!(daily.len() as u32, now.day());
+        let projected = alice["projected_month_end_cost"].as_f64().unwrap();
+        assert!((projected - crate::forecast::linear_forecast(&daily)).abs() < 1e-9);
+        assert!(projected >= 12.0);
+
+        let (_, body) = send(&app, progress("bob@example.com")).await;
+        let bob = &body["data"];
+        assert_eq!(bob["current_month_cost"], 30.0);
+        assert_eq!(bob["percentage_used"], 150.0);
+        assert_eq!(bob["is_over_budget"], true);
+
+        // Setting again replaces the budget
+        send(&app, set("bob@example.com", 50.0)).await;
+        let (_, body) = send(&app, progress("bob@example.com")).await;
+        assert_eq!(body["data"]["is_over_budget"], false);
+    }
+
+    #[tokio::test]
+    async fn test_set_budget_rejects_invalid_amounts() {
+        let (_dir, db) = test_database().await;
+        let app = create_routes().with_state(test_state(db));
+
+        assert_eq!(send(&app, set("alice@example.com", 0.0)).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send(&app, set("alice@example.com", -5.0)).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(send(&app, set("  ", 10.0)).await.0, StatusCode::BAD_REQUEST);
+    }
+}
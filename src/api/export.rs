@@ -0,0 +1,211 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
+
+use crate::storage::{Database, DatabaseError, MetricFilter, MetricRecord};
+use super::analytics::{parse_time_range, AnalyticsQuery};
+use super::metrics::escape_csv_field;
+use super::{ApiError, ApiResult, AppState};
+
+// Leading columns of a CSV export; one column per label key follows
+const CSV_COLUMNS: [&str; 4] = ["timestamp", "name", "value", "session_id"];
+
+#[derive(Debug, Deserialize)]
+pub struct ExportMetricsQuery {
+    pub format: Option<String>, // "json" (default) or "csv"
+    pub metric_name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedMetric {
+    pub timestamp: DateTime<Utc>,
+    pub name: String,
+    pub value: f64,
+    pub session_id: Option<Uuid>,
+    pub labels: HashMap<String, String>,
+}
+
+impl From<MetricRecord> for ExportedMetric {
+    fn from(metric: MetricRecord) -> Self {
+        Self {
+            timestamp: metric.timestamp,
+            name: metric.name,
+            value: metric.value,
+            session_id: metric.session_id,
+            labels: metric.labels,
+        }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/metrics", get(export_metrics))
+}
+
+// GET /api/export/metrics - The raw points of /api/metrics/export, oldest first, as a JSON
+// array or a CSV with one column per label key rather than a `labels` JSON column
+async fn export_metrics(
+    State(db): State<Arc<dyn Database>>,
+    Query(params): Query<ExportMetricsQuery>,
+    Query(range): Query<AnalyticsQuery>,
+) -> ApiResult<Response> {
+    let (start_time, end_time) = parse_time_range(&range)?;
+    let filename = format!(
+        "claude-lens-metrics-{}-{}",
+        start_time.format("%Y%m%dT%H%M%SZ"),
+        end_time.format("%Y%m%dT%H%M%SZ")
+    );
+
+    let filter = MetricFilter { metric_name: params.metric_name.clone(), ..MetricFilter::default() };
+
+    // Rows are written as they are read; a database error mid-way aborts the response
+    match params.format.as_deref().unwrap_or("json") {
+        "csv" => {
+            let label_keys = db.metric_label_keys(start_time, end_time, params.metric_name.as_deref()).await?;
+            let header = csv_row(CSV_COLUMNS.iter().map(|column| column.to_string()).chain(label_keys.iter().cloned()));
+            let rows = db
+                .stream_metrics(start_time, end_time, filter)
+                .map(move |metric| metric.map(|metric| metric_to_csv_row(metric, &label_keys)));
+            let body = stream::once(async { Ok(header) }).chain(rows);
+            Ok(attachment("text/csv; charset=utf-8", format!("{}.csv", filename), Body::from_stream(body)))
+        }
+        "json" => {
+            let items = db
+                .stream_metrics(start_time, end_time, filter)
+                .enumerate()
+                .map(|(index, metric)| {
+                    let json = serde_json::to_string(&ExportedMetric::from(metric?))
+                        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+                    Ok::<_, DatabaseError>(if index == 0 { json } else { format!(",{}", json) })
+                });
+            let body = stream::once(async { Ok("[".to_string()) })
+                .chain(items)
+                .chain(stream::once(async { Ok("]".to_string()) }));
+            Ok(attachment("application/json", format!("{}.json", filename), Body::from_stream(body)))
+        }
+        other => Err(ApiError::InvalidQuery(format!("Invalid format: {}", other))),
+    }
+}
+
+fn attachment(content_type: &'static str, filename: String, body: Body) -> Response {
+    let headers = [
+        (header::CONTENT_TYPE, content_type.to_string()),
+        (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+    ];
+    (headers, body).into_response()
+}
+
+fn metric_to_csv_row(metric: MetricRecord, label_keys: &[String]) -> String {
+    let fields = [
+        metric.timestamp.to_rfc3339(),
+        metric.name,
+        metric.value.to_string(),
+        metric.session_id.map(|id| id.to_string()).unwrap_or_default(),
+    ];
+    let labels = label_keys.iter().map(|key| metric.labels.get(key).cloned().unwrap_or_default());
+    csv_row(fields.into_iter().chain(labels))
+}
+
+fn csv_row(fields: impl Iterator<Item = String>) -> String {
+    let mut row = fields.map(|field| escape_csv_field(&field)).collect::<Vec<_>>().join(",");
+    row.push('\n');
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{Request, StatusCode};
+    use chrono::Duration;
+    use tower::ServiceExt;
+
+    use crate::{api::test_state, storage::sqlite::test_database};
+
+    fn metric(name: &str, value: f64, minutes_ago: i64, labels: &[(&str, &str)]) -> MetricRecord {
+        MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            name: name.to_string(),
+            timestamp: Utc::now() - Duration::minutes(minutes_ago),
+            value,
+            labels: labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    async fn get(app: Router, uri: &str) -> (StatusCode, header::HeaderMap, String) {
+        let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_csv_export_flattens_label_keys() {
+        let (_dir, db) = test_database().await;
+        for record in [
+            metric("claude_code.cost.usage", 0.5, 20, &[("model", "claude-sonnet-4, latest")]),
+            metric("claude_code.token.usage", 120.0, 10, &[("model", "claude-sonnet-4"), ("type", "input")]),
+            metric("claude_code.session.count", 1.0, 60 * 48, &[("stale", "yes")]),
+        ] {
+            db.store_metric(&record).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let (status, headers, body) = get(app.clone(), "/metrics?format=csv").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+        let disposition = headers[header::CONTENT_DISPOSITION].to_str().unwrap();
+        assert!(disposition.starts_with("attachment; filename=\"claude-lens-metrics-"));
+        assert!(disposition.ends_with(".csv\""));
+
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "timestamp,name,value,session_id,model,type");
+        assert!(lines[1].ends_with(",claude_code.cost.usage,0.5,,\"claude-sonnet-4, latest\","));
+        assert!(lines[2].ends_with(",claude_code.token.usage,120,,claude-sonnet-4,input"));
+
+        let (_, _, body) = get(app, "/metrics?format=csv&metric_name=claude_code.cost.usage").await;
+        assert_eq!(body.lines().next(), Some("timestamp,name,value,session_id,model"));
+        assert_eq!(body.lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_json_export_streams_an_array() {
+        let (_dir, db) = test_database().await;
+        // More rows than the stream reads ahead
+        for i in 0..600 {
+            db.store_metric(&metric("claude_code.token.usage", i as f64, 600 - i, &[("type", "output")])).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let (status, headers, body) = get(app.clone(), "/metrics?format=json&range=24h").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/json");
+        assert!(headers[header::CONTENT_DISPOSITION].to_str().unwrap().ends_with(".json\""));
+        let points: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(points.len(), 600);
+        assert_eq!(points[0]["value"], 0.0);
+        assert_eq!(points[599]["value"], 599.0);
+        assert_eq!(points[0]["labels"]["type"], "output");
+
+        let (_, _, body) = get(app.clone(), "/metrics?metric_name=missing").await;
+        assert_eq!(body, "[]");
+
+        let (status, _, _) = get(app, "/metrics?format=xml").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
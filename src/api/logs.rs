@@ -0,0 +1,272 @@
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Json},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::otel::{classify_event, EventType};
+use crate::storage::{Database, LogRecord};
+use super::analytics::{parse_time_range, AnalyticsQuery};
+use super::sessions::PageInfo;
+use super::{ApiError, ApiResponse, ApiResult, AppState};
+
+#[derive(Debug, Deserialize)]
+pub struct LogsQuery {
+    pub level: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogSearchQuery {
+    pub q: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogsResponse {
+    pub logs: Vec<LogEntry>,
+    pub total_count: u64,
+    pub page_info: PageInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogEntry {
+    pub id: Uuid,
+    pub session_id: Option<Uuid>,
+    pub timestamp: DateTime<Utc>,
+    pub level: String,
+    pub message: String,
+    pub event_type: &'static str,
+    pub service: Option<String>,
+    pub attributes: HashMap<String, String>,
+}
+
+impl From<LogRecord> for LogEntry {
+    fn from(log: LogRecord) -> Self {
+        Self {
+            event_type: event_type(&log),
+            id: log.id,
+            session_id: log.session_id,
+            timestamp: log.timestamp,
+            level: log.level,
+            message: log.message,
+            service: log.service,
+            attributes: log.attributes,
+        }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_logs))
+        .route("/search", get(search_logs))
+}
+
+fn event_type(log: &LogRecord) -> &'static str {
+    match classify_event(&log.message, &log.attributes) {
+        EventType::UserPromptSubmitted => "user_prompt",
+        EventType::ToolResult { .. } => "tool_result",
+        EventType::ApiRequest { .. } => "api_request",
+        EventType::ApiRequestFailed { .. } => "api_error",
+        EventType::ToolPermissionDecision { .. } => "tool_decision",
+        EventType::Other { .. } => "other",
+    }
+}
+
+// GET /api/logs?range=&level=&service= - List logs with pagination, newest first
+async fn get_logs(
+    State(db): State<Arc<dyn Database>>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<LogsQuery>,
+    Query(range): Query<AnalyticsQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let (limit, offset) = config.clamp_pagination(params.limit, params.offset);
+    let level = params.level.as_deref().map(str::trim).filter(|level| !level.is_empty());
+    let (start_time, end_time) = parse_time_range(&range)?;
+    let service = range.scope().service;
+
+    let (logs, total_count) = tokio::try_join!(
+        db.list_logs(Some(start_time), Some(end_time), level, service.as_deref(), limit, offset),
+        db.count_logs(Some(start_time), Some(end_time), level, service.as_deref()),
+    )?;
+
+    let response = LogsResponse {
+        logs: logs.into_iter().map(LogEntry::from).collect(),
+        total_count,
+        page_info: PageInfo::new(offset, limit, total_count),
+    };
+
+    Ok(Json(ApiResponse::success(response)))
+}
+
+// GET /api/logs/search?q=&range= - Logs whose message or an attribute value contains `q`
+async fn search_logs(
+    State(db): State<Arc<dyn Database>>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<LogSearchQuery>,
+    Query(range): Query<AnalyticsQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let query = params.q.as_deref().map(str::trim).unwrap_or("");
+    if query.is_empty() {
+        return Err(ApiError::InvalidQuery("Search query `q` must not be empty".to_string()));
+    }
+    let (limit, _) = config.clamp_pagination(params.limit, None);
+    let (start_time, end_time) = parse_time_range(&range)?;
+
+    let logs: Vec<LogEntry> = db
+        .search_logs(query, Some(start_time), Some(end_time), limit)
+        .await?
+        .into_iter()
+        .map(LogEntry::from)
+        .collect();
+
+    Ok(Json(ApiResponse::success(logs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use chrono::Duration;
+    use tower::ServiceExt;
+
+    use crate::{api::test_state, storage::sqlite::test_database};
+
+    fn log(message: &str, attributes: &[(&str, &str)], hours_ago: i64) -> LogRecord {
+        leveled_log("INFO", message, attributes, hours_ago)
+    }
+
+    fn leveled_log(level: &str, message: &str, attributes: &[(&str, &str)], hours_ago: i64) -> LogRecord {
+        LogRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            timestamp: Utc::now() - Duration::hours(hours_ago),
+            level: level.to_string(),
+            message: message.to_string(),
+            attributes: attributes.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    async fn get_json(app: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn search(app: &Router, query: &str) -> (StatusCode, Vec<String>) {
+        let (status, body) = get_json(app, &format!("/search?{}", query)).await;
+        let messages = body["data"]
+            .as_array()
+            .map(|logs| logs.iter().map(|log| log["message"].as_str().unwrap().to_string()).collect())
+            .unwrap_or_default();
+        (status, messages)
+    }
+
+    #[tokio::test]
+    async fn test_search_matches_messages_and_attribute_values() {
+        let (_dir, db) = test_database().await;
+        for record in [
+            log("api_request_failed", &[("error", "Overloaded: upstream 529"), ("model", "claude-sonnet-4")], 1),
+            log("api_request", &[("model", "claude-sonnet-4"), ("duration_ms", "812")], 2),
+            log("tool_result", &[("tool_name", "Bash"), ("error", "exit code 127")], 3),
+            // Outside the default 24h range
+            log("api_request_failed", &[("error", "Overloaded")], 48),
+        ] {
+            db.store_log(&record).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        // Message substring, newest first
+        assert_eq!(search(&app, "q=api_request").await.1, ["api_request_failed", "api_request"]);
+        // Attribute values, case-insensitively
+        assert_eq!(search(&app, "q=overloaded").await.1, ["api_request_failed"]);
+        assert_eq!(search(&app, "q=code%20127").await.1, ["tool_result"]);
+        assert_eq!(search(&app, "q=sonnet&limit=1").await.1, ["api_request_failed"]);
+        // A wider range reaches older logs
+        assert_eq!(search(&app, "q=overloaded&range=7d").await.1.len(), 2);
+
+        // Keys are not searched, and LIKE wildcards are literal
+        assert!(search(&app, "q=tool_name").await.1.is_empty());
+        assert!(search(&app, "q=%25").await.1.is_empty());
+        assert!(search(&app, "q=nothing-like-this").await.1.is_empty());
+
+        assert_eq!(search(&app, "q=%20").await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_list_filters_by_level() {
+        let (_dir, db) = test_database().await;
+        for record in [
+            leveled_log("ERROR", "api_request_failed", &[("error_code", "overloaded_error")], 1),
+            leveled_log("INFO", "tool_result", &[("tool_name", "Bash")], 2),
+            leveled_log("error", "tool_result", &[("tool_name", "Edit")], 3),
+            leveled_log("ERROR", "api_request_failed", &[], 30),
+        ] {
+            db.store_log(&record).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let (status, body) = get_json(&app, "/?range=24h&level=ERROR").await;
+        assert_eq!(status, StatusCode::OK);
+        let data = &body["data"];
+        assert_eq!(data["total_count"], 2);
+        let logs = data["logs"].as_array().unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0]["event_type"], "api_error");
+        assert_eq!(logs[0]["attributes"]["error_code"], "overloaded_error");
+        assert_eq!(logs[1]["event_type"], "tool_result");
+
+        let (_, body) = get_json(&app, "/?range=24h").await;
+        assert_eq!(body["data"]["total_count"], 3);
+        let (_, body) = get_json(&app, "/?range=24h&level=DEBUG").await;
+        assert_eq!(body["data"]["total_count"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_list_pagination_bounds() {
+        let (_dir, db) = test_database().await;
+        for minutes_ago in 0..5 {
+            let mut record = log("user_prompt_submitted", &[], 0);
+            record.timestamp = Utc::now() - Duration::minutes(minutes_ago + 1);
+            db.store_log(&record).await.unwrap();
+        }
+        let mut state = test_state(db);
+        state.config = Arc::new(Config { max_page_size: 3, ..Config::default() });
+        let app = routes().with_state(state);
+
+        let (_, body) = get_json(&app, "/?limit=2&offset=2").await;
+        let data = &body["data"];
+        assert_eq!(data["logs"].as_array().unwrap().len(), 2);
+        assert_eq!(data["logs"][0]["event_type"], "user_prompt");
+        assert_eq!(data["page_info"]["current_page"], 2);
+        assert_eq!(data["page_info"]["total_pages"], 3);
+        assert_eq!(data["page_info"]["has_prev"], true);
+        assert_eq!(data["page_info"]["has_next"], true);
+
+        // Past the end yields an empty page
+        let (_, body) = get_json(&app, "/?limit=2&offset=10").await;
+        assert!(body["data"]["logs"].as_array().unwrap().is_empty());
+        assert_eq!(body["data"]["page_info"]["has_next"], false);
+
+        // A zero limit is raised to one, an oversized one capped at `max_page_size`
+        let (_, body) = get_json(&app, "/?limit=0").await;
+        assert_eq!(body["data"]["logs"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"]["page_info"]["total_pages"], 5);
+        let (_, body) = get_json(&app, "/?limit=100000").await;
+        assert_eq!(body["data"]["logs"].as_array().unwrap().len(), 3);
+        assert_eq!(body["data"]["page_info"]["total_pages"], 2);
+    }
+}
@@ -0,0 +1,141 @@
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Json},
+    routing::get,
+    Router,
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::privacy::Privacy;
+use crate::storage::{Database, FacetCount};
+use super::analytics::{parse_time_range, AnalyticsQuery};
+use super::{ApiResponse, ApiResult, AppState};
+
+#[derive(Debug, Serialize)]
+pub struct FacetValue {
+    pub value: String,
+    pub count: u64,
+}
+
+impl From<FacetCount> for FacetValue {
+    fn from(facet: FacetCount) -> Self {
+        Self { value: facet.value, count: facet.count }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FacetsResponse {
+    pub metric_names: Vec<FacetValue>,
+    pub models: Vec<FacetValue>,
+    pub user_emails: Vec<FacetValue>,
+    pub tool_names: Vec<FacetValue>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/facets", get(get_facets))
+}
+
+// GET /api/metadata/facets?range=30d - Distinct values for filter dropdowns, most frequent first
+async fn get_facets(
+    State(db): State<Arc<dyn Database>>,
+    State(privacy): State<Privacy>,
+    Query(mut params): Query<AnalyticsQuery>,
+) -> ApiResult<impl IntoResponse> {
+    params.range.get_or_insert_with(|| "30d".to_string());
+    let (start_time, end_time) = parse_time_range(&params)?;
+    let facets = db.facets(start_time, end_time).await?;
+
+    let values = |facets: Vec<FacetCount>| facets.into_iter().map(FacetValue::from).collect();
+    let response = FacetsResponse {
+        metric_names: values(facets.metric_names),
+        models: values(facets.models),
+        user_emails: facets
+            .user_emails
+            .into_iter()
+            .map(|facet| FacetValue { value: privacy.mask(&facet.value), count: facet.count })
+            .collect(),
+        tool_names: values(facets.tool_names),
+    };
+
+    Ok(Json(ApiResponse::success(response)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use chrono::{Duration, Utc};
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::{
+        api::{create_routes, test_state},
+        storage::{sqlite::test_database, LogRecord, MetricRecord},
+    };
+
+    #[tokio::test]
+    async fn test_facets_count_distinct_values_in_the_range() {
+        let (_dir, db) = test_database().await;
+        let now = Utc::now();
+        for (name, labels, days_ago) in [
+            ("claude_code.cost.usage", vec![("model", "sonnet"), ("user.email", "alice@example.com")], 1),
+            ("claude_code.cost.usage", vec![("model", "opus"), ("user.email", "alice@example.com")], 2),
+            ("claude_code.token.usage", vec![("model", "sonnet"), ("user.email", "bob@example.com")], 3),
+            ("claude_code.commit.count", vec![], 4),
+            // Outside the 30d default
+            ("claude_code.session.count", vec![("model", "haiku")], 40),
+        ] {
+            db.store_metric(&MetricRecord {
+                id: Uuid::new_v4(),
+                session_id: None,
+                name: name.to_string(),
+                timestamp: now - Duration::days(days_ago),
+                value: 1.0,
+                labels: labels.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+                unit: None,
+                description: None,
+                service: None,
+                created_at: now,
+            })
+            .await
+            .unwrap();
+        }
+        for (message, tool) in [("tool_result", Some("Edit")), ("tool_result", Some("Edit")), ("tool_result", None), ("api_request", Some("Bash"))] {
+            db.store_log(&LogRecord {
+                id: Uuid::new_v4(),
+                session_id: None,
+                timestamp: now - Duration::hours(1),
+                level: "INFO".to_string(),
+                message: message.to_string(),
+                attributes: tool.map(|tool| HashMap::from([("tool_name".to_string(), tool.to_string())])).unwrap_or_default(),
+                service: None,
+                created_at: now,
+            })
+            .await
+            .unwrap();
+        }
+        let app = create_routes().with_state(test_state(db));
+
+        let response = app.oneshot(Request::builder().uri("/metadata/facets").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let facet = |name: &str| {
+            body["data"][name]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|facet| (facet["value"].as_str().unwrap().to_string(), facet["count"].as_u64().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let expected = |pairs: &[(&str, u64)]| pairs.iter().map(|(value, count)| (value.to_string(), *count)).collect::<Vec<_>>();
+
+        assert_eq!(
+            facet("metric_names"),
+            expected(&[("claude_code.cost.usage", 2), ("claude_code.commit.count", 1), ("claude_code.token.usage", 1)])
+        );
+        assert_eq!(facet("models"), expected(&[("sonnet", 2), ("opus", 1)]));
+        assert_eq!(facet("user_emails"), expected(&[("alice@example.com", 2), ("bob@example.com", 1)]));
+        assert_eq!(facet("tool_names"), expected(&[("Edit", 2), ("unknown", 1)]));
+    }
+}
@@ -1,15 +1,32 @@
 use axum::{
-    extract::{Query, State},
-    response::{IntoResponse, Json},
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::header,
+    response::{IntoResponse, Json, Response},
     routing::get,
     Router,
 };
 use chrono::{DateTime, Duration, Utc};
+use futures_util::{stream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
 
-use crate::storage::Database;
-use super::{ApiError, ApiResponse, ApiResult, MetricPoint};
+use crate::otel::receiver::MetricFeed;
+use crate::storage::{Database, DatabaseError, MetricBucket, MetricFilter, MetricRecord, MetricStats};
+use crate::util::parse_range;
+use super::{
+    prometheus::{self, MetricKind, PrometheusWriter},
+    analytics::{parse_time_range, AnalyticsQuery, MAX_WINDOW_DAYS},
+    ApiError, ApiResponse, ApiResult, AppState, MetricPoint,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MetricsQuery {
@@ -24,6 +41,49 @@ pub struct TimelineQuery {
     pub metric_name: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<String>, // "json" (default) or "csv"
+    pub range: Option<String>,
+    pub start_time: Option<DateTime<Utc>>, // with end_time, overrides range
+    pub end_time: Option<DateTime<Utc>>,
+    pub metric_name: Option<String>,
+    pub label: Option<String>, // "key=value", matched against any label
+    pub service: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AggregateQuery {
+    pub range: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetricAggregate {
+    pub name: String,
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+impl From<MetricStats> for MetricAggregate {
+    fn from(stats: MetricStats) -> Self {
+        Self {
+            name: stats.name,
+            unit: stats.unit,
+            description: stats.description,
+            count: stats.count,
+            sum: stats.sum,
+            min: stats.min,
+            max: stats.max,
+            avg: stats.avg,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct MetricsOverview {
     pub total_sessions: u64,
@@ -44,6 +104,7 @@ pub struct ToolUsage {
 #[derive(Debug, Serialize)]
 pub struct TimelineData {
     pub range: String,
+    pub bucket_seconds: i64,
     pub points: Vec<MetricPoint>,
     pub summary: TimelineSummary,
 }
@@ -56,10 +117,22 @@ pub struct TimelineSummary {
     pub max_value: f64,
 }
 
-pub fn routes() -> Router<Arc<dyn Database>> {
+/// Header row of the metrics CSV export
+const CSV_HEADER: &str = "timestamp,name,value,session_id,labels\n";
+
+// Roughly how many points a timeline returns per metric name
+const TIMELINE_BUCKETS: i64 = 120;
+// Candidate bucket widths in seconds, from one minute to one week
+const BUCKET_WIDTHS: [i64; 10] = [60, 300, 900, 1800, 3600, 3 * 3600, 6 * 3600, 12 * 3600, 86400, 7 * 86400];
+
+pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/overview", get(get_metrics_overview))
         .route("/timeline", get(get_metrics_timeline))
+        .route("/prometheus", get(get_prometheus_metrics))
+        .route("/export", get(export_metrics))
+        .route("/aggregate", get(aggregate_metrics))
+        .route("/stream", get(stream_metrics))
 }
 
 // GET /api/metrics/overview - Overview of all metrics and activity
@@ -110,12 +183,7 @@ async fn get_metrics_overview(
     let recent_activity: Vec<MetricPoint> = recent_metrics
         .into_iter()
         .take(10)
-        .map(|m| MetricPoint {
-            timestamp: m.timestamp,
-            name: m.name,
-            value: m.value,
-            labels: m.labels,
-        })
+        .map(MetricPoint::from)
         .collect();
 
     let overview = MetricsOverview {
@@ -130,73 +198,683 @@ async fn get_metrics_overview(
     Ok(Json(ApiResponse::success(overview)))
 }
 
-// GET /api/metrics/timeline - Time series data with range parameter
+// GET /api/metrics/timeline - One aggregated point per bucket and metric name
 async fn get_metrics_timeline(
     State(db): State<Arc<dyn Database>>,
     Query(params): Query<TimelineQuery>,
 ) -> ApiResult<impl IntoResponse> {
     let range = params.range.as_deref().unwrap_or("24h");
-    
-    // Parse range parameter
-    let (start_time, duration_label) = match range {
-        "1h" => (Utc::now() - Duration::hours(1), "1 hour"),
-        "24h" => (Utc::now() - Duration::hours(24), "24 hours"),
-        "7d" => (Utc::now() - Duration::days(7), "7 days"),
-        "30d" => (Utc::now() - Duration::days(30), "30 days"),
-        _ => return Err(ApiError::InvalidQuery(format!("Invalid range: {}", range))),
-    };
+    let end_time = Utc::now();
+    let start_time = range_start(range, end_time)?;
+    let width = bucket_width(end_time - start_time);
 
-    // Get metrics from database
-    let metrics = db.get_metrics(
-        Some(start_time),
-        Some(Utc::now()),
-        params.metric_name.as_deref()
+    let buckets = db.metric_buckets(
+        start_time,
+        end_time,
+        params.metric_name.as_deref(),
+        width,
     ).await?;
 
-    // Convert to MetricPoints
-    let points: Vec<MetricPoint> = metrics
+    let summary = timeline_summary(&buckets);
+    let mut series: BTreeMap<String, HashMap<DateTime<Utc>, MetricBucket>> = BTreeMap::new();
+    for bucket in buckets {
+        series.entry(bucket.name.clone()).or_default().insert(bucket.start, bucket);
+    }
+    // A named metric with no points still gets a flat line
+    if let Some(name) = params.metric_name {
+        series.entry(name).or_default();
+    }
+
+    // Every bucket from the aligned start to now, with zeros where nothing was recorded
+    let first = DateTime::from_timestamp(start_time.timestamp().div_euclid(width) * width, 0).unwrap_or(start_time);
+    let mut points = Vec::new();
+    for (name, mut buckets) in series {
+        let kind = MetricKind::for_name(&name);
+        let unit = buckets.values().find_map(|bucket| bucket.unit.clone());
+        let description = buckets.values().find_map(|bucket| bucket.description.clone());
+        let mut timestamp = first;
+        while timestamp <= end_time {
+            let value = match buckets.remove(&timestamp) {
+                Some(bucket) if kind == MetricKind::Counter => bucket.sum,
+                Some(bucket) => bucket.sum / bucket.count as f64,
+                None => 0.0,
+            };
+            points.push(MetricPoint {
+                timestamp,
+                name: name.clone(),
+                value,
+                labels: HashMap::new(),
+                unit: unit.clone(),
+                description: description.clone(),
+                service: None,
+            });
+            timestamp += Duration::seconds(width);
+        }
+    }
+    points.sort_by_key(|point| point.timestamp);
+
+    let timeline = TimelineData {
+        range: range_label(range),
+        bucket_seconds: width,
+        points,
+        summary,
+    };
+
+    Ok(Json(ApiResponse::success(timeline)))
+}
+
+// Smallest step that keeps the window within TIMELINE_BUCKETS, in seconds
+fn bucket_width(window: Duration) -> i64 {
+    let seconds = window.num_seconds();
+    BUCKET_WIDTHS
         .into_iter()
-        .map(|m| MetricPoint {
-            timestamp: m.timestamp,
-            name: m.name,
-            value: m.value,
-            labels: m.labels,
-        })
-        .collect();
+        .find(|width| seconds / width <= TIMELINE_BUCKETS)
+        .unwrap_or((seconds + TIMELINE_BUCKETS - 1) / TIMELINE_BUCKETS)
+}
 
-    // Calculate summary statistics
-    let values: Vec<f64> = points.iter().map(|p| p.value).collect();
-    let summary = if values.is_empty() {
-        TimelineSummary {
+// Stats over the raw points behind the buckets, not the bucketed values
+fn timeline_summary(buckets: &[MetricBucket]) -> TimelineSummary {
+    let total_points: u64 = buckets.iter().map(|bucket| bucket.count).sum();
+    if total_points == 0 {
+        return TimelineSummary {
             total_points: 0,
             avg_value: 0.0,
             min_value: 0.0,
             max_value: 0.0,
+        };
+    }
+    TimelineSummary {
+        total_points,
+        avg_value: buckets.iter().map(|bucket| bucket.sum).sum::<f64>() / total_points as f64,
+        min_value: buckets.iter().map(|bucket| bucket.min).fold(f64::INFINITY, f64::min),
+        max_value: buckets.iter().map(|bucket| bucket.max).fold(f64::NEG_INFINITY, f64::max),
+    }
+}
+
+// GET /api/metrics/prometheus - Every stored series in Prometheus text format: the running
+// total of counters, whose points are stored as increases, and the latest value of the rest
+async fn get_prometheus_metrics(
+    State(db): State<Arc<dyn Database>>,
+) -> ApiResult<impl IntoResponse> {
+    let is_counter = |metric: &MetricRecord| MetricKind::for_name(&metric.name) == MetricKind::Counter;
+    let mut series: Vec<MetricRecord> = db.get_latest_metrics().await?
+        .into_iter()
+        .filter(|metric| !is_counter(metric))
+        .chain(db.get_series_totals().await?.into_iter().filter(is_counter))
+        .collect();
+    // Group by exposed name so each family gets a single TYPE line
+    series.sort_by_cached_key(|m| prometheus::sanitize_metric_name(&m.name));
+
+    let mut writer = PrometheusWriter::new();
+    for metric in series {
+        let kind = MetricKind::for_name(&metric.name);
+        let labels: BTreeMap<String, String> = metric.labels.into_iter().collect();
+        writer.sample(&metric.name, kind, &labels, metric.value);
+    }
+
+    Ok(([(header::CONTENT_TYPE, prometheus::CONTENT_TYPE)], writer.finish()))
+}
+
+// GET /api/metrics/export - Raw metric points, oldest first, streamed as JSON or a CSV download
+async fn export_metrics(
+    State(db): State<Arc<dyn Database>>,
+    Query(params): Query<ExportQuery>,
+) -> ApiResult<Response> {
+    let range = params.range.clone().unwrap_or_else(|| "24h".to_string());
+    let window = AnalyticsQuery {
+        start_time: params.start_time,
+        end_time: params.end_time,
+        range: Some(range.clone()),
+        ..AnalyticsQuery::default()
+    };
+    let (start_time, end_time) = parse_time_range(&window)?;
+    // Named after the range, or the window when both bounds were given
+    let filename = match (params.start_time, params.end_time) {
+        (Some(_), Some(_)) => format!(
+            "claude-lens-metrics-{}-{}",
+            start_time.format("%Y%m%dT%H%M%SZ"),
+            end_time.format("%Y%m%dT%H%M%SZ")
+        ),
+        _ => format!("claude-lens-metrics-{}", range),
+    };
+
+    let label = params
+        .label
+        .as_deref()
+        .map(|label| {
+            label
+                .split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .ok_or_else(|| ApiError::InvalidQuery(format!("Invalid label filter: {}", label)))
+        })
+        .transpose()?;
+    let filter = MetricFilter {
+        metric_name: params.metric_name,
+        label,
+        service: params.service.map(|service| service.trim().to_string()).filter(|service| !service.is_empty()),
+    };
+
+    // Rows are written as they are read; a database error mid-way aborts the response
+    match params.format.as_deref().unwrap_or("json") {
+        "json" => {
+            // The usual response envelope, with `data` written one point at a time
+            let timestamp = serde_json::to_string(&Utc::now()).unwrap_or_default();
+            let items = db
+                .stream_metrics(start_time, end_time, filter)
+                .enumerate()
+                .map(|(index, metric)| {
+                    let json = serde_json::to_string(&MetricPoint::from(metric?))
+                        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+                    Ok::<_, DatabaseError>(if index == 0 { json } else { format!(",{}", json) })
+                });
+            let body = stream::once(async { Ok(r#"{"success":true,"data":["#.to_string()) })
+                .chain(items)
+                .chain(stream::once(async move { Ok(format!(r#"],"error":null,"timestamp":{}}}"#, timestamp)) }));
+            Ok(([(header::CONTENT_TYPE, "application/json")], Body::from_stream(body)).into_response())
         }
-    } else {
-        TimelineSummary {
-            total_points: values.len() as u64,
-            avg_value: values.iter().sum::<f64>() / values.len() as f64,
-            min_value: values.iter().fold(f64::INFINITY, |a, &b| a.min(b)),
-            max_value: values.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b)),
+        "csv" => {
+            let disposition = format!("attachment; filename=\"{}.csv\"", filename);
+            let headers = [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (header::CONTENT_DISPOSITION, disposition),
+            ];
+            let rows = db
+                .stream_metrics(start_time, end_time, filter)
+                .map_ok(|metric| metric_to_csv_row(&metric));
+            let body = stream::once(async { Ok(CSV_HEADER.to_string()) }).chain(rows);
+            Ok((headers, Body::from_stream(body)).into_response())
+        }
+        other => Err(ApiError::InvalidQuery(format!("Invalid format: {}", other))),
+    }
+}
+
+// GET /api/metrics/aggregate - Count, sum, min, max and average per metric name
+async fn aggregate_metrics(
+    State(db): State<Arc<dyn Database>>,
+    Query(params): Query<AggregateQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let range = params.range.as_deref().unwrap_or("24h");
+    let end_time = Utc::now();
+    let start_time = range_start(range, end_time)?;
+
+    let aggregates: Vec<MetricAggregate> = db
+        .metric_stats(start_time, end_time)
+        .await?
+        .into_iter()
+        .map(MetricAggregate::from)
+        .collect();
+
+    Ok(Json(ApiResponse::success(aggregates)))
+}
+
+// GET /api/metrics/stream - WebSocket relaying every newly ingested metric as JSON
+async fn stream_metrics(ws: WebSocketUpgrade, State(feed): State<MetricFeed>) -> Response {
+    // Subscribe before upgrading so nothing ingested after the handshake is missed
+    let metrics = feed.subscribe();
+    ws.on_upgrade(move |socket| relay_metrics(socket, metrics))
+}
+
+async fn relay_metrics(mut socket: WebSocket, mut metrics: broadcast::Receiver<MetricRecord>) {
+    loop {
+        tokio::select! {
+            received = metrics.recv() => match received {
+                Ok(metric) => {
+                    let point = MetricPoint::from(metric);
+                    let Ok(json) = serde_json::to_string(&point) else { continue };
+                    if socket.send(Message::Text(json)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    // Ingestion never waits on a slow client; it gets disconnected instead
+                    warn!("Live-tail client fell {} metrics behind, disconnecting", skipped);
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
         }
+    }
+}
+
+fn metric_to_csv_row(metric: &MetricRecord) -> String {
+    // Sorted keys keep the encoded labels stable between exports
+    let labels: BTreeMap<&String, &String> = metric.labels.iter().collect();
+    let labels = serde_json::to_string(&labels).unwrap_or_default();
+    let session_id = metric.session_id.map(|id| id.to_string()).unwrap_or_default();
+    let fields = [
+        metric.timestamp.to_rfc3339(),
+        metric.name.clone(),
+        metric.value.to_string(),
+        session_id,
+        labels,
+    ];
+    let mut row = fields.iter().map(|f| escape_csv_field(f)).collect::<Vec<_>>().join(",");
+    row.push('\n');
+    row
+}
+
+// Names and labels come from exporters, so text a spreadsheet would run as a formula gets a
+// leading `'`; numbers such as negative values are left alone
+pub(super) fn escape_csv_field(field: &str) -> String {
+    if field.starts_with(['=', '+', '-', '@']) && field.parse::<f64>().is_err() {
+        format!("\"'{}\"", field.replace('"', "\"\""))
+    } else if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Start of the window `range` reaches back from `end_time`, no longer than the analytics windows
+fn range_start(range: &str, end_time: DateTime<Utc>) -> ApiResult<DateTime<Utc>> {
+    let invalid = || ApiError::InvalidQuery(format!("Invalid range: {}", range));
+    let length = parse_range(range).ok_or_else(invalid)?;
+    if length > Duration::days(MAX_WINDOW_DAYS) {
+        return Err(ApiError::InvalidQuery(format!(
+            "Time window exceeds the maximum of {} days",
+            MAX_WINDOW_DAYS
+        )));
+    }
+    end_time.checked_sub_signed(length).ok_or_else(invalid)
+}
+
+// "12h" -> "12 hours"; only called on ranges `parse_range` accepted
+fn range_label(range: &str) -> String {
+    let (count, unit) = range.split_at(range.len() - 1);
+    let unit = match unit {
+        "h" => "hour",
+        "d" => "day",
+        _ => "week",
     };
+    format!("{} {}{}", count, unit, if count == "1" { "" } else { "s" })
+}
 
-    let timeline = TimelineData {
-        range: duration_label.to_string(),
-        points,
-        summary,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use chrono::TimeZone;
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+    use tower::ServiceExt;
+
+    use crate::{
+        api::test_state,
+        otel::{json::parse_metrics_request, receiver::OtelReceiver},
+        storage::sqlite::test_database,
     };
 
-    Ok(Json(ApiResponse::success(timeline)))
-}
+    #[test]
+    fn test_range_label() {
+        assert_eq!(range_label("1h"), "1 hour");
+        assert_eq!(range_label("12h"), "12 hours");
+        assert_eq!(range_label("3d"), "3 days");
+        assert_eq!(range_label("2w"), "2 weeks");
+    }
+
+    #[tokio::test]
+    async fn test_timeline_accepts_custom_ranges() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db));
+
+        for (range, status) in [("12h", StatusCode::OK), ("3d", StatusCode::OK), ("2w", StatusCode::OK), ("5x", StatusCode::BAD_REQUEST)] {
+            let uri = format!("/timeline?range={}", range);
+            let response = app.clone().oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), status, "{}", range);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ranges_past_the_window_cap_are_rejected() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db));
+
+        for path in ["/timeline", "/export", "/aggregate"] {
+            for (range, status) in [("52w", StatusCode::OK), ("53w", StatusCode::BAD_REQUEST), ("100000000w", StatusCode::BAD_REQUEST)] {
+                let uri = format!("{}?range={}", path, range);
+                let response = app.clone().oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(response.status(), status, "{} {}", path, range);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bucket_width_targets_bucket_count() {
+        assert_eq!(bucket_width(Duration::hours(1)), 60);
+        assert_eq!(bucket_width(Duration::hours(24)), 900);
+        assert_eq!(bucket_width(Duration::days(30)), 6 * 3600);
+        assert_eq!(bucket_width(Duration::weeks(52)), 7 * 86400);
+        // Past the widest step the width grows with the window
+        assert_eq!(bucket_width(Duration::weeks(240)), 2 * 7 * 86400);
+    }
+
+    #[tokio::test]
+    async fn test_timeline_aggregates_per_bucket() {
+        let (_dir, db) = test_database().await;
+        let minute = DateTime::from_timestamp(Utc::now().timestamp() / 60 * 60, 0).unwrap() - Duration::minutes(10);
+        for (name, value, seconds) in [
+            ("claude_code.token.usage", 100.0, 5),
+            ("claude_code.token.usage", 50.0, 20),
+            ("process.memory.rss", 10.0, 5),
+            ("process.memory.rss", 30.0, 40),
+        ] {
+            db.store_metric(&MetricRecord {
+                id: uuid::Uuid::new_v4(),
+                session_id: None,
+                name: name.to_string(),
+                timestamp: minute + Duration::seconds(seconds),
+                value,
+                labels: HashMap::new(),
+                unit: None,
+                description: None,
+                service: None,
+                created_at: Utc::now(),
+            }).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
 
-fn parse_duration(range: &str) -> ApiResult<Duration> {
-    match range {
-        "1h" => Ok(Duration::hours(1)),
-        "24h" => Ok(Duration::hours(24)),
-        "7d" => Ok(Duration::days(7)),
-        "30d" => Ok(Duration::days(30)),
-        _ => Err(ApiError::InvalidQuery(format!("Invalid time range: {}", range))),
+        let response = app.clone().oneshot(Request::builder().uri("/timeline?range=1h").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let data = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+        assert_eq!(data["bucket_seconds"], 60);
+
+        let points = data["points"].as_array().unwrap();
+        let value_at = |name: &str| {
+            points.iter()
+                .find(|p| p["name"] == name && p["timestamp"].as_str().unwrap().parse::<DateTime<Utc>>().unwrap() == minute)
+                .map(|p| p["value"].as_f64().unwrap())
+        };
+        // Counters are summed, gauges averaged
+        assert_eq!(value_at("claude_code.token.usage"), Some(150.0));
+        assert_eq!(value_at("process.memory.rss"), Some(20.0));
+
+        // Both series cover the whole hour, zero-filled and in time order
+        let tokens: Vec<f64> = points.iter()
+            .filter(|p| p["name"] == "claude_code.token.usage")
+            .map(|p| p["value"].as_f64().unwrap())
+            .collect();
+        assert!((60..=61).contains(&tokens.len()), "{}", tokens.len());
+        assert_eq!(tokens.iter().filter(|v| **v == 0.0).count(), tokens.len() - 1);
+        assert!(points.windows(2).all(|w| w[0]["timestamp"].as_str() <= w[1]["timestamp"].as_str()));
+
+        // The summary covers the raw points
+        let summary = &data["summary"];
+        assert_eq!(summary["total_points"], 4);
+        assert_eq!(summary["avg_value"], 47.5);
+        assert_eq!(summary["min_value"], 10.0);
+        assert_eq!(summary["max_value"], 100.0);
+
+        // A named metric without data is still a continuous zero line
+        let response = app.oneshot(Request::builder().uri("/timeline?range=1h&metric_name=claude_code.cost.usage").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let data = &serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"];
+        let points = data["points"].as_array().unwrap();
+        assert!(points.len() >= 60);
+        assert!(points.iter().all(|p| p["name"] == "claude_code.cost.usage" && p["value"] == 0.0));
+        assert_eq!(data["summary"]["total_points"], 0);
+    }
+
+    #[test]
+    fn test_escape_csv_field() {
+        assert_eq!(escape_csv_field("plain"), "plain");
+        assert_eq!(escape_csv_field("{\"a\":\"b\"}"), "\"{\"\"a\"\":\"\"b\"\"}\"");
+        assert_eq!(escape_csv_field("a,b"), "\"a,b\"");
+        assert_eq!(escape_csv_field("-1.5"), "-1.5");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_csv_row_neutralizes_formulas() {
+        let metric = MetricRecord {
+            id: uuid::Uuid::new_v4(),
+            session_id: None,
+            name: "=HYPERLINK(\"http://evil\",\"x\")".to_string(),
+            timestamp: Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap(),
+            value: -2.0,
+            labels: HashMap::new(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        };
+        assert_eq!(
+            metric_to_csv_row(&metric),
+            "2025-03-01T12:00:00+00:00,\"'=HYPERLINK(\"\"http://evil\"\",\"\"x\"\")\",-2,,{}\n"
+        );
+        for field in ["+1+1", "-1+cmd", "@SUM(A1)"] {
+            assert_eq!(escape_csv_field(field), format!("\"'{}\"", field));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_metrics_csv() {
+        let (_dir, db) = test_database().await;
+        let mut labels = HashMap::new();
+        labels.insert("model".to_string(), "claude-sonnet".to_string());
+        db.store_metric(&MetricRecord {
+            id: uuid::Uuid::new_v4(),
+            session_id: None,
+            name: "claude_code.cost.usage".to_string(),
+            timestamp: Utc::now() - Duration::minutes(5),
+            value: 0.25,
+            labels,
+            unit: None,
+            description: None,
+            service: Some("claude-code".to_string()),
+            created_at: Utc::now(),
+        }).await.unwrap();
+        // Left out by the service filter
+        db.store_metric(&MetricRecord {
+            id: uuid::Uuid::new_v4(),
+            session_id: None,
+            name: "claude_code.cost.usage".to_string(),
+            timestamp: Utc::now() - Duration::minutes(4),
+            value: 9.0,
+            labels: HashMap::new(),
+            unit: None,
+            description: None,
+            service: Some("other".to_string()),
+            created_at: Utc::now(),
+        }).await.unwrap();
+
+        let app = routes().with_state(test_state(db));
+        let response = app
+            .oneshot(Request::builder().uri("/export?format=csv&range=7d&service=claude-code").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"claude-lens-metrics-7d.csv\""
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let mut lines = body.lines();
+        assert_eq!(lines.next(), Some("timestamp,name,value,session_id,labels"));
+        let row = lines.next().unwrap();
+        assert!(row.contains(",claude_code.cost.usage,0.25,,\"{\"\"model\"\":\"\"claude-sonnet\"\"}\""));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[tokio::test]
+    async fn test_export_metrics_json_streams_the_window() {
+        let (_dir, db) = test_database().await;
+        let now = Utc::now();
+        // More rows than the stream reads ahead
+        for i in 0..600 {
+            db.store_metric(&MetricRecord {
+                id: uuid::Uuid::new_v4(),
+                session_id: None,
+                name: "claude_code.token.usage".to_string(),
+                timestamp: now - Duration::minutes(600 - i),
+                value: i as f64,
+                labels: HashMap::new(),
+                unit: None,
+                description: None,
+                service: None,
+                created_at: now,
+            }).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let window = format!(
+            "start_time={}&end_time={}",
+            (now - Duration::minutes(100)).format("%Y-%m-%dT%H:%M:%SZ"),
+            now.format("%Y-%m-%dT%H:%M:%SZ")
+        );
+        let response = app.clone()
+            .oneshot(Request::builder().uri(format!("/export?{}", window)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], true);
+        let points = body["data"].as_array().unwrap();
+        assert!((99..=100).contains(&points.len()), "{}", points.len());
+        assert_eq!(points.last().unwrap()["value"], 599.0);
+
+        let response = app.clone()
+            .oneshot(Request::builder().uri(format!("/export?format=csv&{}", window)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let disposition = response.headers()[header::CONTENT_DISPOSITION].to_str().unwrap().to_string();
+        assert!(disposition.starts_with("attachment; filename=\"claude-lens-metrics-20"), "{}", disposition);
+
+        let response = app.clone().oneshot(Request::builder().uri("/export?metric_name=missing").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["data"], serde_json::json!([]));
+
+        let response = app.oneshot(Request::builder().uri("/export?format=xml").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_groups_values_by_name() {
+        let (_dir, db) = test_database().await;
+        let point = |name: &str, value: f64, hours_ago: i64| MetricRecord {
+            id: uuid::Uuid::new_v4(),
+            session_id: None,
+            name: name.to_string(),
+            timestamp: Utc::now() - Duration::hours(hours_ago),
+            value,
+            labels: HashMap::new(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        };
+        for metric in [
+            point("claude_code.cost.usage", 0.25, 1),
+            point("claude_code.cost.usage", 0.75, 30),
+            point("claude_code.cost.usage", 2.0, 100),
+            point("claude_code.token.usage", 100.0, 2),
+            point("claude_code.token.usage", 300.0, 3),
+            // Outside the 7d window
+            point("claude_code.token.usage", 9999.0, 24 * 8),
+        ] {
+            db.store_metric(&metric).await.unwrap();
+        }
+
+        let app = routes().with_state(test_state(db));
+        let response = app
+            .oneshot(Request::builder().uri("/aggregate?range=7d").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let aggregates = body["data"].as_array().unwrap();
+        assert_eq!(aggregates.len(), 2);
+
+        let cost = &aggregates[0];
+        assert_eq!(cost["name"], "claude_code.cost.usage");
+        assert_eq!(cost["count"], 3);
+        assert_eq!(cost["sum"], 3.0);
+        assert_eq!(cost["min"], 0.25);
+        assert_eq!(cost["max"], 2.0);
+        assert_eq!(cost["avg"], 1.0);
+
+        let tokens = &aggregates[1];
+        assert_eq!(tokens["name"], "claude_code.token.usage");
+        assert_eq!(tokens["count"], 2);
+        assert_eq!(tokens["sum"], 400.0);
+        assert_eq!(tokens["min"], 100.0);
+        assert_eq!(tokens["max"], 300.0);
+        assert_eq!(tokens["avg"], 200.0);
+    }
+
+    #[tokio::test]
+    async fn test_stream_relays_ingested_metrics() {
+        let (_dir, db) = test_database().await;
+        let state = test_state(db);
+        let receiver = OtelReceiver::new(state.db.clone(), state.stats.clone()).with_feed(state.metric_feed.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, routes().with_state(state)).await });
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/stream", addr)).await.unwrap();
+
+        let body = r#"{"resourceMetrics": [{"scopeMetrics": [{"metrics": [{
+            "name": "claude_code.cost.usage",
+            "gauge": {"dataPoints": [{"timeUnixNano": "1700000000000000000", "asDouble": 0.5,
+                "attributes": [{"key": "model", "value": {"stringValue": "sonnet"}}]}]}
+        }]}]}]}"#;
+        receiver.ingest_metrics(parse_metrics_request(body.as_bytes()).unwrap()).await.unwrap();
+
+        let message = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+            .await
+            .expect("no metric received")
+            .unwrap()
+            .unwrap();
+        let ClientMessage::Text(text) = message else { panic!("expected a text frame") };
+        let point: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(point["name"], "claude_code.cost.usage");
+        assert_eq!(point["value"], 0.5);
+        assert_eq!(point["labels"]["model"], "sonnet");
+    }
+
+    #[tokio::test]
+    async fn test_ingested_unit_and_description_are_returned() {
+        let (_dir, db) = test_database().await;
+        let state = test_state(db);
+        let receiver = OtelReceiver::new(state.db.clone(), state.stats.clone());
+        let now = Utc::now().timestamp_nanos_opt().unwrap();
+        let body = format!(r#"{{"resourceMetrics": [{{"scopeMetrics": [{{"metrics": [
+            {{"name": "claude_code.cost.usage", "unit": "USD", "description": "Cost of the session",
+              "sum": {{"dataPoints": [{{"timeUnixNano": "{now}", "asDouble": 0.5}}]}}}},
+            {{"name": "claude_code.request.duration", "unit": "ms",
+              "histogram": {{"dataPoints": [{{"timeUnixNano": "{now}", "count": "2", "sum": 340.0}}]}}}},
+            {{"name": "claude_code.session.count",
+              "sum": {{"dataPoints": [{{"timeUnixNano": "{now}", "asInt": "1"}}]}}}}
+        ]}}]}}]}}"#);
+        receiver.ingest_metrics(parse_metrics_request(body.as_bytes()).unwrap()).await.unwrap();
+        let app = routes().with_state(state);
+
+        let response = app.clone().oneshot(Request::builder().uri("/export?format=json").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let point = |name: &str| body["data"].as_array().unwrap().iter().find(|p| p["name"] == name).unwrap().clone();
+
+        assert_eq!(point("claude_code.cost.usage")["unit"], "USD");
+        assert_eq!(point("claude_code.cost.usage")["description"], "Cost of the session");
+        // The count of a histogram is dimensionless; its sum keeps the unit
+        assert_eq!(point("claude_code.request.duration_sum")["unit"], "ms");
+        assert!(point("claude_code.request.duration_count")["unit"].is_null());
+        // Unset fields stay null rather than empty strings
+        assert!(point("claude_code.session.count")["unit"].is_null());
+        assert!(point("claude_code.session.count")["description"].is_null());
+
+        let response = app.oneshot(Request::builder().uri("/aggregate").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let cost = body["data"].as_array().unwrap().iter().find(|a| a["name"] == "claude_code.cost.usage").unwrap();
+        assert_eq!(cost["unit"], "USD");
+    }
+}
@@ -1,8 +1,19 @@
 pub mod metrics;
 pub mod sessions;
 pub mod analytics;
+pub mod prometheus;
+pub mod admin;
+pub mod traces;
+pub mod overview;
+pub mod logs;
+pub mod stream;
+pub mod export;
+pub mod budgets;
+pub mod users;
+pub mod metadata;
 
 use axum::{
+    extract::{FromRef, State},
     http::StatusCode,
     response::{IntoResponse, Json},
     routing::get,
@@ -10,9 +21,125 @@ use axum::{
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    config::Config,
+    otel::{aggregation::MetricAggregator, idempotency::IdempotencyCache, queue::IngestQueue, receiver::MetricFeed, temporality::CumulativeDeltas},
+    pricing::PricingStore,
+    privacy::Privacy,
+    stats::{HttpStats, IngestStats},
+    storage::{Database, DatabaseHealth, MetricRecord, PoolStats},
+};
+
+/// Metrics a live-tail subscriber may fall behind by before it starts missing some
+const METRIC_FEED_CAPACITY: usize = 1024;
+
+// Shared state for all HTTP routes
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Arc<dyn Database>,
+    pub stats: Arc<IngestStats>,
+    pub http_stats: Arc<HttpStats>,
+    pub config: Arc<Config>,
+    pub pricing: Arc<PricingStore>,
+    pub privacy: Privacy,
+    pub metric_feed: MetricFeed,
+    pub idempotency: Arc<IdempotencyCache>,
+    /// Counters and gauges waiting for the next flush, when aggregation is enabled
+    pub aggregator: Option<Arc<MetricAggregator>>,
+    /// Last totals of cumulative sums, so both receivers store the same increases
+    pub cumulative: Arc<CumulativeDeltas>,
+    /// Hands parsed exports to the storage writer, when one is running
+    pub ingest_queue: Option<IngestQueue>,
+    /// Set once the database is initialized and migrated; `/readyz` fails until then
+    pub ready: Arc<AtomicBool>,
+}
+
+impl AppState {
+    pub fn new(db: Arc<dyn Database>, stats: Arc<IngestStats>, config: Config) -> Self {
+        Self {
+            db,
+            stats,
+            http_stats: Arc::new(HttpStats::new()),
+            privacy: Privacy::new(config.privacy_mode, config.privacy_salt.as_deref()),
+            aggregator: config.aggregation_enabled.then(|| Arc::new(MetricAggregator::new())),
+            cumulative: Arc::new(CumulativeDeltas::new()),
+            ingest_queue: None,
+            config: Arc::new(config),
+            pricing: Arc::new(PricingStore::default()),
+            metric_feed: tokio::sync::broadcast::channel(METRIC_FEED_CAPACITY).0,
+            idempotency: Arc::new(IdempotencyCache::default()),
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_pricing(mut self, pricing: Arc<PricingStore>) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    pub fn with_ingest_queue(mut self, queue: Option<IngestQueue>) -> Self {
+        self.ingest_queue = queue;
+        self
+    }
+
+    pub fn mark_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+}
+
+/// State with fresh counters and the default configuration
+#[cfg(test)]
+pub(crate) fn test_state(db: Arc<dyn Database>) -> AppState {
+    AppState::new(db, Arc::new(IngestStats::new()), Config::default())
+}
+
+impl FromRef<AppState> for Arc<dyn Database> {
+    fn from_ref(state: &AppState) -> Self {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
 
-use crate::storage::Database;
+impl FromRef<AppState> for Arc<PricingStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.pricing.clone()
+    }
+}
+
+impl FromRef<AppState> for MetricFeed {
+    fn from_ref(state: &AppState) -> Self {
+        state.metric_feed.clone()
+    }
+}
+
+impl FromRef<AppState> for Privacy {
+    fn from_ref(state: &AppState) -> Self {
+        state.privacy.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<IngestStats> {
+    fn from_ref(state: &AppState) -> Self {
+        state.stats.clone()
+    }
+}
 
 // Common API response wrapper
 #[derive(Serialize, Deserialize)]
@@ -20,6 +147,9 @@ pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<String>,
+    /// Machine-readable error kind, e.g. `INVALID_QUERY`; omitted on success
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
     pub timestamp: DateTime<Utc>,
 }
 
@@ -32,6 +162,7 @@ where
             success: true,
             data: Some(data),
             error: None,
+            code: None,
             timestamp: Utc::now(),
         }
     }
@@ -41,9 +172,15 @@ where
             success: false,
             data: None,
             error: Some(message.to_string()),
+            code: None,
             timestamp: Utc::now(),
         }
     }
+
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
 }
 
 // Common data structures
@@ -53,6 +190,23 @@ pub struct MetricPoint {
     pub name: String,
     pub value: f64,
     pub labels: HashMap<String, String>,
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    pub service: Option<String>,
+}
+
+impl From<MetricRecord> for MetricPoint {
+    fn from(metric: MetricRecord) -> Self {
+        Self {
+            timestamp: metric.timestamp,
+            name: metric.name,
+            value: metric.value,
+            labels: metric.labels,
+            unit: metric.unit,
+            description: metric.description,
+            service: metric.service,
+        }
+    }
 }
 
 // API Error handling
@@ -64,10 +218,34 @@ pub enum ApiError {
     InvalidQuery(String),
     #[error("Resource not found")]
     NotFound,
+    #[error("Missing or invalid API key")]
+    Unauthorized,
+    #[error("Request timed out")]
+    Timeout,
+    #[error("Request body too large")]
+    PayloadTooLarge,
+    #[error("Not configured: {0}")]
+    NotConfigured(String),
     #[error("Internal server error: {0}")]
     Internal(String),
 }
 
+impl ApiError {
+    /// Stable identifier clients can branch on, unlike the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Database(_) => "DATABASE_ERROR",
+            ApiError::InvalidQuery(_) => "INVALID_QUERY",
+            ApiError::NotFound => "NOT_FOUND",
+            ApiError::Unauthorized => "UNAUTHORIZED",
+            ApiError::Timeout => "TIMEOUT",
+            ApiError::PayloadTooLarge => "PAYLOAD_TOO_LARGE",
+            ApiError::NotConfigured(_) => "NOT_CONFIGURED",
+            ApiError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> axum::response::Response {
         let (status, message) = match self {
@@ -77,33 +255,244 @@ impl IntoResponse for ApiError {
             }
             ApiError::InvalidQuery(ref msg) => (StatusCode::BAD_REQUEST, msg.as_str()),
             ApiError::NotFound => (StatusCode::NOT_FOUND, "Resource not found"),
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "Missing or invalid API key"),
+            ApiError::Timeout => (StatusCode::REQUEST_TIMEOUT, "Request timed out"),
+            ApiError::PayloadTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large"),
+            ApiError::NotConfigured(ref msg) => (StatusCode::NOT_FOUND, msg.as_str()),
             ApiError::Internal(ref msg) => {
                 tracing::error!("Internal error: {}", msg);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
             }
         };
 
-        let body = Json(ApiResponse::<()>::error(message));
+        let body = Json(ApiResponse::<()>::error(message).with_code(self.code()));
         (status, body).into_response()
     }
 }
 
 type ApiResult<T> = Result<T, ApiError>;
 
-// Health check endpoint
-async fn health_check() -> impl IntoResponse {
-    Json(ApiResponse::success(serde_json::json!({
-        "status": "healthy",
+// Health check endpoint: 503 when the database doesn't answer a trivial query
+async fn health_check(State(db): State<Arc<dyn Database>>) -> impl IntoResponse {
+    // Reads keep working in read-only mode, only ingestion is disabled
+    let read_only = db.is_read_only();
+    let ping = db.ping().await;
+    let status = match (&ping, read_only) {
+        (Err(_), _) => "unhealthy",
+        (Ok(()), true) => "read_only",
+        (Ok(()), false) => "healthy",
+    };
+    let report = serde_json::json!({
+        "status": status,
+        "read_only": read_only,
         "timestamp": Utc::now(),
         "version": env!("CARGO_PKG_VERSION")
-    })))
+    });
+
+    match ping {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success(report))),
+        Err(e) => {
+            tracing::warn!("Health check failed: {}", e);
+            let mut body = ApiResponse::error(&e.to_string()).with_code("DATABASE_UNAVAILABLE");
+            body.data = Some(report);
+            (StatusCode::SERVICE_UNAVAILABLE, Json(body))
+        }
+    }
 }
 
-// Create all API routes
-pub fn create_routes() -> Router<Arc<dyn Database>> {
+/// Kubernetes liveness probe: answers as long as the process can serve requests
+pub async fn livez() -> impl IntoResponse {
+    Json(ApiResponse::success(serde_json::json!({ "status": "alive" })))
+}
+
+/// Kubernetes readiness probe: 503 until migrations have run, and whenever the database can't be pinged
+pub async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    if !state.is_ready() {
+        let body = ApiResponse::<serde_json::Value>::error("Database is not initialized yet").with_code("NOT_READY");
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(body));
+    }
+    match state.db.ping().await {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::success(serde_json::json!({ "status": "ready" })))),
+        Err(e) => {
+            tracing::warn!("Readiness check failed: {}", e);
+            let body = ApiResponse::error(&e.to_string()).with_code("DATABASE_UNAVAILABLE");
+            (StatusCode::SERVICE_UNAVAILABLE, Json(body))
+        }
+    }
+}
+
+/// Readiness report of `/health/deep`
+#[derive(Debug, Serialize)]
+pub struct DeepHealth {
+    pub status: &'static str,
+    pub read_only: bool,
+    pub timestamp: DateTime<Utc>,
+    pub version: &'static str,
+    pub pool: PoolStatus,
+    /// Absent when the database could not be reached
+    pub database_size_bytes: Option<u64>,
+    pub table_rows: Option<BTreeMap<String, u64>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: u32,
+}
+
+impl From<PoolStats> for PoolStatus {
+    fn from(stats: PoolStats) -> Self {
+        Self { size: stats.size, idle: stats.idle }
+    }
+}
+
+// Readiness check: queries the database and answers 503 with the report when that fails
+async fn deep_health_check(State(db): State<Arc<dyn Database>>) -> impl IntoResponse {
+    let read_only = db.is_read_only();
+    let health = db.check_health().await;
+    let report = |status, health: Option<DatabaseHealth>| DeepHealth {
+        status,
+        read_only,
+        timestamp: Utc::now(),
+        version: env!("CARGO_PKG_VERSION"),
+        pool: db.pool_stats().into(),
+        database_size_bytes: health.as_ref().map(|h| h.size_bytes),
+        table_rows: health.map(|h| h.table_rows),
+    };
+
+    match health {
+        Ok(health) => {
+            let status = if read_only { "read_only" } else { "healthy" };
+            (StatusCode::OK, Json(ApiResponse::success(report(status, Some(health)))))
+        }
+        Err(e) => {
+            tracing::warn!("Deep health check failed: {}", e);
+            let mut body = ApiResponse::error(&e.to_string()).with_code("DATABASE_UNAVAILABLE");
+            body.data = Some(report("unavailable", None));
+            (StatusCode::SERVICE_UNAVAILABLE, Json(body))
+        }
+    }
+}
+
+// Routes that stay reachable without an API key
+pub fn public_routes() -> Router<AppState> {
     Router::new()
         .route("/health", get(health_check))
+        .route("/health/deep", get(deep_health_check))
+}
+
+// Create all API routes
+pub fn create_routes() -> Router<AppState> {
+    Router::new()
         .nest("/metrics", metrics::routes())
         .nest("/sessions", sessions::routes())
         .nest("/analytics", analytics::routes())
-}
\ No newline at end of file
+        .nest("/overview", overview::routes())
+        .nest("/logs", logs::routes())
+        .nest("/traces", traces::routes())
+        .nest("/stream", stream::routes())
+        .nest("/export", export::routes())
+        .nest("/admin", admin::routes())
+        .nest("/budgets", budgets::routes())
+        .nest("/users", users::routes())
+        .nest("/metadata", metadata::routes())
+        .route("/config", get(admin::get_config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use crate::storage::sqlite::{test_database, unavailable_database};
+
+    #[tokio::test]
+    async fn test_error_bodies_carry_a_code() {
+        let (_dir, db) = test_database().await;
+        let app = create_routes().with_state(test_state(db));
+
+        let response = app.oneshot(Request::builder().uri("/sessions/search?q=").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "INVALID_QUERY");
+        assert_eq!(body["success"], false);
+
+        // Successful responses keep their previous shape
+        let success = serde_json::to_value(ApiResponse::success(1)).unwrap();
+        assert!(success.get("code").is_none());
+    }
+
+    async fn health(db: Arc<dyn Database>, uri: &str) -> (StatusCode, serde_json::Value) {
+        let app = public_routes().with_state(test_state(db));
+        let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_health_pings_the_database() {
+        let (_dir, db) = test_database().await;
+        let (status, body) = health(db, "/health").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["status"], "healthy");
+        assert_eq!(body["data"]["version"], env!("CARGO_PKG_VERSION"));
+
+        let (_dir, db) = unavailable_database().await;
+        let (status, body) = health(db, "/health").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["data"]["status"], "unhealthy");
+        assert_eq!(body["data"]["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    async fn probe(state: AppState, uri: &str) -> StatusCode {
+        let app = Router::new().route("/livez", get(livez)).route("/readyz", get(readyz)).with_state(state);
+        app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_readyz_waits_for_the_ready_flag_and_a_working_database() {
+        let (_dir, db) = test_database().await;
+        let state = test_state(db);
+        assert_eq!(probe(state.clone(), "/livez").await, StatusCode::OK);
+        assert_eq!(probe(state.clone(), "/readyz").await, StatusCode::SERVICE_UNAVAILABLE);
+
+        state.mark_ready();
+        assert_eq!(probe(state.clone(), "/readyz").await, StatusCode::OK);
+
+        let (_dir, db) = unavailable_database().await;
+        let state = test_state(db);
+        state.mark_ready();
+        assert_eq!(probe(state.clone(), "/readyz").await, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(probe(state, "/livez").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_deep_health_reports_pool_size_and_row_counts() {
+        let (_dir, db) = test_database().await;
+        db.create_session("alice@example.com").await.unwrap();
+
+        let (status, body) = health(db, "/health/deep").await;
+        assert_eq!(status, StatusCode::OK);
+        let report = &body["data"];
+        assert_eq!(report["status"], "healthy");
+        assert_eq!(report["table_rows"]["sessions"], 1);
+        assert_eq!(report["table_rows"]["metrics"], 0);
+        assert!(report["database_size_bytes"].as_u64().unwrap() > 0);
+        assert!(report["pool"]["size"].as_u64().unwrap() >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_deep_health_is_unavailable_when_the_database_is() {
+        let (_dir, db) = unavailable_database().await;
+
+        let (status, body) = health(db, "/health/deep").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "DATABASE_UNAVAILABLE");
+        assert_eq!(body["data"]["status"], "unavailable");
+        assert!(body["data"]["table_rows"].is_null());
+        assert!(body["error"].as_str().unwrap().contains("connection"));
+    }
+}
@@ -0,0 +1,300 @@
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Json},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::otel::metrics::{cost_or_derived, EnhancedClaudeMetric};
+use crate::otel::{classify_event, classify_metric, CodeChangeType, EventType, MetricType};
+use crate::pricing::{PricingStore, PricingTable};
+use crate::privacy::Privacy;
+use crate::storage::{Database, LogRecord, MetricRecord};
+use super::analytics::{parse_time_range, tool_usage, AnalyticsQuery, ToolUsageStats};
+use super::sessions::SessionSummary;
+use super::{ApiResponse, ApiResult, AppState};
+
+// Rows per list section; the home page only shows a handful
+const RECENT_SESSIONS: u32 = 5;
+const RECENT_ERRORS: usize = 5;
+
+#[derive(Debug, Serialize)]
+pub struct Overview {
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub kpis: Option<OverviewKpis>,
+    /// Ranked like `/api/analytics/dashboard/tool-usage`; the overview loads no spans, so durations are unavailable
+    pub top_tools: Option<Vec<ToolUsageStats>>,
+    pub recent_sessions: Option<Vec<SessionSummary>>,
+    pub recent_errors: Option<Vec<RecentError>>,
+    /// Sections left out because their query missed the time budget
+    pub incomplete: Vec<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct OverviewKpis {
+    /// Sessions that reported metrics in the window, open or not
+    pub total_sessions: u64,
+    pub total_tokens: u64,
+    pub total_cost: f64,
+    pub lines_added: u64,
+    pub lines_removed: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecentError {
+    pub timestamp: DateTime<Utc>,
+    pub session_id: Option<Uuid>,
+    pub event: String,
+    pub error: String,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(get_overview))
+}
+
+// GET /api/overview - KPIs, top tools, recent sessions and errors in one payload
+async fn get_overview(
+    State(db): State<Arc<dyn Database>>,
+    State(config): State<Arc<Config>>,
+    State(pricing): State<Arc<PricingStore>>,
+    State(privacy): State<Privacy>,
+    Query(params): Query<AnalyticsQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let (start_time, end_time) = parse_time_range(&params)?;
+
+    // The three queries run concurrently, so one budget bounds the whole request
+    let budget = Duration::from_millis(config.overview_budget_ms);
+    let (metrics, logs, sessions) = tokio::join!(
+        tokio::time::timeout(budget, db.get_metrics(Some(start_time), Some(end_time), None)),
+        tokio::time::timeout(budget, db.get_logs(Some(start_time), Some(end_time), None)),
+        tokio::time::timeout(budget, db.list_sessions(None, RECENT_SESSIONS, 0)),
+    );
+    let metrics = metrics.ok().transpose()?;
+    let logs = logs.ok().transpose()?;
+    let sessions = sessions.ok().transpose()?;
+
+    let mut incomplete = Vec::new();
+    if metrics.is_none() {
+        incomplete.push("kpis");
+    }
+    if logs.is_none() {
+        incomplete.extend(["top_tools", "recent_errors"]);
+    }
+    if sessions.is_none() {
+        incomplete.push("recent_sessions");
+    }
+    if !incomplete.is_empty() {
+        tracing::warn!("Overview exceeded its {}ms budget; omitting {:?}", config.overview_budget_ms, incomplete);
+    }
+
+    let overview = Overview {
+        start_time,
+        end_time,
+        kpis: metrics.as_deref().map(|metrics| kpis(metrics, &pricing.current())),
+        top_tools: logs.as_deref().map(|logs| tool_usage(logs, &[])),
+        recent_sessions: sessions.map(|sessions| {
+            sessions
+                .into_iter()
+                .map(|session| {
+                    let user_id = privacy.mask(&session.user_id);
+                    SessionSummary { user_id, ..SessionSummary::from(session) }
+                })
+                .collect()
+        }),
+        recent_errors: logs.as_deref().map(recent_errors),
+        incomplete,
+    };
+
+    Ok(Json(ApiResponse::success(overview)))
+}
+
+// Classified like the analytics KPIs, with cost derived from tokens when no cost was reported
+fn kpis(metrics: &[MetricRecord], pricing: &PricingTable) -> OverviewKpis {
+    let mut kpis = OverviewKpis::default();
+    let mut sessions = HashSet::new();
+    let mut usage = Vec::new();
+
+    for metric in metrics {
+        if let Some(session_id) = metric.session_id {
+            sessions.insert(session_id);
+        }
+        let metric_type = classify_metric(&metric.name, &metric.labels);
+        match metric_type {
+            MetricType::TokenUsage { .. } => kpis.total_tokens += metric.value as u64,
+            MetricType::LinesOfCode { change_type: CodeChangeType::Added } => kpis.lines_added += metric.value as u64,
+            MetricType::LinesOfCode { change_type: CodeChangeType::Removed } => kpis.lines_removed += metric.value as u64,
+            _ => {}
+        }
+        if matches!(metric_type, MetricType::TokenUsage { .. } | MetricType::CostUsage { .. }) {
+            usage.push(EnhancedClaudeMetric::from_basic_metric(
+                metric.name.clone(),
+                metric.value,
+                metric.timestamp,
+                metric.labels.clone(),
+            ));
+        }
+    }
+
+    kpis.total_cost = cost_or_derived(&usage, pricing);
+    kpis.total_sessions = sessions.len() as u64;
+    kpis
+}
+
+// Logs arrive newest first
+fn recent_errors(logs: &[LogRecord]) -> Vec<RecentError> {
+    logs.iter()
+        .filter_map(|log| {
+            let error = match classify_event(&log.message, &log.attributes) {
+                EventType::ApiRequestFailed { error_code } => error_code,
+                _ if log.level.eq_ignore_ascii_case("error") => log.attributes.get("error").cloned().unwrap_or_default(),
+                _ => return None,
+            };
+            Some(RecentError {
+                timestamp: log.timestamp,
+                session_id: log.session_id,
+                event: log.message.clone(),
+                error,
+            })
+        })
+        .take(RECENT_ERRORS)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use chrono::Duration;
+    use tower::ServiceExt;
+
+    use crate::{api::test_state, storage::sqlite::test_database};
+
+    fn event(session_id: Uuid, name: &str, attributes: &[(&str, &str)], minutes_ago: i64) -> LogRecord {
+        LogRecord {
+            id: Uuid::new_v4(),
+            session_id: Some(session_id),
+            timestamp: Utc::now() - Duration::minutes(minutes_ago),
+            level: "INFO".to_string(),
+            message: name.to_string(),
+            attributes: attributes.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn metric(session_id: Uuid, name: &str, value: f64, labels: &[(&str, &str)]) -> MetricRecord {
+        MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: Some(session_id),
+            name: name.to_string(),
+            timestamp: Utc::now() - Duration::minutes(30),
+            value,
+            labels: labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overview_populates_every_section() {
+        let (_dir, db) = test_database().await;
+        let session_id = db.create_session("alice@example.com").await.unwrap();
+
+        for record in [
+            metric(session_id, "claude_code.token.usage", 1200.0, &[("type", "input")]),
+            metric(session_id, "claude_code.token.usage", 300.0, &[("type", "output")]),
+            metric(session_id, "claude_code.cost.usage", 0.42, &[("model", "claude-sonnet-4")]),
+            metric(session_id, "claude_code.lines_of_code.count", 40.0, &[("type", "added")]),
+            metric(session_id, "claude_code.lines_of_code.count", 7.0, &[("type", "removed")]),
+        ] {
+            db.store_metric(&record).await.unwrap();
+        }
+        for log in [
+            event(session_id, "tool_result", &[("tool_name", "Bash"), ("success", "false")], 20),
+            event(session_id, "tool_result", &[("tool_name", "Bash"), ("success", "true")], 15),
+            // A failure reported only through its error
+            event(session_id, "tool_result", &[("tool_name", "Bash"), ("error", "exit code 1")], 12),
+            event(session_id, "tool_result", &[("tool_name", "Read"), ("success", "true")], 10),
+            event(session_id, "api_request_failed", &[("error_code", "overloaded_error")], 5),
+        ] {
+            db.store_log(&log).await.unwrap();
+        }
+
+        let mut state = test_state(db);
+        state.privacy = Privacy::new(true, Some("pepper"));
+        let app = routes().with_state(state);
+        let response = app
+            .oneshot(Request::builder().uri("/?range=24h").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let overview: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let overview = &overview["data"];
+
+        assert_eq!(overview["incomplete"], serde_json::json!([]));
+
+        let kpis = &overview["kpis"];
+        assert_eq!(kpis["total_sessions"], 1);
+        assert_eq!(kpis["total_tokens"], 1500);
+        assert_eq!(kpis["total_cost"], 0.42);
+        assert_eq!(kpis["lines_added"], 40);
+        assert_eq!(kpis["lines_removed"], 7);
+
+        let tools = overview["top_tools"].as_array().unwrap();
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[0]["tool_name"], "Bash");
+        assert_eq!(tools[0]["usage_count"], 3);
+        assert!((tools[0]["success_rate"].as_f64().unwrap() - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(tools[0]["duration_available"], false);
+        assert_eq!(tools[1]["tool_name"], "Read");
+
+        let sessions = overview["recent_sessions"].as_array().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0]["id"], session_id.to_string());
+        assert_eq!(sessions[0]["user_id"], Privacy::new(true, Some("pepper")).mask("alice@example.com"));
+
+        let errors = overview["recent_errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["error"], "overloaded_error");
+        assert_eq!(errors[0]["session_id"], session_id.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_kpis_classify_change_type_and_derive_cost_from_tokens() {
+        let (_dir, db) = test_database().await;
+        let session_id = db.create_session("alice@example.com").await.unwrap();
+
+        // No cost metric, so the cost comes from the tokens at claude-sonnet-4's default prices
+        for record in [
+            metric(session_id, "claude_code.token.usage", 1_000_000.0, &[("token_type", "input"), ("model", "claude-sonnet-4")]),
+            metric(session_id, "claude_code.token.usage", 100_000.0, &[("token_type", "output"), ("model", "claude-sonnet-4")]),
+            metric(session_id, "claude_code.lines_of_code.count", 40.0, &[("change_type", "added")]),
+            metric(session_id, "claude_code.lines_of_code.count", 7.0, &[("change_type", "removed")]),
+        ] {
+            db.store_metric(&record).await.unwrap();
+        }
+
+        let app = routes().with_state(test_state(db));
+        let response = app
+            .oneshot(Request::builder().uri("/?range=24h").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let overview: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let kpis = &overview["data"]["kpis"];
+
+        assert_eq!(kpis["total_tokens"], 1_100_000);
+        assert!((kpis["total_cost"].as_f64().unwrap() - 4.5).abs() < 1e-9);
+        assert_eq!(kpis["lines_added"], 40);
+        assert_eq!(kpis["lines_removed"], 7);
+    }
+}
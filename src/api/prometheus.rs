@@ -0,0 +1,252 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+use std::{collections::BTreeMap, fmt::Write};
+
+use super::{ApiResult, AppState};
+use crate::stats::{HttpStats, LATENCY_BUCKETS};
+use crate::storage::SessionFilter;
+
+/// Content type for the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricKind {
+    /// Claude Code names its cumulative metrics `*.count` and `*.usage`; the rest are gauges
+    pub fn for_name(name: &str) -> Self {
+        if name.ends_with(".count") || name.ends_with(".usage") {
+            MetricKind::Counter
+        } else {
+            MetricKind::Gauge
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+/// Builds a text-format exposition, emitting one `# TYPE` line per family
+#[derive(Default)]
+pub struct PrometheusWriter {
+    output: String,
+    current_family: Option<String>,
+}
+
+impl PrometheusWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn help(&mut self, name: &str, help: &str) {
+        let _ = writeln!(self.output, "# HELP {} {}", sanitize_metric_name(name), help);
+    }
+
+    pub fn sample(&mut self, name: &str, kind: MetricKind, labels: &BTreeMap<String, String>, value: f64) {
+        let name = sanitize_metric_name(name);
+        self.family(&name, kind);
+        self.line(&name, labels, value);
+    }
+
+    /// Cumulative `_bucket` series plus `_sum` and `_count` for one label set.
+    /// `buckets` are (upper bound, non-cumulative count) pairs in ascending order.
+    pub fn histogram(&mut self, name: &str, labels: &BTreeMap<String, String>, buckets: &[(f64, u64)], sum: f64, count: u64) {
+        let name = sanitize_metric_name(name);
+        self.family(&name, MetricKind::Histogram);
+
+        let bucket_name = format!("{}_bucket", name);
+        let mut cumulative = 0;
+        for (le, bucket_count) in buckets {
+            cumulative += bucket_count;
+            let mut bucket_labels = labels.clone();
+            bucket_labels.insert("le".to_string(), format_value(*le));
+            self.line(&bucket_name, &bucket_labels, cumulative as f64);
+        }
+        let mut inf_labels = labels.clone();
+        inf_labels.insert("le".to_string(), "+Inf".to_string());
+        self.line(&bucket_name, &inf_labels, count as f64);
+
+        self.line(&format!("{}_sum", name), labels, sum);
+        self.line(&format!("{}_count", name), labels, count as f64);
+    }
+
+    fn family(&mut self, name: &str, kind: MetricKind) {
+        if self.current_family.as_deref() != Some(name) {
+            let _ = writeln!(self.output, "# TYPE {} {}", name, kind.as_str());
+            self.current_family = Some(name.to_string());
+        }
+    }
+
+    fn line(&mut self, name: &str, labels: &BTreeMap<String, String>, value: f64) {
+        self.output.push_str(name);
+        if !labels.is_empty() {
+            let pairs: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", sanitize_label_name(k), escape_label_value(v)))
+                .collect();
+            let _ = write!(self.output, "{{{}}}", pairs.join(","));
+        }
+        let _ = writeln!(self.output, " {}", format_value(value));
+    }
+
+    pub fn finish(self) -> String {
+        self.output
+    }
+}
+
+// GET /metrics - Self-monitoring counters for Prometheus scraping
+pub async fn get_self_metrics(State(state): State<AppState>) -> ApiResult<impl IntoResponse> {
+    let no_labels = BTreeMap::new();
+    let counters = [
+        ("claude_lens_metrics_ingested_total", "Metric data points stored", state.stats.metrics_ingested()),
+        ("claude_lens_logs_ingested_total", "Log records stored", state.stats.logs_ingested()),
+        ("claude_lens_traces_ingested_total", "Spans stored", state.stats.traces_ingested()),
+        ("claude_lens_ingestion_errors_total", "Records that failed to parse or store", state.stats.ingestion_errors()),
+        ("claude_lens_ingest_queue_shed_total", "Records dropped because the ingestion queue was full", state.stats.records_shed()),
+        ("claude_lens_metrics_deduplicated_total", "Metric data points skipped as already stored", state.stats.metrics_deduplicated()),
+    ];
+
+    let mut writer = PrometheusWriter::new();
+    for (name, help, value) in counters {
+        writer.help(name, help);
+        writer.sample(name, MetricKind::Counter, &no_labels, value as f64);
+    }
+
+    if let Some(queue) = &state.ingest_queue {
+        writer.help("claude_lens_ingest_queue_depth", "Parsed exports waiting to be stored");
+        writer.sample("claude_lens_ingest_queue_depth", MetricKind::Gauge, &no_labels, queue.depth() as f64);
+    }
+
+    let sessions = state.db.count_sessions(&SessionFilter::default()).await?;
+    writer.help("claude_lens_sessions", "Sessions currently stored");
+    writer.sample("claude_lens_sessions", MetricKind::Gauge, &no_labels, sessions as f64);
+
+    write_http_metrics(&mut writer, &state.http_stats);
+
+    Ok(([(header::CONTENT_TYPE, CONTENT_TYPE)], writer.finish()))
+}
+
+fn write_http_metrics(writer: &mut PrometheusWriter, http_stats: &HttpStats) {
+    let routes = http_stats.snapshot();
+    let route_labels = |route: &str, class: &str| {
+        BTreeMap::from([
+            ("route".to_string(), route.to_string()),
+            ("status_class".to_string(), class.to_string()),
+        ])
+    };
+
+    writer.help("claude_lens_http_requests_total", "HTTP requests by matched route and status class");
+    for (route, class, stats) in &routes {
+        writer.sample("claude_lens_http_requests_total", MetricKind::Counter, &route_labels(route, class), stats.requests as f64);
+    }
+
+    writer.help("claude_lens_http_request_errors_total", "HTTP requests answered with a 5xx status");
+    for (route, class, stats) in &routes {
+        writer.sample("claude_lens_http_request_errors_total", MetricKind::Counter, &route_labels(route, class), stats.errors as f64);
+    }
+
+    writer.help("claude_lens_http_request_duration_seconds", "HTTP request latency");
+    for (route, class, stats) in &routes {
+        let buckets: Vec<(f64, u64)> = LATENCY_BUCKETS.iter().copied().zip(stats.latency_buckets).collect();
+        writer.histogram(
+            "claude_lens_http_request_duration_seconds",
+            &route_labels(route, class),
+            &buckets,
+            stats.latency_sum,
+            stats.requests,
+        );
+    }
+}
+
+/// `claude_code.token.usage` -> `claude_code_token_usage`
+pub fn sanitize_metric_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// `user.email` -> `user_email`
+pub fn sanitize_label_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_names() {
+        assert_eq!(sanitize_metric_name("claude_code.token.usage"), "claude_code_token_usage");
+        assert_eq!(sanitize_label_name("user.email"), "user_email");
+        assert_eq!(sanitize_label_name("1st"), "_1st");
+    }
+
+    #[test]
+    fn test_writer_emits_type_once_per_family() {
+        let mut writer = PrometheusWriter::new();
+        let mut labels = BTreeMap::new();
+        labels.insert("type".to_string(), "in\"put".to_string());
+        writer.sample("claude_code.token.usage", MetricKind::Counter, &labels, 10.0);
+        labels.insert("type".to_string(), "output".to_string());
+        writer.sample("claude_code.token.usage", MetricKind::Counter, &labels, 2.5);
+        writer.sample("claude_code.active_time", MetricKind::Gauge, &BTreeMap::new(), 1.0);
+
+        let output = writer.finish();
+        assert_eq!(output.matches("# TYPE claude_code_token_usage counter").count(), 1);
+        assert!(output.contains("claude_code_token_usage{type=\"in\\\"put\"} 10\n"));
+        assert!(output.contains("claude_code_token_usage{type=\"output\"} 2.5\n"));
+        assert!(output.contains("# TYPE claude_code_active_time gauge\nclaude_code_active_time 1\n"));
+    }
+
+    #[test]
+    fn test_writer_emits_cumulative_histogram() {
+        let mut writer = PrometheusWriter::new();
+        let labels = BTreeMap::from([("route".to_string(), "/api/health".to_string())]);
+        writer.histogram("latency_seconds", &labels, &[(0.1, 2), (1.0, 1)], 0.75, 4);
+
+        let output = writer.finish();
+        assert!(output.starts_with("# TYPE latency_seconds histogram\n"));
+        assert!(output.contains("latency_seconds_bucket{le=\"0.1\",route=\"/api/health\"} 2\n"));
+        assert!(output.contains("latency_seconds_bucket{le=\"1\",route=\"/api/health\"} 3\n"));
+        assert!(output.contains("latency_seconds_bucket{le=\"+Inf\",route=\"/api/health\"} 4\n"));
+        assert!(output.contains("latency_seconds_sum{route=\"/api/health\"} 0.75\n"));
+        assert!(output.contains("latency_seconds_count{route=\"/api/health\"} 4\n"));
+    }
+}
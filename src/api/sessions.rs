@@ -1,7 +1,8 @@
 use axum::{
     extract::{Path, Query, State},
+    http::StatusCode,
     response::{IntoResponse, Json},
-    routing::get,
+    routing::{get, put},
     Router,
 };
 use chrono::{DateTime, Utc};
@@ -9,16 +10,33 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::storage::Database;
-use super::{ApiError, ApiResponse, ApiResult, MetricPoint};
+use crate::config::Config;
+use crate::storage::{Database, PurgeCounts, SessionEndReason, SessionFilter, SessionRecord, SessionSort, SessionSortKey, SessionState, SortOrder};
+use super::logs::LogEntry;
+use super::{ApiError, ApiResponse, ApiResult, AppState};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SessionsQuery {
-    pub start_time: Option<DateTime<Utc>>,
+    pub start_time: Option<DateTime<Utc>>, // sessions started within [start_time, end_time]
     pub end_time: Option<DateTime<Utc>>,
     pub user_id: Option<String>,
+    pub min_command_count: Option<u64>,
+    pub status: Option<String>, // "active", "completed"
     pub limit: Option<u32>,
     pub offset: Option<u32>,
+    pub sort: Option<String>,  // "start_time", "duration", "command_count"
+    pub order: Option<String>, // "asc", "desc"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionSearchQuery {
+    pub q: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSessionRequest {
+    pub user_id: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -40,6 +58,64 @@ pub struct SessionData {
     pub status: SessionStatus,
 }
 
+/// A session without tool usage, as returned by search and close
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    pub id: Uuid,
+    pub user_id: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub duration_seconds: Option<u64>,
+    pub command_count: u64,
+    pub status: SessionStatus,
+}
+
+impl From<SessionRecord> for SessionSummary {
+    fn from(s: SessionRecord) -> Self {
+        Self {
+            duration_seconds: duration_seconds(&s),
+            status: SessionStatus::of(&s),
+            id: s.id,
+            user_id: s.user_id,
+            start_time: s.start_time,
+            end_time: s.end_time,
+            command_count: s.command_count,
+        }
+    }
+}
+
+// None while the session is open; an end recorded before the start (a clock moved back)
+// counts as 0 rather than wrapping around
+fn duration_seconds(session: &SessionRecord) -> Option<u64> {
+    session.end_time.map(|end_time| u64::try_from((end_time - session.start_time).num_seconds()).unwrap_or(0))
+}
+
+/// Rows removed by a deletion request
+#[derive(Debug, Serialize)]
+pub struct DeletedRows {
+    pub sessions: u64,
+    pub metrics: u64,
+    pub logs: u64,
+    pub traces: u64,
+    /// Only reported when deleting a user's data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budgets: Option<u64>,
+    pub total: u64,
+}
+
+impl From<PurgeCounts> for DeletedRows {
+    fn from(counts: PurgeCounts) -> Self {
+        Self {
+            sessions: counts.sessions,
+            metrics: counts.metrics,
+            logs: counts.logs,
+            traces: counts.traces,
+            budgets: None,
+            total: counts.total(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ToolUsage {
     pub tool_name: String,
@@ -53,6 +129,17 @@ pub enum SessionStatus {
     Terminated,
 }
 
+impl SessionStatus {
+    // Sessions ended by the idle reaper are Terminated, explicitly closed ones Completed
+    fn of(session: &SessionRecord) -> Self {
+        match (session.end_time, session.end_reason) {
+            (None, _) => SessionStatus::Active,
+            (Some(_), Some(SessionEndReason::Terminated)) => SessionStatus::Terminated,
+            (Some(_), _) => SessionStatus::Completed,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct PageInfo {
     pub has_next: bool,
@@ -61,43 +148,51 @@ pub struct PageInfo {
     pub total_pages: u32,
 }
 
-pub fn routes() -> Router<Arc<dyn Database>> {
+impl PageInfo {
+    pub fn new(offset: u32, limit: u32, total_count: u64) -> Self {
+        Self {
+            has_next: (offset as u64 + limit as u64) < total_count,
+            has_prev: offset > 0,
+            current_page: (offset / limit) + 1,
+            total_pages: total_count.div_ceil(limit as u64) as u32,
+        }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
     Router::new()
-        .route("/", get(get_sessions))
-        .route("/:id", get(get_session_by_id))
-        .route("/:id/metrics", get(get_session_metrics))
+        .route("/", get(get_sessions).post(create_session))
+        .route("/search", get(search_sessions))
+        .route("/:id", get(get_session_by_id).delete(delete_session))
+        .route("/:id/metrics", get(get_session_summary))
+        .route("/:id/events", get(get_session_events))
+        .route("/:id/summary", get(get_session_summary))
+        .route("/:id/close", put(close_session))
 }
 
 // GET /api/sessions - List sessions with pagination
 async fn get_sessions(
     State(db): State<Arc<dyn Database>>,
+    State(config): State<Arc<Config>>,
     Query(params): Query<SessionsQuery>,
 ) -> ApiResult<impl IntoResponse> {
-    let limit = params.limit.unwrap_or(20).min(100); // Max 100 per page
-    let offset = params.offset.unwrap_or(0);
+    let (limit, offset) = config.clamp_pagination(params.limit, params.offset);
+    let sort = parse_sort(params.sort.as_deref(), params.order.as_deref())?;
+    let filter = parse_filter(&params)?;
 
     // Get sessions from database
-    let sessions_db = db.list_sessions(
-        params.user_id.as_deref(),
-        limit,
-        offset
-    ).await?;
+    let (sessions_db, total_count) = tokio::try_join!(
+        db.list_sessions_sorted(&filter, sort, limit, offset),
+        db.count_sessions(&filter),
+    )?;
 
     // Convert to API format
     let sessions: Vec<SessionData> = sessions_db
         .into_iter()
         .map(|s| {
-            let duration_seconds = if let Some(end_time) = s.end_time {
-                Some((end_time - s.start_time).num_seconds() as u64)
-            } else {
-                None
-            };
+            let duration_seconds = duration_seconds(&s);
 
-            let status = if s.end_time.is_some() {
-                SessionStatus::Completed
-            } else {
-                SessionStatus::Active
-            };
+            let status = SessionStatus::of(&s);
 
             // Mock tool usage (TODO: implement real tool tracking)
             let tool_usage = vec![
@@ -119,27 +214,108 @@ async fn get_sessions(
         })
         .collect();
 
-    // Calculate pagination info
-    let total_count = sessions.len() as u64; // TODO: get real total count
-    let current_page = (offset / limit) + 1;
-    let total_pages = (total_count + limit as u64 - 1) / limit as u64;
-
-    let page_info = PageInfo {
-        has_next: offset + limit < total_count as u32,
-        has_prev: offset > 0,
-        current_page,
-        total_pages: total_pages as u32,
-    };
-
     let response = SessionsResponse {
         sessions,
         total_count,
-        page_info,
+        page_info: PageInfo::new(offset, limit, total_count),
     };
 
     Ok(Json(ApiResponse::success(response)))
 }
 
+// POST /api/sessions - Start a session for a user
+async fn create_session(
+    State(db): State<Arc<dyn Database>>,
+    Json(request): Json<CreateSessionRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let user_id = request.user_id.trim();
+    if user_id.is_empty() {
+        return Err(ApiError::InvalidQuery("user_id must not be empty".to_string()));
+    }
+
+    let id = db.create_session(user_id).await?;
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(id))))
+}
+
+// PUT /api/sessions/:id/close - End a session now; closing twice keeps the first end time
+async fn close_session(
+    State(db): State<Arc<dyn Database>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<impl IntoResponse> {
+    let mut session = db.get_session(id).await?
+        .ok_or(ApiError::NotFound)?;
+
+    if session.end_time.is_none() {
+        let now = Utc::now();
+        db.update_session(id, Some(now)).await?;
+        session.end_time = Some(now);
+    }
+
+    Ok(Json(ApiResponse::success(SessionSummary::from(session))))
+}
+
+// GET /api/sessions/search?q= - Sessions matching a user id or a log/metric attribute value
+async fn search_sessions(
+    State(db): State<Arc<dyn Database>>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<SessionSearchQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let query = params.q.as_deref().map(str::trim).unwrap_or("");
+    if query.is_empty() {
+        return Err(ApiError::InvalidQuery("Search query `q` must not be empty".to_string()));
+    }
+    let (limit, _) = config.clamp_pagination(params.limit, None);
+
+    let sessions: Vec<SessionSummary> = db
+        .search_sessions(query, limit)
+        .await?
+        .into_iter()
+        .map(SessionSummary::from)
+        .collect();
+
+    Ok(Json(ApiResponse::success(sessions)))
+}
+
+fn parse_sort(sort: Option<&str>, order: Option<&str>) -> ApiResult<SessionSort> {
+    let default = SessionSort::default();
+
+    let key = match sort {
+        Some(sort) => SessionSortKey::parse(sort)
+            .ok_or_else(|| ApiError::InvalidQuery(format!("Invalid sort key: {}", sort)))?,
+        None => default.key,
+    };
+
+    let order = match order {
+        Some(order) => SortOrder::parse(order)
+            .ok_or_else(|| ApiError::InvalidQuery(format!("Invalid sort order: {}", order)))?,
+        None => default.order,
+    };
+
+    Ok(SessionSort { key, order })
+}
+
+fn parse_filter(params: &SessionsQuery) -> ApiResult<SessionFilter> {
+    if let (Some(start_time), Some(end_time)) = (params.start_time, params.end_time) {
+        if start_time > end_time {
+            return Err(ApiError::InvalidQuery("start_time must not be after end_time".to_string()));
+        }
+    }
+
+    let status = match params.status.as_deref() {
+        Some(status) => Some(SessionState::parse(status)
+            .ok_or_else(|| ApiError::InvalidQuery(format!("Invalid status: {}", status)))?),
+        None => None,
+    };
+
+    Ok(SessionFilter {
+        user_id: params.user_id.clone(),
+        start_time: params.start_time,
+        end_time: params.end_time,
+        min_command_count: params.min_command_count,
+        status,
+    })
+}
+
 // GET /api/sessions/:id - Get session details
 async fn get_session_by_id(
     State(db): State<Arc<dyn Database>>,
@@ -149,17 +325,9 @@ async fn get_session_by_id(
     let session_db = db.get_session(id).await?
         .ok_or(ApiError::NotFound)?;
 
-    let duration_seconds = if let Some(end_time) = session_db.end_time {
-        Some((end_time - session_db.start_time).num_seconds() as u64)
-    } else {
-        None
-    };
+    let duration_seconds = duration_seconds(&session_db);
 
-    let status = if session_db.end_time.is_some() {
-        SessionStatus::Completed
-    } else {
-        SessionStatus::Active
-    };
+    let status = SessionStatus::of(&session_db);
 
     // Mock detailed tool usage for session
     let tool_usage = vec![
@@ -184,37 +352,418 @@ async fn get_session_by_id(
     Ok(Json(ApiResponse::success(session_data)))
 }
 
-// GET /api/sessions/:id/metrics - Get metrics for a specific session
-async fn get_session_metrics(
+// DELETE /api/sessions/:id - Remove a session with its metrics, logs and traces
+async fn delete_session(
     State(db): State<Arc<dyn Database>>,
     Path(id): Path<Uuid>,
 ) -> ApiResult<impl IntoResponse> {
-    // Verify session exists
-    let _session = db.get_session(id).await?
+    let counts = db.delete_session(id).await?
         .ok_or(ApiError::NotFound)?;
 
-    // Get metrics for this session
-    let metrics = db.get_metrics(None, None, None).await?;
-    
-    // Filter metrics that belong to this session (if session_id is tracked)
-    // For now, return empty since we don't have session linking implemented
-    let session_metrics: Vec<MetricPoint> = metrics
+    Ok(Json(ApiResponse::success(DeletedRows::from(counts))))
+}
+
+// GET /api/sessions/:id/events - The session's log events in chronological order
+async fn get_session_events(
+    State(db): State<Arc<dyn Database>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<impl IntoResponse> {
+    db.get_session(id).await?
+        .ok_or(ApiError::NotFound)?;
+
+    let events: Vec<LogEntry> = db
+        .get_logs_for_session(id)
+        .await?
         .into_iter()
-        .filter_map(|m| {
-            // TODO: Implement proper session-metric linking
-            // For now, return some mock data
-            if m.name.contains("session") {
-                Some(MetricPoint {
-                    timestamp: m.timestamp,
-                    name: m.name,
-                    value: m.value,
-                    labels: m.labels,
-                })
+        .map(LogEntry::from)
+        .collect();
+
+    Ok(Json(ApiResponse::success(events)))
+}
+
+// GET /api/sessions/:id/summary (and /metrics) - Token, cost, code and tool totals for one
+// session, kept up to date on ingest and read without scanning
+async fn get_session_summary(
+    State(db): State<Arc<dyn Database>>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<impl IntoResponse> {
+    let summary = db.get_session_rollup(id).await?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(Json(ApiResponse::success(summary)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use tower::ServiceExt;
+
+    use crate::{api::test_state, storage::sqlite::test_database};
+
+    #[test]
+    fn test_duration_of_a_session_ending_before_its_start_is_zero() {
+        let start = Utc::now();
+        let session = SessionRecord {
+            id: Uuid::new_v4(),
+            user_id: "dev@example.com".to_string(),
+            start_time: start,
+            end_time: Some(start - chrono::Duration::seconds(5)),
+            end_reason: None,
+            command_count: 0,
+            created_at: start,
+            updated_at: start,
+        };
+        assert_eq!(SessionSummary::from(session).duration_seconds, Some(0));
+    }
+
+    #[test]
+    fn test_parse_sort_defaults_to_newest_first() {
+        assert_eq!(parse_sort(None, None).unwrap(), SessionSort::default());
+    }
+
+    #[test]
+    fn test_parse_sort_accepts_each_key_and_direction() {
+        for (key, expected) in [
+            ("start_time", SessionSortKey::StartTime),
+            ("duration", SessionSortKey::Duration),
+            ("command_count", SessionSortKey::CommandCount),
+        ] {
+            for (order, expected_order) in [("asc", SortOrder::Asc), ("desc", SortOrder::Desc)] {
+                let sort = parse_sort(Some(key), Some(order)).unwrap();
+                assert_eq!(sort.key, expected);
+                assert_eq!(sort.order, expected_order);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_sort_rejects_unknown_values() {
+        assert!(matches!(parse_sort(Some("user_id; DROP TABLE sessions"), None), Err(ApiError::InvalidQuery(_))));
+        assert!(matches!(parse_sort(Some("start_time"), Some("sideways")), Err(ApiError::InvalidQuery(_))));
+    }
+
+    #[tokio::test]
+    async fn test_list_sessions_applies_each_filter() {
+        use chrono::{Duration, TimeZone};
+        use std::collections::HashMap;
+
+        let (_dir, db) = test_database().await;
+        let day = |d: u32| Utc.with_ymd_and_hms(2025, 3, d, 9, 0, 0).unwrap();
+        let mut ids = HashMap::new();
+        for (name, user, start, commands, ended) in [
+            ("a", "alice", 1, 5, true),
+            ("b", "alice", 2, 1, false),
+            ("c", "bob", 3, 8, true),
+            ("d", "bob", 4, 0, false),
+        ] {
+            let id = Uuid::new_v4();
+            db.upsert_session(id, user, day(start)).await.unwrap();
+            for _ in 0..commands {
+                db.increment_command_count(id).await.unwrap();
+            }
+            if ended {
+                db.update_session(id, Some(day(start) + Duration::hours(1))).await.unwrap();
+            }
+            ids.insert(id.to_string(), name);
+        }
+        let app = routes().with_state(test_state(db.clone()));
+        let list = |query: &str| {
+            let app = app.clone();
+            // Oldest first unless the case picks its own order
+            let uri = if query.contains("sort=") {
+                format!("/?{}", query)
             } else {
-                None
+                format!("/?sort=start_time&order=asc&{}", query)
+            };
+            let ids = &ids;
+            async move {
+                let response = app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                if status != StatusCode::OK {
+                    return (status, Vec::new(), 0);
+                }
+                let names: Vec<&str> = body["data"]["sessions"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|session| ids[session["id"].as_str().unwrap()])
+                    .collect();
+                (status, names, body["data"]["total_count"].as_u64().unwrap())
             }
-        })
-        .collect();
+        };
+
+        assert_eq!(list("").await, (StatusCode::OK, vec!["a", "b", "c", "d"], 4));
+        assert_eq!(list("user_id=alice").await, (StatusCode::OK, vec!["a", "b"], 2));
+        assert_eq!(list("status=active").await, (StatusCode::OK, vec!["b", "d"], 2));
+        assert_eq!(list("status=completed").await, (StatusCode::OK, vec!["a", "c"], 2));
+        assert_eq!(list("min_command_count=5").await, (StatusCode::OK, vec!["a", "c"], 2));
+        assert_eq!(list("start_time=2025-03-02T00:00:00Z").await, (StatusCode::OK, vec!["b", "c", "d"], 3));
+        assert_eq!(list("end_time=2025-03-02T23:59:59Z").await, (StatusCode::OK, vec!["a", "b"], 2));
+        assert_eq!(
+            list("start_time=2025-03-02T00:00:00Z&end_time=2025-03-03T23:59:59Z").await,
+            (StatusCode::OK, vec!["b", "c"], 2)
+        );
+        assert_eq!(list("user_id=bob&status=completed&min_command_count=1").await, (StatusCode::OK, vec!["c"], 1));
+        assert_eq!(list("user_id=alice&status=active&min_command_count=2").await, (StatusCode::OK, vec![], 0));
+        assert_eq!(list("status=completed&sort=command_count&order=desc").await.1, vec!["c", "a"]);
+        assert_eq!(list("status=done").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            list("start_time=2025-03-03T00:00:00Z&end_time=2025-03-02T00:00:00Z").await.0,
+            StatusCode::BAD_REQUEST
+        );
+
+        // Reaping the open sessions leaves explicitly closed ones Completed
+        assert_eq!(list("status=terminated").await, (StatusCode::OK, vec![], 0));
+        assert_eq!(db.end_idle_sessions(day(5)).await.unwrap(), 2);
+        assert_eq!(list("status=terminated").await, (StatusCode::OK, vec!["b", "d"], 2));
+        assert_eq!(list("status=completed").await, (StatusCode::OK, vec!["a", "c"], 2));
+        assert_eq!(list("status=active").await, (StatusCode::OK, vec![], 0));
+    }
+
+    #[tokio::test]
+    async fn test_search_requires_a_query() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db));
+
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/search?q=%20").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .oneshot(Request::builder().uri("/search?q=Bash").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_session_lifecycle_from_active_to_completed() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db));
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"user_id": "dev@example.com"}"#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let id = body["data"].as_str().unwrap().to_string();
+
+        let response = app.clone()
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let sessions = body["data"]["sessions"].as_array().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0]["id"], id.as_str());
+        assert_eq!(sessions[0]["status"], "Active");
+
+        let close = |uri: String| Request::builder().method("PUT").uri(uri).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(close(format!("/{}/close", id))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["status"], "Completed");
+        assert!(body["data"]["end_time"].is_string());
 
-    Ok(Json(ApiResponse::success(session_metrics)))
-}
\ No newline at end of file
+        let response = app
+            .oneshot(close(format!("/{}/close", Uuid::new_v4())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_delete_session_removes_its_metrics() {
+        use crate::storage::MetricRecord;
+
+        let (_dir, db) = test_database().await;
+        let session_id = db.create_session("dev@example.com").await.unwrap();
+        db.store_metric(&MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: Some(session_id),
+            name: "claude_code.cost.usage".to_string(),
+            timestamp: Utc::now(),
+            value: 0.5,
+            labels: Default::default(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }).await.unwrap();
+        let app = routes().with_state(test_state(db.clone()));
+        let delete = |id: Uuid| Request::builder().method("DELETE").uri(format!("/{}", id)).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(delete(session_id)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"], serde_json::json!({"sessions": 1, "metrics": 1, "logs": 0, "traces": 0, "total": 2}));
+        assert!(db.get_session(session_id).await.unwrap().is_none());
+        assert!(db.get_metrics(None, None, None).await.unwrap().is_empty());
+
+        let response = app.oneshot(delete(session_id)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_events_come_back_in_chronological_order() {
+        use crate::storage::LogRecord;
+        use chrono::Duration;
+
+        let (_dir, db) = test_database().await;
+        let session_id = db.create_session("dev@example.com").await.unwrap();
+        let other_session = db.create_session("other@example.com").await.unwrap();
+        let start = Utc::now() - Duration::minutes(10);
+        // Stored out of order, as a batched exporter may deliver them
+        for (session, offset, message, tool) in [
+            (session_id, 2, "tool_result", Some("Bash")),
+            (session_id, 0, "user_prompt_submitted", None),
+            (other_session, 1, "user_prompt_submitted", None),
+            (session_id, 3, "api_request", None),
+            (session_id, 1, "tool_result", Some("Read")),
+        ] {
+            db.store_log(&LogRecord {
+                id: Uuid::new_v4(),
+                session_id: Some(session),
+                timestamp: start + Duration::seconds(offset),
+                level: "INFO".to_string(),
+                message: message.to_string(),
+                attributes: tool.map(|tool| [("tool_name".to_string(), tool.to_string())].into()).unwrap_or_default(),
+                service: None,
+                created_at: Utc::now(),
+            }).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let response = app.clone()
+            .oneshot(Request::builder().uri(format!("/{}/events", session_id)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let events = body["data"].as_array().unwrap();
+        let types: Vec<&str> = events.iter().map(|event| event["event_type"].as_str().unwrap()).collect();
+        assert_eq!(types, ["user_prompt", "tool_result", "tool_result", "api_request"]);
+        assert_eq!(events[1]["attributes"]["tool_name"], "Read");
+        assert_eq!(events[2]["attributes"]["tool_name"], "Bash");
+
+        let response = app
+            .oneshot(Request::builder().uri(format!("/{}/events", Uuid::new_v4())).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_summary_totals_match_stored_rows() {
+        use crate::storage::{LogRecord, MetricRecord};
+        use std::collections::HashMap;
+
+        let (_dir, db) = test_database().await;
+        let session_id = db.create_session("dev@example.com").await.unwrap();
+        let other_session = db.create_session("other@example.com").await.unwrap();
+        let attrs = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+
+        for (session, name, value, labels) in [
+            (session_id, "claude_code.token.usage", 1200.0, attrs(&[("type", "input"), ("model", "claude-sonnet-4")])),
+            (session_id, "claude_code.token.usage", 800.0, attrs(&[("type", "input"), ("model", "claude-opus-4")])),
+            (session_id, "claude_code.token.usage", 300.0, attrs(&[("type", "output")])),
+            (session_id, "claude_code.token.usage", 50.0, attrs(&[("type", "cache_read")])),
+            (session_id, "claude_code.cost.usage", 0.25, attrs(&[("model", "claude-sonnet-4")])),
+            (session_id, "claude_code.cost.usage", 0.5, attrs(&[("model", "claude-opus-4")])),
+            (session_id, "claude_code.lines_of_code.count", 40.0, attrs(&[("type", "added")])),
+            (session_id, "claude_code.lines_of_code.count", 7.0, attrs(&[("type", "removed")])),
+            (session_id, "claude_code.commit.count", 2.0, attrs(&[])),
+            (other_session, "claude_code.token.usage", 9999.0, attrs(&[("type", "input")])),
+        ] {
+            db.store_metric(&MetricRecord {
+                id: Uuid::new_v4(),
+                session_id: Some(session),
+                name: name.to_string(),
+                timestamp: Utc::now(),
+                value,
+                labels,
+                unit: None,
+                description: None,
+                service: None,
+                created_at: Utc::now(),
+            }).await.unwrap();
+        }
+        for (session, message, attributes) in [
+            (session_id, "tool_result", attrs(&[("tool_name", "Bash")])),
+            (session_id, "tool_result", attrs(&[("tool_name", "Bash"), ("success", "false")])),
+            (session_id, "tool_result", attrs(&[("tool_name", "Read")])),
+            (session_id, "api_request", attrs(&[("model", "claude-sonnet-4")])),
+            (session_id, "api_request_failed", attrs(&[("error_code", "overloaded_error")])),
+            (session_id, "user_prompt_submitted", attrs(&[])),
+            (other_session, "tool_result", attrs(&[("tool_name", "Edit")])),
+        ] {
+            db.store_log(&LogRecord {
+                id: Uuid::new_v4(),
+                session_id: Some(session),
+                timestamp: Utc::now(),
+                level: "INFO".to_string(),
+                message: message.to_string(),
+                attributes,
+                service: None,
+                created_at: Utc::now(),
+            }).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+
+        let response = app.clone()
+            .oneshot(Request::builder().uri(format!("/{}/summary", session_id)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let summary = &body["data"];
+
+        assert_eq!(summary["session_id"], session_id.to_string());
+        assert_eq!(summary["total_tokens_input"], 2000);
+        assert_eq!(summary["total_tokens_output"], 300);
+        assert_eq!(summary["total_tokens_cache_read"], 50);
+        assert_eq!(summary["total_tokens_cache_creation"], 0);
+        assert_eq!(summary["total_cost"], 0.75);
+        assert_eq!(summary["lines_added"], 40);
+        assert_eq!(summary["lines_removed"], 7);
+        assert_eq!(summary["total_commits"], 2);
+        assert_eq!(summary["tool_usage"], serde_json::json!({"Bash": 2, "Read": 1}));
+        assert_eq!(summary["api_requests"], 1);
+        assert_eq!(summary["api_failures"], 1);
+
+        // The older per-session metrics route serves the same rollup
+        let response = app.clone()
+            .oneshot(Request::builder().uri(format!("/{}/metrics", session_id)).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let rollup: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(&rollup["data"], summary);
+
+        for uri in ["summary", "metrics"] {
+            let response = app.clone()
+                .oneshot(Request::builder().uri(format!("/{}/{}", Uuid::new_v4(), uri)).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Router,
+};
+use futures_util::stream::{self, Stream};
+use serde::Deserialize;
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::otel::receiver::MetricFeed;
+use super::{AppState, MetricPoint};
+
+#[derive(Debug, Deserialize)]
+pub struct StreamQuery {
+    pub metric_name: Option<String>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/metrics", get(stream_metrics))
+}
+
+// GET /api/stream/metrics - Server-Sent Events carrying each newly ingested metric as JSON
+async fn stream_metrics(
+    State(feed): State<MetricFeed>,
+    Query(params): Query<StreamQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let metrics = feed.subscribe();
+
+    let events = stream::unfold((metrics, params.metric_name), |(mut metrics, metric_name)| async move {
+        loop {
+            let event = match metrics.recv().await {
+                Ok(metric) if metric_name.as_ref().is_some_and(|name| *name != metric.name) => continue,
+                Ok(metric) => {
+                    let point = MetricPoint::from(metric);
+                    let Ok(event) = Event::default().event("metric").json_data(&point) else { continue };
+                    event
+                }
+                // Ingestion never waits on a slow client; it skips ahead and is told how far
+                Err(RecvError::Lagged(skipped)) => Event::default().comment(format!("dropped {}", skipped)),
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((Ok(event), (metrics, metric_name)));
+        }
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::{Body, BodyDataStream}, http::{header, Request, StatusCode}};
+    use chrono::Utc;
+    use futures_util::StreamExt;
+    use std::{collections::HashMap, time::Duration};
+    use tokio::sync::broadcast;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::{api::test_state, storage::{sqlite::test_database, MetricRecord}};
+
+    fn metric(name: &str, value: f64) -> MetricRecord {
+        MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            name: name.to_string(),
+            timestamp: Utc::now(),
+            value,
+            labels: HashMap::from([("model".to_string(), "sonnet".to_string())]),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    async fn subscribe(feed: MetricFeed, uri: &str) -> BodyDataStream {
+        let (_dir, db) = test_database().await;
+        let mut state = test_state(db);
+        state.metric_feed = feed;
+        let response = routes()
+            .with_state(state)
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+        response.into_body().into_data_stream()
+    }
+
+    async fn next_event(body: &mut BodyDataStream) -> String {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+            .await
+            .expect("no event received")
+            .unwrap()
+            .unwrap();
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_sends_matching_metrics_as_json() {
+        let feed = broadcast::channel(16).0;
+        let mut body = subscribe(feed.clone(), "/metrics?metric_name=claude_code.cost.usage").await;
+
+        feed.send(metric("claude_code.token.usage", 1200.0)).unwrap();
+        feed.send(metric("claude_code.cost.usage", 0.5)).unwrap();
+
+        let event = next_event(&mut body).await;
+        let mut lines = event.lines();
+        assert_eq!(lines.next(), Some("event: metric"));
+        let data = lines.next().unwrap().strip_prefix("data: ").unwrap();
+        let point: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(point["name"], "claude_code.cost.usage");
+        assert_eq!(point["value"], 0.5);
+        assert_eq!(point["labels"]["model"], "sonnet");
+    }
+
+    #[tokio::test]
+    async fn test_lagging_client_is_told_how_many_were_dropped() {
+        let feed = broadcast::channel(2).0;
+        let mut body = subscribe(feed.clone(), "/metrics").await;
+
+        for value in 0..5 {
+            feed.send(metric("claude_code.cost.usage", value as f64)).unwrap();
+        }
+
+        assert_eq!(next_event(&mut body).await, ": dropped 3\n\n");
+        // The client keeps receiving from the oldest metric still buffered
+        assert!(next_event(&mut body).await.contains("\"value\":3.0"));
+        assert!(next_event(&mut body).await.contains("\"value\":4.0"));
+    }
+}
@@ -0,0 +1,355 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Json},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::config::Config;
+use crate::storage::{Database, TraceRecord};
+use super::analytics::{parse_time_range, AnalyticsQuery};
+use super::{ApiError, ApiResponse, ApiResult, AppState};
+
+#[derive(Debug, Deserialize)]
+pub struct TracesQuery {
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: Option<DateTime<Utc>>,
+    pub range: Option<String>, // "1h", "24h", "7d", ...
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TraceListItem {
+    pub trace_id: String,
+    pub span_count: u64,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub root_name: Option<String>,
+    pub root_duration_ns: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TraceTree {
+    pub trace_id: String,
+    pub span_count: usize,
+    pub roots: Vec<SpanNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpanNode {
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    pub name: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub duration_ns: u64,
+    pub attributes: HashMap<String, String>,
+    /// True for the placeholders that hold spans whose parent was never received
+    /// or whose parent references form a cycle
+    pub synthetic: bool,
+    pub children: Vec<SpanNode>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_traces))
+        .route("/:trace_id", get(get_trace))
+}
+
+// GET /api/traces - Distinct traces in a time range with span counts and their root span
+async fn list_traces(
+    State(db): State<Arc<dyn Database>>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<TracesQuery>,
+) -> ApiResult<impl IntoResponse> {
+    let (limit, _) = config.clamp_pagination(params.limit, None);
+    let (start_time, end_time) = match params.range {
+        Some(range) => {
+            let (start_time, end_time) =
+                parse_time_range(&AnalyticsQuery { range: Some(range), ..Default::default() })?;
+            (Some(start_time), Some(end_time))
+        }
+        None => (params.start_time, params.end_time),
+    };
+
+    let traces: Vec<TraceListItem> = db
+        .list_traces(start_time, end_time, limit)
+        .await?
+        .into_iter()
+        .map(|t| TraceListItem {
+            trace_id: t.trace_id,
+            span_count: t.span_count,
+            start_time: t.start_time,
+            end_time: t.end_time,
+            root_name: t.root_name,
+            root_duration_ns: t.root_duration_ns,
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::success(traces)))
+}
+
+// GET /api/traces/:trace_id - All spans of a trace assembled into a tree
+async fn get_trace(
+    State(db): State<Arc<dyn Database>>,
+    Path(trace_id): Path<String>,
+) -> ApiResult<impl IntoResponse> {
+    let spans = db.get_traces(None, None, Some(&trace_id)).await?;
+    if spans.is_empty() {
+        return Err(ApiError::NotFound);
+    }
+
+    let tree = TraceTree {
+        trace_id,
+        span_count: spans.len(),
+        roots: build_span_tree(spans),
+    };
+
+    Ok(Json(ApiResponse::success(tree)))
+}
+
+// Spans without a parent become roots; spans whose parent is missing from the
+// trace are grouped under one synthetic root, and spans only reachable through a
+// parent cycle under another. Input order (by start time) is kept.
+fn build_span_tree(spans: Vec<TraceRecord>) -> Vec<SpanNode> {
+    let span_ids: HashSet<String> = spans.iter().map(|s| s.span_id.clone()).collect();
+    let mut children: HashMap<String, Vec<TraceRecord>> = HashMap::new();
+    let mut roots = Vec::new();
+    let mut orphans = Vec::new();
+
+    for span in spans {
+        match span.parent_span_id.as_deref() {
+            Some(parent) if span_ids.contains(parent) => {
+                children.entry(parent.to_string()).or_default().push(span)
+            }
+            Some(_) => orphans.push(span),
+            None => roots.push(span),
+        }
+    }
+
+    let mut tree: Vec<SpanNode> = roots.into_iter().map(|span| attach_children(span, &mut children)).collect();
+
+    if !orphans.is_empty() {
+        let nodes = orphans.into_iter().map(|span| attach_children(span, &mut children)).collect();
+        tree.push(synthetic_root("(missing parent)", nodes));
+    }
+
+    // Whatever is left was never reached from a root, so its parent links loop back on
+    // themselves. Cut each cycle at its earliest span, which then heads the rest of it.
+    let mut cycles = Vec::new();
+    while let Some(parent) = earliest_unattached(&children) {
+        let siblings = children.get_mut(&parent).unwrap();
+        let span = siblings.remove(0);
+        if siblings.is_empty() {
+            children.remove(&parent);
+        }
+        cycles.push(attach_children(span, &mut children));
+    }
+    if !cycles.is_empty() {
+        tree.push(synthetic_root("(parent cycle)", cycles));
+    }
+
+    tree
+}
+
+// Parent key of the unattached span that started first; each sibling list is already in start order
+fn earliest_unattached(children: &HashMap<String, Vec<TraceRecord>>) -> Option<String> {
+    children
+        .iter()
+        .filter_map(|(parent, spans)| spans.first().map(|span| (span.start_time, &span.span_id, parent)))
+        .min()
+        .map(|(_, _, parent)| parent.clone())
+}
+
+fn synthetic_root(name: &str, children: Vec<SpanNode>) -> SpanNode {
+    let start_time = children.iter().map(|s| s.start_time).min().unwrap();
+    let end_time = children.iter().map(|s| s.end_time).max().unwrap();
+    SpanNode {
+        span_id: String::new(),
+        parent_span_id: None,
+        name: name.to_string(),
+        start_time,
+        end_time,
+        duration_ns: (end_time - start_time).num_nanoseconds().unwrap_or(0).max(0) as u64,
+        attributes: HashMap::new(),
+        synthetic: true,
+        children,
+    }
+}
+
+fn attach_children(span: TraceRecord, children: &mut HashMap<String, Vec<TraceRecord>>) -> SpanNode {
+    let kids = children.remove(&span.span_id).unwrap_or_default();
+    SpanNode {
+        children: kids.into_iter().map(|child| attach_children(child, children)).collect(),
+        span_id: span.span_id,
+        parent_span_id: span.parent_span_id,
+        name: span.name,
+        start_time: span.start_time,
+        end_time: span.end_time,
+        duration_ns: span.duration_ns,
+        attributes: span.attributes,
+        synthetic: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use chrono::{Duration, TimeZone};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::{api::test_state, storage::sqlite::test_database};
+
+    fn span(span_id: &str, parent: Option<&str>, offset_ms: i64) -> TraceRecord {
+        let start_time = Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap() + Duration::milliseconds(offset_ms);
+        TraceRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            trace_id: "trace-1".to_string(),
+            span_id: span_id.to_string(),
+            parent_span_id: parent.map(str::to_string),
+            name: format!("span {}", span_id),
+            start_time,
+            end_time: start_time + Duration::milliseconds(10),
+            duration_ns: 10_000_000,
+            attributes: HashMap::from([("tool".to_string(), "Bash".to_string())]),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_build_span_tree_nests_children_and_collects_orphans() {
+        let spans = vec![
+            span("root", None, 0),
+            span("a", Some("root"), 1),
+            span("b", Some("root"), 2),
+            span("a1", Some("a"), 3),
+            span("lost", Some("never-received"), 4),
+            span("lost-child", Some("lost"), 5),
+        ];
+
+        let tree = build_span_tree(spans);
+
+        assert_eq!(tree.len(), 2);
+        let root = &tree[0];
+        assert_eq!(root.span_id, "root");
+        assert_eq!(root.children.iter().map(|c| c.span_id.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(root.children[0].children[0].span_id, "a1");
+        assert_eq!(root.children[0].children[0].duration_ns, 10_000_000);
+        assert_eq!(root.children[0].attributes["tool"], "Bash");
+
+        let synthetic = &tree[1];
+        assert!(synthetic.synthetic);
+        assert_eq!(synthetic.children.len(), 1);
+        assert_eq!(synthetic.children[0].span_id, "lost");
+        assert_eq!(synthetic.children[0].children[0].span_id, "lost-child");
+    }
+
+    #[test]
+    fn test_build_span_tree_breaks_parent_cycles() {
+        let spans = vec![
+            span("root", None, 0),
+            span("a", Some("c"), 1),
+            span("b", Some("a"), 2),
+            span("c", Some("b"), 3),
+            span("self", Some("self"), 4),
+        ];
+
+        let tree = build_span_tree(spans);
+
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree[0].span_id, "root");
+        let cycles = &tree[1];
+        assert!(cycles.synthetic);
+        assert_eq!(cycles.name, "(parent cycle)");
+        assert_eq!(cycles.children.len(), 2);
+        // The loop is cut at its earliest span
+        let a = &cycles.children[0];
+        assert_eq!(a.span_id, "a");
+        assert_eq!(a.children[0].span_id, "b");
+        assert_eq!(a.children[0].children[0].span_id, "c");
+        assert!(a.children[0].children[0].children.is_empty());
+        assert_eq!(cycles.children[1].span_id, "self");
+        assert!(cycles.children[1].children.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_trace_routes() {
+        let (_dir, db) = test_database().await;
+        for record in [span("root", None, 0), span("child", Some("root"), 1)] {
+            db.store_trace(&record).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get("/")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"][0]["trace_id"], "trace-1");
+        assert_eq!(body["data"][0]["span_count"], 2);
+
+        let response = app.clone().oneshot(get("/trace-1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["roots"][0]["children"][0]["name"], "span child");
+
+        let response = app.clone().oneshot(get("/unknown")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // The fixture spans are long past any relative range
+        let response = app.oneshot(get("/?range=24h")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_three_span_trace_tree_and_listing() {
+        let (_dir, db) = test_database().await;
+        let now = Utc::now();
+        for (span_id, parent, offset_ms) in [("root", None, 0), ("tool", Some("root"), 5), ("exec", Some("tool"), 6)] {
+            let mut record = span(span_id, parent, 0);
+            record.trace_id = "trace-2".to_string();
+            record.start_time = now - Duration::minutes(5) + Duration::milliseconds(offset_ms);
+            record.end_time = record.start_time + Duration::milliseconds(10);
+            db.store_trace(&record).await.unwrap();
+        }
+        let app = routes().with_state(test_state(db));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get("/?range=24h")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let traces = body["data"].as_array().unwrap();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0]["span_count"], 3);
+        assert_eq!(traces[0]["root_name"], "span root");
+        assert_eq!(traces[0]["root_duration_ns"], 10_000_000);
+
+        let response = app.oneshot(get("/trace-2")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let tree = &body["data"];
+        assert_eq!(tree["span_count"], 3);
+        let roots = tree["roots"].as_array().unwrap();
+        assert_eq!(roots.len(), 1);
+        assert_eq!(roots[0]["name"], "span root");
+        let tool = &roots[0]["children"][0];
+        assert_eq!(tool["name"], "span tool");
+        assert_eq!(tool["parent_span_id"], "root");
+        assert_eq!(tool["attributes"]["tool"], "Bash");
+        let exec = &tool["children"][0];
+        assert_eq!(exec["name"], "span exec");
+        assert_eq!(exec["duration_ns"], 10_000_000);
+        assert_eq!(exec["children"], serde_json::json!([]));
+    }
+}
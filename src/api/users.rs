@@ -0,0 +1,68 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Json},
+    routing::delete,
+    Router,
+};
+use std::sync::Arc;
+
+use crate::storage::Database;
+use super::{sessions::DeletedRows, ApiError, ApiResponse, ApiResult, AppState};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/:email/data", delete(delete_user_data))
+}
+
+// DELETE /api/users/:email/data - Remove every session of a user with its metrics, logs and traces,
+// the records without a session that name the user, and their budget
+async fn delete_user_data(
+    State(db): State<Arc<dyn Database>>,
+    Path(email): Path<String>,
+) -> ApiResult<impl IntoResponse> {
+    let email = email.trim();
+    if email.is_empty() {
+        return Err(ApiError::InvalidQuery("email must not be empty".to_string()));
+    }
+
+    // Sessions carry the user.email of their telemetry as user_id
+    let counts = db.delete_user_sessions(email).await?;
+    if counts.total() == 0 {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok(Json(ApiResponse::success(DeletedRows { budgets: Some(counts.budgets), ..DeletedRows::from(counts) })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use tower::ServiceExt;
+
+    use crate::{api::{create_routes, test_state}, storage::{sqlite::test_database, SessionFilter}};
+
+    fn delete_data(email: &str) -> Request<Body> {
+        Request::delete(format!("/users/{}/data", email)).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_data_removes_only_their_sessions() {
+        let (_dir, db) = test_database().await;
+        for user in ["alice@example.com", "alice@example.com", "bob@example.com"] {
+            db.create_session(user).await.unwrap();
+        }
+        db.set_budget("alice@example.com", 100.0).await.unwrap();
+        let app = create_routes().with_state(test_state(db.clone()));
+
+        let response = app.clone().oneshot(delete_data("alice@example.com")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["data"]["sessions"], 2);
+        assert_eq!(body["data"]["budgets"], 1);
+        assert_eq!(db.count_sessions(&SessionFilter::default()).await.unwrap(), 1);
+        assert!(db.get_budget("alice@example.com").await.unwrap().is_none());
+
+        let response = app.oneshot(delete_data("alice@example.com")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
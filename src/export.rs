@@ -0,0 +1,328 @@
+// Scheduled analytics snapshots uploaded to an S3-compatible bucket
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+use tracing::{info, warn};
+
+use crate::config::{ExportSchedule, S3ExportConfig};
+use crate::storage::{Database, DatabaseError};
+
+// Leave room for late exports before a closed period is snapshotted
+const EXPORT_GRACE: Duration = Duration::minutes(10);
+
+#[derive(Debug, Serialize)]
+pub struct AnalyticsSnapshot {
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub generated_at: DateTime<Utc>,
+    /// Distinct sessions that reported metrics in the period
+    pub sessions: usize,
+    /// Sum of every point per metric name
+    pub metric_totals: BTreeMap<String, f64>,
+    /// `claude_code.cost.usage` summed per model
+    pub cost_by_model: BTreeMap<String, f64>,
+}
+
+/// Summarise the points in `[start, end)`
+pub async fn build_snapshot(
+    db: &dyn Database,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<AnalyticsSnapshot, DatabaseError> {
+    let metrics = db.get_metrics(Some(start), Some(end), None).await?;
+
+    let mut sessions = HashSet::new();
+    let mut metric_totals = BTreeMap::new();
+    let mut cost_by_model = BTreeMap::new();
+    // get_metrics includes `end`; it belongs to the next period
+    for metric in metrics.iter().filter(|m| m.timestamp < end) {
+        if let Some(session_id) = metric.session_id {
+            sessions.insert(session_id);
+        }
+        *metric_totals.entry(metric.name.clone()).or_insert(0.0) += metric.value;
+        if metric.name == "claude_code.cost.usage" {
+            let model = metric.labels.get("model").cloned().unwrap_or_else(|| "unknown".to_string());
+            *cost_by_model.entry(model).or_insert(0.0) += metric.value;
+        }
+    }
+
+    Ok(AnalyticsSnapshot {
+        period_start: start,
+        period_end: end,
+        generated_at: Utc::now(),
+        sessions: sessions.len(),
+        metric_totals,
+        cost_by_model,
+    })
+}
+
+/// The most recent period that had fully closed at `now`
+pub fn last_closed_period(schedule: ExportSchedule, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let today = now.date_naive();
+    let (end, length) = match schedule {
+        ExportSchedule::Daily => (today, Duration::days(1)),
+        ExportSchedule::Weekly => {
+            let monday = today - Duration::days(today.weekday().num_days_from_monday().into());
+            (monday, Duration::weeks(1))
+        }
+    };
+    let end = end.and_time(NaiveTime::MIN).and_utc();
+    (end - length, end)
+}
+
+/// "<prefix>/daily/2025-03-01.json" or "<prefix>/weekly/2025-W09.json"
+pub fn object_key(prefix: &str, schedule: ExportSchedule, period_start: DateTime<Utc>) -> String {
+    let name = match schedule {
+        ExportSchedule::Daily => format!("daily/{}.json", period_start.format("%Y-%m-%d")),
+        ExportSchedule::Weekly => {
+            let week = period_start.iso_week();
+            format!("weekly/{}-W{:02}.json", week.year(), week.week())
+        }
+    };
+    match prefix.trim_matches('/') {
+        "" => name,
+        prefix => format!("{}/{}", prefix, name),
+    }
+}
+
+/// Snapshot the last closed period and upload it, returning the object key
+pub async fn export_once(
+    db: &dyn Database,
+    uploader: &S3Uploader,
+    now: DateTime<Utc>,
+) -> Result<String, ExportError> {
+    let schedule = uploader.config.schedule;
+    let (start, end) = last_closed_period(schedule, now);
+    let snapshot = build_snapshot(db, start, end).await?;
+    let body = serde_json::to_vec_pretty(&snapshot).map_err(|e| ExportError::Serialize(e.to_string()))?;
+
+    let key = object_key(&uploader.config.prefix, schedule, start);
+    uploader.put_object(&key, body, "application/json").await?;
+    Ok(key)
+}
+
+/// Export each period shortly after it closes
+pub fn spawn_scheduled_export(db: Arc<dyn Database>, config: S3ExportConfig) {
+    info!("Exporting {:?} analytics snapshots to s3://{}/{}", config.schedule, config.bucket, config.prefix);
+
+    tokio::spawn(async move {
+        let uploader = S3Uploader::new(config);
+        loop {
+            let now = Utc::now();
+            let (_, current_start) = last_closed_period(uploader.config.schedule, now);
+            let period = match uploader.config.schedule {
+                ExportSchedule::Daily => Duration::days(1),
+                ExportSchedule::Weekly => Duration::weeks(1),
+            };
+            let next_run = current_start + period + EXPORT_GRACE;
+            tokio::time::sleep((next_run - now).to_std().unwrap_or_default()).await;
+
+            match export_once(&*db, &uploader, Utc::now()).await {
+                Ok(key) => info!("Uploaded analytics snapshot to s3://{}/{}", uploader.config.bucket, key),
+                Err(e) => warn!("Analytics export failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Minimal path-style PUT client signed with AWS Signature Version 4
+pub struct S3Uploader {
+    client: reqwest::Client,
+    config: S3ExportConfig,
+}
+
+impl S3Uploader {
+    pub fn new(config: S3ExportConfig) -> Self {
+        Self { client: reqwest::Client::new(), config }
+    }
+
+    pub async fn put_object(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<(), ExportError> {
+        let url = format!(
+            "{}/{}/{}",
+            self.config.endpoint.trim_end_matches('/'),
+            uri_encode(&self.config.bucket),
+            uri_encode(key)
+        );
+        let url = reqwest::Url::parse(&url).map_err(|e| ExportError::Endpoint(e.to_string()))?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(ExportError::Endpoint(self.config.endpoint.clone())),
+        };
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            url.path(),
+            host,
+            payload_hash,
+            amz_date,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signing_key = [self.config.region.as_str(), "s3", "aws4_request"].iter().fold(
+            hmac_sha256(format!("AWS4{}", self.config.secret_access_key).as_bytes(), date.as_bytes()),
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        );
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key_id, scope, signed_headers, signature
+        );
+
+        let response = self
+            .client
+            .put(url)
+            .header("authorization", authorization)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("content-type", content_type)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| ExportError::Upload(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let detail = response.text().await.unwrap_or_default();
+            return Err(ExportError::Upload(format!("{}: {}", status, detail)));
+        }
+        Ok(())
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// S3 canonical URIs percent-encode everything but unreserved characters and '/'
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("Failed to build snapshot: {0}")]
+    Database(#[from] DatabaseError),
+    #[error("Failed to serialize snapshot: {0}")]
+    Serialize(String),
+    #[error("Invalid S3 endpoint: {0}")]
+    Endpoint(String),
+    #[error("Upload failed: {0}")]
+    Upload(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{sqlite::SqliteDatabase, MetricRecord};
+    use axum::{body::Bytes, extract::State, http::{HeaderMap, Uri}, routing::put, Router};
+    use chrono::TimeZone;
+    use std::{collections::HashMap, sync::Mutex};
+    use uuid::Uuid;
+
+    type Uploads = Arc<Mutex<Vec<(String, String, Bytes)>>>;
+
+    async fn capture_put(State(uploads): State<Uploads>, uri: Uri, headers: HeaderMap, body: Bytes) {
+        let authorization = headers.get("authorization").unwrap().to_str().unwrap().to_string();
+        uploads.lock().unwrap().push((uri.path().to_string(), authorization, body));
+    }
+
+    fn cost(session_id: Uuid, model: &str, value: f64, timestamp: DateTime<Utc>) -> MetricRecord {
+        MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: Some(session_id),
+            name: "claude_code.cost.usage".to_string(),
+            timestamp,
+            value,
+            labels: HashMap::from([("model".to_string(), model.to_string())]),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: timestamp,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_daily_export_uploads_snapshot_to_dated_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display());
+        let db = SqliteDatabase::new(&url, &[], 5).await.unwrap();
+        db.migrate().await.unwrap();
+        let session = Uuid::new_v4();
+        let day = Utc.with_ymd_and_hms(2025, 3, 1, 0, 0, 0).unwrap();
+        db.upsert_session(session, "dev@example.com", day).await.unwrap();
+        db.store_metric(&cost(session, "claude-sonnet-4", 0.25, day + Duration::hours(9))).await.unwrap();
+        db.store_metric(&cost(session, "claude-opus-4", 1.5, day + Duration::hours(17))).await.unwrap();
+        // Outside the exported day
+        db.store_metric(&cost(session, "claude-opus-4", 9.0, day + Duration::days(1))).await.unwrap();
+
+        let uploads = Uploads::default();
+        let app = Router::new().route("/*path", put(capture_put)).with_state(uploads.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let uploader = S3Uploader::new(S3ExportConfig {
+            endpoint,
+            bucket: "analytics".to_string(),
+            prefix: "claude-lens/".to_string(),
+            region: "us-east-1".to_string(),
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "secret".to_string(),
+            schedule: ExportSchedule::Daily,
+        });
+        let key = export_once(&db, &uploader, day + Duration::days(1) + EXPORT_GRACE).await.unwrap();
+        assert_eq!(key, "claude-lens/daily/2025-03-01.json");
+
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(uploads.len(), 1);
+        let (path, authorization, body) = &uploads[0];
+        assert_eq!(path, "/analytics/claude-lens/daily/2025-03-01.json");
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+
+        let snapshot: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(snapshot["period_start"], "2025-03-01T00:00:00Z");
+        assert_eq!(snapshot["period_end"], "2025-03-02T00:00:00Z");
+        assert_eq!(snapshot["sessions"], 1);
+        assert_eq!(snapshot["metric_totals"]["claude_code.cost.usage"], 1.75);
+        assert_eq!(snapshot["cost_by_model"]["claude-opus-4"], 1.5);
+        assert_eq!(snapshot["cost_by_model"]["claude-sonnet-4"], 0.25);
+    }
+
+    #[test]
+    fn test_weekly_period_and_key_use_iso_weeks() {
+        // Wednesday 2025-03-05
+        let now = Utc.with_ymd_and_hms(2025, 3, 5, 12, 0, 0).unwrap();
+        let (start, end) = last_closed_period(ExportSchedule::Weekly, now);
+        assert_eq!(start, Utc.with_ymd_and_hms(2025, 2, 24, 0, 0, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2025, 3, 3, 0, 0, 0).unwrap());
+        assert_eq!(object_key("", ExportSchedule::Weekly, start), "weekly/2025-W09.json");
+    }
+}
@@ -0,0 +1,94 @@
+// Month-end projections from daily series
+use chrono::{DateTime, Datelike, Months, NaiveTime, Utc};
+
+/// Projected total for the month of the latest point: what the month's points already
+/// add up to, plus a least-squares line through all points for each day left in the
+/// month (never below zero). `points` are daily totals keyed by the start of their day.
+/// With fewer than two distinct days, the month-to-date run rate is extrapolated instead.
+pub fn linear_forecast(points: &[(DateTime<Utc>, f64)]) -> f64 {
+    let Some(last) = points.iter().map(|(timestamp, _)| *timestamp).max() else {
+        return 0.0;
+    };
+    let month_start = last.date_naive().with_day(1).expect("every month has a first day");
+    let next_month = month_start.checked_add_months(Months::new(1)).expect("date within chrono's range");
+    let days_in_month = (next_month - month_start).num_days();
+    let month_start = month_start.and_time(NaiveTime::MIN).and_utc();
+
+    // Days since the start of the month, negative for points in earlier months
+    let day = |timestamp: DateTime<Utc>| (timestamp - month_start).num_seconds() as f64 / 86_400.0;
+    let last_day = day(last).floor() as i64;
+    let month_to_date: f64 = points
+        .iter()
+        .filter(|(timestamp, _)| *timestamp >= month_start)
+        .map(|(_, value)| value)
+        .sum();
+
+    let Some((intercept, slope)) = least_squares(points.iter().map(|(timestamp, value)| (day(*timestamp), *value))) else {
+        return month_to_date / (last_day + 1) as f64 * days_in_month as f64;
+    };
+
+    let remaining: f64 = (last_day + 1..days_in_month)
+        .map(|day| (intercept + slope * day as f64).max(0.0))
+        .sum();
+    month_to_date + remaining
+}
+
+// Intercept and slope of the best-fitting line, or None without two distinct x values
+fn least_squares(points: impl Iterator<Item = (f64, f64)> + Clone) -> Option<(f64, f64)> {
+    let n = points.clone().count() as f64;
+    if n < 2.0 {
+        return None;
+    }
+    let mean_x = points.clone().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.clone().map(|(_, y)| y).sum::<f64>() / n;
+    let sxx: f64 = points.clone().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let sxy: f64 = points.map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    if sxx == 0.0 {
+        return None;
+    }
+
+    let slope = sxy / sxx;
+    Some((mean_y - slope * mean_x, slope))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn day(month: u32, day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, month, day, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_linear_series_projects_the_closed_form_total() {
+        // 1, 2, ..., 10 on April 1st to 10th; the line continues to 30 on the 30th
+        let points: Vec<_> = (1..=10).map(|d| (day(4, d), d as f64)).collect();
+
+        // 1 + 2 + ... + 30
+        assert!((linear_forecast(&points) - 465.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_points_from_the_previous_month_shape_the_line_only() {
+        // A flat 2.0 a day from March 27th to April 3rd
+        let points: Vec<_> = (27..=31).map(|d| (day(3, d), 2.0)).chain((1..=3).map(|d| (day(4, d), 2.0))).collect();
+
+        assert!((linear_forecast(&points) - 60.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_falling_spend_stops_at_zero() {
+        let points = [(day(4, 1), 20.0), (day(4, 2), 10.0)];
+
+        // The line reaches zero on the 3rd
+        assert_eq!(linear_forecast(&points), 30.0);
+    }
+
+    #[test]
+    fn test_fewer_than_two_points_use_the_run_rate() {
+        assert_eq!(linear_forecast(&[]), 0.0);
+        // 5.0 over the first 5 days of a 30-day month
+        assert_eq!(linear_forecast(&[(day(4, 5), 5.0)]), 30.0);
+    }
+}
@@ -1,80 +1,559 @@
 use clap::Parser;
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 use tokio::signal;
-use tracing::{info, warn};
+use tracing::{info, warn, Subscriber};
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, Layer};
 
 mod config;
+#[cfg(feature = "s3-export")]
+mod export;
+mod forecast;
 mod server;
 mod api;
 mod otel;
+mod pricing;
+mod privacy;
+mod shutdown;
+mod stats;
 mod storage;
+mod util;
 
-use config::Config;
+use api::AppState;
+use otel::{auth::IngestAuth, queue::IngestQueue, receiver::{OtelReceiver, OtlpLimits}};
+use config::{Config, ConfigError, LogFormat};
+use pricing::PricingStore;
+use shutdown::Shutdown;
+use stats::IngestStats;
 
 #[derive(Parser, Debug)]
 #[command(name = "claude-scope")]
 #[command(about = "Claude Code monitoring tool with OpenTelemetry data collection")]
 struct Args {
-    #[arg(long, default_value = "3000")]
-    port: u16,
+    /// TOML config file; environment variables and flags override its values
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-    #[arg(long, default_value = "4317")]
-    otel_port: u16,
+    /// HTTP server port (default: 3000)
+    #[arg(long)]
+    port: Option<u16>,
 
-    #[arg(long, default_value = "./claude-scope.db")]
-    db_path: String,
+    /// OpenTelemetry gRPC server port (default: 4317)
+    #[arg(long)]
+    otel_port: Option<u16>,
+
+    /// SQLite database path (default: ./claude-lens.db)
+    #[arg(long)]
+    db_path: Option<String>,
+
+    /// Address to bind both servers to (default: 0.0.0.0)
+    #[arg(long)]
+    bind_address: Option<String>,
+
+    /// Database URL, e.g. postgres://user@host/db; overrides --db-path
+    #[arg(long)]
+    database_url: Option<String>,
+
+    /// Bearer token required on OTLP ingestion (default: none, ingestion is open)
+    #[arg(long)]
+    ingest_token: Option<String>,
+
+    /// API key accepted in X-API-Key on /api/*; repeat for several (default: none, the API is open)
+    #[arg(long = "api-key", value_name = "KEY")]
+    api_keys: Vec<String>,
+
+    /// Delete telemetry and finished sessions older than this many days (default: keep everything)
+    #[arg(long)]
+    retention_days: Option<u32>,
+
+    /// Log output format: pretty, compact or json (default: pretty)
+    #[arg(long)]
+    log_format: Option<String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("claude_scope=info,tower_http=debug"))
+    let args = Args::parse();
+    let (config, warnings) = match load_config(args) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Initialize tracing; RUST_LOG takes precedence over the configured level
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                tracing_subscriber::EnvFilter::new(format!("claude_lens={},tower_http=debug", config.log_level))
+            })
         )
+        .with(fmt_layer(config.log_format, std::io::stdout))
         .init();
+    for warning in warnings {
+        warn!("{}", warning);
+    }
 
-    let args = Args::parse();
-    
-    // Create configuration
-    let mut config = Config::from_env();
-    config.http_port = args.port;
-    config.otel_port = args.otel_port;
-    config.database_path = args.db_path.clone();
+    // Fail before either server starts rather than on bind
+    let bind_ip = config.bind_ip()?;
 
     info!("Starting Claude Scope");
+    info!("Binding to {}", bind_ip);
     info!("HTTP server will listen on port {}", config.http_port);
-    info!("OpenTelemetry gRPC server will listen on port {}", config.otel_port);
-    info!("Database path: {}", config.database_path);
+    let otel_listen = config.otel_listen()?;
+    info!("OpenTelemetry gRPC server will listen on {}", otel_listen);
+    info!("Database: {}", config.redacted().database_url());
 
     // Initialize database
-    let db = storage::sqlite::init_database(&config.database_path).await?;
+    let db = storage::init_database(&config).await?;
     info!("Database initialized");
 
     // Start both servers concurrently
-    let http_addr: SocketAddr = ([0, 0, 0, 0], config.http_port).into();
-    let otel_addr: SocketAddr = ([0, 0, 0, 0], config.otel_port).into();
+    let http_addr = SocketAddr::new(bind_ip, config.http_port);
 
-    let http_server = server::start_http_server(http_addr, db.clone());
-    let otel_server = otel::receiver::start_otel_server(otel_addr, db.clone());
+    if let Some(days) = config.retention_days {
+        spawn_retention_task(db.clone(), days);
+    }
+    if config.session_idle_timeout_secs > 0 {
+        spawn_session_reaper(db.clone(), Duration::from_secs(config.session_idle_timeout_secs));
+    }
 
-    tokio::select! {
-        result = http_server => {
-            if let Err(e) = result {
+    #[cfg(feature = "s3-export")]
+    if let Some(s3) = config.s3_export.clone() {
+        export::spawn_scheduled_export(db.clone(), s3);
+    }
+    #[cfg(not(feature = "s3-export"))]
+    if config.s3_export.is_some() {
+        warn!("S3 export is configured but this build lacks the s3-export feature");
+    }
+
+    let stats = Arc::new(IngestStats::new());
+    let pricing = Arc::new(PricingStore::load(config.pricing_file.clone().map(Into::into))?);
+    spawn_pricing_reload_on_sighup(pricing.clone());
+
+    let state = AppState::new(db.clone(), stats.clone(), config.clone()).with_pricing(pricing);
+    // init_database has run the migrations by now
+    state.mark_ready();
+
+    // Stores what the receivers parse, either before answering or from the ingestion queue
+    let store = OtelReceiver::new(db, stats)
+        .with_feed(state.metric_feed.clone())
+        .with_command_events(&config.command_events)
+        .with_aggregator(state.aggregator.clone())
+        .with_cumulative(state.cumulative.clone());
+    if config.aggregation_enabled {
+        spawn_aggregation_flush(store.clone(), Duration::from_millis(config.flush_interval_ms));
+    }
+
+    // Stopped only after both servers, so nothing is queued once the writer has drained
+    let writer_shutdown = Shutdown::new();
+    let (ingest_queue, writer) = if config.ingest_queue_depth > 0 {
+        info!("Queueing up to {} export(s) for storage", config.ingest_queue_depth);
+        let (queue, batches) = IngestQueue::new(config.ingest_queue_depth, config.ingest_queue_full);
+        (Some(queue), Some(otel::queue::spawn_writer(store.clone(), batches, writer_shutdown.clone())))
+    } else {
+        (None, None)
+    };
+    let state = state.with_ingest_queue(ingest_queue.clone());
+    let otel_receiver = store.clone().with_queue(ingest_queue);
+    let shutdown = Shutdown::new();
+    let ingest_auth = IngestAuth::new(config.ingest_token.as_deref());
+    let otlp_limits = OtlpLimits {
+        max_message_bytes: config.max_otlp_message_bytes,
+        request_timeout: Duration::from_secs(config.otlp_request_timeout_secs),
+        gzip: config.otlp_gzip,
+    };
+    if config.ingest_token.is_none() {
+        warn!("No ingest token configured; OTLP ingestion is unauthenticated");
+    }
+
+    // Either server exiting on its own takes the other one down with it
+    let http_server = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            if let Err(e) = server::start_http_server(http_addr, state, shutdown.clone()).await {
                 warn!("HTTP server error: {}", e);
             }
+            shutdown.trigger();
         }
-        result = otel_server => {
-            if let Err(e) = result {
+    });
+    let otel_server = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            if let Err(e) = otel::receiver::start_otel_server(otel_listen, otel_receiver, ingest_auth, otlp_limits, shutdown.clone()).await {
                 warn!("OpenTelemetry server error: {}", e);
             }
+            shutdown.trigger();
         }
+    });
+
+    tokio::select! {
         _ = signal::ctrl_c() => {
             info!("Received Ctrl+C, shutting down gracefully...");
         }
+        _ = shutdown.clone().wait() => {}
+    }
+    shutdown.trigger();
+
+    // Give in-flight requests a bounded amount of time to finish
+    let timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    let drained = tokio::time::timeout(timeout, async {
+        let _ = http_server.await;
+        let _ = otel_server.await;
+    })
+    .await;
+    if drained.is_err() {
+        warn!("In-flight requests did not finish within {}s, exiting anyway", config.shutdown_timeout_secs);
+    }
+
+    // Exports accepted before the servers stopped are stored before exiting
+    writer_shutdown.trigger();
+    if let Some(writer) = writer {
+        if tokio::time::timeout(timeout, writer).await.is_err() {
+            warn!("Queued exports were not stored within {}s, exiting anyway", config.shutdown_timeout_secs);
+        }
+    }
+
+    // Counters still buffered would otherwise be lost
+    if let Err(e) = store.flush_aggregated().await {
+        warn!("Failed to flush aggregated metrics: {}", e);
     }
 
     info!("Claude Scope shutdown complete");
     Ok(())
-}
\ No newline at end of file
+}
+
+// Config file, then environment, then command-line flags, each overriding the last; the
+// validation warnings come back with the config, to be logged once tracing is set up
+fn load_config(args: Args) -> Result<(Config, Vec<String>), ConfigError> {
+    load_config_with(args, |key| std::env::var(key).ok())
+}
+
+// `load_config` reading environment variables through `var`
+fn load_config_with(args: Args, var: impl Fn(&str) -> Option<String>) -> Result<(Config, Vec<String>), ConfigError> {
+    let mut config = Config::load_with(args.config.as_ref(), var)?;
+    if let Some(port) = args.port {
+        config.http_port = port;
+    }
+    if let Some(port) = args.otel_port {
+        config.otel_port = port;
+    }
+    if let Some(path) = args.db_path {
+        config.database_path = path;
+    }
+    if let Some(address) = args.bind_address {
+        config.bind_address = address;
+    }
+    if let Some(url) = args.database_url {
+        config.database_url = Some(url);
+    }
+    if let Some(token) = args.ingest_token {
+        config.ingest_token = Some(token);
+    }
+    if !args.api_keys.is_empty() {
+        config.api_keys = args.api_keys;
+    }
+    if let Some(days) = args.retention_days {
+        config.retention_days = Some(days);
+    }
+    if let Some(format) = args.log_format {
+        config.log_format = LogFormat::parse(&format)
+            .ok_or_else(|| ConfigError::InvalidValue(format!("Invalid log format: {}", format)))?;
+    }
+
+    let warnings = config.validate()?;
+    Ok((config, warnings))
+}
+
+// Log lines in the configured format, written to `writer`
+fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Pretty => layer.boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+// Purge expired rows once at startup and then hourly
+fn spawn_retention_task(db: Arc<dyn storage::Database>, days: u32) {
+    info!("Retaining data for {} day(s)", days);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            let cutoff = chrono::Utc::now() - chrono::Duration::days(days.into());
+            match db.delete_older_than(cutoff).await {
+                Ok(purged) if purged.total() > 0 => info!(
+                    "Retention purged {} metric(s), {} log(s), {} span(s) and {} session(s) older than {}",
+                    purged.metrics, purged.logs, purged.traces, purged.sessions, cutoff
+                ),
+                Ok(_) => {}
+                Err(e) => warn!("Retention pass failed: {}", e),
+            }
+        }
+    });
+}
+
+// End sessions that have gone quiet, checking once a minute
+fn spawn_session_reaper(db: Arc<dyn storage::Database>, idle_timeout: Duration) {
+    info!("Ending sessions idle for {}s", idle_timeout.as_secs());
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match reap_idle_sessions(&*db, chrono::Utc::now(), idle_timeout).await {
+                Ok(0) => {}
+                Ok(ended) => info!("Ended {} idle session(s)", ended),
+                Err(e) => warn!("Idle session pass failed: {}", e),
+            }
+        }
+    });
+}
+
+// Write buffered counters and gauges every `interval`
+fn spawn_aggregation_flush(receiver: OtelReceiver, interval: Duration) {
+    info!("Aggregating counters and gauges, flushing every {}ms", interval.as_millis());
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = receiver.flush_aggregated().await {
+                warn!("Failed to flush aggregated metrics: {}", e);
+            }
+        }
+    });
+}
+
+// `now` comes from the caller so tests can run the reaper on a fixed clock
+async fn reap_idle_sessions(
+    db: &dyn storage::Database,
+    now: chrono::DateTime<chrono::Utc>,
+    idle_timeout: Duration,
+) -> Result<u64, storage::DatabaseError> {
+    let idle_timeout = chrono::Duration::from_std(idle_timeout).unwrap_or(chrono::Duration::MAX);
+    db.end_idle_sessions(now - idle_timeout).await
+}
+
+#[cfg(unix)]
+fn spawn_pricing_reload_on_sighup(pricing: Arc<PricingStore>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                warn!("Could not install SIGHUP handler: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            info!("Received SIGHUP, reloading pricing");
+            if let Err(e) = pricing.reload() {
+                warn!("Pricing reload failed, keeping previous table: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_pricing_reload_on_sighup(_pricing: Arc<PricingStore>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keeps the variables of the shell running the tests out of the loaded config
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn test_flags_override_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("claude-lens.toml");
+        std::fs::write(&path, "http_port = 4000\ndatabase_path = \"/var/lib/claude-lens.db\"\nretention_days = 7\n").unwrap();
+
+        let args = Args::parse_from(["claude-lens", "--config", path.to_str().unwrap(), "--port", "8080"]);
+        let (config, _) = load_config_with(args, no_env).unwrap();
+        assert_eq!(config.http_port, 8080);
+        assert_eq!(config.database_path, "/var/lib/claude-lens.db");
+        assert_eq!(config.retention_days, Some(7));
+
+        // The merged result is validated before anything starts
+        let args = Args::parse_from(["claude-lens", "--config", path.to_str().unwrap(), "--port", "0"]);
+        assert!(matches!(load_config_with(args, no_env), Err(ConfigError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_flags_set_ingest_token_api_keys_and_retention() {
+        let (config, _) = load_config_with(Args::parse_from(["claude-lens"]), no_env).unwrap();
+        let defaults = Config::default();
+        assert_eq!((config.ingest_token, config.api_keys, config.retention_days), (defaults.ingest_token, defaults.api_keys, defaults.retention_days));
+
+        let args = Args::parse_from([
+            "claude-lens",
+            "--ingest-token", "secret",
+            "--api-key", "key-1",
+            "--api-key", "key-2",
+            "--retention-days", "30",
+            "--database-url", "sqlite:/data/lens.db?mode=rwc",
+        ]);
+        let (config, _) = load_config_with(args, no_env).unwrap();
+        assert_eq!(config.ingest_token.as_deref(), Some("secret"));
+        assert_eq!(config.api_keys, ["key-1", "key-2"]);
+        assert_eq!(config.retention_days, Some(30));
+        assert_eq!(config.database_url(), "sqlite:/data/lens.db?mode=rwc");
+    }
+
+    #[test]
+    fn test_startup_rejects_http_and_otel_on_the_same_port() {
+        let args = Args::parse_from(["claude-lens", "--port", "4317", "--otel-port", "4317"]);
+        let err = load_config_with(args, no_env).unwrap_err();
+        assert!(matches!(&err, ConfigError::InvalidValue(msg) if msg.contains("ports must be different")), "{}", err);
+    }
+
+    #[test]
+    fn test_file_then_env_then_flags_take_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("claude-lens.toml");
+        std::fs::write(&path, "bind_address = \"127.0.0.1\"\nretention_days = 7\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let (from_file, _) = load_config_with(Args::parse_from(["claude-lens", "--config", path]), no_env).unwrap();
+        assert_eq!(from_file.bind_address, "127.0.0.1");
+
+        let env = |key: &str| (key == "CLAUDE_LENS_BIND_ADDRESS").then(|| "::1".to_string());
+        let (from_env, _) = load_config_with(Args::parse_from(["claude-lens", "--config", path]), env).unwrap();
+        let from_flag = load_config_with(Args::parse_from(["claude-lens", "--config", path, "--bind-address", "0.0.0.0"]), env);
+
+        assert_eq!(from_env.bind_address, "::1");
+        assert_eq!(from_env.retention_days, Some(7));
+        assert_eq!(from_flag.unwrap().0.bind_address, "0.0.0.0");
+    }
+
+    // Collects formatted log output in memory
+    #[derive(Clone, Default)]
+    struct Captured(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self {
+            self.clone()
+        }
+    }
+
+    fn log_line(format: LogFormat) -> String {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::registry().with(fmt_layer(format, captured.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("export", signal = "metrics").in_scope(|| info!(points = 3, "stored"));
+        });
+        let output = captured.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_log_format_selects_the_formatter() {
+        let args = Args::parse_from(["claude-lens", "--log-format", "json"]);
+        assert_eq!(load_config_with(args, no_env).unwrap().0.log_format, LogFormat::Json);
+        let args = Args::parse_from(["claude-lens", "--log-format", "yaml"]);
+        assert!(matches!(load_config_with(args, no_env), Err(ConfigError::InvalidValue(_))));
+
+        // Pretty leads with the span and its fields, compact moves span fields to the end
+        let pretty = log_line(LogFormat::Pretty);
+        assert!(pretty.find("signal").unwrap() < pretty.find("stored").unwrap());
+        let compact = log_line(LogFormat::Compact);
+        assert!(compact.find("signal").unwrap() > compact.find("stored").unwrap());
+
+        let json: serde_json::Value = serde_json::from_str(&log_line(LogFormat::Json)).unwrap();
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["fields"]["message"], "stored");
+        assert_eq!(json["fields"]["points"], 3);
+        assert_eq!(json["span"]["signal"], "metrics");
+    }
+
+    #[tokio::test]
+    async fn test_reaper_ends_sessions_idle_past_the_timeout() {
+        use chrono::{TimeZone, Utc};
+        use std::collections::HashMap;
+        use storage::{LogRecord, MetricRecord, SessionEndReason};
+        use uuid::Uuid;
+
+        let (_dir, db) = storage::sqlite::test_database().await;
+        let at = |hour: u32, minute: u32| Utc.with_ymd_and_hms(2025, 3, 1, hour, minute, 0).unwrap();
+        let session = |start| {
+            let db = db.clone();
+            async move {
+                let id = Uuid::new_v4();
+                db.upsert_session(id, "dev@example.com", start).await.unwrap();
+                id
+            }
+        };
+        let recent_log = session(at(10, 0)).await;
+        let stale_metric = session(at(10, 0)).await;
+        let just_started = session(at(11, 45)).await;
+        let never_active = session(at(9, 0)).await;
+        let closed = session(at(9, 0)).await;
+        db.update_session(closed, Some(at(9, 30))).await.unwrap();
+        db.store_log(&LogRecord {
+            id: Uuid::new_v4(),
+            session_id: Some(recent_log),
+            timestamp: at(11, 50),
+            level: "INFO".to_string(),
+            message: "user_prompt_submitted".to_string(),
+            attributes: HashMap::new(),
+            service: None,
+            created_at: Utc::now(),
+        }).await.unwrap();
+        db.store_metric(&MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: Some(stale_metric),
+            name: "claude_code.cost.usage".to_string(),
+            timestamp: at(11, 0),
+            value: 1.0,
+            labels: HashMap::new(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }).await.unwrap();
+        let idle = Duration::from_secs(30 * 60);
+
+        assert_eq!(reap_idle_sessions(&*db, at(12, 0), idle).await.unwrap(), 2);
+        for (id, end_time) in [(stale_metric, at(11, 0)), (never_active, at(9, 0))] {
+            let session = db.get_session(id).await.unwrap().unwrap();
+            assert_eq!(session.end_time, Some(end_time));
+            assert_eq!(session.end_reason, Some(SessionEndReason::Terminated));
+        }
+        for id in [recent_log, just_started] {
+            assert!(db.get_session(id).await.unwrap().unwrap().end_time.is_none());
+        }
+        let closed = db.get_session(closed).await.unwrap().unwrap();
+        assert_eq!((closed.end_time, closed.end_reason), (Some(at(9, 30)), Some(SessionEndReason::Completed)));
+
+        // Half an hour later the other two have gone quiet as well
+        assert_eq!(reap_idle_sessions(&*db, at(12, 30), idle).await.unwrap(), 2);
+        assert_eq!(db.get_session(recent_log).await.unwrap().unwrap().end_time, Some(at(11, 50)));
+        assert_eq!(reap_idle_sessions(&*db, at(13, 0), idle).await.unwrap(), 0);
+    }
+}
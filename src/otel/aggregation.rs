@@ -0,0 +1,130 @@
+// Optional pre-aggregation of counters and gauges between flushes, so a counter
+// exported many times a second is stored as one row per interval
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+use crate::otel::{queue::SessionStarts, receiver::note_session};
+use crate::storage::MetricRecord;
+
+/// How points of one series combine while buffered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// Increments are added up
+    Sum,
+    /// The most recent reading wins
+    LastValue,
+}
+
+// Points of the same metric, label set and session fold into one row
+#[derive(Debug, Hash, PartialEq, Eq)]
+pub(super) struct SeriesKey {
+    name: String,
+    labels: Vec<(String, String)>,
+    session_id: Option<Uuid>,
+}
+
+impl SeriesKey {
+    pub(super) fn of(metric: &MetricRecord) -> Self {
+        let mut labels: Vec<_> = metric.labels.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        labels.sort();
+        Self { name: metric.name.clone(), labels, session_id: metric.session_id }
+    }
+}
+
+#[derive(Default)]
+pub struct MetricAggregator {
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    rows: HashMap<SeriesKey, MetricRecord>,
+    // Sessions of the buffered rows, stored along with them since the export that
+    // created a session may never reach storage itself
+    sessions: SessionStarts,
+}
+
+impl MetricAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fold `metric` into its series, keeping its session as noted in `sessions`; the row
+    /// keeps the latest timestamp seen
+    pub fn add(&self, metric: MetricRecord, aggregation: Aggregation, sessions: &SessionStarts) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some((id, (user, start))) = metric.session_id.and_then(|id| sessions.get_key_value(&id)) {
+            note_session(&mut pending.sessions, *id, Some(user), *start);
+        }
+        match pending.rows.entry(SeriesKey::of(&metric)) {
+            Entry::Vacant(entry) => {
+                entry.insert(metric);
+            }
+            Entry::Occupied(mut entry) => {
+                let row = entry.get_mut();
+                match aggregation {
+                    Aggregation::Sum => {
+                        row.value += metric.value;
+                        row.timestamp = row.timestamp.max(metric.timestamp);
+                    }
+                    // Out-of-order readings never replace a newer one
+                    Aggregation::LastValue if metric.timestamp >= row.timestamp => *row = metric,
+                    Aggregation::LastValue => {}
+                }
+            }
+        }
+    }
+
+    /// Take every buffered row and the sessions they belong to, leaving the aggregator empty
+    pub fn drain(&self) -> (Vec<MetricRecord>, SessionStarts) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        (pending.rows.into_values().collect(), pending.sessions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn point(name: &str, value: f64, second: u32, labels: &[(&str, &str)]) -> MetricRecord {
+        MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            name: name.to_string(),
+            timestamp: at(second),
+            value,
+            labels: labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn at(second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, second).unwrap()
+    }
+
+    #[test]
+    fn test_sums_add_up_and_gauges_keep_the_latest_reading() {
+        let aggregator = MetricAggregator::new();
+        let none = SessionStarts::new();
+        aggregator.add(point("tokens", 10.0, 1, &[("type", "input"), ("model", "sonnet")]), Aggregation::Sum, &none);
+        // Same labels in another order belong to the same series
+        aggregator.add(point("tokens", 5.0, 2, &[("model", "sonnet"), ("type", "input")]), Aggregation::Sum, &none);
+        aggregator.add(point("tokens", 7.0, 2, &[("type", "output"), ("model", "sonnet")]), Aggregation::Sum, &none);
+        aggregator.add(point("active", 3.0, 5, &[]), Aggregation::LastValue, &none);
+        aggregator.add(point("active", 1.0, 4, &[]), Aggregation::LastValue, &none);
+
+        let (mut rows, sessions) = aggregator.drain();
+        assert!(sessions.is_empty());
+        rows.sort_by(|a, b| (&a.name, a.value.to_bits()).cmp(&(&b.name, b.value.to_bits())));
+        let rows: Vec<_> = rows.iter().map(|m| (m.name.as_str(), m.value, m.timestamp)).collect();
+        assert_eq!(rows, vec![("active", 3.0, at(5)), ("tokens", 7.0, at(2)), ("tokens", 15.0, at(2))]);
+        assert!(aggregator.drain().0.is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+// Bearer-token check shared by the gRPC and HTTP ingestion paths
+use std::sync::Arc;
+use tonic::{service::Interceptor, Request, Status};
+
+/// Rejects requests whose `authorization` header doesn't carry the ingest token.
+/// With no token configured every request is accepted.
+#[derive(Clone, Default)]
+pub struct IngestAuth {
+    token: Option<Arc<str>>,
+}
+
+impl IngestAuth {
+    pub fn new(token: Option<&str>) -> Self {
+        Self { token: token.map(Arc::from) }
+    }
+
+    pub fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        let Some(expected) = self.token.as_deref() else {
+            return true;
+        };
+
+        authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|presented| constant_time_eq(presented.trim().as_bytes(), expected.as_bytes()))
+    }
+}
+
+impl Interceptor for IngestAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+
+        if self.is_authorized(authorization) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("Missing or invalid ingest token"))
+        }
+    }
+}
+
+// Avoid leaking how much of the token matched through timing
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn grpc_request(authorization: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(value) = authorization {
+            request.metadata_mut().insert("authorization", value.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn test_interceptor_accepts_matching_token() {
+        let mut auth = IngestAuth::new(Some("s3cret"));
+        assert!(auth.call(grpc_request(Some("Bearer s3cret"))).is_ok());
+    }
+
+    #[test]
+    fn test_interceptor_rejects_missing_or_wrong_token() {
+        let mut auth = IngestAuth::new(Some("s3cret"));
+        for header in [None, Some("Bearer wrong"), Some("s3cret"), Some("Basic s3cret")] {
+            let status = auth.call(grpc_request(header)).unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+        }
+    }
+
+    #[test]
+    fn test_no_token_configured_is_open() {
+        let mut auth = IngestAuth::new(None);
+        assert!(auth.call(grpc_request(None)).is_ok());
+    }
+}
@@ -0,0 +1,476 @@
+// OTLP/HTTP receiver: POST /v1/metrics, /v1/logs and /v1/traces, as JSON or protobuf
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Router,
+};
+use opentelemetry_proto::tonic::collector::{
+    logs::v1::ExportLogsServiceResponse,
+    metrics::v1::ExportMetricsServiceResponse,
+    trace::v1::ExportTraceServiceResponse,
+};
+use prost::Message;
+use std::future::Future;
+use tracing::{debug, warn};
+
+use crate::api::AppState;
+use crate::otel::{
+    auth::IngestAuth,
+    idempotency::{CachedResponse, Claim},
+    json,
+    receiver::{ExportResponse, OtelReceiver, Rejected},
+};
+use crate::storage::DatabaseError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OtlpHttpError {
+    #[error("Missing or invalid ingest token")]
+    Unauthorized,
+    #[error("Unsupported content type: {0}")]
+    UnsupportedContentType(String),
+    #[error("Invalid OTLP/{encoding} {signal} payload: {message}")]
+    InvalidPayload { encoding: &'static str, signal: &'static str, message: String },
+    #[error("Ingestion unavailable: {0}")]
+    Unavailable(#[from] DatabaseError),
+}
+
+impl IntoResponse for OtlpHttpError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            OtlpHttpError::Unauthorized => StatusCode::UNAUTHORIZED,
+            OtlpHttpError::UnsupportedContentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            OtlpHttpError::InvalidPayload { .. } => StatusCode::BAD_REQUEST,
+            OtlpHttpError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        warn!("Rejected OTLP/HTTP request: {}", self);
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Body encodings defined by the OTLP/HTTP spec; responses use the request's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Json,
+    Protobuf,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/metrics", post(export_metrics))
+        .route("/logs", post(export_logs))
+        .route("/traces", post(export_traces))
+}
+
+// POST /v1/metrics
+async fn export_metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, OtlpHttpError> {
+    authorize(&state, &headers)?;
+    let encoding = encoding(&headers)?;
+    let request = decode(encoding, "metrics", body, json::parse_metrics_request)?;
+    let export = async {
+        let rejected = receiver(state.clone()).ingest_metrics(request).await?;
+        Ok(export_response::<ExportMetricsServiceResponse>(encoding, &rejected))
+    };
+    once_per_key(&state, &headers, "metrics", export).await
+}
+
+// POST /v1/logs
+async fn export_logs(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, OtlpHttpError> {
+    authorize(&state, &headers)?;
+    let encoding = encoding(&headers)?;
+    let request = decode(encoding, "logs", body, json::parse_logs_request)?;
+    let export = async {
+        let rejected = receiver(state.clone()).ingest_logs(request).await?;
+        Ok(export_response::<ExportLogsServiceResponse>(encoding, &rejected))
+    };
+    once_per_key(&state, &headers, "logs", export).await
+}
+
+// POST /v1/traces
+async fn export_traces(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, OtlpHttpError> {
+    authorize(&state, &headers)?;
+    let encoding = encoding(&headers)?;
+    let request = decode(encoding, "traces", body, json::parse_traces_request)?;
+    let export = async {
+        let rejected = receiver(state.clone()).ingest_traces(request).await?;
+        Ok(export_response::<ExportTraceServiceResponse>(encoding, &rejected))
+    };
+    once_per_key(&state, &headers, "traces", export).await
+}
+
+// Runs the export unless the request's `Idempotency-Key` was already used recently, in
+// which case the response sent for it is replayed; a repeat arriving while the first
+// request is still running waits for that response
+async fn once_per_key(
+    state: &AppState,
+    headers: &HeaderMap,
+    signal: &'static str,
+    export: impl Future<Output = Result<CachedResponse, DatabaseError>>,
+) -> Result<Response, OtlpHttpError> {
+    let key = headers
+        .get("idempotency-key")
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|key| !key.is_empty());
+
+    let Some(key) = key else {
+        return Ok(export.await?.into_response());
+    };
+    let reservation = loop {
+        match state.idempotency.claim(signal, key) {
+            Claim::New(reservation) => break reservation,
+            Claim::Replay(response) => {
+                debug!("Replaying OTLP/HTTP {} export response for idempotency key {:?}", signal, key);
+                return Ok(response.into_response());
+            }
+            // If the first request fails instead, the key is free to claim again
+            Claim::Running(mut running) => loop {
+                if let Some(response) = running.borrow_and_update().clone() {
+                    debug!("Replaying OTLP/HTTP {} export response for idempotency key {:?}", signal, key);
+                    return Ok(response.into_response());
+                }
+                if running.changed().await.is_err() {
+                    break;
+                }
+            },
+        }
+    };
+
+    // A failed export drops the reservation, so a retry runs again
+    let response = export.await?;
+    reservation.complete(response.clone());
+    Ok(response.into_response())
+}
+
+fn receiver(state: AppState) -> OtelReceiver {
+    OtelReceiver::new(state.db, state.stats)
+        .with_feed(state.metric_feed)
+        .with_command_events(&state.config.command_events)
+        .with_aggregator(state.aggregator)
+        .with_cumulative(state.cumulative)
+        .with_queue(state.ingest_queue)
+}
+
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), OtlpHttpError> {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+
+    if IngestAuth::new(state.config.ingest_token.as_deref()).is_authorized(authorization) {
+        Ok(())
+    } else {
+        Err(OtlpHttpError::Unauthorized)
+    }
+}
+
+fn encoding(headers: &HeaderMap) -> Result<Encoding, OtlpHttpError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    // Ignore parameters such as "; charset=utf-8"
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+
+    if media_type.eq_ignore_ascii_case("application/json") {
+        Ok(Encoding::Json)
+    } else if media_type.eq_ignore_ascii_case("application/x-protobuf") {
+        Ok(Encoding::Protobuf)
+    } else {
+        Err(OtlpHttpError::UnsupportedContentType(content_type.to_string()))
+    }
+}
+
+fn decode<T: Message + Default>(
+    encoding: Encoding,
+    signal: &'static str,
+    body: Bytes,
+    parse_json: fn(&[u8]) -> Result<T, serde_json::Error>,
+) -> Result<T, OtlpHttpError> {
+    match encoding {
+        Encoding::Json => parse_json(&body).map_err(|e| OtlpHttpError::InvalidPayload {
+            encoding: "JSON",
+            signal,
+            message: e.to_string(),
+        }),
+        Encoding::Protobuf => T::decode(body).map_err(|e| OtlpHttpError::InvalidPayload {
+            encoding: "protobuf",
+            signal,
+            message: e.to_string(),
+        }),
+    }
+}
+
+// The Export*ServiceResponse in the request's encoding: empty on full success,
+// with `partialSuccess` when some records were rejected
+fn export_response<R: ExportResponse>(encoding: Encoding, rejected: &Rejected) -> CachedResponse {
+    match encoding {
+        Encoding::Json => {
+            let body = if rejected.count > 0 {
+                serde_json::json!({
+                    "partialSuccess": { R::REJECTED_FIELD: rejected.count, "errorMessage": rejected.message() }
+                })
+            } else {
+                serde_json::json!({})
+            };
+            CachedResponse { content_type: "application/json", body: Bytes::from(body.to_string()) }
+        }
+        Encoding::Protobuf => CachedResponse {
+            content_type: "application/x-protobuf",
+            body: Bytes::from(R::from_rejected(rejected).encode_to_vec()),
+        },
+    }
+}
+
+impl IntoResponse for CachedResponse {
+    fn into_response(self) -> Response {
+        ([(header::CONTENT_TYPE, self.content_type)], self.body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    use crate::{api::test_state, config::Config, storage::sqlite::test_database};
+
+    fn post_json(uri: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_json_metrics_are_stored() {
+        let (_dir, db) = test_database().await;
+        let state = test_state(db.clone());
+        let stats = state.stats.clone();
+        let app = routes().with_state(state);
+
+        let body = r#"{"resourceMetrics": [{
+            "resource": {"attributes": [{"key": "user.email", "value": {"stringValue": "dev@example.com"}}]},
+            "scopeMetrics": [{"metrics": [{
+                "name": "claude_code.token.usage",
+                "sum": {"aggregationTemporality": 1, "dataPoints": [{
+                    "attributes": [{"key": "type", "value": {"stringValue": "output"}}],
+                    "timeUnixNano": "1700000000000000000",
+                    "asInt": "42"
+                }]}
+            }]}]
+        }]}"#;
+        let response = app.oneshot(post_json("/metrics", body)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(stats.metrics_ingested(), 1);
+
+        let stored = db.get_metrics(None, None, Some("claude_code.token.usage")).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].value, 42.0);
+        assert_eq!(stored[0].timestamp.timestamp(), 1_700_000_000);
+        assert_eq!(stored[0].labels["type"], "output");
+        assert_eq!(stored[0].labels["user.email"], "dev@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_rejected_metric_is_reported_as_partial_success() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db.clone()));
+
+        // The second metric carries no data points of any type
+        let body = r#"{"resourceMetrics": [{"scopeMetrics": [{"metrics": [
+            {"name": "claude_code.cost.usage", "gauge": {"dataPoints": [{"timeUnixNano": "1700000000000000000", "asDouble": 0.5}]}},
+            {"name": "claude_code.token.usage"}
+        ]}]}]}"#;
+        let response = app.oneshot(post_json("/metrics", body)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["partialSuccess"]["rejectedDataPoints"], 1);
+        let message = body["partialSuccess"]["errorMessage"].as_str().unwrap();
+        assert!(message.contains("claude_code.token.usage has no data"), "{}", message);
+
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_protobuf_metrics_are_stored() {
+        use opentelemetry_proto::tonic::{
+            collector::metrics::v1::ExportMetricsServiceRequest,
+            common::v1::{any_value, AnyValue, KeyValue},
+            metrics::v1::{metric, number_data_point, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics},
+        };
+
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db.clone()));
+
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "claude_code.cost.usage".to_string(),
+                        data: Some(metric::Data::Gauge(Gauge {
+                            data_points: vec![NumberDataPoint {
+                                attributes: vec![KeyValue {
+                                    key: "model".to_string(),
+                                    value: Some(AnyValue {
+                                        value: Some(any_value::Value::StringValue("claude-sonnet-4".to_string())),
+                                    }),
+                                }],
+                                time_unix_nano: 1_700_000_000_000_000_000,
+                                value: Some(number_data_point::Value::AsDouble(0.75)),
+                                ..Default::default()
+                            }],
+                        })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let request = Request::builder()
+            .method("POST")
+            .uri("/metrics")
+            .header(header::CONTENT_TYPE, "application/x-protobuf")
+            .body(Body::from(request.encode_to_vec()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-protobuf");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let response = ExportMetricsServiceResponse::decode(body).unwrap();
+        assert!(response.partial_success.is_none());
+
+        let stored = db.get_metrics(None, None, Some("claude_code.cost.usage")).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].value, 0.75);
+        assert_eq!(stored[0].timestamp.timestamp(), 1_700_000_000);
+        assert_eq!(stored[0].labels["model"], "claude-sonnet-4");
+    }
+
+    #[tokio::test]
+    async fn test_malformed_protobuf_returns_bad_request() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db));
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/logs")
+            .header(header::CONTENT_TYPE, "application/x-protobuf")
+            .body(Body::from(vec![0xff, 0xff, 0xff]))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("Invalid OTLP/protobuf logs payload:"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json_returns_bad_request() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db));
+
+        let response = app.oneshot(post_json("/metrics", "{\"resourceMetrics\": 5}")).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("Invalid OTLP/JSON metrics payload:"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_content_type_is_rejected() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db));
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/logs")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from("hello"))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_idempotency_key_stores_once() {
+        let (_dir, db) = test_database().await;
+        let app = routes().with_state(test_state(db.clone()));
+        // Each key sends a distinct point, so storage-level deduplication doesn't hide a second
+        // write; the metric without data is rejected, so the response carries partialSuccess
+        let body = |ts: &str| format!(r#"{{"resourceMetrics": [{{"scopeMetrics": [{{"metrics": [{{
+            "name": "claude_code.cost.usage",
+            "gauge": {{"dataPoints": [{{"timeUnixNano": "{}", "asDouble": 0.5}}]}}
+        }}, {{"name": "claude_code.broken"}}]}}]}}]}}"#, ts);
+        let with_key = |key: &str, ts: &str| {
+            let mut request = post_json("/metrics", &body(ts));
+            request.headers_mut().insert("idempotency-key", key.parse().unwrap());
+            request
+        };
+
+        let mut responses = Vec::new();
+        for request in [with_key("ci-run-42", "1700000000000000000"), with_key("ci-run-42", "1700000001000000000")] {
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            responses.push(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap());
+        }
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 1);
+        // The repeat gets the original answer, not a bare success
+        let original: serde_json::Value = serde_json::from_slice(&responses[0]).unwrap();
+        assert_eq!(original["partialSuccess"]["errorMessage"], "Rejected 1 record(s); first error: Metric claude_code.broken has no data");
+        assert_eq!(responses[1], responses[0]);
+
+        app.oneshot(with_key("ci-run-43", "1700000002000000000")).await.unwrap();
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_token_is_required_when_configured() {
+        let (_dir, db) = test_database().await;
+        let mut state = test_state(db);
+        state.config = Arc::new(Config {
+            ingest_token: Some("s3cret".to_string()),
+            ..Config::default()
+        });
+        let app = routes().with_state(state);
+        let body = r#"{"resourceMetrics": []}"#;
+
+        let response = app.clone().oneshot(post_json("/metrics", body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut request = post_json("/metrics", body);
+        request.headers_mut().insert(header::AUTHORIZATION, "Bearer wrong".parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut request = post_json("/metrics", body);
+        request.headers_mut().insert(header::AUTHORIZATION, "Bearer s3cret".parse().unwrap());
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
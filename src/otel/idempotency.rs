@@ -0,0 +1,145 @@
+// Remembers recent `Idempotency-Key`s on the OTLP/HTTP endpoints (`/v1/metrics`, `/v1/logs`
+// and `/v1/traces`) with the response sent for them, so a retried export within the window
+// gets the original answer without being stored twice
+use axum::body::Bytes;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+/// How long a key is remembered after the first request that used it
+pub const IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// An export response as sent, replayed to requests repeating its key
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResponse {
+    pub content_type: &'static str,
+    pub body: Bytes,
+}
+
+type Key = (&'static str, String);
+
+enum State {
+    /// The first request is still running; waiters get its response through the channel,
+    /// or see it close if the request fails
+    Running(watch::Sender<Option<CachedResponse>>),
+    Done(CachedResponse),
+}
+
+struct Seen {
+    since: Instant,
+    state: State,
+}
+
+pub struct IdempotencyCache {
+    window: Duration,
+    keys: Mutex<HashMap<Key, Seen>>,
+}
+
+/// What a request should do with its key
+pub enum Claim<'a> {
+    /// The key is new (or expired) and reserved for this request
+    New(Reservation<'a>),
+    /// An earlier request with the key finished with this response
+    Replay(CachedResponse),
+    /// An earlier request with the key is still running
+    Running(watch::Receiver<Option<CachedResponse>>),
+}
+
+/// A claimed key; `complete` records the response, dropping it instead frees the key so
+/// a failed or abandoned request can be retried
+pub struct Reservation<'a> {
+    cache: &'a IdempotencyCache,
+    key: Option<Key>,
+}
+
+impl IdempotencyCache {
+    pub fn new(window: Duration) -> Self {
+        Self { window, keys: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn claim(&self, signal: &'static str, key: &str) -> Claim<'_> {
+        let now = Instant::now();
+        let mut keys = self.keys.lock().unwrap();
+        keys.retain(|_, seen| matches!(seen.state, State::Running(_)) || now.duration_since(seen.since) < self.window);
+
+        match keys.entry((signal, key.to_string())) {
+            Entry::Occupied(entry) => match &entry.get().state {
+                State::Running(sender) => Claim::Running(sender.subscribe()),
+                State::Done(response) => Claim::Replay(response.clone()),
+            },
+            Entry::Vacant(entry) => {
+                let key = entry.key().clone();
+                entry.insert(Seen { since: now, state: State::Running(watch::channel(None).0) });
+                Claim::New(Reservation { cache: self, key: Some(key) })
+            }
+        }
+    }
+}
+
+impl Reservation<'_> {
+    /// Remember `response` for the key and hand it to requests waiting on it
+    pub fn complete(mut self, response: CachedResponse) {
+        let Some(key) = self.key.take() else { return };
+        let mut keys = self.cache.keys.lock().unwrap();
+        if let Some(seen) = keys.get_mut(&key) {
+            if let State::Running(sender) = &seen.state {
+                sender.send_replace(Some(response.clone()));
+            }
+            seen.state = State::Done(response);
+        }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.keys.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(IDEMPOTENCY_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &'static str) -> CachedResponse {
+        CachedResponse { content_type: "application/json", body: Bytes::from(body) }
+    }
+
+    #[test]
+    fn test_keys_replay_their_response_until_they_expire() {
+        let cache = IdempotencyCache::new(Duration::from_millis(20));
+        let Claim::New(first) = cache.claim("metrics", "a") else { panic!("expected a new key") };
+        let Claim::Running(waiting) = cache.claim("metrics", "a") else { panic!("expected a running key") };
+        // Keys are scoped per signal
+        assert!(matches!(cache.claim("logs", "a"), Claim::New(_)));
+
+        first.complete(response("{}"));
+        assert_eq!(*waiting.borrow(), Some(response("{}")));
+        assert!(matches!(cache.claim("metrics", "a"), Claim::Replay(cached) if cached == response("{}")));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(matches!(cache.claim("metrics", "a"), Claim::New(_)));
+    }
+
+    #[test]
+    fn test_dropped_reservation_frees_the_key() {
+        let cache = IdempotencyCache::default();
+        let Claim::New(first) = cache.claim("metrics", "a") else { panic!("expected a new key") };
+        let Claim::Running(waiting) = cache.claim("metrics", "a") else { panic!("expected a running key") };
+
+        drop(first);
+        // Waiters see the channel close and can claim the key themselves
+        assert!(waiting.has_changed().is_err());
+        assert!(matches!(cache.claim("metrics", "a"), Claim::New(_)));
+    }
+}
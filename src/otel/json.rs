@@ -0,0 +1,882 @@
+// OTLP/JSON decoding
+//
+// The protobuf JSON mapping used by OTLP differs from a plain serde derive of the
+// generated structs: field names are lowerCamelCase, 64-bit integers may arrive as
+// strings, trace/span ids are hex strings and bytes are base64. These mirror types
+// accept that encoding and convert into the regular request structs so both the
+// gRPC and HTTP paths share the same parse functions.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use opentelemetry_proto::tonic::{
+    collector::{
+        logs::v1::ExportLogsServiceRequest,
+        metrics::v1::ExportMetricsServiceRequest,
+        trace::v1::ExportTraceServiceRequest,
+    },
+    common::v1 as common,
+    logs::v1 as logs,
+    metrics::v1 as metrics,
+    resource::v1 as resource,
+    trace::v1 as trace,
+};
+
+pub fn parse_metrics_request(body: &[u8]) -> Result<ExportMetricsServiceRequest, serde_json::Error> {
+    let request: MetricsRequestJson = serde_json::from_slice(body)?;
+    Ok(ExportMetricsServiceRequest {
+        resource_metrics: request.resource_metrics.into_iter().map(Into::into).collect(),
+    })
+}
+
+pub fn parse_logs_request(body: &[u8]) -> Result<ExportLogsServiceRequest, serde_json::Error> {
+    let request: LogsRequestJson = serde_json::from_slice(body)?;
+    Ok(ExportLogsServiceRequest {
+        resource_logs: request.resource_logs.into_iter().map(Into::into).collect(),
+    })
+}
+
+pub fn parse_traces_request(body: &[u8]) -> Result<ExportTraceServiceRequest, serde_json::Error> {
+    let request: TracesRequestJson = serde_json::from_slice(body)?;
+    Ok(ExportTraceServiceRequest {
+        resource_spans: request.resource_spans.into_iter().map(Into::into).collect(),
+    })
+}
+
+// Numeric quirks: int64/uint64/fixed64 may be JSON numbers or strings, doubles may be
+// the strings "NaN", "Infinity" and "-Infinity"
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString<T> {
+    Number(T),
+    String(String),
+}
+
+fn de_u64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match NumberOrString::<u64>::deserialize(deserializer)? {
+        NumberOrString::Number(n) => Ok(n),
+        NumberOrString::String(s) => s.parse().map_err(|_| D::Error::custom(format!("invalid uint64: {:?}", s))),
+    }
+}
+
+fn de_i64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    match NumberOrString::<i64>::deserialize(deserializer)? {
+        NumberOrString::Number(n) => Ok(n),
+        NumberOrString::String(s) => s.parse().map_err(|_| D::Error::custom(format!("invalid int64: {:?}", s))),
+    }
+}
+
+fn de_opt_i64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    de_i64(deserializer).map(Some)
+}
+
+fn parse_f64<E: serde::de::Error>(value: NumberOrString<f64>) -> Result<f64, E> {
+    match value {
+        NumberOrString::Number(n) => Ok(n),
+        NumberOrString::String(s) => match s.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            _ => s.parse().map_err(|_| E::custom(format!("invalid double: {:?}", s))),
+        },
+    }
+}
+
+fn de_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    parse_f64(NumberOrString::deserialize(deserializer)?)
+}
+
+fn de_opt_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    parse_f64(NumberOrString::deserialize(deserializer)?).map(Some)
+}
+
+fn de_vec_u64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u64>, D::Error> {
+    Vec::<NumberOrString<u64>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|value| match value {
+            NumberOrString::Number(n) => Ok(n),
+            NumberOrString::String(s) => s.parse().map_err(|_| D::Error::custom(format!("invalid uint64: {:?}", s))),
+        })
+        .collect()
+}
+
+fn de_vec_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f64>, D::Error> {
+    Vec::<NumberOrString<f64>>::deserialize(deserializer)?
+        .into_iter()
+        .map(parse_f64)
+        .collect()
+}
+
+// Trace and span ids are hex encoded rather than base64
+fn de_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    hex::decode(&s).map_err(|_| D::Error::custom(format!("invalid hex id: {:?}", s)))
+}
+
+fn de_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    BASE64.decode(&s).map(Some).map_err(|_| D::Error::custom("invalid base64 bytesValue"))
+}
+
+// Common
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct ResourceJson {
+    attributes: Vec<KeyValueJson>,
+    dropped_attributes_count: u32,
+}
+
+impl From<ResourceJson> for resource::Resource {
+    fn from(json: ResourceJson) -> Self {
+        Self {
+            attributes: json.attributes.into_iter().map(Into::into).collect(),
+            dropped_attributes_count: json.dropped_attributes_count,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct ScopeJson {
+    name: String,
+    version: String,
+    attributes: Vec<KeyValueJson>,
+    dropped_attributes_count: u32,
+}
+
+impl From<ScopeJson> for common::InstrumentationScope {
+    fn from(json: ScopeJson) -> Self {
+        Self {
+            name: json.name,
+            version: json.version,
+            attributes: json.attributes.into_iter().map(Into::into).collect(),
+            dropped_attributes_count: json.dropped_attributes_count,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyValueJson {
+    key: String,
+    #[serde(default)]
+    value: Option<AnyValueJson>,
+}
+
+impl From<KeyValueJson> for common::KeyValue {
+    fn from(json: KeyValueJson) -> Self {
+        Self {
+            key: json.key,
+            value: json.value.map(Into::into),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct AnyValueJson {
+    string_value: Option<String>,
+    bool_value: Option<bool>,
+    #[serde(deserialize_with = "de_opt_i64")]
+    int_value: Option<i64>,
+    #[serde(deserialize_with = "de_opt_f64")]
+    double_value: Option<f64>,
+    array_value: Option<ArrayValueJson>,
+    kvlist_value: Option<KeyValueListJson>,
+    #[serde(deserialize_with = "de_base64")]
+    bytes_value: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ArrayValueJson {
+    values: Vec<AnyValueJson>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct KeyValueListJson {
+    values: Vec<KeyValueJson>,
+}
+
+impl From<AnyValueJson> for common::AnyValue {
+    fn from(json: AnyValueJson) -> Self {
+        use common::any_value::Value;
+
+        let value = if let Some(s) = json.string_value {
+            Some(Value::StringValue(s))
+        } else if let Some(b) = json.bool_value {
+            Some(Value::BoolValue(b))
+        } else if let Some(i) = json.int_value {
+            Some(Value::IntValue(i))
+        } else if let Some(d) = json.double_value {
+            Some(Value::DoubleValue(d))
+        } else if let Some(array) = json.array_value {
+            Some(Value::ArrayValue(common::ArrayValue {
+                values: array.values.into_iter().map(Into::into).collect(),
+            }))
+        } else if let Some(kvlist) = json.kvlist_value {
+            Some(Value::KvlistValue(common::KeyValueList {
+                values: kvlist.values.into_iter().map(Into::into).collect(),
+            }))
+        } else {
+            json.bytes_value.map(Value::BytesValue)
+        };
+
+        Self { value }
+    }
+}
+
+// Metrics
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MetricsRequestJson {
+    #[serde(default)]
+    resource_metrics: Vec<ResourceMetricsJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceMetricsJson {
+    #[serde(default)]
+    resource: Option<ResourceJson>,
+    #[serde(default)]
+    scope_metrics: Vec<ScopeMetricsJson>,
+    #[serde(default)]
+    schema_url: String,
+}
+
+impl From<ResourceMetricsJson> for metrics::ResourceMetrics {
+    fn from(json: ResourceMetricsJson) -> Self {
+        Self {
+            resource: json.resource.map(Into::into),
+            scope_metrics: json.scope_metrics.into_iter().map(Into::into).collect(),
+            schema_url: json.schema_url,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScopeMetricsJson {
+    #[serde(default)]
+    scope: Option<ScopeJson>,
+    #[serde(default)]
+    metrics: Vec<MetricJson>,
+    #[serde(default)]
+    schema_url: String,
+}
+
+impl From<ScopeMetricsJson> for metrics::ScopeMetrics {
+    fn from(json: ScopeMetricsJson) -> Self {
+        Self {
+            scope: json.scope.map(Into::into),
+            metrics: json.metrics.into_iter().map(Into::into).collect(),
+            schema_url: json.schema_url,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MetricJson {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    unit: String,
+    #[serde(default)]
+    gauge: Option<GaugeJson>,
+    #[serde(default)]
+    sum: Option<SumJson>,
+    #[serde(default)]
+    histogram: Option<HistogramJson>,
+    #[serde(default)]
+    exponential_histogram: Option<ExponentialHistogramJson>,
+    #[serde(default)]
+    summary: Option<SummaryJson>,
+}
+
+impl From<MetricJson> for metrics::Metric {
+    fn from(json: MetricJson) -> Self {
+        use metrics::metric::Data;
+
+        let data = if let Some(gauge) = json.gauge {
+            Some(Data::Gauge(metrics::Gauge {
+                data_points: gauge.data_points.into_iter().map(Into::into).collect(),
+            }))
+        } else if let Some(sum) = json.sum {
+            Some(Data::Sum(metrics::Sum {
+                data_points: sum.data_points.into_iter().map(Into::into).collect(),
+                aggregation_temporality: sum.aggregation_temporality,
+                is_monotonic: sum.is_monotonic,
+            }))
+        } else if let Some(histogram) = json.histogram {
+            Some(Data::Histogram(metrics::Histogram {
+                data_points: histogram.data_points.into_iter().map(Into::into).collect(),
+                aggregation_temporality: histogram.aggregation_temporality,
+            }))
+        } else if let Some(histogram) = json.exponential_histogram {
+            Some(Data::ExponentialHistogram(metrics::ExponentialHistogram {
+                data_points: histogram.data_points.into_iter().map(Into::into).collect(),
+                aggregation_temporality: histogram.aggregation_temporality,
+            }))
+        } else {
+            json.summary.map(|summary| Data::Summary(metrics::Summary {
+                data_points: summary.data_points.into_iter().map(Into::into).collect(),
+            }))
+        };
+
+        Self {
+            name: json.name,
+            description: json.description,
+            unit: json.unit,
+            data,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GaugeJson {
+    #[serde(default)]
+    data_points: Vec<NumberDataPointJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SumJson {
+    #[serde(default)]
+    data_points: Vec<NumberDataPointJson>,
+    #[serde(default)]
+    aggregation_temporality: i32,
+    #[serde(default)]
+    is_monotonic: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistogramJson {
+    #[serde(default)]
+    data_points: Vec<HistogramDataPointJson>,
+    #[serde(default)]
+    aggregation_temporality: i32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct NumberDataPointJson {
+    attributes: Vec<KeyValueJson>,
+    #[serde(deserialize_with = "de_u64")]
+    start_time_unix_nano: u64,
+    #[serde(deserialize_with = "de_u64")]
+    time_unix_nano: u64,
+    #[serde(deserialize_with = "de_opt_f64")]
+    as_double: Option<f64>,
+    #[serde(deserialize_with = "de_opt_i64")]
+    as_int: Option<i64>,
+    flags: u32,
+}
+
+impl From<NumberDataPointJson> for metrics::NumberDataPoint {
+    fn from(json: NumberDataPointJson) -> Self {
+        use metrics::number_data_point::Value;
+
+        Self {
+            attributes: json.attributes.into_iter().map(Into::into).collect(),
+            start_time_unix_nano: json.start_time_unix_nano,
+            time_unix_nano: json.time_unix_nano,
+            exemplars: Vec::new(),
+            flags: json.flags,
+            value: json.as_double.map(Value::AsDouble).or(json.as_int.map(Value::AsInt)),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct HistogramDataPointJson {
+    attributes: Vec<KeyValueJson>,
+    #[serde(deserialize_with = "de_u64")]
+    start_time_unix_nano: u64,
+    #[serde(deserialize_with = "de_u64")]
+    time_unix_nano: u64,
+    #[serde(deserialize_with = "de_u64")]
+    count: u64,
+    #[serde(deserialize_with = "de_opt_f64")]
+    sum: Option<f64>,
+    #[serde(deserialize_with = "de_vec_u64")]
+    bucket_counts: Vec<u64>,
+    #[serde(deserialize_with = "de_vec_f64")]
+    explicit_bounds: Vec<f64>,
+    flags: u32,
+    #[serde(deserialize_with = "de_opt_f64")]
+    min: Option<f64>,
+    #[serde(deserialize_with = "de_opt_f64")]
+    max: Option<f64>,
+}
+
+impl From<HistogramDataPointJson> for metrics::HistogramDataPoint {
+    fn from(json: HistogramDataPointJson) -> Self {
+        Self {
+            attributes: json.attributes.into_iter().map(Into::into).collect(),
+            start_time_unix_nano: json.start_time_unix_nano,
+            time_unix_nano: json.time_unix_nano,
+            count: json.count,
+            sum: json.sum,
+            bucket_counts: json.bucket_counts,
+            explicit_bounds: json.explicit_bounds,
+            exemplars: Vec::new(),
+            flags: json.flags,
+            min: json.min,
+            max: json.max,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExponentialHistogramJson {
+    #[serde(default)]
+    data_points: Vec<ExponentialHistogramDataPointJson>,
+    #[serde(default)]
+    aggregation_temporality: i32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct ExponentialHistogramDataPointJson {
+    attributes: Vec<KeyValueJson>,
+    #[serde(deserialize_with = "de_u64")]
+    start_time_unix_nano: u64,
+    #[serde(deserialize_with = "de_u64")]
+    time_unix_nano: u64,
+    #[serde(deserialize_with = "de_u64")]
+    count: u64,
+    #[serde(deserialize_with = "de_opt_f64")]
+    sum: Option<f64>,
+    scale: i32,
+    #[serde(deserialize_with = "de_u64")]
+    zero_count: u64,
+    positive: Option<BucketsJson>,
+    negative: Option<BucketsJson>,
+    flags: u32,
+    #[serde(deserialize_with = "de_opt_f64")]
+    min: Option<f64>,
+    #[serde(deserialize_with = "de_opt_f64")]
+    max: Option<f64>,
+    #[serde(deserialize_with = "de_f64")]
+    zero_threshold: f64,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct BucketsJson {
+    offset: i32,
+    #[serde(deserialize_with = "de_vec_u64")]
+    bucket_counts: Vec<u64>,
+}
+
+impl From<ExponentialHistogramDataPointJson> for metrics::ExponentialHistogramDataPoint {
+    fn from(json: ExponentialHistogramDataPointJson) -> Self {
+        use metrics::exponential_histogram_data_point::Buckets;
+
+        let buckets = |json: BucketsJson| Buckets { offset: json.offset, bucket_counts: json.bucket_counts };
+        Self {
+            attributes: json.attributes.into_iter().map(Into::into).collect(),
+            start_time_unix_nano: json.start_time_unix_nano,
+            time_unix_nano: json.time_unix_nano,
+            count: json.count,
+            sum: json.sum,
+            scale: json.scale,
+            zero_count: json.zero_count,
+            positive: json.positive.map(buckets),
+            negative: json.negative.map(buckets),
+            flags: json.flags,
+            exemplars: Vec::new(),
+            min: json.min,
+            max: json.max,
+            zero_threshold: json.zero_threshold,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SummaryJson {
+    #[serde(default)]
+    data_points: Vec<SummaryDataPointJson>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct SummaryDataPointJson {
+    attributes: Vec<KeyValueJson>,
+    #[serde(deserialize_with = "de_u64")]
+    start_time_unix_nano: u64,
+    #[serde(deserialize_with = "de_u64")]
+    time_unix_nano: u64,
+    #[serde(deserialize_with = "de_u64")]
+    count: u64,
+    #[serde(deserialize_with = "de_f64")]
+    sum: f64,
+    quantile_values: Vec<ValueAtQuantileJson>,
+    flags: u32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ValueAtQuantileJson {
+    #[serde(deserialize_with = "de_f64")]
+    quantile: f64,
+    #[serde(deserialize_with = "de_f64")]
+    value: f64,
+}
+
+impl From<SummaryDataPointJson> for metrics::SummaryDataPoint {
+    fn from(json: SummaryDataPointJson) -> Self {
+        use metrics::summary_data_point::ValueAtQuantile;
+
+        Self {
+            attributes: json.attributes.into_iter().map(Into::into).collect(),
+            start_time_unix_nano: json.start_time_unix_nano,
+            time_unix_nano: json.time_unix_nano,
+            count: json.count,
+            sum: json.sum,
+            quantile_values: json
+                .quantile_values
+                .into_iter()
+                .map(|q| ValueAtQuantile { quantile: q.quantile, value: q.value })
+                .collect(),
+            flags: json.flags,
+        }
+    }
+}
+
+// Logs
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogsRequestJson {
+    #[serde(default)]
+    resource_logs: Vec<ResourceLogsJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceLogsJson {
+    #[serde(default)]
+    resource: Option<ResourceJson>,
+    #[serde(default)]
+    scope_logs: Vec<ScopeLogsJson>,
+    #[serde(default)]
+    schema_url: String,
+}
+
+impl From<ResourceLogsJson> for logs::ResourceLogs {
+    fn from(json: ResourceLogsJson) -> Self {
+        Self {
+            resource: json.resource.map(Into::into),
+            scope_logs: json.scope_logs.into_iter().map(Into::into).collect(),
+            schema_url: json.schema_url,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScopeLogsJson {
+    #[serde(default)]
+    scope: Option<ScopeJson>,
+    #[serde(default)]
+    log_records: Vec<LogRecordJson>,
+    #[serde(default)]
+    schema_url: String,
+}
+
+impl From<ScopeLogsJson> for logs::ScopeLogs {
+    fn from(json: ScopeLogsJson) -> Self {
+        Self {
+            scope: json.scope.map(Into::into),
+            log_records: json.log_records.into_iter().map(Into::into).collect(),
+            schema_url: json.schema_url,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct LogRecordJson {
+    #[serde(deserialize_with = "de_u64")]
+    time_unix_nano: u64,
+    #[serde(deserialize_with = "de_u64")]
+    observed_time_unix_nano: u64,
+    severity_number: i32,
+    severity_text: String,
+    body: Option<AnyValueJson>,
+    attributes: Vec<KeyValueJson>,
+    dropped_attributes_count: u32,
+    flags: u32,
+    #[serde(deserialize_with = "de_hex")]
+    trace_id: Vec<u8>,
+    #[serde(deserialize_with = "de_hex")]
+    span_id: Vec<u8>,
+}
+
+impl From<LogRecordJson> for logs::LogRecord {
+    fn from(json: LogRecordJson) -> Self {
+        Self {
+            time_unix_nano: json.time_unix_nano,
+            observed_time_unix_nano: json.observed_time_unix_nano,
+            severity_number: json.severity_number,
+            severity_text: json.severity_text,
+            body: json.body.map(Into::into),
+            attributes: json.attributes.into_iter().map(Into::into).collect(),
+            dropped_attributes_count: json.dropped_attributes_count,
+            flags: json.flags,
+            trace_id: json.trace_id,
+            span_id: json.span_id,
+        }
+    }
+}
+
+// Traces
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TracesRequestJson {
+    #[serde(default)]
+    resource_spans: Vec<ResourceSpansJson>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceSpansJson {
+    #[serde(default)]
+    resource: Option<ResourceJson>,
+    #[serde(default)]
+    scope_spans: Vec<ScopeSpansJson>,
+    #[serde(default)]
+    schema_url: String,
+}
+
+impl From<ResourceSpansJson> for trace::ResourceSpans {
+    fn from(json: ResourceSpansJson) -> Self {
+        Self {
+            resource: json.resource.map(Into::into),
+            scope_spans: json.scope_spans.into_iter().map(Into::into).collect(),
+            schema_url: json.schema_url,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScopeSpansJson {
+    #[serde(default)]
+    scope: Option<ScopeJson>,
+    #[serde(default)]
+    spans: Vec<SpanJson>,
+    #[serde(default)]
+    schema_url: String,
+}
+
+impl From<ScopeSpansJson> for trace::ScopeSpans {
+    fn from(json: ScopeSpansJson) -> Self {
+        Self {
+            scope: json.scope.map(Into::into),
+            spans: json.spans.into_iter().map(Into::into).collect(),
+            schema_url: json.schema_url,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct SpanJson {
+    #[serde(deserialize_with = "de_hex")]
+    trace_id: Vec<u8>,
+    #[serde(deserialize_with = "de_hex")]
+    span_id: Vec<u8>,
+    trace_state: String,
+    #[serde(deserialize_with = "de_hex")]
+    parent_span_id: Vec<u8>,
+    name: String,
+    kind: i32,
+    #[serde(deserialize_with = "de_u64")]
+    start_time_unix_nano: u64,
+    #[serde(deserialize_with = "de_u64")]
+    end_time_unix_nano: u64,
+    attributes: Vec<KeyValueJson>,
+    dropped_attributes_count: u32,
+    status: Option<StatusJson>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct StatusJson {
+    message: String,
+    code: i32,
+}
+
+impl From<SpanJson> for trace::Span {
+    fn from(json: SpanJson) -> Self {
+        Self {
+            trace_id: json.trace_id,
+            span_id: json.span_id,
+            trace_state: json.trace_state,
+            parent_span_id: json.parent_span_id,
+            name: json.name,
+            kind: json.kind,
+            start_time_unix_nano: json.start_time_unix_nano,
+            end_time_unix_nano: json.end_time_unix_nano,
+            attributes: json.attributes.into_iter().map(Into::into).collect(),
+            dropped_attributes_count: json.dropped_attributes_count,
+            events: Vec::new(),
+            dropped_events_count: 0,
+            links: Vec::new(),
+            dropped_links_count: 0,
+            status: json.status.map(|status| trace::Status {
+                message: status.message,
+                code: status.code,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::{metric::Data, number_data_point::Value};
+
+    const METRICS_JSON: &str = r#"{
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [
+                    {"key": "service.name", "value": {"stringValue": "claude-code"}},
+                    {"key": "host.cpus", "value": {"intValue": "8"}}
+                ]
+            },
+            "scopeMetrics": [{
+                "scope": {"name": "com.anthropic.claude_code", "version": "1.0.0"},
+                "metrics": [
+                    {
+                        "name": "claude_code.token.usage",
+                        "unit": "tokens",
+                        "sum": {
+                            "aggregationTemporality": 2,
+                            "isMonotonic": true,
+                            "dataPoints": [{
+                                "attributes": [{"key": "type", "value": {"stringValue": "input"}}],
+                                "startTimeUnixNano": "1700000000000000000",
+                                "timeUnixNano": "1700000060000000000",
+                                "asInt": "1234"
+                            }]
+                        }
+                    },
+                    {
+                        "name": "claude_code.cost.usage",
+                        "gauge": {
+                            "dataPoints": [{"timeUnixNano": 1700000060000000000, "asDouble": 0.05}]
+                        }
+                    }
+                ]
+            }]
+        }]
+    }"#;
+
+    #[test]
+    fn test_parse_metrics_request() {
+        let request = parse_metrics_request(METRICS_JSON.as_bytes()).unwrap();
+
+        let resource_metrics = &request.resource_metrics[0];
+        let resource = resource_metrics.resource.as_ref().unwrap();
+        assert_eq!(resource.attributes[0].key, "service.name");
+        assert_eq!(
+            resource.attributes[1].value.as_ref().unwrap().value,
+            Some(common::any_value::Value::IntValue(8))
+        );
+
+        let scope_metrics = &resource_metrics.scope_metrics[0];
+        assert_eq!(scope_metrics.scope.as_ref().unwrap().version, "1.0.0");
+
+        let Some(Data::Sum(sum)) = &scope_metrics.metrics[0].data else {
+            panic!("expected a sum");
+        };
+        assert_eq!(sum.aggregation_temporality, 2);
+        assert!(sum.is_monotonic);
+        assert_eq!(sum.data_points[0].time_unix_nano, 1_700_000_060_000_000_000);
+        assert_eq!(sum.data_points[0].value, Some(Value::AsInt(1234)));
+
+        let Some(Data::Gauge(gauge)) = &scope_metrics.metrics[1].data else {
+            panic!("expected a gauge");
+        };
+        assert_eq!(gauge.data_points[0].value, Some(Value::AsDouble(0.05)));
+    }
+
+    #[test]
+    fn test_parse_exponential_histogram_and_summary() {
+        let body = r#"{"resourceMetrics": [{"scopeMetrics": [{"metrics": [
+            {
+                "name": "claude_code.api.latency",
+                "exponentialHistogram": {"aggregationTemporality": 2, "dataPoints": [{
+                    "count": "3", "sum": 1.5, "scale": 2, "zeroCount": "1",
+                    "positive": {"offset": -1, "bucketCounts": ["1", 1]}
+                }]}
+            },
+            {
+                "name": "claude_code.api.latency_summary",
+                "summary": {"dataPoints": [{
+                    "count": "2", "sum": "4.0",
+                    "quantileValues": [{"quantile": 0.5, "value": 1.0}, {"quantile": 1.0, "value": 3.0}]
+                }]}
+            }
+        ]}]}]}"#;
+
+        let request = parse_metrics_request(body.as_bytes()).unwrap();
+        let metrics = &request.resource_metrics[0].scope_metrics[0].metrics;
+
+        let Some(Data::ExponentialHistogram(histogram)) = &metrics[0].data else {
+            panic!("expected an exponential histogram");
+        };
+        let point = &histogram.data_points[0];
+        assert_eq!((point.count, point.sum, point.scale, point.zero_count), (3, Some(1.5), 2, 1));
+        assert_eq!(point.positive.as_ref().unwrap().bucket_counts, vec![1, 1]);
+        assert_eq!(point.positive.as_ref().unwrap().offset, -1);
+
+        let Some(Data::Summary(summary)) = &metrics[1].data else {
+            panic!("expected a summary");
+        };
+        let point = &summary.data_points[0];
+        assert_eq!((point.count, point.sum), (2, 4.0));
+        assert_eq!(point.quantile_values[1].value, 3.0);
+    }
+
+    #[test]
+    fn test_parse_span_ids_from_hex() {
+        let body = r#"{"resourceSpans": [{"scopeSpans": [{"spans": [{
+            "traceId": "5b8efff798038103d269b633813fc60c",
+            "spanId": "eee19b7ec3c1b174",
+            "name": "tool.call",
+            "startTimeUnixNano": "1700000000000000000",
+            "endTimeUnixNano": "1700000000500000000"
+        }]}]}]}"#;
+
+        let request = parse_traces_request(body.as_bytes()).unwrap();
+        let span = &request.resource_spans[0].scope_spans[0].spans[0];
+        assert_eq!(span.trace_id.len(), 16);
+        assert_eq!(span.span_id, vec![0xee, 0xe1, 0x9b, 0x7e, 0xc3, 0xc1, 0xb1, 0x74]);
+        assert!(span.parent_span_id.is_empty());
+    }
+
+    #[test]
+    fn test_malformed_payloads_are_rejected() {
+        assert!(parse_metrics_request(b"{\"resourceMetrics\": [").is_err());
+        let bad_int = r#"{"resourceMetrics": [{"scopeMetrics": [{"metrics": [{"name": "m",
+            "gauge": {"dataPoints": [{"asInt": "twelve"}]}}]}]}]}"#;
+        let err = parse_metrics_request(bad_int.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("invalid int64"));
+    }
+}
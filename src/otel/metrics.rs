@@ -2,52 +2,13 @@ use std::collections::HashMap;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-/// Claude Code specific metric types based on Datadog monitoring patterns
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ClaudeCodeMetricType {
-    // Core session metrics
-    SessionCount,
-    SessionDuration,
-    
-    // Token and cost tracking
-    TokenUsage(TokenType),
-    CostUsage,
-    
-    // Productivity metrics
-    CommitCount,
-    PullRequestCount,
-    LinesOfCode(LinesType),
-    
-    // Tool usage tracking
-    ToolUsage(String),
-    
-    // Error and performance tracking
-    ErrorRate,
-    ResponseTime,
-    
-    // Custom metrics
-    Custom(String),
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum TokenType {
-    Input,
-    Output,
-    Total,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum LinesType {
-    Added,
-    Removed,
-    Modified,
-    Total,
-}
+use super::{classify_metric, MetricType, TokenType};
+use crate::pricing::{PricingTable, TokenCounts};
 
 /// Enhanced metric structure with user context and classification
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EnhancedClaudeMetric {
-    pub metric_type: ClaudeCodeMetricType,
+    pub metric_type: MetricType,
     pub name: String,
     pub value: f64,
     pub timestamp: DateTime<Utc>,
@@ -67,56 +28,10 @@ pub struct EnhancedClaudeMetric {
     pub service: Option<String>,
 }
 
-/// Metric classifier to identify Claude Code metric types
+/// Pulls user and session context out of metric labels
 pub struct MetricClassifier;
 
 impl MetricClassifier {
-    /// Classify a metric based on its name and labels
-    pub fn classify_metric(name: &str, labels: &HashMap<String, String>) -> ClaudeCodeMetricType {
-        match name {
-            // Core Claude Code metrics from the blog
-            "claude_code.session.count" => ClaudeCodeMetricType::SessionCount,
-            "claude_code.token.usage" => {
-                match labels.get("token_type").map(|s| s.as_str()) {
-                    Some("input") => ClaudeCodeMetricType::TokenUsage(TokenType::Input),
-                    Some("output") => ClaudeCodeMetricType::TokenUsage(TokenType::Output),
-                    _ => ClaudeCodeMetricType::TokenUsage(TokenType::Total),
-                }
-            },
-            "claude_code.cost.usage" => ClaudeCodeMetricType::CostUsage,
-            "claude_code.commit.count" => ClaudeCodeMetricType::CommitCount,
-            "claude_code.pull_request.count" => ClaudeCodeMetricType::PullRequestCount,
-            "claude_code.lines_of_code.count" => {
-                match labels.get("change_type").map(|s| s.as_str()) {
-                    Some("added") => ClaudeCodeMetricType::LinesOfCode(LinesType::Added),
-                    Some("removed") => ClaudeCodeMetricType::LinesOfCode(LinesType::Removed),
-                    Some("modified") => ClaudeCodeMetricType::LinesOfCode(LinesType::Modified),
-                    _ => ClaudeCodeMetricType::LinesOfCode(LinesType::Total),
-                }
-            },
-            
-            // Tool usage metrics
-            name if name.starts_with("claude_code.tool.") => {
-                let tool_name = name.strip_prefix("claude_code.tool.")
-                    .unwrap_or("unknown")
-                    .to_string();
-                ClaudeCodeMetricType::ToolUsage(tool_name)
-            },
-            
-            // Session duration
-            "claude_code.session.duration" => ClaudeCodeMetricType::SessionDuration,
-            
-            // Error metrics
-            "claude_code.error.rate" => ClaudeCodeMetricType::ErrorRate,
-            
-            // Performance metrics
-            "claude_code.response.time" => ClaudeCodeMetricType::ResponseTime,
-            
-            // Fallback to custom metric
-            _ => ClaudeCodeMetricType::Custom(name.to_string()),
-        }
-    }
-    
     /// Extract user context from metric labels
     pub fn extract_user_context(labels: &HashMap<String, String>) -> UserContext {
         UserContext {
@@ -160,7 +75,7 @@ impl EnhancedClaudeMetric {
         timestamp: DateTime<Utc>,
         labels: HashMap<String, String>,
     ) -> Self {
-        let metric_type = MetricClassifier::classify_metric(&name, &labels);
+        let metric_type = classify_metric(&name, &labels);
         let user_context = MetricClassifier::extract_user_context(&labels);
         let session_context = MetricClassifier::extract_session_context(&labels);
         
@@ -182,88 +97,117 @@ impl EnhancedClaudeMetric {
     
     /// Check if this metric represents a cost-related measurement
     pub fn is_cost_metric(&self) -> bool {
-        matches!(self.metric_type, ClaudeCodeMetricType::CostUsage)
+        matches!(self.metric_type, MetricType::CostUsage { .. })
     }
     
     /// Check if this metric represents token usage
     pub fn is_token_metric(&self) -> bool {
-        matches!(self.metric_type, ClaudeCodeMetricType::TokenUsage(_))
+        matches!(self.metric_type, MetricType::TokenUsage { .. })
     }
     
     /// Check if this metric represents productivity data
     pub fn is_productivity_metric(&self) -> bool {
         matches!(
             self.metric_type,
-            ClaudeCodeMetricType::CommitCount
-                | ClaudeCodeMetricType::PullRequestCount
-                | ClaudeCodeMetricType::LinesOfCode(_)
+            MetricType::CommitCount
+                | MetricType::PullRequestCount
+                | MetricType::LinesOfCode { .. }
         )
     }
     
     /// Get metric category for grouping
     pub fn get_category(&self) -> MetricCategory {
         match &self.metric_type {
-            ClaudeCodeMetricType::SessionCount | ClaudeCodeMetricType::SessionDuration => {
-                MetricCategory::Session
-            }
-            ClaudeCodeMetricType::TokenUsage(_) => MetricCategory::Usage,
-            ClaudeCodeMetricType::CostUsage => MetricCategory::Cost,
-            ClaudeCodeMetricType::CommitCount
-            | ClaudeCodeMetricType::PullRequestCount
-            | ClaudeCodeMetricType::LinesOfCode(_) => MetricCategory::Productivity,
-            ClaudeCodeMetricType::ToolUsage(_) => MetricCategory::Tools,
-            ClaudeCodeMetricType::ErrorRate => MetricCategory::Errors,
-            ClaudeCodeMetricType::ResponseTime => MetricCategory::Performance,
-            ClaudeCodeMetricType::Custom(_) => MetricCategory::Custom,
+            MetricType::SessionCount => MetricCategory::Session,
+            MetricType::TokenUsage { .. } => MetricCategory::Usage,
+            MetricType::CostUsage { .. } => MetricCategory::Cost,
+            MetricType::CommitCount
+            | MetricType::PullRequestCount
+            | MetricType::LinesOfCode { .. } => MetricCategory::Productivity,
+            MetricType::Other => MetricCategory::Custom,
         }
     }
 }
 
+/// Cost in USD of a batch of metrics: the sum of its `cost.usage` points when there are
+/// any, otherwise its token usage priced per `model` label. Tokens of unpriced models count as free.
+pub fn cost_or_derived(metrics: &[EnhancedClaudeMetric], pricing: &PricingTable) -> f64 {
+    if metrics.iter().any(EnhancedClaudeMetric::is_cost_metric) {
+        return metrics.iter().filter(|metric| metric.is_cost_metric()).map(|metric| metric.value).sum();
+    }
+
+    let mut tokens_by_model: HashMap<&str, TokenCounts> = HashMap::new();
+    for metric in metrics {
+        let MetricType::TokenUsage { token_type } = &metric.metric_type else { continue };
+        let Some(model) = metric.labels.get("model") else { continue };
+        let tokens = tokens_by_model.entry(model).or_default();
+        match token_type {
+            TokenType::Input => tokens.input += metric.value,
+            TokenType::Output => tokens.output += metric.value,
+            TokenType::CacheRead => tokens.cache_read += metric.value,
+            TokenType::CacheCreation => tokens.cache_creation += metric.value,
+        }
+    }
+
+    tokens_by_model
+        .into_iter()
+        .filter_map(|(model, tokens)| pricing.cost(model, tokens))
+        .sum()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MetricCategory {
     Session,
     Usage,
     Cost,
     Productivity,
-    Tools,
-    Errors,
-    Performance,
     Custom,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::otel::{CodeChangeType, TokenType};
     
     #[test]
-    fn test_metric_classification() {
-        let labels = HashMap::new();
-        
-        assert!(matches!(
-            MetricClassifier::classify_metric("claude_code.session.count", &labels),
-            ClaudeCodeMetricType::SessionCount
-        ));
-        
-        assert!(matches!(
-            MetricClassifier::classify_metric("claude_code.cost.usage", &labels),
-            ClaudeCodeMetricType::CostUsage
-        ));
-        
-        assert!(matches!(
-            MetricClassifier::classify_metric("claude_code.tool.read", &labels),
-            ClaudeCodeMetricType::ToolUsage(_)
-        ));
+    fn test_enhanced_metric_uses_the_shared_classifier() {
+        // Claude Code labels token usage with `type`, which this path used to ignore
+        let labels = HashMap::from([("type".to_string(), "output".to_string())]);
+        let metric = EnhancedClaudeMetric::from_basic_metric("claude_code.token.usage".to_string(), 42.0, Utc::now(), labels);
+        assert!(matches!(metric.metric_type, MetricType::TokenUsage { token_type: TokenType::Output }));
+        assert!(matches!(metric.get_category(), MetricCategory::Usage));
+
+        let labels = HashMap::from([("change_type".to_string(), "removed".to_string())]);
+        let metric = EnhancedClaudeMetric::from_basic_metric("claude_code.lines_of_code.count".to_string(), 3.0, Utc::now(), labels);
+        assert!(matches!(metric.metric_type, MetricType::LinesOfCode { change_type: CodeChangeType::Removed }));
+        assert!(metric.is_productivity_metric());
     }
-    
+
+    fn metric(name: &str, value: f64, labels: &[(&str, &str)]) -> EnhancedClaudeMetric {
+        let labels = labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        EnhancedClaudeMetric::from_basic_metric(name.to_string(), value, Utc::now(), labels)
+    }
+
     #[test]
-    fn test_token_type_classification() {
-        let mut labels = HashMap::new();
-        labels.insert("token_type".to_string(), "input".to_string());
-        
-        assert!(matches!(
-            MetricClassifier::classify_metric("claude_code.token.usage", &labels),
-            ClaudeCodeMetricType::TokenUsage(TokenType::Input)
-        ));
+    fn test_cost_is_derived_from_tokens_without_cost_metrics() {
+        let pricing = PricingTable::default();
+        let tokens = [
+            metric("claude_code.token.usage", 1_000_000.0, &[("type", "input"), ("model", "claude-sonnet-4-20250514")]),
+            metric("claude_code.token.usage", 100_000.0, &[("type", "output"), ("model", "claude-sonnet-4-20250514")]),
+            metric("claude_code.token.usage", 1_000_000.0, &[("type", "cache_read"), ("model", "claude-3-5-haiku")]),
+            // No price, and no model at all
+            metric("claude_code.token.usage", 1_000_000.0, &[("type", "input"), ("model", "gpt-4")]),
+            metric("claude_code.token.usage", 1_000_000.0, &[("type", "input")]),
+        ];
+        // 3.00 input + 1.50 output for Sonnet, 0.08 cache reads for Haiku
+        assert!((cost_or_derived(&tokens, &pricing) - 4.58).abs() < 1e-9);
+
+        // An explicit cost wins over the token estimate
+        let mut with_cost = tokens.to_vec();
+        with_cost.push(metric("claude_code.cost.usage", 0.42, &[("model", "claude-sonnet-4")]));
+        assert_eq!(cost_or_derived(&with_cost, &pricing), 0.42);
+
+        assert_eq!(cost_or_derived(&[], &pricing), 0.0);
     }
     
     #[test]
@@ -1,5 +1,12 @@
 pub mod receiver;
+pub mod aggregation;
+pub mod temporality;
+pub mod queue;
 pub mod metrics;
+pub mod auth;
+pub mod http;
+pub mod idempotency;
+pub mod json;
 
 use std::collections::HashMap;
 use chrono::{DateTime, Utc};
@@ -86,10 +93,11 @@ pub fn validate_claude_code_event(event_name: &str) -> bool {
     CLAUDE_CODE_EVENTS.contains(&event_name)
 }
 
+/// The one place metric names and labels map to a `MetricType`; every ingest and read path uses it
 pub fn classify_metric(name: &str, labels: &HashMap<String, String>) -> MetricType {
     match name {
         "claude_code.token.usage" => {
-            let token_type = match labels.get("type").map(|s| s.as_str()) {
+            let token_type = match label(labels, &["type", "token_type"]) {
                 Some("input") => TokenType::Input,
                 Some("output") => TokenType::Output,
                 Some("cache_creation") => TokenType::CacheCreation,
@@ -106,7 +114,7 @@ pub fn classify_metric(name: &str, labels: &HashMap<String, String>) -> MetricTy
         }
         "claude_code.session.count" => MetricType::SessionCount,
         "claude_code.lines_of_code.count" => {
-            let change_type = match labels.get("type").map(|s| s.as_str()) {
+            let change_type = match label(labels, &["type", "change_type"]) {
                 Some("added") => CodeChangeType::Added,
                 Some("removed") => CodeChangeType::Removed,
                 _ => CodeChangeType::Added, // Default
@@ -119,6 +127,11 @@ pub fn classify_metric(name: &str, labels: &HashMap<String, String>) -> MetricTy
     }
 }
 
+// Claude Code sends `type`; older exporters spelled it `token_type` / `change_type`
+fn label<'a>(labels: &'a HashMap<String, String>, keys: &[&str]) -> Option<&'a str> {
+    keys.iter().find_map(|key| labels.get(*key)).map(String::as_str)
+}
+
 pub fn classify_event(name: &str, attributes: &HashMap<String, String>) -> EventType {
     match name {
         "user_prompt_submitted" => EventType::UserPromptSubmitted,
@@ -225,19 +238,24 @@ impl SessionSummary {
     }
     
     pub fn update_from_event(&mut self, event: &ProcessedEvent) {
-        match &event.event_type {
+        self.add_events(&event.event_type, 1);
+        self.last_updated = Utc::now();
+    }
+
+    /// Count `count` occurrences of one event type at once
+    pub fn add_events(&mut self, event_type: &EventType, count: u64) {
+        match event_type {
             EventType::ToolResult { tool_name } => {
-                *self.tool_usage.entry(tool_name.clone()).or_insert(0) += 1;
+                *self.tool_usage.entry(tool_name.clone()).or_insert(0) += count;
             }
             EventType::ApiRequest { .. } => {
-                self.api_requests += 1;
+                self.api_requests += count;
             }
             EventType::ApiRequestFailed { .. } => {
-                self.api_failures += 1;
+                self.api_failures += count;
             }
             _ => {} // Ignore other events for summary
         }
-        self.last_updated = Utc::now();
     }
 }
 
@@ -262,6 +280,31 @@ mod tests {
             _ => panic!("Expected TokenUsage with Input type"),
         }
     }
+
+    #[test]
+    fn test_classify_metric_accepts_both_label_spellings() {
+        let labels = |key: &str, value: &str| HashMap::from([(key.to_string(), value.to_string())]);
+
+        for key in ["type", "token_type"] {
+            assert!(matches!(
+                classify_metric("claude_code.token.usage", &labels(key, "cache_read")),
+                MetricType::TokenUsage { token_type: TokenType::CacheRead }
+            ), "{}", key);
+        }
+        for key in ["type", "change_type"] {
+            assert!(matches!(
+                classify_metric("claude_code.lines_of_code.count", &labels(key, "removed")),
+                MetricType::LinesOfCode { change_type: CodeChangeType::Removed }
+            ), "{}", key);
+        }
+        // `type` wins when both are present
+        let mut both = labels("type", "output");
+        both.insert("token_type".to_string(), "input".to_string());
+        assert!(matches!(
+            classify_metric("claude_code.token.usage", &both),
+            MetricType::TokenUsage { token_type: TokenType::Output }
+        ));
+    }
     
     #[test] 
     fn test_session_summary_update() {
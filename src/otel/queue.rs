@@ -0,0 +1,190 @@
+// Bounded queue between the export handlers and storage: handlers parse and enqueue,
+// a single writer task stores, so a slow disk doesn't hold exporters until they time out
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::otel::receiver::{OtelReceiver, Rejected};
+use crate::shutdown::Shutdown;
+use crate::storage::{LogRecord, MetricRecord, TraceRecord};
+
+/// Sessions seen in an export: id -> (user, earliest timestamp)
+pub type SessionStarts = HashMap<Uuid, (String, DateTime<Utc>)>;
+
+/// Parsed records of one export, ready to be stored
+#[derive(Debug)]
+pub enum IngestBatch {
+    Metrics {
+        sessions: SessionStarts,
+        metrics: Vec<MetricRecord>,
+    },
+    Logs {
+        sessions: SessionStarts,
+        logs: Vec<LogRecord>,
+        /// Command events per session
+        commands: HashMap<Uuid, u64>,
+    },
+    Traces {
+        sessions: SessionStarts,
+        traces: Vec<TraceRecord>,
+    },
+}
+
+impl IngestBatch {
+    /// Records the batch would store
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Metrics { metrics, .. } => metrics.len(),
+            Self::Logs { logs, .. } => logs.len(),
+            Self::Traces { traces, .. } => traces.len(),
+        }
+    }
+
+    /// True when storing the batch would change nothing
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Metrics { sessions, metrics } => sessions.is_empty() && metrics.is_empty(),
+            Self::Logs { sessions, logs, commands } => sessions.is_empty() && logs.is_empty() && commands.is_empty(),
+            Self::Traces { sessions, traces } => sessions.is_empty() && traces.is_empty(),
+        }
+    }
+}
+
+/// What an export does when the queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueFullPolicy {
+    /// Wait for room, pushing back on the exporter
+    #[default]
+    Block,
+    /// Drop the export's records and report them as rejected
+    Shed,
+}
+
+impl QueueFullPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "block" => Some(Self::Block),
+            "shed" => Some(Self::Shed),
+            _ => None,
+        }
+    }
+}
+
+/// Why a batch was handed back instead of queued
+#[derive(Debug)]
+pub enum EnqueueError {
+    /// The queue is full and the policy is to shed
+    Full(IngestBatch),
+    /// The writer has stopped
+    Closed(IngestBatch),
+}
+
+#[derive(Clone)]
+pub struct IngestQueue {
+    sender: mpsc::Sender<IngestBatch>,
+    policy: QueueFullPolicy,
+}
+
+impl IngestQueue {
+    pub fn new(depth: usize, policy: QueueFullPolicy) -> (Self, mpsc::Receiver<IngestBatch>) {
+        let (sender, receiver) = mpsc::channel(depth);
+        (Self { sender, policy }, receiver)
+    }
+
+    pub async fn enqueue(&self, batch: IngestBatch) -> Result<(), EnqueueError> {
+        match self.policy {
+            QueueFullPolicy::Block => self.sender.send(batch).await.map_err(|e| EnqueueError::Closed(e.0)),
+            QueueFullPolicy::Shed => self.sender.try_send(batch).map_err(|e| match e {
+                mpsc::error::TrySendError::Full(batch) => EnqueueError::Full(batch),
+                mpsc::error::TrySendError::Closed(batch) => EnqueueError::Closed(batch),
+            }),
+        }
+    }
+
+    /// Batches waiting for the writer
+    pub fn depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.sender.max_capacity()
+    }
+}
+
+/// Store queued batches through `store` until `shutdown`, then drain what is left and stop
+pub fn spawn_writer(store: OtelReceiver, mut queue: mpsc::Receiver<IngestBatch>, shutdown: Shutdown) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut shutdown = std::pin::pin!(shutdown.wait());
+        loop {
+            tokio::select! {
+                batch = queue.recv() => match batch {
+                    Some(batch) => write(&store, batch).await,
+                    None => return,
+                },
+                _ = &mut shutdown => break,
+            }
+        }
+
+        queue.close();
+        if !queue.is_empty() {
+            info!("Storing {} queued export(s) before exiting", queue.len());
+        }
+        while let Some(batch) = queue.recv().await {
+            write(&store, batch).await;
+        }
+    })
+}
+
+// The exporter has already been answered, so failures can only be logged
+async fn write(store: &OtelReceiver, batch: IngestBatch) {
+    let records = batch.len();
+    let mut rejected = Rejected::default();
+    match store.store_batch(batch, &mut rejected).await {
+        Ok(()) if rejected.count == 0 => debug!("Stored {} queued record(s)", records),
+        Ok(()) => warn!("Dropped {} of {} queued record(s): {}", rejected.count, records, rejected.message()),
+        Err(e) => warn!("Failed to store {} queued record(s): {}", records, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn traces(count: usize) -> IngestBatch {
+        let trace = TraceRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            trace_id: "t".to_string(),
+            span_id: "s".to_string(),
+            parent_span_id: None,
+            name: "span".to_string(),
+            start_time: Utc::now(),
+            end_time: Utc::now(),
+            duration_ns: 0,
+            attributes: HashMap::new(),
+            created_at: Utc::now(),
+        };
+        IngestBatch::Traces { sessions: SessionStarts::new(), traces: vec![trace; count] }
+    }
+
+    #[tokio::test]
+    async fn test_shed_policy_hands_back_batches_once_full() {
+        let (queue, mut receiver) = IngestQueue::new(1, QueueFullPolicy::Shed);
+        queue.enqueue(traces(2)).await.unwrap();
+        assert_eq!(queue.depth(), 1);
+
+        let Err(EnqueueError::Full(batch)) = queue.enqueue(traces(3)).await else {
+            panic!("expected a full queue");
+        };
+        assert_eq!(batch.len(), 3);
+
+        assert_eq!(receiver.recv().await.unwrap().len(), 2);
+        assert_eq!(queue.depth(), 0);
+        drop(receiver);
+        assert!(matches!(queue.enqueue(traces(1)).await, Err(EnqueueError::Closed(_))));
+    }
+}
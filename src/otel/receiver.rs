@@ -1,5 +1,19 @@
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use tonic::{transport::Server, Request, Response, Status};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::broadcast,
+};
+#[cfg(unix)]
+use tokio_stream::wrappers::UnixListenerStream;
+use tokio_stream::{wrappers::TcpListenerStream, Stream};
+use tonic::{
+    codec::CompressionEncoding,
+    codegen::{http, InterceptedService},
+    transport::{server::Connected, Server},
+    Code, Request, Response, Status,
+};
+use tower::util::MapResponseLayer;
 use tracing::{info, warn, error, debug};
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
@@ -7,25 +21,153 @@ use uuid::Uuid;
 use opentelemetry_proto::tonic::collector::{
     metrics::v1::{
         metrics_service_server::{MetricsService, MetricsServiceServer},
-        ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+        ExportMetricsPartialSuccess, ExportMetricsServiceRequest, ExportMetricsServiceResponse,
     },
     logs::v1::{
         logs_service_server::{LogsService, LogsServiceServer}, 
-        ExportLogsServiceRequest, ExportLogsServiceResponse,
+        ExportLogsPartialSuccess, ExportLogsServiceRequest, ExportLogsServiceResponse,
+    },
+    trace::v1::{
+        trace_service_server::{TraceService, TraceServiceServer},
+        ExportTracePartialSuccess, ExportTraceServiceRequest, ExportTraceServiceResponse,
     },
 };
+use opentelemetry_proto::tonic::resource::v1::Resource;
 
-use crate::storage::{Database, DatabaseError, MetricRecord, LogRecord};
+use crate::otel::aggregation::{Aggregation, MetricAggregator};
+use crate::otel::temporality::CumulativeDeltas;
+use crate::otel::auth::IngestAuth;
+use crate::otel::queue::{EnqueueError, IngestBatch, IngestQueue, SessionStarts};
+use crate::shutdown::Shutdown;
+use crate::stats::IngestStats;
+use crate::storage::{Database, DatabaseError, MetricRecord, LogRecord, TraceRecord};
 use crate::otel::metrics::{EnhancedClaudeMetric, MetricClassifier};
+use crate::config::{OtelListen, DEFAULT_COMMAND_EVENTS};
+
+/// Every metric stored by the receivers, for live-tail subscribers
+pub type MetricFeed = broadcast::Sender<MetricRecord>;
 
 #[derive(Clone)]
 pub struct OtelReceiver {
     db: Arc<dyn Database>,
+    stats: Arc<IngestStats>,
+    feed: Option<MetricFeed>,
+    command_events: Arc<[String]>,
+    aggregator: Option<Arc<MetricAggregator>>,
+    cumulative: Arc<CumulativeDeltas>,
+    queue: Option<IngestQueue>,
 }
 
 impl OtelReceiver {
-    pub fn new(db: Arc<dyn Database>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<dyn Database>, stats: Arc<IngestStats>) -> Self {
+        let command_events = DEFAULT_COMMAND_EVENTS.iter().map(|event| event.to_string()).collect();
+        Self {
+            db,
+            stats,
+            feed: None,
+            command_events,
+            aggregator: None,
+            cumulative: Arc::new(CumulativeDeltas::new()),
+            queue: None,
+        }
+    }
+
+    pub fn with_feed(mut self, feed: MetricFeed) -> Self {
+        self.feed = Some(feed);
+        self
+    }
+
+    /// Log events that each count as one command of their session
+    pub fn with_command_events(mut self, events: &[String]) -> Self {
+        self.command_events = events.into();
+        self
+    }
+
+    /// Buffer counters and gauges in `aggregator` until `flush_aggregated` instead of storing each point
+    pub fn with_aggregator(mut self, aggregator: Option<Arc<MetricAggregator>>) -> Self {
+        self.aggregator = aggregator;
+        self
+    }
+
+    /// Track cumulative sums in `cumulative`, shared with the other receivers
+    pub fn with_cumulative(mut self, cumulative: Arc<CumulativeDeltas>) -> Self {
+        self.cumulative = cumulative;
+        self
+    }
+
+    /// Hand parsed exports to the writer behind `queue` instead of storing them before answering
+    pub fn with_queue(mut self, queue: Option<IngestQueue>) -> Self {
+        self.queue = queue;
+        self
+    }
+}
+
+/// Records of one export that failed to parse, queue or store, reported back to the exporter as partial success
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rejected {
+    pub count: i64,
+    /// The first parse or store error, as an example of what went wrong
+    pub first_error: Option<String>,
+}
+
+impl Rejected {
+    fn add(&mut self, count: i64, error: String) {
+        self.count += count;
+        self.first_error.get_or_insert(error);
+    }
+
+    pub fn message(&self) -> String {
+        match &self.first_error {
+            Some(error) => format!("Rejected {} record(s); first error: {}", self.count, error),
+            None => String::new(),
+        }
+    }
+}
+
+/// An Export*ServiceResponse, carrying partial success when records were rejected
+pub trait ExportResponse: prost::Message + Default {
+    /// OTLP/JSON name of the partial success count field
+    const REJECTED_FIELD: &'static str;
+
+    fn from_rejected(rejected: &Rejected) -> Self;
+}
+
+impl ExportResponse for ExportMetricsServiceResponse {
+    const REJECTED_FIELD: &'static str = "rejectedDataPoints";
+
+    fn from_rejected(rejected: &Rejected) -> Self {
+        Self {
+            partial_success: (rejected.count > 0).then(|| ExportMetricsPartialSuccess {
+                rejected_data_points: rejected.count,
+                error_message: rejected.message(),
+            }),
+        }
+    }
+}
+
+impl ExportResponse for ExportLogsServiceResponse {
+    const REJECTED_FIELD: &'static str = "rejectedLogRecords";
+
+    fn from_rejected(rejected: &Rejected) -> Self {
+        Self {
+            partial_success: (rejected.count > 0).then(|| ExportLogsPartialSuccess {
+                rejected_log_records: rejected.count,
+                error_message: rejected.message(),
+            }),
+        }
+    }
+}
+
+impl ExportResponse for ExportTraceServiceResponse {
+    const REJECTED_FIELD: &'static str = "rejectedSpans";
+
+    fn from_rejected(rejected: &Rejected) -> Self {
+        Self {
+            partial_success: (rejected.count > 0).then(|| ExportTracePartialSuccess {
+                rejected_spans: rejected.count,
+                error_message: rejected.message(),
+            }),
+        }
     }
 }
 
@@ -37,6 +179,12 @@ pub struct ClaudeCodeMetric {
     pub timestamp: DateTime<Utc>,
     pub labels: HashMap<String, String>,
     pub session_id: Option<String>,
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    /// A running total of a cumulative sum rather than an increase
+    pub cumulative: bool,
+    /// When a cumulative sum's counter started, if the exporter says
+    pub start_time: Option<DateTime<Utc>>,
 }
 
 // Claude Code specific log event
@@ -48,36 +196,30 @@ pub struct ClaudeCodeEvent {
     pub session_id: Option<String>,
 }
 
-#[tonic::async_trait]
-impl MetricsService for OtelReceiver {
-    async fn export(
-        &self,
-        request: Request<ExportMetricsServiceRequest>,
-    ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
-        let req = request.into_inner();
-        
+impl OtelReceiver {
+    /// Parse and store a metrics export; shared by the gRPC and HTTP receivers
+    pub async fn ingest_metrics(&self, req: ExportMetricsServiceRequest) -> Result<Rejected, DatabaseError> {
+        if self.db.is_read_only() {
+            return Err(DatabaseError::ReadOnly);
+        }
+
         info!("Received {} metric resource(s)", req.resource_metrics.len());
         
         let mut metrics_to_store = Vec::new();
+        let mut rejected = Rejected::default();
+        let mut sessions = SessionStarts::new();
         
         // Process each resource metric
         for resource_metrics in req.resource_metrics {
-            // Extract resource attributes
-            let mut resource_attrs = HashMap::new();
-            if let Some(resource) = resource_metrics.resource {
-                for attr in resource.attributes {
-                    if let Some(value) = attr.value {
-                        if let Some(value_data) = value.value {
-                            resource_attrs.insert(attr.key, extract_attribute_value(value_data));
-                        }
-                    }
-                }
-            }
+            let resource_attrs = resource_attributes(resource_metrics.resource);
+            let service = resource_attrs.get("service.name").cloned();
             
             // Process scope metrics
             for scope_metrics in resource_metrics.scope_metrics {
                 for metric in scope_metrics.metrics {
                     let metric_name = metric.name.clone();
+                    let data_points = data_point_count(&metric);
+                    let aggregation = self.aggregator.as_ref().zip(aggregation_of(&metric));
                     match parse_claude_code_metric(metric, &resource_attrs) {
                         Ok(parsed_metrics) => {
                             for claude_metric in parsed_metrics {
@@ -95,67 +237,85 @@ impl MetricsService for OtelReceiver {
                                 debug!("Enhanced metric type: {:?}, User: {:?}", 
                                     enhanced_metric.metric_type, enhanced_metric.user_email);
                                 
-                                let metric_record = MetricRecord {
+                                let session_id = enhanced_metric.session_id.as_deref()
+                                    .and_then(|s| Uuid::parse_str(s).ok());
+                                if let Some(id) = session_id {
+                                    let user = enhanced_metric.user_email.as_deref().or(enhanced_metric.user_id.as_deref());
+                                    note_session(&mut sessions, id, user, enhanced_metric.timestamp);
+                                }
+
+                                let mut metric_record = MetricRecord {
                                     id: Uuid::new_v4(),
-                                    session_id: enhanced_metric.session_id
-                                        .and_then(|s| Uuid::parse_str(&s).ok()),
+                                    session_id,
                                     name: enhanced_metric.name,
                                     timestamp: enhanced_metric.timestamp,
                                     value: enhanced_metric.value,
                                     labels: enhanced_metric.labels,
+                                    unit: claude_metric.unit,
+                                    description: claude_metric.description,
+                                    service: service.clone(),
                                     created_at: Utc::now(),
                                 };
+                                if claude_metric.cumulative {
+                                    if !self.cumulative.tracks(&metric_record) {
+                                        // Start from what is stored, so a restart doesn't count the total again
+                                        match self.db.series_total(&metric_record, claude_metric.start_time).await {
+                                            Ok(stored) => self.cumulative.seed(&metric_record, claude_metric.start_time, stored),
+                                            Err(DatabaseError::ReadOnly) => return Err(DatabaseError::ReadOnly),
+                                            Err(e) => {
+                                                warn!("Failed to read the stored total of {}: {}", metric_record.name, e);
+                                                self.stats.record_errors(1);
+                                                rejected.add(1, format!("Failed to read the stored total of {}: {}", metric_record.name, e));
+                                                continue;
+                                            }
+                                        }
+                                    }
+                                    match self.cumulative.delta(&metric_record, claude_metric.start_time) {
+                                        Some(delta) => metric_record.value = delta,
+                                        None => {
+                                            debug!("Skipping out-of-order cumulative point of {}", metric_record.name);
+                                            continue;
+                                        }
+                                    }
+                                }
                                 
-                                metrics_to_store.push(metric_record);
+                                match aggregation {
+                                    Some((aggregator, aggregation)) => aggregator.add(metric_record, aggregation, &sessions),
+                                    None => metrics_to_store.push(metric_record),
+                                }
                             }
                         }
                         Err(e) => {
                             warn!("Failed to parse metric {}: {}", metric_name, e);
+                            self.stats.record_errors(1);
+                            rejected.add(data_points, e);
                         }
                     }
                 }
             }
         }
         
-        // Batch store metrics
-        if !metrics_to_store.is_empty() {
-            match store_metrics_batch(&*self.db, metrics_to_store).await {
-                Ok(_) => info!("Successfully stored metrics batch"),
-                Err(e) => error!("Failed to store metrics: {}", e),
-            }
-        }
-        
-        Ok(Response::new(ExportMetricsServiceResponse {
-            partial_success: None,
-        }))
+        self.dispatch(IngestBatch::Metrics { sessions, metrics: metrics_to_store }, rejected).await
     }
-}
 
-#[tonic::async_trait]
-impl LogsService for OtelReceiver {
-    async fn export(
-        &self,
-        request: Request<ExportLogsServiceRequest>,
-    ) -> Result<Response<ExportLogsServiceResponse>, Status> {
-        let req = request.into_inner();
-        
+    /// Parse and store a logs export; shared by the gRPC and HTTP receivers
+    pub async fn ingest_logs(&self, req: ExportLogsServiceRequest) -> Result<Rejected, DatabaseError> {
+        if self.db.is_read_only() {
+            return Err(DatabaseError::ReadOnly);
+        }
+
         info!("Received {} log resource(s)", req.resource_logs.len());
         
         let mut logs_to_store = Vec::new();
+        let mut rejected = Rejected::default();
+        // Sessions seen in this export: id -> (user, earliest timestamp)
+        let mut sessions = SessionStarts::new();
+        let mut commands: HashMap<Uuid, u64> = HashMap::new();
         
         // Process each resource log
         for resource_logs in req.resource_logs {
-            // Extract resource attributes
-            let mut resource_attrs = HashMap::new();
-            if let Some(resource) = resource_logs.resource {
-                for attr in resource.attributes {
-                    if let Some(value) = attr.value {
-                        if let Some(value_data) = value.value {
-                            resource_attrs.insert(attr.key, extract_attribute_value(value_data));
-                        }
-                    }
-                }
-            }
+            let resource_attrs = resource_attributes(resource_logs.resource);
+            let service = resource_attrs.get("service.name").cloned();
             
             // Process scope logs
             for scope_logs in resource_logs.scope_logs {
@@ -164,14 +324,26 @@ impl LogsService for OtelReceiver {
                         Ok(claude_event) => {
                             debug!("Processing Claude Code event: {}", claude_event.event_type);
                             
+                            let session_id = claude_event.session_id.as_deref()
+                                .and_then(|s| Uuid::parse_str(s).ok());
+                            let is_command = self.command_events.contains(&claude_event.event_type);
+                            if let Some(id) = session_id {
+                                if is_command {
+                                    *commands.entry(id).or_default() += 1;
+                                }
+                                let user = claude_event.attributes.get("user.email")
+                                    .or_else(|| claude_event.attributes.get("user.id"));
+                                note_session(&mut sessions, id, user.map(String::as_str), claude_event.timestamp);
+                            }
+                            
                             let log_record = LogRecord {
                                 id: Uuid::new_v4(),
-                                session_id: claude_event.session_id
-                                    .and_then(|s| Uuid::parse_str(&s).ok()),
+                                session_id,
                                 timestamp: claude_event.timestamp,
                                 level: "INFO".to_string(), // Claude Code events are typically info level
                                 message: claude_event.event_type.clone(),
                                 attributes: claude_event.attributes,
+                                service: service.clone(),
                                 created_at: Utc::now(),
                             };
                             
@@ -179,23 +351,226 @@ impl LogsService for OtelReceiver {
                         }
                         Err(e) => {
                             warn!("Failed to parse log record: {}", e);
+                            self.stats.record_errors(1);
+                            rejected.add(1, e);
                         }
                     }
                 }
             }
         }
         
-        // Batch store logs
-        if !logs_to_store.is_empty() {
-            match store_logs_batch(&*self.db, logs_to_store).await {
-                Ok(_) => info!("Successfully stored logs batch"),
-                Err(e) => error!("Failed to store logs: {}", e),
+        self.dispatch(IngestBatch::Logs { sessions, logs: logs_to_store, commands }, rejected).await
+    }
+
+    // Store now, or hand the batch to the writer task when a queue is configured
+    async fn dispatch(&self, batch: IngestBatch, mut rejected: Rejected) -> Result<Rejected, DatabaseError> {
+        if batch.is_empty() {
+            return Ok(rejected);
+        }
+        let Some(queue) = &self.queue else {
+            self.store_batch(batch, &mut rejected).await?;
+            return Ok(rejected);
+        };
+
+        match queue.enqueue(batch).await {
+            Ok(()) => debug!("Ingestion queue holds {} of {} export(s)", queue.depth(), queue.capacity()),
+            Err(EnqueueError::Full(batch)) => {
+                if let IngestBatch::Metrics { metrics, .. } = &batch {
+                    metrics.iter().for_each(|metric| self.cumulative.give_back(metric));
+                }
+                // A batch holding only sessions or command counts still loses them, so it
+                // rejects at least one record and the exporter hears about it
+                let count = batch.len().max(1) as u64;
+                warn!("Ingestion queue is full; shedding {} record(s)", count);
+                self.stats.record_shed(count);
+                rejected.add(count as i64, "Ingestion queue is full".to_string());
             }
+            // Only once shutdown has stopped the writer; storing directly loses nothing
+            Err(EnqueueError::Closed(batch)) => self.store_batch(batch, &mut rejected).await?,
         }
-        
-        Ok(Response::new(ExportLogsServiceResponse {
-            partial_success: None,
-        }))
+        Ok(rejected)
+    }
+
+    /// Store a parsed export, adding records that fail to `rejected`; only a read-only database fails it
+    pub async fn store_batch(&self, batch: IngestBatch, rejected: &mut Rejected) -> Result<(), DatabaseError> {
+        match batch {
+            IngestBatch::Metrics { sessions, metrics } => {
+                // Create sessions first so the metrics' session_id links resolve
+                self.upsert_sessions(sessions).await?;
+                if !metrics.is_empty() {
+                    let count = metrics.len() as u64;
+                    let (stored, duplicates) = store_metrics_batch(&*self.db, metrics, &SessionStarts::new(), &self.cumulative, rejected).await?;
+                    info!("Stored {} of {} metric(s), {} already stored", stored.len(), count, duplicates);
+                    self.stats.record_metrics(stored.len() as u64);
+                    self.stats.record_deduplicated(duplicates);
+                    self.stats.record_errors(count - stored.len() as u64 - duplicates);
+                    self.publish(stored);
+                }
+            }
+            IngestBatch::Logs { sessions, logs, commands } => {
+                // Create sessions first so the logs' session_id links resolve
+                self.upsert_sessions(sessions).await?;
+                if !logs.is_empty() {
+                    let count = logs.len() as u64;
+                    let stored = store_logs_batch(&*self.db, logs, rejected).await?;
+                    info!("Stored {} of {} log(s)", stored, count);
+                    self.stats.record_logs(stored);
+                    self.stats.record_errors(count - stored);
+                }
+
+                // Each command event (by default a submitted prompt) adds one
+                for (id, count) in commands {
+                    for _ in 0..count {
+                        if let Err(e) = self.db.increment_command_count(id).await {
+                            warn!("Failed to update command count for session {}: {}", id, e);
+                            if matches!(e, DatabaseError::ReadOnly) {
+                                return Err(e);
+                            }
+                        }
+                    }
+                }
+            }
+            IngestBatch::Traces { sessions, traces } => {
+                // Create sessions first so the spans' session_id links resolve
+                self.upsert_sessions(sessions).await?;
+                let count = traces.len() as u64;
+                let stored = store_traces_batch(&*self.db, traces, rejected).await?;
+                info!("Stored {} of {} span(s)", stored, count);
+                self.stats.record_traces(stored);
+                self.stats.record_errors(count - stored);
+            }
+        }
+        Ok(())
+    }
+
+    /// Store the rows buffered since the last flush, returning how many were stored
+    pub async fn flush_aggregated(&self) -> Result<u64, DatabaseError> {
+        let Some(aggregator) = &self.aggregator else {
+            return Ok(0);
+        };
+        let (metrics, sessions) = aggregator.drain();
+        if metrics.is_empty() {
+            return Ok(0);
+        }
+
+        let count = metrics.len() as u64;
+        let mut rejected = Rejected::default();
+        let (stored, duplicates) = store_metrics_batch(&*self.db, metrics, &sessions, &self.cumulative, &mut rejected).await?;
+        if let Some(error) = &rejected.first_error {
+            warn!("Dropped {} aggregated metric(s); first error: {}", rejected.count, error);
+        }
+        let stored_count = stored.len() as u64;
+        self.stats.record_metrics(stored_count);
+        self.stats.record_deduplicated(duplicates);
+        self.stats.record_errors(count - stored_count - duplicates);
+        self.publish(stored);
+        Ok(stored_count)
+    }
+
+    // Sending never blocks; subscribers that fall behind see a lag error instead
+    fn publish(&self, metrics: Vec<MetricRecord>) {
+        let Some(feed) = &self.feed else { return };
+        if feed.receiver_count() == 0 {
+            return;
+        }
+        for metric in metrics {
+            let _ = feed.send(metric);
+        }
+    }
+
+    // Insert or merge into the stored rows; only a read-only database aborts the export
+    async fn upsert_sessions(&self, sessions: SessionStarts) -> Result<(), DatabaseError> {
+        for (id, (user, start)) in sessions {
+            if let Err(e) = self.db.upsert_session(id, &user, start).await {
+                warn!("Failed to record session {}: {}", id, e);
+                if matches!(e, DatabaseError::ReadOnly) {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse and store a traces export; shared by the gRPC and HTTP receivers
+    pub async fn ingest_traces(&self, req: ExportTraceServiceRequest) -> Result<Rejected, DatabaseError> {
+        if self.db.is_read_only() {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        info!("Received {} span resource(s)", req.resource_spans.len());
+
+        let mut traces_to_store = Vec::new();
+        let mut rejected = Rejected::default();
+        let mut sessions = SessionStarts::new();
+
+        for resource_spans in req.resource_spans {
+            let resource_attrs = resource_attributes(resource_spans.resource);
+
+            for scope_spans in resource_spans.scope_spans {
+                for span in scope_spans.spans {
+                    match parse_span(span, &resource_attrs) {
+                        Ok(trace_record) => {
+                            if let Some(id) = trace_record.session_id {
+                                let user = trace_record.attributes.get("user.email")
+                                    .or_else(|| trace_record.attributes.get("user.id"));
+                                note_session(&mut sessions, id, user.map(String::as_str), trace_record.start_time);
+                            }
+                            traces_to_store.push(trace_record);
+                        }
+                        Err(e) => {
+                            warn!("Failed to parse span: {}", e);
+                            self.stats.record_errors(1);
+                            rejected.add(1, e);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.dispatch(IngestBatch::Traces { sessions, traces: traces_to_store }, rejected).await
+    }
+}
+
+#[tonic::async_trait]
+impl MetricsService for OtelReceiver {
+    async fn export(
+        &self,
+        request: Request<ExportMetricsServiceRequest>,
+    ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+        let rejected = self.ingest_metrics(request.into_inner()).await.map_err(ingest_status)?;
+
+        Ok(Response::new(ExportMetricsServiceResponse::from_rejected(&rejected)))
+    }
+}
+
+#[tonic::async_trait]
+impl LogsService for OtelReceiver {
+    async fn export(
+        &self,
+        request: Request<ExportLogsServiceRequest>,
+    ) -> Result<Response<ExportLogsServiceResponse>, Status> {
+        let rejected = self.ingest_logs(request.into_inner()).await.map_err(ingest_status)?;
+
+        Ok(Response::new(ExportLogsServiceResponse::from_rejected(&rejected)))
+    }
+}
+
+#[tonic::async_trait]
+impl TraceService for OtelReceiver {
+    async fn export(
+        &self,
+        request: Request<ExportTraceServiceRequest>,
+    ) -> Result<Response<ExportTraceServiceResponse>, Status> {
+        let rejected = self.ingest_traces(request.into_inner()).await.map_err(ingest_status)?;
+
+        Ok(Response::new(ExportTraceServiceResponse::from_rejected(&rejected)))
+    }
+}
+
+fn ingest_status(error: DatabaseError) -> Status {
+    match error {
+        DatabaseError::ReadOnly => Status::unavailable("Database is read-only; ingestion is disabled"),
+        other => Status::internal(other.to_string()),
     }
 }
 
@@ -204,6 +579,13 @@ fn parse_claude_code_metric(
     metric: opentelemetry_proto::tonic::metrics::v1::Metric,
     resource_attrs: &HashMap<String, String>,
 ) -> Result<Vec<ClaudeCodeMetric>, String> {
+    if metric.name.is_empty() {
+        return Err("Metric has no name".to_string());
+    }
+    if metric.data.is_none() {
+        return Err(format!("Metric {} has no data", metric.name));
+    }
+
     let mut parsed_metrics = Vec::new();
     
     // Extract session ID from resource attributes
@@ -211,7 +593,7 @@ fn parse_claude_code_metric(
     
     // Handle different metric data types
     if let Some(data) = metric.data {
-        use opentelemetry_proto::tonic::metrics::v1::metric::Data;
+        use opentelemetry_proto::tonic::metrics::v1::{metric::Data, AggregationTemporality};
         
         match data {
             Data::Gauge(gauge) => {
@@ -235,15 +617,23 @@ fn parse_claude_code_metric(
                         timestamp,
                         labels,
                         session_id: session_id.clone(),
+                        unit: None,
+                        description: None,
+                        cumulative: false,
+                        start_time: None,
                     });
                 }
             }
             Data::Sum(sum) => {
+                let cumulative = sum.aggregation_temporality == AggregationTemporality::Cumulative as i32;
                 for data_point in sum.data_points {
                     let mut labels = extract_labels(&data_point.attributes);
                     labels.extend(resource_attrs.clone());
                     
                     let timestamp = timestamp_from_nanos(data_point.time_unix_nano);
+                    // Zero means the exporter left it unset
+                    let start_time = (data_point.start_time_unix_nano != 0)
+                        .then(|| timestamp_from_nanos(data_point.start_time_unix_nano));
                     
                     let value = match data_point.value {
                         Some(opentelemetry_proto::tonic::metrics::v1::number_data_point::Value::AsDouble(v)) => v,
@@ -257,6 +647,10 @@ fn parse_claude_code_metric(
                         timestamp,
                         labels,
                         session_id: session_id.clone(),
+                        unit: None,
+                        description: None,
+                        cumulative,
+                        start_time,
                     });
                 }
             }
@@ -268,36 +662,131 @@ fn parse_claude_code_metric(
                     let timestamp = timestamp_from_nanos(data_point.time_unix_nano);
                     
                     // For histograms, we'll store the count and sum as separate metrics
-                    if data_point.count > 0 {
-                        parsed_metrics.push(ClaudeCodeMetric {
-                            name: format!("{}_count", metric.name),
-                            value: data_point.count as f64,
-                            timestamp,
-                            labels: labels.clone(),
-                            session_id: session_id.clone(),
-                        });
-                    }
+                    push_count_and_sum(&mut parsed_metrics, &metric.name, data_point.count, data_point.sum, timestamp, labels, &session_id);
+                }
+            }
+            Data::ExponentialHistogram(histogram) => {
+                for data_point in histogram.data_points {
+                    let mut labels = extract_labels(&data_point.attributes);
+                    labels.extend(resource_attrs.clone());
                     
-                    if let Some(sum) = data_point.sum {
+                    let timestamp = timestamp_from_nanos(data_point.time_unix_nano);
+                    
+                    // Bucket layout (scale, offsets) isn't kept, only the aggregate
+                    push_count_and_sum(&mut parsed_metrics, &metric.name, data_point.count, data_point.sum, timestamp, labels, &session_id);
+                }
+            }
+            Data::Summary(summary) => {
+                for data_point in summary.data_points {
+                    let mut labels = extract_labels(&data_point.attributes);
+                    labels.extend(resource_attrs.clone());
+                    
+                    let timestamp = timestamp_from_nanos(data_point.time_unix_nano);
+                    
+                    // One row per quantile, labeled like a Prometheus summary
+                    for quantile in &data_point.quantile_values {
+                        let mut labels = labels.clone();
+                        labels.insert("quantile".to_string(), quantile.quantile.to_string());
                         parsed_metrics.push(ClaudeCodeMetric {
-                            name: format!("{}_sum", metric.name),
-                            value: sum,
+                            name: metric.name.clone(),
+                            value: quantile.value,
                             timestamp,
                             labels,
                             session_id: session_id.clone(),
+                            unit: None,
+                            description: None,
+                            cumulative: false,
+                            start_time: None,
                         });
                     }
+                    
+                    push_count_and_sum(&mut parsed_metrics, &metric.name, data_point.count, Some(data_point.sum), timestamp, labels, &session_id);
                 }
             }
-            _ => {
-                return Err(format!("Unsupported metric data type for {}", metric.name));
-            }
         }
     }
+
+    // Proto3 leaves unset strings empty. A `_count` row is a plain count, so it keeps only the description
+    let unit = Some(metric.unit).filter(|unit| !unit.is_empty());
+    let description = Some(metric.description).filter(|description| !description.is_empty());
+    let count_name = format!("{}_count", metric.name);
+    for parsed in &mut parsed_metrics {
+        if parsed.name != count_name {
+            parsed.unit = unit.clone();
+        }
+        parsed.description = description.clone();
+    }
     
     Ok(parsed_metrics)
 }
 
+// Points a metric carries, all of which are rejected when it fails to parse; at least one
+fn data_point_count(metric: &opentelemetry_proto::tonic::metrics::v1::Metric) -> i64 {
+    use opentelemetry_proto::tonic::metrics::v1::metric::Data;
+
+    let count = match &metric.data {
+        Some(Data::Gauge(gauge)) => gauge.data_points.len(),
+        Some(Data::Sum(sum)) => sum.data_points.len(),
+        Some(Data::Histogram(histogram)) => histogram.data_points.len(),
+        Some(Data::ExponentialHistogram(histogram)) => histogram.data_points.len(),
+        Some(Data::Summary(summary)) => summary.data_points.len(),
+        None => 0,
+    };
+    count.max(1) as i64
+}
+
+// How buffered points of a metric combine: sums add up, cumulative ones having been turned
+// into increases first, and gauges keep their latest reading; histograms and summaries are
+// never buffered
+fn aggregation_of(metric: &opentelemetry_proto::tonic::metrics::v1::Metric) -> Option<Aggregation> {
+    use opentelemetry_proto::tonic::metrics::v1::metric::Data;
+
+    match &metric.data {
+        Some(Data::Gauge(_)) => Some(Aggregation::LastValue),
+        Some(Data::Sum(_)) => Some(Aggregation::Sum),
+        _ => None,
+    }
+}
+
+// Aggregates are stored as `{name}_count` and `{name}_sum` rows
+fn push_count_and_sum(
+    parsed_metrics: &mut Vec<ClaudeCodeMetric>,
+    name: &str,
+    count: u64,
+    sum: Option<f64>,
+    timestamp: DateTime<Utc>,
+    labels: HashMap<String, String>,
+    session_id: &Option<String>,
+) {
+    if count > 0 {
+        parsed_metrics.push(ClaudeCodeMetric {
+            name: format!("{}_count", name),
+            value: count as f64,
+            timestamp,
+            labels: labels.clone(),
+            session_id: session_id.clone(),
+            unit: None,
+            description: None,
+            cumulative: false,
+            start_time: None,
+        });
+    }
+    
+    if let Some(sum) = sum {
+        parsed_metrics.push(ClaudeCodeMetric {
+            name: format!("{}_sum", name),
+            value: sum,
+            timestamp,
+            labels,
+            session_id: session_id.clone(),
+            unit: None,
+            description: None,
+            cumulative: false,
+            start_time: None,
+        });
+    }
+}
+
 // Parse Claude Code specific log events
 fn parse_claude_code_event(
     log_record: opentelemetry_proto::tonic::logs::v1::LogRecord,
@@ -330,7 +819,56 @@ fn parse_claude_code_event(
     })
 }
 
+// Remember a session an export refers to, so its row exists before records link to it.
+// Without a user.email or user.id the session is recorded for "unknown"; a later record
+// of the same export naming the user takes precedence.
+pub(super) fn note_session(sessions: &mut SessionStarts, id: Uuid, user: Option<&str>, timestamp: DateTime<Utc>) {
+    let seen = sessions
+        .entry(id)
+        .or_insert_with(|| (user.unwrap_or("unknown").to_string(), timestamp));
+    if let (Some(user), "unknown") = (user, seen.0.as_str()) {
+        seen.0 = user.to_string();
+    }
+    seen.1 = seen.1.min(timestamp);
+}
+
+// Convert an OTLP span into a stored trace record
+fn parse_span(
+    span: opentelemetry_proto::tonic::trace::v1::Span,
+    resource_attrs: &HashMap<String, String>,
+) -> Result<TraceRecord, String> {
+    if span.trace_id.is_empty() || span.span_id.is_empty() {
+        return Err(format!("Span {} is missing its trace or span id", span.name));
+    }
+
+    let mut attributes = extract_labels(&span.attributes);
+    attributes.extend(resource_attrs.clone());
+
+    let session_id = resource_attrs.get("session.id")
+        .and_then(|s| Uuid::parse_str(s).ok());
+
+    Ok(TraceRecord {
+        id: Uuid::new_v4(),
+        session_id,
+        trace_id: hex::encode(&span.trace_id),
+        span_id: hex::encode(&span.span_id),
+        parent_span_id: (!span.parent_span_id.is_empty()).then(|| hex::encode(&span.parent_span_id)),
+        name: span.name,
+        start_time: timestamp_from_nanos(span.start_time_unix_nano),
+        end_time: timestamp_from_nanos(span.end_time_unix_nano),
+        duration_ns: span.end_time_unix_nano.saturating_sub(span.start_time_unix_nano),
+        attributes,
+        created_at: Utc::now(),
+    })
+}
+
 // Helper functions
+fn resource_attributes(resource: Option<Resource>) -> HashMap<String, String> {
+    resource
+        .map(|resource| extract_labels(&resource.attributes))
+        .unwrap_or_default()
+}
+
 fn extract_attribute_value(
     value: opentelemetry_proto::tonic::common::v1::any_value::Value
 ) -> String {
@@ -404,47 +942,143 @@ fn timestamp_from_nanos(nanos: u64) -> DateTime<Utc> {
 }
 
 // Batch processing functions
+// Each record is stored on its own so one failure only rejects that record; a read-only
+// database fails the whole export instead, since every later write would fail too
+
+/// Store `metrics`, returning the ones that were stored and how many were already there;
+/// a point whose session is in `sessions` is stored together with that session, and the
+/// increase of one that fails goes back to `cumulative`
 async fn store_metrics_batch(
     db: &dyn Database,
-    metrics: Vec<MetricRecord>
-) -> Result<(), DatabaseError> {
-    // Store metrics in batches for better performance
-    const BATCH_SIZE: usize = 100;
-    
-    for chunk in metrics.chunks(BATCH_SIZE) {
-        for metric in chunk {
-            db.store_metric(metric).await?;
+    metrics: Vec<MetricRecord>,
+    sessions: &SessionStarts,
+    cumulative: &CumulativeDeltas,
+    rejected: &mut Rejected,
+) -> Result<(Vec<MetricRecord>, u64), DatabaseError> {
+    let mut stored = Vec::with_capacity(metrics.len());
+    let mut duplicates = 0;
+    for metric in metrics {
+        let result = match metric.session_id.and_then(|id| sessions.get(&id)) {
+            Some((user, start)) => db.store_metric_in_session(&metric, user, *start).await,
+            None => db.store_metric(&metric).await,
+        };
+        match result {
+            Ok(true) => stored.push(metric),
+            Ok(false) => duplicates += 1,
+            Err(DatabaseError::ReadOnly) => return Err(DatabaseError::ReadOnly),
+            Err(e) => {
+                error!("Failed to store metric {}: {}", metric.name, e);
+                cumulative.give_back(&metric);
+                rejected.add(1, format!("Failed to store metric {}: {}", metric.name, e));
+            }
         }
     }
-    
-    Ok(())
+
+    Ok((stored, duplicates))
 }
 
+/// Store `logs`, returning how many were stored
 async fn store_logs_batch(
     db: &dyn Database,
-    logs: Vec<LogRecord>
-) -> Result<(), DatabaseError> {
-    // Store logs in batches for better performance  
-    const BATCH_SIZE: usize = 100;
-    
-    for chunk in logs.chunks(BATCH_SIZE) {
-        for log in chunk {
-            db.store_log(log).await?;
+    logs: Vec<LogRecord>,
+    rejected: &mut Rejected,
+) -> Result<u64, DatabaseError> {
+    let mut stored = 0;
+    for log in logs {
+        match db.store_log(&log).await {
+            Ok(()) => stored += 1,
+            Err(DatabaseError::ReadOnly) => return Err(DatabaseError::ReadOnly),
+            Err(e) => {
+                error!("Failed to store log {}: {}", log.message, e);
+                rejected.add(1, format!("Failed to store log {}: {}", log.message, e));
+            }
         }
     }
-    
-    Ok(())
+
+    Ok(stored)
+}
+
+/// Store `traces`, returning how many were stored
+async fn store_traces_batch(
+    db: &dyn Database,
+    traces: Vec<TraceRecord>,
+    rejected: &mut Rejected,
+) -> Result<u64, DatabaseError> {
+    let mut stored = 0;
+    for trace in traces {
+        match db.store_trace(&trace).await {
+            Ok(()) => stored += 1,
+            Err(DatabaseError::ReadOnly) => return Err(DatabaseError::ReadOnly),
+            Err(e) => {
+                error!("Failed to store span {}: {}", trace.name, e);
+                rejected.add(1, format!("Failed to store span {}: {}", trace.name, e));
+            }
+        }
+    }
+
+    Ok(stored)
 }
 
 // Main server startup function
 pub async fn start_otel_server(
-    addr: SocketAddr,
-    db: Arc<dyn Database>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let otel_receiver = OtelReceiver::new(db);
+    listen: OtelListen,
+    otel_receiver: OtelReceiver,
+    auth: IngestAuth,
+    limits: OtlpLimits,
+    shutdown: Shutdown,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match listen {
+        OtelListen::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).await?;
+            info!("OpenTelemetry gRPC server listening on {}", addr);
+            serve_otel(TcpListenerStream::new(listener), otel_receiver, auth, limits, shutdown).await
+        }
+        #[cfg(unix)]
+        OtelListen::Unix(path) => {
+            let listener = bind_unix_socket(&path)?;
+            info!("OpenTelemetry gRPC server listening on unix://{}", path.display());
+            let served = serve_otel(UnixListenerStream::new(listener), otel_receiver, auth, limits, shutdown).await;
+            let _ = std::fs::remove_file(&path);
+            served
+        }
+        #[cfg(not(unix))]
+        OtelListen::Unix(path) => Err(format!("Unix domain sockets are not supported on this platform: {}", path.display()).into()),
+    }
+}
+
+// Binds `path`, replacing a socket left behind by an earlier run but never another kind of file
+#[cfg(unix)]
+fn bind_unix_socket(path: &std::path::Path) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
 
-    info!("OpenTelemetry gRPC server listening on {}", addr);
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    tokio::net::UnixListener::bind(path)
+}
+
+/// Bounds on a single OTLP/gRPC export
+#[derive(Debug, Clone, Copy)]
+pub struct OtlpLimits {
+    pub max_message_bytes: usize,
+    pub request_timeout: Duration,
+    /// Accept gzip-compressed exports and compress responses for clients that accept it
+    pub gzip: bool,
+}
 
+async fn serve_otel<I, IO, IE>(
+    incoming: I,
+    otel_receiver: OtelReceiver,
+    auth: IngestAuth,
+    limits: OtlpLimits,
+    shutdown: Shutdown,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    I: Stream<Item = Result<IO, IE>>,
+    IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+    IO::ConnectInfo: Clone + Send + Sync + 'static,
+    IE: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
     let reflection_service = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(include_bytes!("../../opentelemetry_descriptor.bin"))
         .build()
@@ -453,14 +1087,725 @@ pub async fn start_otel_server(
             panic!("Failed to build reflection service");
         });
 
+    let max_bytes = limits.max_message_bytes;
+    let mut metrics = MetricsServiceServer::new(otel_receiver.clone()).max_decoding_message_size(max_bytes);
+    let mut logs = LogsServiceServer::new(otel_receiver.clone()).max_decoding_message_size(max_bytes);
+    let mut traces = TraceServiceServer::new(otel_receiver).max_decoding_message_size(max_bytes);
+    // Exporters set OTEL_EXPORTER_OTLP_COMPRESSION=gzip; responses are only compressed when asked for
+    if limits.gzip {
+        metrics = metrics.accept_compressed(CompressionEncoding::Gzip).send_compressed(CompressionEncoding::Gzip);
+        logs = logs.accept_compressed(CompressionEncoding::Gzip).send_compressed(CompressionEncoding::Gzip);
+        traces = traces.accept_compressed(CompressionEncoding::Gzip).send_compressed(CompressionEncoding::Gzip);
+    }
+
     Server::builder()
-        .add_service(MetricsServiceServer::new(otel_receiver.clone()))
-        .add_service(LogsServiceServer::new(otel_receiver))
+        .timeout(limits.request_timeout)
+        .layer(MapResponseLayer::new(resource_exhausted_when_too_large))
+        .add_service(InterceptedService::new(metrics, auth.clone()))
+        .add_service(InterceptedService::new(logs, auth.clone()))
+        .add_service(InterceptedService::new(traces, auth))
         .add_service(tonic_web::enable(reflection_service))
-        .serve(addr)
+        .serve_with_incoming_shutdown(incoming, shutdown.wait())
         .await
         .map_err(|e| {
             error!("OpenTelemetry server error: {}", e);
             e.into()
         })
-}
\ No newline at end of file
+}
+
+// tonic rejects an oversized message with OUT_OF_RANGE, which OTLP exporters retry
+// as-is; RESOURCE_EXHAUSTED tells them not to resend the same batch
+fn resource_exhausted_when_too_large<B>(mut response: http::Response<B>) -> http::Response<B> {
+    let too_large = Status::from_header_map(response.headers()).is_some_and(|status| {
+        status.code() == Code::OutOfRange && status.message().starts_with("Error, message length too large")
+    });
+    if too_large {
+        response.headers_mut().insert("grpc-status", http::HeaderValue::from(Code::ResourceExhausted as i32));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::{
+        common::v1::{any_value, AnyValue, KeyValue},
+        logs::v1::{LogRecord as OtlpLogRecord, ResourceLogs, ScopeLogs},
+        metrics::v1::{
+            exponential_histogram_data_point::Buckets, metric, number_data_point, summary_data_point::ValueAtQuantile,
+            AggregationTemporality, ExponentialHistogram, ExponentialHistogramDataPoint, Metric, NumberDataPoint,
+            ResourceMetrics, ScopeMetrics, Sum, Summary, SummaryDataPoint,
+        },
+    };
+
+    use crate::storage::{sqlite::test_database, MetricScope, PurgeCounts, SessionFilter};
+
+    fn attribute(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue { value: Some(any_value::Value::StringValue(value.to_string())) }),
+        }
+    }
+
+    fn token_usage(session_id: &str, timestamps: &[u64]) -> ExportMetricsServiceRequest {
+        let data_points = timestamps
+            .iter()
+            .map(|ts| NumberDataPoint {
+                time_unix_nano: *ts,
+                value: Some(number_data_point::Value::AsInt(10)),
+                ..Default::default()
+            })
+            .collect();
+
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(Resource {
+                    attributes: vec![attribute("session.id", session_id), attribute("user.email", "dev@example.com")],
+                    ..Default::default()
+                }),
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "claude_code.token.usage".to_string(),
+                        data: Some(metric::Data::Sum(Sum { data_points, ..Default::default() })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics_with_new_session_id_create_one_session() {
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        let session_id = Uuid::new_v4();
+        let (first, second) = (1_700_000_000_000_000_000, 1_700_000_060_000_000_000);
+
+        receiver.ingest_metrics(token_usage(&session_id.to_string(), &[second, first])).await.unwrap();
+        receiver.ingest_metrics(token_usage(&session_id.to_string(), &[second + 1])).await.unwrap();
+
+        assert_eq!(db.count_sessions(&SessionFilter::default()).await.unwrap(), 1);
+        let session = db.get_session(session_id).await.unwrap().unwrap();
+        assert_eq!(session.user_id, "dev@example.com");
+        assert_eq!(session.start_time.timestamp(), 1_700_000_000);
+
+        let metrics = db.get_metrics(None, None, Some("claude_code.token.usage")).await.unwrap();
+        assert_eq!(metrics.len(), 3);
+        assert!(metrics.iter().all(|m| m.session_id == Some(session_id)));
+
+        // Untyped token points count as input in the session's rollup
+        let rollup = db.get_session_rollup(session_id).await.unwrap().unwrap();
+        assert_eq!(rollup.total_tokens_input as f64, metrics.iter().map(|m| m.value).sum::<f64>());
+    }
+
+    #[tokio::test]
+    async fn test_retried_export_stores_each_point_once() {
+        let (_dir, db) = test_database().await;
+        let stats = Arc::new(IngestStats::new());
+        let receiver = OtelReceiver::new(db.clone(), stats.clone());
+        let session_id = Uuid::new_v4();
+        let request = token_usage(&session_id.to_string(), &[1_700_000_000_000_000_000]);
+
+        receiver.ingest_metrics(request.clone()).await.unwrap();
+        receiver.ingest_metrics(request).await.unwrap();
+
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 1);
+        assert_eq!(db.get_session_rollup(session_id).await.unwrap().unwrap().total_tokens_input, 10);
+        assert_eq!(stats.metrics_ingested(), 1);
+        assert_eq!(stats.metrics_deduplicated(), 1);
+        assert_eq!(stats.ingestion_errors(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_export_reports_rejected_data_points() {
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        let mut request = token_usage(&Uuid::new_v4().to_string(), &[1_700_000_000_000_000_000]);
+        request.resource_metrics[0].scope_metrics[0].metrics.push(Metric {
+            name: String::new(),
+            data: Some(metric::Data::Sum(Sum {
+                data_points: vec![NumberDataPoint::default(), NumberDataPoint::default()],
+                ..Default::default()
+            })),
+            ..Default::default()
+        });
+
+        let response = MetricsService::export(&receiver, Request::new(request)).await.unwrap().into_inner();
+
+        let partial = response.partial_success.unwrap();
+        assert_eq!(partial.rejected_data_points, 2);
+        assert_eq!(partial.error_message, "Rejected 2 record(s); first error: Metric has no name");
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 1);
+
+        // A fully accepted export leaves partial_success unset
+        let request = token_usage(&Uuid::new_v4().to_string(), &[1_700_000_000_000_000_000]);
+        let response = MetricsService::export(&receiver, Request::new(request)).await.unwrap().into_inner();
+        assert!(response.partial_success.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_aggregated_counter_flushes_as_one_summed_row() {
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()))
+            .with_aggregator(Some(Arc::new(MetricAggregator::new())));
+        let session_id = Uuid::new_v4().to_string();
+
+        receiver.ingest_metrics(token_usage(&session_id, &[1_700_000_000_000_000_000])).await.unwrap();
+        receiver.ingest_metrics(token_usage(&session_id, &[1_700_000_000_100_000_000])).await.unwrap();
+        assert!(db.get_metrics(None, None, None).await.unwrap().is_empty());
+
+        assert_eq!(receiver.flush_aggregated().await.unwrap(), 1);
+        let metrics = db.get_metrics(None, None, None).await.unwrap();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].value, 20.0);
+        assert_eq!(metrics[0].timestamp, timestamp_from_nanos(1_700_000_000_100_000_000));
+        assert_eq!(receiver.flush_aggregated().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_aggregated_rows_bring_their_session_when_the_export_is_shed() {
+        use crate::otel::queue::QueueFullPolicy;
+
+        let (_dir, db) = test_database().await;
+        // Nothing drains this queue, so the second export finds it full
+        let (queue, _batches) = IngestQueue::new(1, QueueFullPolicy::Shed);
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()))
+            .with_aggregator(Some(Arc::new(MetricAggregator::new())))
+            .with_queue(Some(queue));
+        let (queued, shed) = (Uuid::new_v4(), Uuid::new_v4());
+
+        // Every point is buffered, so the batches carry nothing but their session
+        let rejected = receiver.ingest_metrics(token_usage(&queued.to_string(), &[1_700_000_000_000_000_000])).await.unwrap();
+        assert_eq!(rejected.count, 0);
+        let rejected = receiver.ingest_metrics(token_usage(&shed.to_string(), &[1_700_000_000_000_000_000])).await.unwrap();
+        assert_eq!(rejected.count, 1);
+        assert_eq!(rejected.message(), "Rejected 1 record(s); first error: Ingestion queue is full");
+
+        // The flush creates both sessions with the rows instead of failing their links
+        assert_eq!(receiver.flush_aggregated().await.unwrap(), 2);
+        for session_id in [queued, shed] {
+            let session = db.get_session(session_id).await.unwrap().unwrap();
+            assert_eq!(session.user_id, "dev@example.com");
+            assert_eq!(session.start_time.timestamp(), 1_700_000_000);
+        }
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_queued_exports_are_stored_by_the_writer_or_shed_when_full() {
+        use crate::otel::queue::{spawn_writer, QueueFullPolicy};
+
+        let (_dir, db) = test_database().await;
+        let stats = Arc::new(IngestStats::new());
+        let store = OtelReceiver::new(db.clone(), stats.clone());
+
+        // Exports are answered once queued and stored by the time the writer has drained
+        let (queue, batches) = IngestQueue::new(8, QueueFullPolicy::Block);
+        let receiver = store.clone().with_queue(Some(queue));
+        let writer_shutdown = Shutdown::new();
+        let writer = spawn_writer(store.clone(), batches, writer_shutdown.clone());
+        for _ in 0..3 {
+            let request = token_usage(&Uuid::new_v4().to_string(), &[1_700_000_000_000_000_000]);
+            assert_eq!(receiver.ingest_metrics(request).await.unwrap(), Rejected::default());
+        }
+        writer_shutdown.trigger();
+        writer.await.unwrap();
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 3);
+        assert_eq!(db.count_sessions(&SessionFilter::default()).await.unwrap(), 3);
+
+        // Nothing drains this queue, so the second export finds it full
+        let (queue, _batches) = IngestQueue::new(1, QueueFullPolicy::Shed);
+        let receiver = store.with_queue(Some(queue));
+        let request = || token_usage(&Uuid::new_v4().to_string(), &[1_700_000_000_000_000_000, 1_700_000_000_000_000_001]);
+        assert_eq!(receiver.ingest_metrics(request()).await.unwrap().count, 0);
+        let rejected = receiver.ingest_metrics(request()).await.unwrap();
+        assert_eq!(rejected.count, 2);
+        assert_eq!(rejected.message(), "Rejected 2 record(s); first error: Ingestion queue is full");
+        assert_eq!(stats.records_shed(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_records_that_fail_to_store_are_reported_as_rejected() {
+        use crate::storage::sqlite::unavailable_database;
+
+        // Every write fails, so each point and log of the exports is rejected
+        let (_dir, db) = unavailable_database().await;
+        let stats = Arc::new(IngestStats::new());
+        let receiver = OtelReceiver::new(db, stats.clone());
+
+        let request = token_usage(&Uuid::new_v4().to_string(), &[1_700_000_000_000_000_000, 1_700_000_001_000_000_000]);
+        let response = MetricsService::export(&receiver, Request::new(request)).await.unwrap().into_inner();
+        let partial = response.partial_success.unwrap();
+        assert_eq!(partial.rejected_data_points, 2);
+        assert!(partial.error_message.starts_with("Rejected 2 record(s); first error: Failed to store metric claude_code.token.usage"));
+        assert_eq!((stats.metrics_ingested(), stats.ingestion_errors()), (0, 2));
+
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![attribute("session.id", &Uuid::new_v4().to_string())],
+                    ..Default::default()
+                }),
+                scope_logs: vec![ScopeLogs {
+                    log_records: vec![OtlpLogRecord {
+                        time_unix_nano: 1_700_000_000_000_000_000,
+                        body: Some(AnyValue { value: Some(any_value::Value::StringValue("tool_result".to_string())) }),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let response = LogsService::export(&receiver, Request::new(request)).await.unwrap().into_inner();
+        let partial = response.partial_success.unwrap();
+        assert_eq!(partial.rejected_log_records, 1);
+        assert!(partial.error_message.contains("Failed to store log tool_result"));
+    }
+
+    #[tokio::test]
+    async fn test_grpc_rejects_oversized_export() {
+        use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let limits = OtlpLimits { max_message_bytes: 1024, request_timeout: Duration::from_secs(5), gzip: true };
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve_otel(TcpListenerStream::new(listener), receiver, IngestAuth::new(None), limits, shutdown.clone()));
+
+        let mut client = MetricsServiceClient::connect(format!("http://{}", addr)).await.unwrap();
+        let small = token_usage(&Uuid::new_v4().to_string(), &[1_700_000_000_000_000_000]);
+        client.export(small).await.unwrap();
+
+        let timestamps: Vec<u64> = (0..200).map(|i| 1_700_000_000_000_000_000 + i).collect();
+        let large = token_usage(&Uuid::new_v4().to_string(), &timestamps);
+        let status = client.export(large).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted, "{}", status.message());
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 1);
+
+        shutdown.trigger();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_grpc_accepts_gzip_compressed_exports() {
+        use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+
+        let serve = |gzip: bool| async move {
+            let (dir, db) = test_database().await;
+            let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let limits = OtlpLimits { max_message_bytes: 4096, request_timeout: Duration::from_secs(5), gzip };
+            let shutdown = Shutdown::new();
+            tokio::spawn(serve_otel(TcpListenerStream::new(listener), receiver, IngestAuth::new(None), limits, shutdown.clone()));
+            let client = MetricsServiceClient::connect(format!("http://{}", addr))
+                .await
+                .unwrap()
+                .send_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Gzip);
+            (dir, db, client, shutdown)
+        };
+        let request = || token_usage(&Uuid::new_v4().to_string(), &[1_700_000_000_000_000_000, 1_700_000_000_000_000_001]);
+
+        let (_dir, db, mut client, shutdown) = serve(true).await;
+        let response = client.export(request()).await.unwrap().into_inner();
+        assert!(response.partial_success.is_none());
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 2);
+        shutdown.trigger();
+
+        // With compression turned off the same export is refused
+        let (_dir, db, mut client, shutdown) = serve(false).await;
+        let status = client.export(request()).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented, "{}", status.message());
+        assert!(db.get_metrics(None, None, None).await.unwrap().is_empty());
+        shutdown.trigger();
+    }
+
+    fn values_by_name(parsed: &[ClaudeCodeMetric]) -> HashMap<String, f64> {
+        parsed.iter().filter(|m| !m.labels.contains_key("quantile")).map(|m| (m.name.clone(), m.value)).collect()
+    }
+
+    #[test]
+    fn test_exponential_histogram_emits_count_and_sum() {
+        let metric = Metric {
+            name: "claude_code.api.latency".to_string(),
+            data: Some(metric::Data::ExponentialHistogram(ExponentialHistogram {
+                data_points: vec![ExponentialHistogramDataPoint {
+                    attributes: vec![attribute("model", "sonnet")],
+                    time_unix_nano: 1_700_000_000_000_000_000,
+                    count: 4,
+                    sum: Some(2.5),
+                    scale: 3,
+                    zero_count: 1,
+                    positive: Some(Buckets { offset: 2, bucket_counts: vec![1, 2] }),
+                    ..Default::default()
+                }],
+                aggregation_temporality: 2,
+            })),
+            ..Default::default()
+        };
+
+        let parsed = parse_claude_code_metric(metric, &HashMap::new()).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        let values = values_by_name(&parsed);
+        assert_eq!(values["claude_code.api.latency_count"], 4.0);
+        assert_eq!(values["claude_code.api.latency_sum"], 2.5);
+        assert!(parsed.iter().all(|m| m.labels["model"] == "sonnet"));
+    }
+
+    #[tokio::test]
+    async fn test_exponential_histogram_is_stored_on_ingest() {
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                scope_metrics: vec![ScopeMetrics {
+                    metrics: vec![Metric {
+                        name: "claude_code.api.latency".to_string(),
+                        data: Some(metric::Data::ExponentialHistogram(ExponentialHistogram {
+                            data_points: vec![ExponentialHistogramDataPoint {
+                                attributes: vec![attribute("model", "opus")],
+                                time_unix_nano: 1_700_000_000_000_000_000,
+                                count: 3,
+                                sum: Some(1.5),
+                                ..Default::default()
+                            }],
+                            aggregation_temporality: 2,
+                        })),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        receiver.ingest_metrics(request).await.unwrap();
+
+        let stored = db.get_metrics(None, None, None).await.unwrap();
+        let values: HashMap<&str, f64> = stored.iter().map(|m| (m.name.as_str(), m.value)).collect();
+        assert_eq!(values, HashMap::from([("claude_code.api.latency_count", 3.0), ("claude_code.api.latency_sum", 1.5)]));
+        assert!(stored.iter().all(|m| m.labels["model"] == "opus"));
+    }
+
+    #[test]
+    fn test_summary_emits_quantiles_count_and_sum() {
+        let metric = Metric {
+            name: "claude_code.api.latency".to_string(),
+            data: Some(metric::Data::Summary(Summary {
+                data_points: vec![SummaryDataPoint {
+                    time_unix_nano: 1_700_000_000_000_000_000,
+                    count: 10,
+                    sum: 12.0,
+                    quantile_values: vec![
+                        ValueAtQuantile { quantile: 0.5, value: 1.0 },
+                        ValueAtQuantile { quantile: 0.99, value: 3.5 },
+                    ],
+                    ..Default::default()
+                }],
+            })),
+            ..Default::default()
+        };
+
+        let parsed = parse_claude_code_metric(metric, &HashMap::new()).unwrap();
+
+        assert_eq!(parsed.len(), 4);
+        let values = values_by_name(&parsed);
+        assert_eq!(values["claude_code.api.latency_count"], 10.0);
+        assert_eq!(values["claude_code.api.latency_sum"], 12.0);
+        let quantiles: HashMap<&str, f64> = parsed
+            .iter()
+            .filter_map(|m| m.labels.get("quantile").map(|q| (q.as_str(), m.value)))
+            .collect();
+        assert_eq!(quantiles, HashMap::from([("0.5", 1.0), ("0.99", 3.5)]));
+    }
+
+    #[tokio::test]
+    async fn test_prompt_events_increment_command_count() {
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        let session_id = Uuid::new_v4();
+        let event = |name: &str| OtlpLogRecord {
+            time_unix_nano: 1_700_000_000_000_000_000,
+            body: Some(AnyValue { value: Some(any_value::Value::StringValue(name.to_string())) }),
+            ..Default::default()
+        };
+        let request = |events: Vec<OtlpLogRecord>| ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![attribute("session.id", &session_id.to_string()), attribute("user.email", "dev@example.com")],
+                    ..Default::default()
+                }),
+                scope_logs: vec![ScopeLogs { log_records: events, ..Default::default() }],
+                ..Default::default()
+            }],
+        };
+
+        receiver
+            .ingest_logs(request(vec![event("user_prompt_submitted"), event("tool_result"), event("user_prompt_submitted")]))
+            .await
+            .unwrap();
+        receiver.ingest_logs(request(vec![event("user_prompt_submitted")])).await.unwrap();
+
+        let session = db.get_session(session_id).await.unwrap().unwrap();
+        assert_eq!(session.command_count, 3);
+        assert_eq!(session.user_id, "dev@example.com");
+
+        // Counting tool results instead leaves prompts out
+        let session_id = Uuid::new_v4();
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()))
+            .with_command_events(&["tool_result".to_string()]);
+        receiver
+            .ingest_logs(ExportLogsServiceRequest {
+                resource_logs: vec![ResourceLogs {
+                    resource: Some(Resource {
+                        attributes: vec![attribute("session.id", &session_id.to_string())],
+                        ..Default::default()
+                    }),
+                    scope_logs: vec![ScopeLogs {
+                        log_records: vec![event("user_prompt_submitted"), event("tool_result"), event("tool_result")],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            })
+            .await
+            .unwrap();
+        assert_eq!(db.get_session(session_id).await.unwrap().unwrap().command_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_logs_spans_and_userless_metrics_create_their_session() {
+        use opentelemetry_proto::tonic::trace::v1::{ResourceSpans, ScopeSpans, Span};
+
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        let (log_session, span_session, metric_session) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let resource = |session_id: Uuid| Some(Resource {
+            attributes: vec![attribute("session.id", &session_id.to_string()), attribute("user.email", "dev@example.com")],
+            ..Default::default()
+        });
+
+        // A log that isn't a command event and a span, each for a session not seen before
+        receiver
+            .ingest_logs(ExportLogsServiceRequest {
+                resource_logs: vec![ResourceLogs {
+                    resource: resource(log_session),
+                    scope_logs: vec![ScopeLogs {
+                        log_records: vec![OtlpLogRecord {
+                            time_unix_nano: 1_700_000_000_000_000_000,
+                            body: Some(AnyValue { value: Some(any_value::Value::StringValue("tool_result".to_string())) }),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            })
+            .await
+            .unwrap();
+        receiver
+            .ingest_traces(ExportTraceServiceRequest {
+                resource_spans: vec![ResourceSpans {
+                    resource: resource(span_session),
+                    scope_spans: vec![ScopeSpans {
+                        spans: vec![Span {
+                            trace_id: vec![1; 16],
+                            span_id: vec![2; 8],
+                            name: "tool".to_string(),
+                            start_time_unix_nano: 1_700_000_000_000_000_000,
+                            end_time_unix_nano: 1_700_000_001_000_000_000,
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(db.get_logs(None, None, None).await.unwrap()[0].session_id, Some(log_session));
+        assert_eq!(db.get_traces(None, None, None).await.unwrap()[0].session_id, Some(span_session));
+        for session_id in [log_session, span_session] {
+            let session = db.get_session(session_id).await.unwrap().unwrap();
+            assert_eq!(session.user_id, "dev@example.com");
+            assert_eq!(session.command_count, 0);
+        }
+
+        // A point without a user is kept under "unknown" until a point naming the user,
+        // here an earlier one, fills it in and moves the start back
+        let mut userless = token_usage(&metric_session.to_string(), &[1_700_000_060_000_000_000]);
+        userless.resource_metrics[0].resource.as_mut().unwrap().attributes.retain(|kv| kv.key != "user.email");
+        receiver.ingest_metrics(userless).await.unwrap();
+        assert_eq!(db.get_session(metric_session).await.unwrap().unwrap().user_id, "unknown");
+
+        receiver.ingest_metrics(token_usage(&metric_session.to_string(), &[1_700_000_000_000_000_000])).await.unwrap();
+        let session = db.get_session(metric_session).await.unwrap().unwrap();
+        assert_eq!(session.user_id, "dev@example.com");
+        assert_eq!(session.start_time.timestamp(), 1_700_000_000);
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_deleting_a_user_covers_data_ingested_before_they_were_known() {
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        let session_id = Uuid::new_v4();
+        let without = |mut request: ExportMetricsServiceRequest, key: &str| {
+            request.resource_metrics[0].resource.as_mut().unwrap().attributes.retain(|kv| kv.key != key);
+            request
+        };
+
+        // The session's first point names no user, a later one does
+        receiver.ingest_metrics(without(token_usage(&session_id.to_string(), &[1_700_000_000_000_000_000]), "user.email")).await.unwrap();
+        receiver.ingest_metrics(token_usage(&session_id.to_string(), &[1_700_000_060_000_000_000])).await.unwrap();
+
+        // Records without a session know the user only from their attributes
+        receiver.ingest_metrics(without(token_usage("", &[1_700_000_000_000_000_000]), "session.id")).await.unwrap();
+        receiver
+            .ingest_logs(ExportLogsServiceRequest {
+                resource_logs: vec![ResourceLogs {
+                    resource: Some(Resource { attributes: vec![attribute("user.email", "dev@example.com")], ..Default::default() }),
+                    scope_logs: vec![ScopeLogs {
+                        log_records: vec![OtlpLogRecord {
+                            time_unix_nano: 1_700_000_000_000_000_000,
+                            body: Some(AnyValue { value: Some(any_value::Value::StringValue("tool_result".to_string())) }),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            })
+            .await
+            .unwrap();
+        // Nor does a point without either
+        let anonymous = without(token_usage("", &[1_700_000_000_000_000_000]), "user.email");
+        receiver.ingest_metrics(without(anonymous, "session.id")).await.unwrap();
+
+        let counts = db.delete_user_sessions("dev@example.com").await.unwrap();
+        assert_eq!(counts, PurgeCounts { metrics: 3, logs: 1, traces: 0, sessions: 1, budgets: 0 });
+        assert!(db.get_session(session_id).await.unwrap().is_none());
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 1);
+        assert!(db.get_logs(None, None, None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_records_keep_the_exporting_service() {
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        let at = 1_700_000_000_000_000_000;
+        for (service, session) in [("claude-code", Uuid::new_v4()), ("ci-runner", Uuid::new_v4())] {
+            let mut metrics = token_usage(&session.to_string(), &[at]);
+            let mut logs = ExportLogsServiceRequest {
+                resource_logs: vec![ResourceLogs {
+                    resource: metrics.resource_metrics[0].resource.clone(),
+                    scope_logs: vec![ScopeLogs {
+                        log_records: vec![OtlpLogRecord { time_unix_nano: at, ..Default::default() }],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+            };
+            for resource in [&mut metrics.resource_metrics[0].resource, &mut logs.resource_logs[0].resource] {
+                resource.as_mut().unwrap().attributes.push(attribute("service.name", service));
+            }
+            receiver.ingest_metrics(metrics).await.unwrap();
+            receiver.ingest_logs(logs).await.unwrap();
+        }
+
+        let (start, end) = (DateTime::from_timestamp(1_699_999_000, 0).unwrap(), DateTime::from_timestamp(1_700_001_000, 0).unwrap());
+        let scope = MetricScope { service: Some("ci-runner".to_string()), ..MetricScope::default() };
+        let metrics = db.get_scoped_metrics(start, end, None, &scope).await.unwrap();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].service.as_deref(), Some("ci-runner"));
+        assert_eq!(db.get_scoped_metrics(start, end, None, &MetricScope::default()).await.unwrap().len(), 2);
+
+        let logs = db.list_logs(Some(start), Some(end), None, Some("claude-code"), 10, 0).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].service.as_deref(), Some("claude-code"));
+        assert_eq!(db.count_logs(Some(start), Some(end), None, None).await.unwrap(), 2);
+        assert_eq!(db.count_logs(Some(start), Some(end), None, Some("other")).await.unwrap(), 0);
+    }
+
+    fn counter(session_id: &str, temporality: AggregationTemporality, points: &[(u64, i64)]) -> ExportMetricsServiceRequest {
+        let mut request = token_usage(session_id, &[]);
+        let data_points = points
+            .iter()
+            .map(|(ts, value)| NumberDataPoint {
+                time_unix_nano: *ts,
+                value: Some(number_data_point::Value::AsInt(*value)),
+                ..Default::default()
+            })
+            .collect();
+        request.resource_metrics[0].scope_metrics[0].metrics[0].data =
+            Some(metric::Data::Sum(Sum { data_points, aggregation_temporality: temporality as i32, ..Default::default() }));
+        request
+    }
+
+    #[tokio::test]
+    async fn test_cumulative_sums_are_stored_as_increases() {
+        let (_dir, db) = test_database().await;
+        let receiver = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        let (cumulative, delta) = (Uuid::new_v4(), Uuid::new_v4());
+        let at = |second: u64| 1_700_000_000_000_000_000 + second * 1_000_000_000;
+
+        receiver
+            .ingest_metrics(counter(&cumulative.to_string(), AggregationTemporality::Cumulative, &[(at(0), 100), (at(10), 150)]))
+            .await
+            .unwrap();
+        // A retry, a stale point and a restarted counter
+        receiver
+            .ingest_metrics(counter(&cumulative.to_string(), AggregationTemporality::Cumulative, &[(at(10), 150), (at(5), 120), (at(20), 30)]))
+            .await
+            .unwrap();
+        receiver
+            .ingest_metrics(counter(&delta.to_string(), AggregationTemporality::Delta, &[(at(0), 100), (at(10), 150)]))
+            .await
+            .unwrap();
+
+        let mut stored = db.get_metrics(None, None, Some("claude_code.token.usage")).await.unwrap();
+        stored.sort_by_key(|m| m.timestamp);
+        let values = |session: Uuid| stored.iter().filter(|m| m.session_id == Some(session)).map(|m| m.value).collect::<Vec<_>>();
+        assert_eq!(values(cumulative), [100.0, 50.0, 30.0]);
+        assert_eq!(values(delta), [100.0, 150.0]);
+        assert_eq!(db.get_session_rollup(cumulative).await.unwrap().unwrap().total_tokens_input, 180);
+    }
+
+    #[tokio::test]
+    async fn test_cumulative_totals_survive_a_restart_and_a_shed_export() {
+        use crate::otel::queue::QueueFullPolicy;
+
+        let (_dir, db) = test_database().await;
+        let session = Uuid::new_v4();
+        let at = |second: u64| 1_700_000_000_000_000_000 + second * 1_000_000_000;
+        let points = |points: &[(u64, i64)]| counter(&session.to_string(), AggregationTemporality::Cumulative, points);
+
+        let before = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        before.ingest_metrics(points(&[(at(0), 100), (at(10), 150)])).await.unwrap();
+
+        // A restarted receiver picks up from the stored total
+        let store = OtelReceiver::new(db.clone(), Arc::new(IngestStats::new()));
+        store.ingest_metrics(points(&[(at(20), 180)])).await.unwrap();
+
+        // Nothing drains this queue, so once another export fills it the next point is shed
+        let (queue, _batches) = IngestQueue::new(1, QueueFullPolicy::Shed);
+        let queued = store.clone().with_queue(Some(queue));
+        queued.ingest_metrics(token_usage(&Uuid::new_v4().to_string(), &[at(0)])).await.unwrap();
+        assert_eq!(queued.ingest_metrics(points(&[(at(30), 200)])).await.unwrap().count, 1);
+        store.ingest_metrics(points(&[(at(40), 230)])).await.unwrap();
+
+        let mut stored = db.get_metrics(None, None, Some("claude_code.token.usage")).await.unwrap();
+        stored.retain(|m| m.session_id == Some(session));
+        stored.sort_by_key(|m| m.timestamp);
+        assert_eq!(stored.iter().map(|m| m.value).collect::<Vec<_>>(), [100.0, 50.0, 30.0, 50.0]);
+        assert_eq!(db.get_session_rollup(session).await.unwrap().unwrap().total_tokens_input, 230);
+    }
+}
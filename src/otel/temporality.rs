@@ -0,0 +1,206 @@
+// Cumulative sums report the running total since the counter started; analytics add
+// points up, so each reading is stored as the increase since the previous one
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::otel::aggregation::SeriesKey;
+use crate::storage::{MetricRecord, SeriesTotal};
+
+// A series without readings for this long is forgotten, so label churn can't grow the map
+// without bound; if it reports again it is seeded from storage like after a restart
+const IDLE_SERIES: Duration = Duration::from_secs(60 * 60);
+
+// The last reading of a series and the increase it was stored as
+#[derive(Debug, Clone, Copy)]
+struct Reading {
+    // When the exporter says the counter started, if it says
+    start: Option<DateTime<Utc>>,
+    timestamp: DateTime<Utc>,
+    total: f64,
+    delta: f64,
+    // Increases that never reached storage, added to the next one
+    carry: f64,
+    seen: Instant,
+}
+
+impl Reading {
+    // A series storage holds nothing of
+    fn unseen(start: Option<DateTime<Utc>>, seen: Instant) -> Self {
+        Reading { start, timestamp: DateTime::<Utc>::MIN_UTC, total: 0.0, delta: 0.0, carry: 0.0, seen }
+    }
+}
+
+struct Series {
+    readings: HashMap<SeriesKey, Reading>,
+    pruned: Instant,
+}
+
+/// Last-seen totals of cumulative series, shared by every receiver
+pub struct CumulativeDeltas {
+    series: Mutex<Series>,
+    idle: Duration,
+}
+
+impl Default for CumulativeDeltas {
+    fn default() -> Self {
+        Self::with_idle(IDLE_SERIES)
+    }
+}
+
+impl CumulativeDeltas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_idle(idle: Duration) -> Self {
+        let series = Series { readings: HashMap::new(), pruned: Instant::now() };
+        Self { series: Mutex::new(series), idle }
+    }
+
+    /// Whether `metric`'s series has a reading; one without should be seeded first
+    pub fn tracks(&self, metric: &MetricRecord) -> bool {
+        self.series.lock().unwrap().readings.contains_key(&SeriesKey::of(metric))
+    }
+
+    /// Pick up `metric`'s series where storage left it, so the first reading after a restart
+    /// only counts what it adds to `stored`. A series tracked meanwhile is left alone.
+    pub fn seed(&self, metric: &MetricRecord, start: Option<DateTime<Utc>>, stored: Option<SeriesTotal>) {
+        let reading = match stored {
+            Some(stored) => Reading {
+                start,
+                timestamp: stored.timestamp,
+                total: stored.total,
+                delta: stored.last_value,
+                carry: 0.0,
+                seen: Instant::now(),
+            },
+            None => Reading::unseen(start, Instant::now()),
+        };
+        self.series.lock().unwrap().readings.entry(SeriesKey::of(metric)).or_insert(reading);
+    }
+
+    /// The increase `metric`'s running total represents, or None for a reading older
+    /// than one already counted. An unseeded series counts in full, and a later `start`
+    /// or a total below the previous one means the counter restarted from zero.
+    pub fn delta(&self, metric: &MetricRecord, start: Option<DateTime<Utc>>) -> Option<f64> {
+        let now = Instant::now();
+        let total = metric.value;
+        let mut series = self.series.lock().unwrap();
+        series.prune(now, self.idle);
+        let reading = series.readings.entry(SeriesKey::of(metric)).or_insert(Reading::unseen(start, now));
+        let previous = *reading;
+        reading.seen = now;
+
+        // A retried export gets the same delta, which storage then deduplicates
+        if metric.timestamp == previous.timestamp && total == previous.total {
+            return Some(previous.delta);
+        }
+        let restarted = matches!((start, previous.start), (Some(start), Some(before)) if start > before);
+        if !restarted && metric.timestamp <= previous.timestamp {
+            return None;
+        }
+
+        let increase = if !restarted && total >= previous.total { total - previous.total } else { total };
+        let delta = increase + previous.carry;
+        *reading = Reading { start: start.or(previous.start), timestamp: metric.timestamp, total, delta, carry: 0.0, seen: now };
+        Some(delta)
+    }
+
+    /// Hand back the increase stored as `metric`'s value after it failed to reach storage,
+    /// so the series' next increase includes it; points of untracked series are ignored
+    pub fn give_back(&self, metric: &MetricRecord) {
+        if let Some(reading) = self.series.lock().unwrap().readings.get_mut(&SeriesKey::of(metric)) {
+            reading.carry += metric.value;
+        }
+    }
+}
+
+impl Series {
+    // Forget idle series, scanning at most once per `idle`
+    fn prune(&mut self, now: Instant, idle: Duration) {
+        if now.duration_since(self.pruned) < idle {
+            return;
+        }
+        self.readings.retain(|_, reading| now.duration_since(reading.seen) < idle);
+        self.pruned = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    fn reading(total: f64, second: u32, model: &str) -> MetricRecord {
+        MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            name: "claude_code.token.usage".to_string(),
+            timestamp: Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, second).unwrap(),
+            value: total,
+            labels: HashMap::from([("model".to_string(), model.to_string())]),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_totals_become_increases_per_series() {
+        let deltas = CumulativeDeltas::new();
+        assert_eq!(deltas.delta(&reading(100.0, 10, "sonnet"), None), Some(100.0));
+        assert_eq!(deltas.delta(&reading(40.0, 10, "opus"), None), Some(40.0));
+        assert_eq!(deltas.delta(&reading(150.0, 20, "sonnet"), None), Some(50.0));
+        assert_eq!(deltas.delta(&reading(150.0, 30, "sonnet"), None), Some(0.0));
+        // Retried, then out of order
+        assert_eq!(deltas.delta(&reading(150.0, 30, "sonnet"), None), Some(0.0));
+        assert_eq!(deltas.delta(&reading(120.0, 15, "sonnet"), None), None);
+        // The counter restarted
+        assert_eq!(deltas.delta(&reading(30.0, 40, "sonnet"), None), Some(30.0));
+        assert_eq!(deltas.delta(&reading(45.0, 20, "opus"), None), Some(5.0));
+    }
+
+    #[test]
+    fn test_seeded_series_and_later_starts() {
+        let deltas = CumulativeDeltas::new();
+        let started = |second| Some(Utc.with_ymd_and_hms(2025, 3, 1, 11, 0, second).unwrap());
+        let stored = SeriesTotal { total: 150.0, timestamp: reading(0.0, 20, "sonnet").timestamp, last_value: 50.0 };
+        deltas.seed(&reading(0.0, 0, "sonnet"), started(0), Some(stored));
+        // Seeding again doesn't replace a tracked series
+        deltas.seed(&reading(0.0, 0, "sonnet"), started(0), None);
+        assert_eq!(deltas.delta(&reading(150.0, 20, "sonnet"), started(0)), Some(50.0));
+        assert_eq!(deltas.delta(&reading(180.0, 30, "sonnet"), started(0)), Some(30.0));
+        // Restarted and already past the old total
+        assert_eq!(deltas.delta(&reading(200.0, 40, "sonnet"), started(35)), Some(200.0));
+        assert_eq!(deltas.delta(&reading(210.0, 50, "sonnet"), started(35)), Some(10.0));
+    }
+
+    #[test]
+    fn test_given_back_increases_join_the_next_one() {
+        let deltas = CumulativeDeltas::new();
+        assert_eq!(deltas.delta(&reading(100.0, 10, "sonnet"), None), Some(100.0));
+        let mut lost = reading(130.0, 20, "sonnet");
+        lost.value = deltas.delta(&lost, None).unwrap();
+        deltas.give_back(&lost);
+        deltas.give_back(&reading(5.0, 20, "opus"));
+        assert_eq!(deltas.delta(&reading(140.0, 30, "sonnet"), None), Some(40.0));
+        assert_eq!(deltas.delta(&reading(150.0, 40, "sonnet"), None), Some(10.0));
+        assert!(!deltas.tracks(&reading(0.0, 0, "opus")));
+    }
+
+    #[test]
+    fn test_idle_series_are_forgotten() {
+        let deltas = CumulativeDeltas::with_idle(Duration::ZERO);
+        deltas.delta(&reading(100.0, 10, "sonnet"), None);
+        assert!(deltas.tracks(&reading(0.0, 0, "sonnet")));
+        deltas.delta(&reading(40.0, 10, "opus"), None);
+        assert!(!deltas.tracks(&reading(0.0, 0, "sonnet")));
+        assert!(deltas.tracks(&reading(0.0, 0, "opus")));
+    }
+}
@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tracing::{info, warn};
+
+/// USD per million tokens for one model
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+    #[serde(default)]
+    pub cache_read: f64,
+    #[serde(default)]
+    pub cache_creation: f64,
+}
+
+impl ModelPrice {
+    fn standard(input: f64, output: f64) -> Self {
+        // Anthropic bills cache reads at 10% and cache writes at 125% of the input rate
+        Self { input, output, cache_read: input * 0.1, cache_creation: input * 1.25 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenCounts {
+    pub input: f64,
+    pub output: f64,
+    pub cache_read: f64,
+    pub cache_creation: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PricingTable {
+    /// Keyed by model name or name prefix, e.g. "claude-sonnet-4"
+    pub models: BTreeMap<String, ModelPrice>,
+}
+
+// (model prefix, input, output) in USD per million tokens
+const BUILTIN_PRICES: &[(&str, f64, f64)] = &[
+    ("claude-opus-4", 15.0, 75.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-3-haiku", 0.25, 1.25),
+];
+
+impl Default for PricingTable {
+    fn default() -> Self {
+        Self {
+            models: BUILTIN_PRICES
+                .iter()
+                .map(|(name, input, output)| (name.to_string(), ModelPrice::standard(*input, *output)))
+                .collect(),
+        }
+    }
+}
+
+impl PricingTable {
+    /// Load a table from a `.toml` or `.json` file
+    pub fn from_file(path: &Path) -> Result<Self, PricingError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| PricingError::FileRead(format!("{}: {}", path.display(), e)))?;
+
+        let table: PricingTable = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&content).map_err(|e| PricingError::Parse(e.to_string()))?,
+            Some("toml") => toml::from_str(&content).map_err(|e| PricingError::Parse(e.to_string()))?,
+            _ => return Err(PricingError::Parse(format!("Unsupported pricing file type: {}", path.display()))),
+        };
+
+        table.validate()?;
+        table.warn_unknown_models();
+        Ok(table)
+    }
+
+    pub fn validate(&self) -> Result<(), PricingError> {
+        if self.models.is_empty() {
+            return Err(PricingError::Invalid("Pricing table has no models".to_string()));
+        }
+
+        for (model, price) in &self.models {
+            let rates = [price.input, price.output, price.cache_read, price.cache_creation];
+            if rates.iter().any(|rate| !rate.is_finite() || *rate < 0.0) {
+                return Err(PricingError::Invalid(format!("Rates for {} must be non-negative numbers", model)));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Entries the built-in table doesn't know are usually typos or brand-new models
+    fn warn_unknown_models(&self) {
+        for model in self.models.keys() {
+            if !BUILTIN_PRICES.iter().any(|(known, _, _)| model.starts_with(known)) {
+                warn!("Pricing file lists unknown model {:?}", model);
+            }
+        }
+    }
+
+    /// Exact match first, then the longest configured prefix
+    /// ("claude-sonnet-4-20250514" -> "claude-sonnet-4")
+    pub fn price_for(&self, model: &str) -> Option<&ModelPrice> {
+        self.models.get(model).or_else(|| {
+            self.models
+                .iter()
+                .filter(|(name, _)| model.starts_with(name.as_str()))
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, price)| price)
+        })
+    }
+
+    /// Cost in USD, or None when the model has no price
+    pub fn cost(&self, model: &str, tokens: TokenCounts) -> Option<f64> {
+        let price = self.price_for(model)?;
+        let cost = tokens.input * price.input
+            + tokens.output * price.output
+            + tokens.cache_read * price.cache_read
+            + tokens.cache_creation * price.cache_creation;
+        Some(cost / 1_000_000.0)
+    }
+}
+
+/// The active pricing table plus where to reload it from
+pub struct PricingStore {
+    path: Option<PathBuf>,
+    table: RwLock<Arc<PricingTable>>,
+}
+
+impl PricingStore {
+    /// Built-in defaults, or the file at `path` when one is configured
+    pub fn load(path: Option<PathBuf>) -> Result<Self, PricingError> {
+        let table = match &path {
+            Some(path) => {
+                let table = PricingTable::from_file(path)?;
+                info!("Loaded pricing for {} model(s) from {}", table.models.len(), path.display());
+                table
+            }
+            None => PricingTable::default(),
+        };
+
+        Ok(Self { path, table: RwLock::new(Arc::new(table)) })
+    }
+
+    pub fn current(&self) -> Arc<PricingTable> {
+        self.table.read().unwrap().clone()
+    }
+
+    /// Re-read the pricing file, keeping the current table if it is invalid
+    pub fn reload(&self) -> Result<Arc<PricingTable>, PricingError> {
+        let Some(path) = &self.path else {
+            return Err(PricingError::NoFile);
+        };
+
+        let table = Arc::new(PricingTable::from_file(path)?);
+        *self.table.write().unwrap() = table.clone();
+        info!("Reloaded pricing for {} model(s) from {}", table.models.len(), path.display());
+        Ok(table)
+    }
+}
+
+impl Default for PricingStore {
+    fn default() -> Self {
+        Self { path: None, table: RwLock::new(Arc::new(PricingTable::default())) }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PricingError {
+    #[error("Failed to read pricing file: {0}")]
+    FileRead(String),
+    #[error("Failed to parse pricing file: {0}")]
+    Parse(String),
+    #[error("Invalid pricing table: {0}")]
+    Invalid(String),
+    #[error("No pricing file configured")]
+    NoFile,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_prices_match_model_prefixes() {
+        let table = PricingTable::default();
+        let tokens = TokenCounts { input: 1_000_000.0, output: 100_000.0, ..Default::default() };
+        let cost = table.cost("claude-sonnet-4-20250514", tokens).unwrap();
+        assert!((cost - 4.5).abs() < 1e-9);
+        assert!(table.cost("gpt-4", tokens).is_none());
+    }
+
+    #[test]
+    fn test_custom_pricing_file_rates_are_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pricing.toml");
+        std::fs::write(&path, r#"
+            [models."claude-sonnet-4"]
+            input = 2.0
+            output = 10.0
+            cache_read = 0.5
+        "#).unwrap();
+
+        let store = PricingStore::load(Some(path.clone())).unwrap();
+        let tokens = TokenCounts { input: 1_000_000.0, output: 1_000_000.0, cache_read: 2_000_000.0, cache_creation: 0.0 };
+        let cost = store.current().cost("claude-sonnet-4-20250514", tokens).unwrap();
+        assert!((cost - 13.0).abs() < 1e-9);
+        // Models missing from the file have no price
+        assert!(store.current().cost("claude-opus-4", tokens).is_none());
+
+        std::fs::write(&path, r#"{"models": {"claude-sonnet-4": {"input": 1.0, "output": 1.0}}}"#).unwrap();
+        assert!(matches!(store.reload(), Err(PricingError::Parse(_))));
+        // A failed reload keeps the previous rates
+        assert!((store.current().cost("claude-sonnet-4", tokens).unwrap() - 13.0).abs() < 1e-9);
+
+        std::fs::write(&path, "[models.\"claude-sonnet-4\"]\ninput = 1.0\noutput = 1.0\n").unwrap();
+        store.reload().unwrap();
+        assert!((store.current().cost("claude-sonnet-4", tokens).unwrap() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_invalid_pricing_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pricing.json");
+
+        std::fs::write(&path, r#"{"models": {"claude-opus-4": {"input": -1.0, "output": 75.0}}}"#).unwrap();
+        assert!(matches!(PricingTable::from_file(&path), Err(PricingError::Invalid(_))));
+
+        std::fs::write(&path, r#"{"models": {"claude-opus-4": {"input": 15.0, "outptu": 75.0}}}"#).unwrap();
+        assert!(matches!(PricingTable::from_file(&path), Err(PricingError::Parse(_))));
+    }
+}
@@ -0,0 +1,62 @@
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Replaces user emails in API responses with a salted hash when privacy mode is on.
+/// Stored data keeps the raw values.
+#[derive(Clone, Default)]
+pub struct Privacy {
+    salt: Option<Arc<str>>,
+}
+
+impl Privacy {
+    pub fn new(enabled: bool, salt: Option<&str>) -> Self {
+        if !enabled {
+            return Self::default();
+        }
+
+        let salt = match salt {
+            Some(salt) if !salt.is_empty() => salt.to_string(),
+            _ => {
+                warn!("Privacy mode has no salt configured; user hashes will change on restart");
+                uuid::Uuid::new_v4().to_string()
+            }
+        };
+        Self { salt: Some(Arc::from(salt)) }
+    }
+
+    /// The same user always maps to the same `user-<hex>` value for a given salt
+    pub fn mask(&self, user: &str) -> String {
+        let Some(salt) = self.salt.as_deref() else {
+            return user.to_string();
+        };
+
+        let digest = Sha256::new()
+            .chain_update(salt.as_bytes())
+            .chain_update(b":")
+            .chain_update(user.as_bytes())
+            .finalize();
+        format!("user-{}", hex::encode(&digest[..8]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_is_stable_per_user_and_salt() {
+        let privacy = Privacy::new(true, Some("pepper"));
+        let hashed = privacy.mask("dev@example.com");
+
+        assert!(hashed.starts_with("user-") && !hashed.contains('@'));
+        assert_eq!(privacy.mask("dev@example.com"), hashed);
+        assert_ne!(privacy.mask("ops@example.com"), hashed);
+        assert_ne!(Privacy::new(true, Some("salt")).mask("dev@example.com"), hashed);
+    }
+
+    #[test]
+    fn test_disabled_privacy_passes_values_through() {
+        assert_eq!(Privacy::new(false, Some("pepper")).mask("dev@example.com"), "dev@example.com");
+    }
+}
@@ -1,59 +1,255 @@
 use axum::{
-    http::{HeaderValue, Method, StatusCode},
-    response::{Html, IntoResponse},
+    extract::{DefaultBodyLimit, MatchedPath, Request, State},
+    http::{self, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
     routing::get,
     Router,
 };
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tower::ServiceBuilder;
 use tower_http::{
-    cors::{CorsLayer},
-    trace::TraceLayer,
+    compression::CompressionLayer,
+    cors::{AllowOrigin, Any, CorsLayer},
+    limit::RequestBodyLimitLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    timeout::TimeoutLayer,
+    trace::{MakeSpan, OnRequest, OnResponse, TraceLayer},
     services::ServeDir,
 };
-use tracing::{info, warn};
+use tracing::{info, warn, Level, Span};
 
-use crate::{api, storage::Database};
+use crate::api::{self, ApiError, AppState};
+use crate::config::Config;
+use crate::otel::{self, auth::constant_time_eq};
+use crate::shutdown::Shutdown;
+use crate::stats::HttpStats;
 
 pub async fn start_http_server(
     addr: SocketAddr,
-    db: Arc<dyn Database>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let app = create_app(db).await;
-
-    info!("HTTP server listening on {}", addr);
-    
+    state: AppState,
+    shutdown: Shutdown,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
-    
-    Ok(())
+    serve(listener, state, shutdown).await
 }
 
-async fn create_app(db: Arc<dyn Database>) -> Router {
-    // API routes with database state
-    let api_routes = api::create_routes().with_state(db);
+// Stops accepting connections on shutdown and returns once in-flight requests finish
+async fn serve(
+    listener: tokio::net::TcpListener,
+    state: AppState,
+    shutdown: Shutdown,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let app = create_app(state).await;
 
-    // Configure CORS
-    let cors = CorsLayer::new()
-        .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-        .allow_origin("http://127.0.0.1:3000".parse::<HeaderValue>().unwrap())
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
-        .allow_headers(tower_http::cors::Any);
+    info!("HTTP server listening on {}", listener.local_addr()?);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.wait())
+        .await?;
+
+    info!("HTTP server stopped");
+    Ok(())
+}
+
+async fn create_app(state: AppState) -> Router {
+    let cors = cors_layer(&state.config.cors_origins);
 
     // Create static file service for the entire web/dist directory
     let static_service = ServeDir::new("web/dist")
         .append_index_html_on_directories(true);
 
-    Router::new()
-        .nest("/api", api_routes)
+    let http_stats = state.http_stats.clone();
+    let config = state.config.clone();
+    let access_log = AccessLog { level: config.access_log_level.parse().unwrap_or(Level::INFO) };
+
+    let app = Router::new()
+        .nest(
+            "/api",
+            api::create_routes()
+                .route_layer(middleware::from_fn_with_state(state.config.clone(), require_api_key))
+                .merge(api::public_routes()),
+        )
+        // Self-monitoring scrape endpoint, kept outside /api
+        .route("/metrics", get(api::prometheus::get_self_metrics))
+        // Kubernetes probes, unauthenticated like /api/health
+        .route("/livez", get(api::livez))
+        .route("/readyz", get(api::readyz))
+        // OTLP/HTTP ingestion
+        .nest("/v1", otel::http::routes())
+        .with_state(state)
         .route("/", get(serve_index))
         // Serve all static files from web/dist, excluding API routes
-        .fallback_service(static_service)
+        .fallback_service(static_service);
+
+    with_request_limits(app, &config)
+        .layer(middleware::from_fn_with_state(http_stats, track_http_metrics))
         .layer(
             ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
+                // Requests keep a client's x-request-id or get a fresh one, echoed on the response
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(access_log)
+                        .on_request(access_log)
+                        .on_response(access_log),
+                )
+                .layer(PropagateRequestIdLayer::x_request_id())
                 .layer(cors)
+                // gzip or brotli per Accept-Encoding; SSE, gRPC and images are left alone
+                .layer(CompressionLayer::new())
+        )
+}
+
+// tracing fixes an event's level at its callsite, so each level needs its own
+macro_rules! access_event {
+    ($level:expr, $($field:tt)+) => {
+        match $level {
+            Level::ERROR => tracing::error!(target: "claude_lens::access", $($field)+),
+            Level::WARN => tracing::warn!(target: "claude_lens::access", $($field)+),
+            Level::INFO => tracing::info!(target: "claude_lens::access", $($field)+),
+            Level::DEBUG => tracing::debug!(target: "claude_lens::access", $($field)+),
+            Level::TRACE => tracing::trace!(target: "claude_lens::access", $($field)+),
+        }
+    };
+}
+
+// One span per request carrying its id, so whatever is logged while handling it can
+// be traced back to the request, plus a line when it starts and one when it ends
+#[derive(Clone, Copy)]
+struct AccessLog {
+    level: Level,
+}
+
+impl<B> MakeSpan<B> for AccessLog {
+    fn make_span(&mut self, request: &http::Request<B>) -> Span {
+        tracing::info_span!(
+            "request",
+            method = %request.method(),
+            path = %request.uri().path(),
+            request_id = request_id(request.headers()),
         )
+    }
+}
+
+impl<B> OnRequest<B> for AccessLog {
+    fn on_request(&mut self, request: &http::Request<B>, _: &Span) {
+        access_event!(
+            self.level,
+            method = %request.method(),
+            path = %request.uri().path(),
+            request_id = request_id(request.headers()),
+            "started"
+        );
+    }
+}
+
+impl<B> OnResponse<B> for AccessLog {
+    fn on_response(self, response: &http::Response<B>, latency: Duration, _: &Span) {
+        access_event!(
+            self.level,
+            status = response.status().as_u16(),
+            latency_ms = latency.as_secs_f64() * 1000.0,
+            request_id = request_id(response.headers()),
+            "finished"
+        );
+    }
+}
+
+fn request_id(headers: &HeaderMap) -> &str {
+    headers.get("x-request-id").and_then(|id| id.to_str().ok()).unwrap_or("")
+}
+
+// Bounds how long a request may run and how large its body may be. axum's own
+// 2 MB extractor limit is lifted so `max_request_body_bytes` is the only one.
+fn with_request_limits(router: Router, config: &Config) -> Router {
+    router
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(config.max_request_body_bytes))
+        .layer(TimeoutLayer::new(Duration::from_secs(config.request_timeout_secs)))
+        .layer(middleware::map_response(json_limit_errors))
+}
+
+// The timeout and body-limit layers answer with bare status codes
+async fn json_limit_errors(response: Response) -> Response {
+    match response.status() {
+        StatusCode::REQUEST_TIMEOUT => ApiError::Timeout.into_response(),
+        StatusCode::PAYLOAD_TOO_LARGE => ApiError::PayloadTooLarge.into_response(),
+        _ => response,
+    }
+}
+
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let parsed: Vec<HeaderValue> = origins
+            .iter()
+            .filter_map(|origin| match origin.parse::<HeaderValue>() {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    warn!("Ignoring invalid CORS origin: {:?}", origin);
+                    None
+                }
+            })
+            .collect();
+        AllowOrigin::list(parsed)
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers(Any)
+}
+
+// No-op unless API keys are configured
+async fn require_api_key(
+    State(config): State<Arc<Config>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if config.api_keys.is_empty() {
+        return Ok(next.run(request).await);
+    }
+
+    let presented = request
+        .headers()
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let authorized = config
+        .api_keys
+        .iter()
+        .any(|key| constant_time_eq(key.as_bytes(), presented.as_bytes()));
+
+    if authorized {
+        Ok(next.run(request).await)
+    } else {
+        Err(ApiError::Unauthorized)
+    }
+}
+
+// Label by route template (`/api/sessions/:id`) so path parameters don't add series
+async fn track_http_metrics(
+    State(http_stats): State<Arc<HttpStats>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    http_stats.record(&route, response.status().as_u16(), started.elapsed());
+
+    response
 }
 
 async fn serve_index() -> impl IntoResponse {
@@ -109,4 +305,344 @@ fn serve_fallback_html() -> Html<String> {
 
 async fn serve_fallback() -> impl IntoResponse {
     (StatusCode::NOT_FOUND, "File not found")
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::header};
+    use tower::ServiceExt;
+
+    use crate::storage::sqlite::test_database;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_ids_are_echoed_and_logged() {
+        let (_dir, db) = test_database().await;
+        let mut state = api::test_state(db);
+        state.config = Arc::new(Config { access_log_level: "debug".to_string(), ..Config::default() });
+        let app = create_app(state).await;
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_max_level(Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let request = Request::builder().uri("/api/health").header("x-request-id", "abc-123").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+
+        // Requests without one get a fresh id
+        let response = app.oneshot(Request::builder().uri("/livez").body(Body::empty()).unwrap()).await.unwrap();
+        let generated = response.headers()["x-request-id"].to_str().unwrap().to_string();
+        assert!(uuid::Uuid::parse_str(&generated).is_ok());
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|line: &serde_json::Value| line["target"] == "claude_lens::access")
+            .collect();
+        assert_eq!(lines.len(), 4);
+        let started = &lines[0];
+        assert_eq!(started["level"], "DEBUG");
+        assert_eq!(started["fields"]["message"], "started");
+        assert_eq!(started["fields"]["method"], "GET");
+        assert_eq!(started["fields"]["path"], "/api/health");
+        assert_eq!(started["fields"]["request_id"], "abc-123");
+        let finished = &lines[1];
+        assert_eq!(finished["fields"]["status"], 200);
+        assert!(finished["fields"]["latency_ms"].as_f64().is_some());
+        assert_eq!(finished["span"]["path"], "/api/health");
+        assert_eq!(finished["span"]["request_id"], "abc-123");
+        assert_eq!(lines[3]["fields"]["request_id"], generated.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_self_metrics_endpoint() {
+        let (_dir, db) = test_database().await;
+        let state = api::test_state(db);
+        state.stats.record_metrics(3);
+        let app = create_app(state).await;
+
+        let response = app
+            .oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "text/plain; version=0.0.4; charset=utf-8"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("claude_lens_metrics_ingested_total 3"));
+        assert!(body.contains("claude_lens_logs_ingested_total 0"));
+        assert!(body.contains("claude_lens_traces_ingested_total 0"));
+        assert!(body.contains("claude_lens_ingestion_errors_total 0"));
+        assert!(body.contains("claude_lens_sessions 0"));
+    }
+
+    #[tokio::test]
+    async fn test_per_route_http_metrics() {
+        let (_dir, db) = test_database().await;
+        let app = create_app(api::test_state(db)).await;
+
+        for uri in ["/api/health", "/api/health", "/api/sessions/not-a-uuid", &format!("/api/sessions/{}", uuid::Uuid::new_v4())] {
+            app.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+        }
+
+        let response = app
+            .oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("claude_lens_http_requests_total{route=\"/api/health\",status_class=\"2xx\"} 2\n"), "{}", body);
+        assert!(body.contains("claude_lens_http_requests_total{route=\"/api/sessions/:id\",status_class=\"4xx\"} 2\n"), "{}", body);
+        assert!(body.contains("claude_lens_http_request_errors_total{route=\"/api/health\",status_class=\"2xx\"} 0\n"));
+        assert!(body.contains("claude_lens_http_request_duration_seconds_count{route=\"/api/health\",status_class=\"2xx\"} 2\n"));
+        assert!(body.contains("claude_lens_http_request_duration_seconds_bucket{le=\"+Inf\",route=\"/api/health\",status_class=\"2xx\"} 2\n"));
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_compressed_when_accepted() {
+        let (_dir, db) = test_database().await;
+        let app = create_app(api::test_state(db)).await;
+        let heatmap = |encoding: Option<&str>| {
+            let mut request = Request::builder().uri("/api/analytics/dashboard/usage-heatmap?range=7d");
+            if let Some(encoding) = encoding {
+                request = request.header(header::ACCEPT_ENCODING, encoding);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let response = heatmap(Some("gzip")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let response = heatmap(Some("br")).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+
+        let response = heatmap(None).await.unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(serde_json::from_slice::<serde_json::Value>(&body).is_ok());
+    }
+
+    async fn preflight_origin(origins: &[&str], origin: &str) -> Option<HeaderValue> {
+        let (_dir, db) = test_database().await;
+        let mut state = api::test_state(db);
+        state.config = Arc::new(Config {
+            cors_origins: origins.iter().map(|o| o.to_string()).collect(),
+            ..Config::default()
+        });
+        let app = create_app(state).await;
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/health")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).cloned()
+    }
+
+    #[tokio::test]
+    async fn test_cors_uses_configured_origins() {
+        let origins = ["https://dash.example.com", "not a valid\norigin"];
+        assert_eq!(
+            preflight_origin(&origins, "https://dash.example.com").await.unwrap(),
+            "https://dash.example.com"
+        );
+        assert!(preflight_origin(&origins, "http://localhost:3000").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cors_wildcard_allows_any_origin() {
+        assert_eq!(preflight_origin(&["*"], "https://anywhere.example").await.unwrap(), "*");
+    }
+
+    #[tokio::test]
+    async fn test_api_key_required_when_configured() {
+        let (_dir, db) = test_database().await;
+        let mut state = api::test_state(db);
+        state.config = Arc::new(Config {
+            api_keys: vec!["k1".to_string(), "k2".to_string()],
+            ..Config::default()
+        });
+        let app = create_app(state).await;
+        let get = |uri: &str, key: Option<&str>| {
+            let mut builder = Request::builder().uri(uri);
+            if let Some(key) = key {
+                builder = builder.header("x-api-key", key);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        let response = app.clone().oneshot(get("/api/sessions", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"], "Missing or invalid API key");
+
+        let response = app.clone().oneshot(get("/api/sessions", Some("wrong"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app.clone().oneshot(get("/api/sessions", Some("k2"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Health stays public
+        let response = app.oneshot(get("/api/health", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_times_out_with_json_error() {
+        let config = Config { request_timeout_secs: 1, ..Config::default() };
+        let slow = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                "done"
+            }),
+        );
+        let app = with_request_limits(slow, &config);
+
+        let response = app
+            .oneshot(Request::builder().uri("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"], "Request timed out");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_rejected_with_json_error() {
+        let (_dir, db) = test_database().await;
+        let mut state = api::test_state(db);
+        state.config = Arc::new(Config { max_request_body_bytes: 1024, ..Config::default() });
+        let app = create_app(state).await;
+        let post = |body: Vec<u8>, content_length: bool| {
+            let mut builder = Request::builder()
+                .method("POST")
+                .uri("/v1/metrics")
+                .header(header::CONTENT_TYPE, "application/json");
+            if content_length {
+                builder = builder.header(header::CONTENT_LENGTH, body.len());
+            }
+            builder.body(Body::from(body)).unwrap()
+        };
+
+        // Rejected up front from Content-Length, and while reading when it is absent
+        for content_length in [true, false] {
+            let response = app.clone().oneshot(post(vec![b' '; 4096], content_length)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"], "Request body too large");
+        }
+
+        let response = app.oneshot(post(br#"{"resourceMetrics": []}"#.to_vec(), true)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_api_open_without_keys() {
+        let (_dir, db) = test_database().await;
+        let app = create_app(api::test_state(db)).await;
+
+        let response = app
+            .oneshot(Request::builder().uri("/api/sessions").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_server_stops_on_shutdown_signal() {
+        let (_dir, db) = test_database().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(listener, api::test_state(db), shutdown.clone()));
+
+        // Serving until the signal fires
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut stream, b"GET /api/health HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        shutdown.trigger();
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop after shutdown");
+        assert!(result.unwrap().is_ok());
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_request_completes_after_shutdown_signal() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (_dir, db) = test_database().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(serve(listener, api::test_state(db), shutdown.clone()));
+
+        // The handler is running and waiting on the rest of the body when the signal fires
+        let body = br#"{"user_id":"alice"}"#;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let head = format!(
+            "POST /api/sessions HTTP/1.1\r\nHost: test\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(&body[..5]).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        shutdown.trigger();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!server.is_finished(), "server stopped before the in-flight request finished");
+
+        stream.write_all(&body[5..]).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 201"), "{}", response);
+
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop once the request finished");
+        assert!(result.unwrap().is_ok());
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Cloneable shutdown signal shared by both servers
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Self { sender: Arc::new(sender), receiver }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Resolves once `trigger` has been called on any clone
+    pub async fn wait(mut self) {
+        // An error means every sender is gone, which can only happen after shutdown
+        let _ = self.receiver.wait_for(|triggered| *triggered).await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
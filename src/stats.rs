@@ -0,0 +1,135 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Process-wide ingestion counters, shared by the receivers and the HTTP server
+#[derive(Debug, Default)]
+pub struct IngestStats {
+    metrics_ingested: AtomicU64,
+    logs_ingested: AtomicU64,
+    traces_ingested: AtomicU64,
+    ingestion_errors: AtomicU64,
+    records_shed: AtomicU64,
+    metrics_deduplicated: AtomicU64,
+}
+
+impl IngestStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_metrics(&self, count: u64) {
+        self.metrics_ingested.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_logs(&self, count: u64) {
+        self.logs_ingested.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_traces(&self, count: u64) {
+        self.traces_ingested.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_errors(&self, count: u64) {
+        self.ingestion_errors.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records dropped because the ingestion queue was full
+    pub fn record_shed(&self, count: u64) {
+        self.records_shed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Metric points skipped because the same point was already stored
+    pub fn record_deduplicated(&self, count: u64) {
+        self.metrics_deduplicated.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn metrics_ingested(&self) -> u64 {
+        self.metrics_ingested.load(Ordering::Relaxed)
+    }
+
+    pub fn logs_ingested(&self) -> u64 {
+        self.logs_ingested.load(Ordering::Relaxed)
+    }
+
+    pub fn traces_ingested(&self) -> u64 {
+        self.traces_ingested.load(Ordering::Relaxed)
+    }
+
+    pub fn ingestion_errors(&self) -> u64 {
+        self.ingestion_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn records_shed(&self) -> u64 {
+        self.records_shed.load(Ordering::Relaxed)
+    }
+
+    pub fn metrics_deduplicated(&self) -> u64 {
+        self.metrics_deduplicated.load(Ordering::Relaxed)
+    }
+}
+
+/// Upper bounds (seconds) of the request latency histogram buckets
+pub const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Clone, Default)]
+pub struct RouteStats {
+    pub requests: u64,
+    /// Responses with a 5xx status
+    pub errors: u64,
+    /// Non-cumulative counts per `LATENCY_BUCKETS` entry; slower requests only count in `requests`
+    pub latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    pub latency_sum: f64,
+}
+
+/// Per-route HTTP counters, keyed by matched route and status class
+#[derive(Debug, Default)]
+pub struct HttpStats {
+    routes: Mutex<BTreeMap<(String, &'static str), RouteStats>>,
+}
+
+impl HttpStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, route: &str, status: u16, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let mut routes = self.routes.lock().unwrap();
+        let entry = routes.entry((route.to_string(), status_class(status))).or_default();
+
+        entry.requests += 1;
+        if status >= 500 {
+            entry.errors += 1;
+        }
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            entry.latency_buckets[bucket] += 1;
+        }
+        entry.latency_sum += seconds;
+    }
+
+    /// (route, status class, stats) for every route seen so far, sorted
+    pub fn snapshot(&self) -> Vec<(String, &'static str, RouteStats)> {
+        self.routes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((route, class), stats)| (route.clone(), *class, stats.clone()))
+            .collect()
+    }
+}
+
+fn status_class(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
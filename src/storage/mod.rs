@@ -1,26 +1,157 @@
+#[cfg(feature = "postgres")]
+pub mod postgres;
 pub mod sqlite;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use std::collections::HashMap;
+use futures_util::stream::BoxStream;
+use sha2::{Digest, Sha256};
+use std::{collections::{BTreeMap, HashMap}, sync::Arc};
 use uuid::Uuid;
 
+use crate::config::Config;
+use crate::otel::{classify_event, classify_metric, EventType, MetricType, ProcessedEvent, ProcessedMetric, SessionSummary};
+
+/// Whether `url` names a Postgres server rather than a SQLite file
+pub fn is_postgres_url(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+/// Connect to and migrate the backend the configured database URL's scheme selects
+pub async fn init_database(config: &Config) -> Result<Arc<dyn Database>, DatabaseError> {
+    if !is_postgres_url(&config.database_url()) {
+        return sqlite::init_database(config).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    return postgres::init_database(config).await;
+    #[cfg(not(feature = "postgres"))]
+    Err(DatabaseError::Connection("Postgres URLs need a build with the postgres feature".to_string()))
+}
+
 #[async_trait]
 pub trait Database: Send + Sync {
+    /// True once writes are known to fail; reads keep working
+    fn is_read_only(&self) -> bool;
+    /// Open and idle connections of the pool
+    fn pool_stats(&self) -> PoolStats;
+    /// Cheapest round trip to the database (`SELECT 1`)
+    async fn ping(&self) -> Result<(), DatabaseError>;
+    /// `ping`, then the database's size and row counts; fails when it is unreachable
+    async fn check_health(&self) -> Result<DatabaseHealth, DatabaseError>;
+
     // Session operations
     async fn create_session(&self, user_id: &str) -> Result<Uuid, DatabaseError>;
     async fn get_session(&self, session_id: Uuid) -> Result<Option<SessionRecord>, DatabaseError>;
+    /// Token, cost, code and tool totals kept up to date as metrics and logs are stored, read
+    /// without scanning them; still counts rows since removed by retention. None for unknown sessions
+    async fn get_session_rollup(&self, session_id: Uuid) -> Result<Option<SessionSummary>, DatabaseError>;
+    /// Insert a session with a known id. An existing row keeps its user unless that was
+    /// "unknown", its start moves earlier if `start` is, and it reopens if the idle reaper
+    /// ended it before `start`
+    async fn upsert_session(&self, id: Uuid, user_id: &str, start: DateTime<Utc>) -> Result<(), DatabaseError>;
+    /// Set or clear the end time; a session ended here counts as completed
     async fn update_session(&self, session_id: Uuid, end_time: Option<DateTime<Utc>>) -> Result<(), DatabaseError>;
+    /// End open sessions whose last event (or start, without events) is before `idle_since`,
+    /// as terminated at that last event; returns how many were ended
+    async fn end_idle_sessions(&self, idle_since: DateTime<Utc>) -> Result<u64, DatabaseError>;
+    async fn increment_command_count(&self, session_id: Uuid) -> Result<(), DatabaseError>;
     async fn list_sessions(&self, user_id: Option<&str>, limit: u32, offset: u32) -> Result<Vec<SessionRecord>, DatabaseError>;
+    async fn list_sessions_sorted(
+        &self,
+        filter: &SessionFilter,
+        sort: SessionSort,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<SessionRecord>, DatabaseError>;
+    async fn count_sessions(&self, filter: &SessionFilter) -> Result<u64, DatabaseError>;
+    /// Sessions whose user id contains `query`, or that have a log or metric
+    /// attribute equal to it (case-insensitive), newest first
+    async fn search_sessions(&self, query: &str, limit: u32) -> Result<Vec<SessionRecord>, DatabaseError>;
+    /// Sessions that started in the range and have ended, oldest first
+    async fn list_ended_sessions(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<SessionRecord>, DatabaseError>;
 
     // Metrics operations
-    async fn store_metric(&self, metric: &MetricRecord) -> Result<(), DatabaseError>;
+    /// False when a point with the same `dedupe_key` is already stored
+    async fn store_metric(&self, metric: &MetricRecord) -> Result<bool, DatabaseError>;
+    /// Store `metric` and insert or merge its session as `upsert_session` does, in one
+    /// transaction so the row is never left without its session
+    async fn store_metric_in_session(&self, metric: &MetricRecord, user_id: &str, start: DateTime<Utc>) -> Result<bool, DatabaseError>;
     async fn get_metrics(
         &self,
         start_time: Option<DateTime<Utc>>,
         end_time: Option<DateTime<Utc>>,
         metric_name: Option<&str>,
     ) -> Result<Vec<MetricRecord>, DatabaseError>;
+    /// Points in the range that fall within `scope`, newest first
+    async fn get_scoped_metrics(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+        scope: &MetricScope,
+    ) -> Result<Vec<MetricRecord>, DatabaseError>;
+    /// Points in the range matching `filter`, oldest first, read from the database as the
+    /// stream is polled
+    fn stream_metrics(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        filter: MetricFilter,
+    ) -> BoxStream<'static, Result<MetricRecord, DatabaseError>>;
+    /// Distinct label keys across the points `stream_metrics` would return, sorted
+    async fn metric_label_keys(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+    ) -> Result<Vec<String>, DatabaseError>;
+    /// Points in the range carrying the label `key` = `value`, newest first
+    async fn get_metrics_by_label(
+        &self,
+        key: &str,
+        value: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+    ) -> Result<Vec<MetricRecord>, DatabaseError>;
+    /// Most recent point for every distinct (name, labels) series
+    async fn get_latest_metrics(&self) -> Result<Vec<MetricRecord>, DatabaseError>;
+    /// Like `get_latest_metrics`, with each point's value replaced by its series' sum
+    async fn get_series_totals(&self) -> Result<Vec<MetricRecord>, DatabaseError>;
+    /// Stored points of `metric`'s series (its name, labels and session) at or after `since`
+    /// added up, with the latest of them; None when the series has none
+    async fn series_total(&self, metric: &MetricRecord, since: Option<DateTime<Utc>>) -> Result<Option<SeriesTotal>, DatabaseError>;
+    /// Cost and token sums per UTC bucket in the range over the points within `scope`;
+    /// buckets without points are omitted
+    async fn cost_buckets(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        bucket: TimeBucket,
+        scope: &MetricScope,
+    ) -> Result<Vec<CostBucket>, DatabaseError>;
+    /// Count, sum, min, max and mean of the values of each metric name in the range, by name
+    async fn metric_stats(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<MetricStats>, DatabaseError>;
+    /// Per-name stats over epoch-aligned buckets of `width_secs`; buckets without points are omitted
+    async fn metric_buckets(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+        width_secs: i64,
+    ) -> Result<Vec<MetricBucket>, DatabaseError>;
+    /// Metric names, `model` and `user.email` label values of the points in the range,
+    /// and tool names of its tool results, each with how often it occurs
+    async fn facets(&self, start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> Result<Facets, DatabaseError>;
 
     // Trace operations
     async fn store_trace(&self, trace: &TraceRecord) -> Result<(), DatabaseError>;
@@ -30,6 +161,13 @@ pub trait Database: Send + Sync {
         end_time: Option<DateTime<Utc>>,
         trace_id: Option<&str>,
     ) -> Result<Vec<TraceRecord>, DatabaseError>;
+    /// Distinct traces with at least one span starting in the range, newest first
+    async fn list_traces(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<TraceSummary>, DatabaseError>;
 
     // Log operations
     async fn store_log(&self, log: &LogRecord) -> Result<(), DatabaseError>;
@@ -39,6 +177,49 @@ pub trait Database: Send + Sync {
         end_time: Option<DateTime<Utc>>,
         level: Option<&str>,
     ) -> Result<Vec<LogRecord>, DatabaseError>;
+    /// Every log of one session, oldest first
+    async fn get_logs_for_session(&self, session_id: Uuid) -> Result<Vec<LogRecord>, DatabaseError>;
+    /// One page of logs, newest first
+    async fn list_logs(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        level: Option<&str>,
+        service: Option<&str>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<LogRecord>, DatabaseError>;
+    async fn count_logs(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        level: Option<&str>,
+        service: Option<&str>,
+    ) -> Result<u64, DatabaseError>;
+    /// Logs whose message or an attribute value contains `query` (case-insensitive), newest first
+    async fn search_logs(
+        &self,
+        query: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<LogRecord>, DatabaseError>;
+
+    // Budgets
+    /// Create or replace the monthly budget of `user_id`
+    async fn set_budget(&self, user_id: &str, monthly_budget_usd: f64) -> Result<BudgetRecord, DatabaseError>;
+    async fn get_budget(&self, user_id: &str) -> Result<Option<BudgetRecord>, DatabaseError>;
+
+    // Retention
+    /// Delete telemetry older than `cutoff` and sessions that ended before it
+    async fn delete_older_than(&self, cutoff: DateTime<Utc>) -> Result<PurgeCounts, DatabaseError>;
+
+    // Deletion
+    /// Delete a session with its metrics, logs and traces; None for unknown sessions
+    async fn delete_session(&self, session_id: Uuid) -> Result<Option<PurgeCounts>, DatabaseError>;
+    /// Delete every session of `user_id` with its metrics, logs and traces, along with
+    /// session-less records whose user.email is `user_id` and the user's budget
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<PurgeCounts, DatabaseError>;
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -53,6 +234,183 @@ pub enum DatabaseError {
     NotFound,
     #[error("Invalid data: {0}")]
     InvalidData(String),
+    #[error("Database is read-only")]
+    ReadOnly,
+}
+
+/// Narrows metric queries to one user's or organization's points; the default matches everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricScope {
+    /// Matches the `user.email` label, or the user of the point's session
+    pub user_email: Option<String>,
+    /// Matches the `organization.id` label
+    pub organization_id: Option<String>,
+    /// Matches the `service.name` of the exporting resource
+    pub service: Option<String>,
+}
+
+/// Narrows the points `stream_metrics` returns; the default matches every point in the range
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricFilter {
+    pub metric_name: Option<String>,
+    /// A label `key` = `value` the point carries
+    pub label: Option<(String, String)>,
+    /// Matches the `service.name` of the exporting resource
+    pub service: Option<String>,
+}
+
+/// Narrows session listings; the default matches every session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionFilter {
+    pub user_id: Option<String>,
+    /// Sessions that started at or after this instant
+    pub start_time: Option<DateTime<Utc>>,
+    /// Sessions that started at or before this instant
+    pub end_time: Option<DateTime<Utc>>,
+    pub min_command_count: Option<u64>,
+    pub status: Option<SessionState>,
+}
+
+impl SessionFilter {
+    pub fn for_user(user_id: &str) -> Self {
+        Self { user_id: Some(user_id.to_string()), ..Self::default() }
+    }
+}
+
+/// Whether and how a session has ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    Active,
+    Completed,
+    Terminated,
+}
+
+impl SessionState {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "active" => Some(Self::Active),
+            "completed" => Some(Self::Completed),
+            "terminated" => Some(Self::Terminated),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionSortKey {
+    StartTime,
+    Duration,
+    CommandCount,
+}
+
+impl SessionSortKey {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "start_time" => Some(Self::StartTime),
+            "duration" => Some(Self::Duration),
+            "command_count" => Some(Self::CommandCount),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "asc" => Some(Self::Asc),
+            "desc" => Some(Self::Desc),
+            _ => None,
+        }
+    }
+}
+
+/// Width of a time-series bucket, aligned to UTC (weeks start on Monday)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBucket {
+    Hour,
+    Day,
+    Week,
+}
+
+impl TimeBucket {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "hour" => Some(Self::Hour),
+            "day" => Some(Self::Day),
+            "week" => Some(Self::Week),
+            _ => None,
+        }
+    }
+
+    pub fn duration(&self) -> chrono::Duration {
+        match self {
+            Self::Hour => chrono::Duration::hours(1),
+            Self::Day => chrono::Duration::days(1),
+            Self::Week => chrono::Duration::weeks(1),
+        }
+    }
+
+    /// Start of the bucket containing `timestamp`
+    pub fn truncate(&self, timestamp: DateTime<Utc>) -> DateTime<Utc> {
+        use chrono::{Datelike, NaiveTime, Timelike};
+
+        match self {
+            Self::Hour => timestamp.date_naive().and_hms_opt(timestamp.hour(), 0, 0).unwrap().and_utc(),
+            Self::Day => timestamp.date_naive().and_time(NaiveTime::MIN).and_utc(),
+            Self::Week => {
+                let monday = timestamp.date_naive()
+                    - chrono::Duration::days(timestamp.weekday().num_days_from_monday().into());
+                monday.and_time(NaiveTime::MIN).and_utc()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionSort {
+    pub key: SessionSortKey,
+    pub order: SortOrder,
+}
+
+impl Default for SessionSort {
+    fn default() -> Self {
+        Self {
+            key: SessionSortKey::StartTime,
+            order: SortOrder::Desc,
+        }
+    }
+}
+
+/// How a session came to an end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEndReason {
+    /// Closed explicitly
+    Completed,
+    /// Ended by the idle reaper
+    Terminated,
+}
+
+impl SessionEndReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Completed => "completed",
+            Self::Terminated => "terminated",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "completed" => Some(Self::Completed),
+            "terminated" => Some(Self::Terminated),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -61,6 +419,8 @@ pub struct SessionRecord {
     pub user_id: String,
     pub start_time: DateTime<Utc>,
     pub end_time: Option<DateTime<Utc>>,
+    /// None while the session is open, and for sessions ended before this was recorded
+    pub end_reason: Option<SessionEndReason>,
     pub command_count: u64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -74,9 +434,134 @@ pub struct MetricRecord {
     pub timestamp: DateTime<Utc>,
     pub value: f64,
     pub labels: HashMap<String, String>,
+    /// From the OTLP metric; None for rows stored before it was kept
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    /// The exporter's `service.name` resource attribute
+    pub service: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
+impl MetricRecord {
+    /// Stable hash of what identifies a data point, so a retried export stores it only once
+    pub fn dedupe_key(&self) -> String {
+        let labels: BTreeMap<_, _> = self.labels.iter().collect();
+        let identity = serde_json::json!([
+            self.name,
+            self.timestamp.timestamp(),
+            self.timestamp.timestamp_subsec_nanos(),
+            labels,
+            self.session_id,
+        ]);
+        hex::encode(Sha256::digest(identity.to_string().as_bytes()))
+    }
+
+    /// What the point adds to its session's rollup; None without a session or when it isn't summarized
+    pub fn rollup(&self) -> Option<(Uuid, SessionSummary)> {
+        let session_id = self.session_id?;
+        let metric_type = classify_metric(&self.name, &self.labels);
+        if matches!(metric_type, MetricType::SessionCount | MetricType::Other) {
+            return None;
+        }
+
+        let mut delta = SessionSummary { session_id: session_id.to_string(), ..SessionSummary::default() };
+        delta.update_from_metric(&ProcessedMetric {
+            name: self.name.clone(),
+            value: self.value,
+            timestamp: self.timestamp,
+            labels: self.labels.clone(),
+            session_id: Some(session_id.to_string()),
+            metric_type,
+        });
+        Some((session_id, delta))
+    }
+}
+
+/// Sum of the stored points of one series, as of its latest point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeriesTotal {
+    pub total: f64,
+    pub timestamp: DateTime<Utc>,
+    /// Value of the latest point
+    pub last_value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricStats {
+    pub name: String,
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+/// Values of one metric name within a single timeline bucket
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricBucket {
+    pub name: String,
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    pub start: DateTime<Utc>,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Sums over one bucket of `claude_code.cost.usage` and `claude_code.token.usage`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostBucket {
+    pub start: DateTime<Utc>,
+    pub cost_usd: f64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_creation_tokens: u64,
+    pub cache_read_tokens: u64,
+    /// Distinct sessions reporting cost or tokens in the bucket
+    pub sessions: u64,
+}
+
+/// One distinct value and the number of rows carrying it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: u64,
+}
+
+/// Distinct values for filtering, each list most frequent first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Facets {
+    pub metric_names: Vec<FacetCount>,
+    pub models: Vec<FacetCount>,
+    pub user_emails: Vec<FacetCount>,
+    pub tool_names: Vec<FacetCount>,
+}
+
+impl Facets {
+    /// Append `value` to the list named by `facet`, as the backends' queries label them
+    fn push(&mut self, facet: &str, value: String, count: i64) {
+        let list = match facet {
+            "metric_name" => &mut self.metric_names,
+            "model" => &mut self.models,
+            "user_email" => &mut self.user_emails,
+            "tool_name" => &mut self.tool_names,
+            _ => return,
+        };
+        list.push(FacetCount { value, count: count as u64 });
+    }
+}
+
+/// Monthly spending limit of one user, matched against `sessions.user_id`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetRecord {
+    pub user_id: String,
+    pub monthly_budget_usd: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone)]
 pub struct TraceRecord {
     pub id: Uuid,
@@ -92,6 +577,49 @@ pub struct TraceRecord {
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone)]
+pub struct TraceSummary {
+    pub trace_id: String,
+    pub span_count: u64,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    /// Name and duration of the earliest span without a parent, if one was received
+    pub root_name: Option<String>,
+    pub root_duration_ns: Option<u64>,
+}
+
+/// Rows removed by one retention pass or deletion request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PurgeCounts {
+    pub metrics: u64,
+    pub logs: u64,
+    pub traces: u64,
+    pub sessions: u64,
+    /// Only a user's data deletion removes budgets
+    pub budgets: u64,
+}
+
+impl PurgeCounts {
+    pub fn total(&self) -> u64 {
+        self.metrics + self.logs + self.traces + self.sessions + self.budgets
+    }
+}
+
+/// Tables reported by the deep health check
+pub const HEALTH_TABLES: [&str; 6] = ["sessions", "metrics", "logs", "traces", "budgets", "metric_attributes"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseHealth {
+    pub size_bytes: u64,
+    pub table_rows: BTreeMap<String, u64>,
+}
+
 #[derive(Debug, Clone)]
 pub struct LogRecord {
     pub id: Uuid,
@@ -100,5 +628,27 @@ pub struct LogRecord {
     pub level: String,
     pub message: String,
     pub attributes: HashMap<String, String>,
+    /// The exporter's `service.name` resource attribute
+    pub service: Option<String>,
     pub created_at: DateTime<Utc>,
+}
+
+impl LogRecord {
+    /// What the event adds to its session's rollup; None without a session or when it isn't summarized
+    pub fn rollup(&self) -> Option<(Uuid, SessionSummary)> {
+        let session_id = self.session_id?;
+        let event_type = classify_event(&self.message, &self.attributes);
+        if !matches!(event_type, EventType::ToolResult { .. } | EventType::ApiRequest { .. } | EventType::ApiRequestFailed { .. }) {
+            return None;
+        }
+
+        let mut delta = SessionSummary { session_id: session_id.to_string(), ..SessionSummary::default() };
+        delta.update_from_event(&ProcessedEvent {
+            event_type,
+            timestamp: self.timestamp,
+            attributes: self.attributes.clone(),
+            session_id: Some(session_id.to_string()),
+        });
+        Some((session_id, delta))
+    }
 }
\ No newline at end of file
@@ -0,0 +1,2051 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use sqlx::{
+    postgres::{PgConnection, PgPool, PgPoolOptions, PgRow},
+    types::Json,
+    Executor, Postgres, QueryBuilder, Row,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::otel::SessionSummary;
+use super::{
+    BudgetRecord, CostBucket, Database, DatabaseError, DatabaseHealth, Facets, LogRecord, MetricBucket, MetricFilter, MetricRecord, MetricScope, MetricStats, PoolStats, PurgeCounts, SessionEndReason, SessionFilter, SessionRecord,
+    SeriesTotal, SessionSort, SessionSortKey, SessionState, SortOrder, TimeBucket, TraceRecord, TraceSummary, HEALTH_TABLES,
+};
+
+// Rows read ahead of a slow stream consumer
+const STREAM_BUFFER: usize = 256;
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sql: &'static str,
+}
+
+// The same logical schema as the SQLite migrations, version for version, in native
+// Postgres types. Applied in order and never edited once released.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: r#"
+        CREATE TABLE sessions (
+            id UUID PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            start_time TIMESTAMPTZ NOT NULL,
+            end_time TIMESTAMPTZ NULL,
+            command_count BIGINT NOT NULL DEFAULT 0,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        );
+
+        CREATE INDEX idx_sessions_user_id ON sessions(user_id);
+        CREATE INDEX idx_sessions_start_time ON sessions(start_time);
+
+        CREATE TABLE metrics (
+            id UUID PRIMARY KEY,
+            session_id UUID NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            timestamp TIMESTAMPTZ NOT NULL,
+            value DOUBLE PRECISION NOT NULL,
+            labels JSONB NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        );
+
+        CREATE INDEX idx_metrics_name ON metrics(name);
+        CREATE INDEX idx_metrics_timestamp ON metrics(timestamp);
+        CREATE INDEX idx_metrics_session_id ON metrics(session_id);
+
+        CREATE TABLE metric_attributes (
+            metric_id UUID NOT NULL REFERENCES metrics(id) ON DELETE CASCADE,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (metric_id, key)
+        );
+
+        CREATE INDEX idx_metric_attributes_key_value ON metric_attributes(key, value);
+
+        CREATE TABLE traces (
+            id UUID PRIMARY KEY,
+            session_id UUID NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            trace_id TEXT NOT NULL,
+            span_id TEXT NOT NULL,
+            parent_span_id TEXT NULL,
+            name TEXT NOT NULL,
+            start_time TIMESTAMPTZ NOT NULL,
+            end_time TIMESTAMPTZ NOT NULL,
+            duration_ns BIGINT NOT NULL,
+            attributes JSONB NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        );
+
+        CREATE INDEX idx_traces_trace_id ON traces(trace_id);
+        CREATE INDEX idx_traces_span_id ON traces(span_id);
+        CREATE INDEX idx_traces_start_time ON traces(start_time);
+        CREATE INDEX idx_traces_session_id ON traces(session_id);
+
+        CREATE TABLE logs (
+            id UUID PRIMARY KEY,
+            session_id UUID NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            timestamp TIMESTAMPTZ NOT NULL,
+            level TEXT NOT NULL,
+            message TEXT NOT NULL,
+            attributes JSONB NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        );
+
+        CREATE INDEX idx_logs_timestamp ON logs(timestamp);
+        CREATE INDEX idx_logs_level ON logs(level);
+        CREATE INDEX idx_logs_session_id ON logs(session_id);
+        "#,
+    },
+    Migration {
+        version: 2,
+        description: "metric unit and description",
+        sql: r#"
+        ALTER TABLE metrics ADD COLUMN unit TEXT NULL;
+        ALTER TABLE metrics ADD COLUMN description TEXT NULL;
+        "#,
+    },
+    Migration {
+        version: 3,
+        description: "per-user budgets",
+        sql: r#"
+        CREATE TABLE budgets (
+            user_id TEXT PRIMARY KEY,
+            monthly_budget_usd DOUBLE PRECISION NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL
+        );
+        "#,
+    },
+    Migration {
+        version: 4,
+        description: "session end reason",
+        sql: r#"
+        ALTER TABLE sessions ADD COLUMN end_reason TEXT NULL;
+        "#,
+    },
+    Migration {
+        version: 5,
+        description: "metric dedupe key",
+        sql: r#"
+        ALTER TABLE metrics ADD COLUMN dedupe_key TEXT NULL;
+        CREATE UNIQUE INDEX idx_metrics_dedupe_key ON metrics(dedupe_key);
+        "#,
+    },
+    Migration {
+        version: 6,
+        description: "session rollups",
+        sql: r#"
+        CREATE TABLE session_rollups (
+            session_id UUID PRIMARY KEY REFERENCES sessions(id) ON DELETE CASCADE,
+            tokens_input BIGINT NOT NULL DEFAULT 0,
+            tokens_output BIGINT NOT NULL DEFAULT 0,
+            tokens_cache_creation BIGINT NOT NULL DEFAULT 0,
+            tokens_cache_read BIGINT NOT NULL DEFAULT 0,
+            cost DOUBLE PRECISION NOT NULL DEFAULT 0,
+            commits BIGINT NOT NULL DEFAULT 0,
+            pull_requests BIGINT NOT NULL DEFAULT 0,
+            lines_added BIGINT NOT NULL DEFAULT 0,
+            lines_removed BIGINT NOT NULL DEFAULT 0,
+            api_requests BIGINT NOT NULL DEFAULT 0,
+            api_failures BIGINT NOT NULL DEFAULT 0,
+            updated_at TIMESTAMPTZ NOT NULL
+        );
+
+        CREATE TABLE session_tool_rollups (
+            session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            tool_name TEXT NOT NULL,
+            calls BIGINT NOT NULL,
+            PRIMARY KEY (session_id, tool_name)
+        );
+
+        -- Backfill from existing rows, classifying them like classify_metric / classify_event
+        INSERT INTO session_rollups (
+            session_id, tokens_input, tokens_output, tokens_cache_creation, tokens_cache_read, cost,
+            commits, pull_requests, lines_added, lines_removed, api_requests, api_failures, updated_at
+        )
+        SELECT m.session_id,
+               SUM(CASE WHEN m.name = 'claude_code.token.usage'
+                         AND COALESCE(COALESCE(m.labels->>'type', m.labels->>'token_type'), '') NOT IN ('output', 'cache_creation', 'cache_read')
+                        THEN TRUNC(m.value)::BIGINT ELSE 0 END)::BIGINT,
+               SUM(CASE WHEN m.name = 'claude_code.token.usage' AND COALESCE(m.labels->>'type', m.labels->>'token_type') = 'output'
+                        THEN TRUNC(m.value)::BIGINT ELSE 0 END)::BIGINT,
+               SUM(CASE WHEN m.name = 'claude_code.token.usage' AND COALESCE(m.labels->>'type', m.labels->>'token_type') = 'cache_creation'
+                        THEN TRUNC(m.value)::BIGINT ELSE 0 END)::BIGINT,
+               SUM(CASE WHEN m.name = 'claude_code.token.usage' AND COALESCE(m.labels->>'type', m.labels->>'token_type') = 'cache_read'
+                        THEN TRUNC(m.value)::BIGINT ELSE 0 END)::BIGINT,
+               COALESCE(SUM(CASE WHEN m.name = 'claude_code.cost.usage' THEN m.value ELSE 0 END), 0),
+               SUM(CASE WHEN m.name = 'claude_code.commit.count' THEN TRUNC(m.value)::BIGINT ELSE 0 END)::BIGINT,
+               SUM(CASE WHEN m.name = 'claude_code.pull_request.count' THEN TRUNC(m.value)::BIGINT ELSE 0 END)::BIGINT,
+               SUM(CASE WHEN m.name = 'claude_code.lines_of_code.count' AND COALESCE(COALESCE(m.labels->>'type', m.labels->>'change_type'), '') <> 'removed'
+                        THEN TRUNC(m.value)::BIGINT ELSE 0 END)::BIGINT,
+               SUM(CASE WHEN m.name = 'claude_code.lines_of_code.count' AND COALESCE(m.labels->>'type', m.labels->>'change_type') = 'removed'
+                        THEN TRUNC(m.value)::BIGINT ELSE 0 END)::BIGINT,
+               0,
+               0,
+               MAX(m.timestamp)
+        FROM metrics m
+        JOIN sessions s ON s.id = m.session_id
+        GROUP BY m.session_id;
+
+        INSERT INTO session_rollups (session_id, api_requests, api_failures, updated_at)
+        SELECT l.session_id,
+               COUNT(*) FILTER (WHERE l.message = 'api_request'),
+               COUNT(*) FILTER (WHERE l.message = 'api_request_failed'),
+               MAX(l.timestamp)
+        FROM logs l
+        JOIN sessions s ON s.id = l.session_id
+        WHERE l.message IN ('api_request', 'api_request_failed')
+        GROUP BY l.session_id
+        ON CONFLICT (session_id) DO UPDATE SET
+            api_requests = EXCLUDED.api_requests,
+            api_failures = EXCLUDED.api_failures,
+            updated_at = GREATEST(session_rollups.updated_at, EXCLUDED.updated_at);
+
+        INSERT INTO session_tool_rollups (session_id, tool_name, calls)
+        SELECT l.session_id, COALESCE(l.attributes->>'tool_name', 'unknown'), COUNT(*)
+        FROM logs l
+        JOIN sessions s ON s.id = l.session_id
+        WHERE l.message = 'tool_result'
+        GROUP BY 1, 2;
+        "#,
+    },
+    Migration {
+        version: 7,
+        description: "service column",
+        sql: r#"
+        ALTER TABLE metrics ADD COLUMN service TEXT NULL;
+        ALTER TABLE logs ADD COLUMN service TEXT NULL;
+        UPDATE metrics SET service = labels->>'service.name';
+        UPDATE logs SET service = attributes->>'service.name';
+        CREATE INDEX idx_metrics_service ON metrics(service);
+        CREATE INDEX idx_logs_service ON logs(service);
+        "#,
+    },
+];
+
+pub struct PostgresDatabase {
+    pool: PgPool,
+    index_attributes: bool,
+    read_only: AtomicBool,
+}
+
+impl PostgresDatabase {
+    pub async fn new(database_url: &str, max_connections: u32) -> Result<Self, DatabaseError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(database_url)
+            .await
+            .map_err(|e| DatabaseError::Connection(e.to_string()))?;
+
+        Self::with_pool(pool).await
+    }
+
+    async fn with_pool(pool: PgPool) -> Result<Self, DatabaseError> {
+        let db = Self {
+            pool,
+            index_attributes: false,
+            read_only: AtomicBool::new(false),
+        };
+        if db.probe_read_only().await {
+            tracing::warn!("Database is read-only; ingestion is disabled");
+            db.read_only.store(true, Ordering::Relaxed);
+        }
+
+        Ok(db)
+    }
+
+    // Hot standbys and read-only roles report it up front, no write needed
+    async fn probe_read_only(&self) -> bool {
+        sqlx::query_scalar::<_, String>("SHOW transaction_read_only")
+            .fetch_one(&self.pool)
+            .await
+            .map_or(true, |read_only| read_only == "on")
+    }
+
+    fn ensure_writable(&self) -> Result<(), DatabaseError> {
+        if self.read_only.load(Ordering::Relaxed) {
+            Err(DatabaseError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    // A read-only transaction or a full disk switches the database into read-only mode
+    fn write_error(&self, error: sqlx::Error) -> DatabaseError {
+        const READ_ONLY_SQL_TRANSACTION: &str = "25006";
+        const DISK_FULL: &str = "53100";
+
+        let code = match &error {
+            sqlx::Error::Database(db_error) => db_error.code(),
+            _ => None,
+        };
+
+        match code.as_deref() {
+            Some(READ_ONLY_SQL_TRANSACTION) | Some(DISK_FULL) => {
+                if !self.read_only.swap(true, Ordering::Relaxed) {
+                    tracing::warn!("Database write failed ({}); switching to read-only mode", error);
+                }
+                DatabaseError::ReadOnly
+            }
+            _ => DatabaseError::Query(error.to_string()),
+        }
+    }
+
+    // Sessions matching `filter` (with `value` bound to $1) and their telemetry, counted table
+    // by table since rows removed through ON DELETE CASCADE don't show up in rows_affected.
+    // With `by_user`, session-less records whose user.email is `value` go as well.
+    async fn delete_sessions_where(&self, filter: &str, value: String, by_user: bool) -> Result<PurgeCounts, DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut tx = self.pool.begin().await.map_err(|e| self.write_error(e))?;
+        let mut counts = PurgeCounts::default();
+
+        for (table, attributes, count) in [
+            ("metrics", "labels", &mut counts.metrics),
+            ("logs", "attributes", &mut counts.logs),
+            ("traces", "attributes", &mut counts.traces),
+        ] {
+            let mut sql = format!("DELETE FROM {} WHERE session_id IN (SELECT id FROM sessions WHERE {})", table, filter);
+            if by_user {
+                sql.push_str(&format!(" OR (session_id IS NULL AND {}->>'user.email' = $1)", attributes));
+            }
+            *count = sqlx::query(&sql)
+                .bind(&value)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| self.write_error(e))?
+                .rows_affected();
+        }
+
+        counts.sessions = sqlx::query(&format!("DELETE FROM sessions WHERE {}", filter))
+            .bind(&value)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| self.write_error(e))?
+            .rows_affected();
+
+        // The budget is the user's data too, not telemetry of a session
+        if by_user {
+            counts.budgets = sqlx::query("DELETE FROM budgets WHERE user_id = $1")
+                .bind(&value)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| self.write_error(e))?
+                .rows_affected();
+        }
+
+        tx.commit().await.map_err(|e| self.write_error(e))?;
+        Ok(counts)
+    }
+
+    // Insert or merge a session row as `upsert_session` does, inside the caller's transaction.
+    // Telemetry after the idle reaper ended the session reopens it; closed sessions stay closed
+    async fn upsert_session_in(
+        &self,
+        conn: &mut PgConnection,
+        id: Uuid,
+        user_id: &str,
+        start: DateTime<Utc>,
+    ) -> Result<(), DatabaseError> {
+        let now = Utc::now();
+
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, start_time, command_count, created_at, updated_at)
+            VALUES ($1, $2, $3, 0, $4, $5)
+            ON CONFLICT (id) DO UPDATE SET
+                user_id = CASE WHEN sessions.user_id = 'unknown' THEN excluded.user_id ELSE sessions.user_id END,
+                start_time = LEAST(sessions.start_time, excluded.start_time),
+                end_time = CASE WHEN sessions.end_reason = 'terminated' AND excluded.start_time > sessions.end_time THEN NULL ELSE sessions.end_time END,
+                end_reason = CASE WHEN sessions.end_reason = 'terminated' AND excluded.start_time > sessions.end_time THEN NULL ELSE sessions.end_reason END
+            "#
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(start)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        Ok(())
+    }
+
+    // Insert one point with its attributes and rollup inside the caller's transaction;
+    // false when a retried export already stored it
+    async fn insert_metric(&self, conn: &mut PgConnection, metric: &MetricRecord) -> Result<bool, DatabaseError> {
+        let labels: BTreeMap<_, _> = metric.labels.iter().collect();
+
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO metrics (id, session_id, name, timestamp, value, labels, unit, description, created_at, dedupe_key, service)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (dedupe_key) DO NOTHING
+            "#
+        )
+        .bind(metric.id)
+        .bind(metric.session_id)
+        .bind(&metric.name)
+        .bind(metric.timestamp)
+        .bind(metric.value)
+        .bind(Json(&labels))
+        .bind(&metric.unit)
+        .bind(&metric.description)
+        .bind(metric.created_at)
+        .bind(metric.dedupe_key())
+        .bind(&metric.service)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| self.write_error(e))?;
+        if inserted.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        if let Some((session_id, delta)) = metric.rollup() {
+            self.add_to_rollup(&mut *conn, session_id, &delta, metric.timestamp).await?;
+        }
+
+        if self.index_attributes {
+            for (key, value) in &labels {
+                sqlx::query("INSERT INTO metric_attributes (metric_id, key, value) VALUES ($1, $2, $3)")
+                    .bind(metric.id)
+                    .bind(key.as_str())
+                    .bind(value.as_str())
+                    .execute(&mut *conn)
+                    .await
+                    .map_err(|e| self.write_error(e))?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Add what one stored point or event contributes to its session's running totals,
+    // inside the transaction that stored it
+    async fn add_to_rollup(
+        &self,
+        conn: &mut PgConnection,
+        session_id: Uuid,
+        delta: &SessionSummary,
+        at: DateTime<Utc>,
+    ) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            INSERT INTO session_rollups (
+                session_id, tokens_input, tokens_output, tokens_cache_creation, tokens_cache_read, cost,
+                commits, pull_requests, lines_added, lines_removed, api_requests, api_failures, updated_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            ON CONFLICT (session_id) DO UPDATE SET
+                tokens_input = session_rollups.tokens_input + EXCLUDED.tokens_input,
+                tokens_output = session_rollups.tokens_output + EXCLUDED.tokens_output,
+                tokens_cache_creation = session_rollups.tokens_cache_creation + EXCLUDED.tokens_cache_creation,
+                tokens_cache_read = session_rollups.tokens_cache_read + EXCLUDED.tokens_cache_read,
+                cost = session_rollups.cost + EXCLUDED.cost,
+                commits = session_rollups.commits + EXCLUDED.commits,
+                pull_requests = session_rollups.pull_requests + EXCLUDED.pull_requests,
+                lines_added = session_rollups.lines_added + EXCLUDED.lines_added,
+                lines_removed = session_rollups.lines_removed + EXCLUDED.lines_removed,
+                api_requests = session_rollups.api_requests + EXCLUDED.api_requests,
+                api_failures = session_rollups.api_failures + EXCLUDED.api_failures,
+                updated_at = GREATEST(session_rollups.updated_at, EXCLUDED.updated_at)
+            "#
+        )
+        .bind(session_id)
+        .bind(delta.total_tokens_input as i64)
+        .bind(delta.total_tokens_output as i64)
+        .bind(delta.total_tokens_cache_creation as i64)
+        .bind(delta.total_tokens_cache_read as i64)
+        .bind(delta.total_cost)
+        .bind(delta.total_commits as i64)
+        .bind(delta.total_pull_requests as i64)
+        .bind(delta.lines_added as i64)
+        .bind(delta.lines_removed as i64)
+        .bind(delta.api_requests as i64)
+        .bind(delta.api_failures as i64)
+        .bind(at)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        for (tool_name, calls) in &delta.tool_usage {
+            sqlx::query(
+                r#"
+                INSERT INTO session_tool_rollups (session_id, tool_name, calls) VALUES ($1, $2, $3)
+                ON CONFLICT (session_id, tool_name) DO UPDATE SET calls = session_tool_rollups.calls + EXCLUDED.calls
+                "#
+            )
+            .bind(session_id)
+            .bind(tool_name)
+            .bind(*calls as i64)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| self.write_error(e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Populate `metric_attributes` on ingest and filter labels through it
+    pub fn with_attribute_index(mut self, enabled: bool) -> Self {
+        self.index_attributes = enabled;
+        self
+    }
+
+    /// Apply every migration newer than the recorded schema version, each in its own transaction
+    pub async fn migrate(&self) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL
+            )
+            "#
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+
+        let current = self.schema_version().await?;
+        for migration in MIGRATIONS.iter().filter(|migration| migration.version > current) {
+            let mut tx = self.pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+
+            // A bare string goes over the simple query protocol, which allows several statements
+            (&mut *tx).execute(migration.sql)
+                .await
+                .map_err(|e| DatabaseError::Migration(format!("version {}: {}", migration.version, e)))?;
+
+            sqlx::query("INSERT INTO schema_migrations (version, description, applied_at) VALUES ($1, $2, $3)")
+                .bind(migration.version)
+                .bind(migration.description)
+                .bind(Utc::now())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+            tracing::info!("Applied migration {}: {}", migration.version, migration.description);
+        }
+
+        Ok(())
+    }
+
+    /// Highest applied migration, 0 for an empty database
+    pub async fn schema_version(&self) -> Result<i64, DatabaseError> {
+        sqlx::query_scalar("SELECT COALESCE(MAX(version), 0)::BIGINT FROM schema_migrations")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Migration(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl Database for PostgresDatabase {
+    fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats { size: self.pool.size(), idle: self.pool.num_idle() as u32 }
+    }
+
+    async fn ping(&self) -> Result<(), DatabaseError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Connection(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn check_health(&self) -> Result<DatabaseHealth, DatabaseError> {
+        self.ping().await?;
+
+        let size_bytes: i64 = sqlx::query_scalar("SELECT pg_database_size(current_database())")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        let mut table_rows = BTreeMap::new();
+        for table in HEALTH_TABLES {
+            let rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::Query(e.to_string()))?;
+            table_rows.insert(table.to_string(), rows as u64);
+        }
+
+        Ok(DatabaseHealth { size_bytes: size_bytes as u64, table_rows })
+    }
+
+    async fn create_session(&self, user_id: &str) -> Result<Uuid, DatabaseError> {
+        self.ensure_writable()?;
+
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, start_time, command_count, created_at, updated_at)
+            VALUES ($1, $2, $3, 0, $4, $5)
+            "#
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(now)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        Ok(id)
+    }
+
+    async fn upsert_session(&self, id: Uuid, user_id: &str, start: DateTime<Utc>) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut conn = self.pool.acquire().await.map_err(|e| self.write_error(e))?;
+        self.upsert_session_in(&mut conn, id, user_id, start).await
+    }
+
+    async fn get_session(&self, session_id: Uuid) -> Result<Option<SessionRecord>, DatabaseError> {
+        let row = sqlx::query("SELECT id, user_id, start_time, end_time, end_reason, command_count, created_at, updated_at FROM sessions WHERE id = $1")
+            .bind(session_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        row.as_ref().map(session_from_row).transpose()
+    }
+
+    async fn get_session_rollup(&self, session_id: Uuid) -> Result<Option<SessionSummary>, DatabaseError> {
+        let Some(session) = self.get_session(session_id).await? else {
+            return Ok(None);
+        };
+
+        let row = sqlx::query(
+            r#"
+            SELECT tokens_input, tokens_output, tokens_cache_creation, tokens_cache_read, cost, commits, pull_requests, lines_added, lines_removed, api_requests, api_failures, updated_at
+            FROM session_rollups
+            WHERE session_id = $1
+            "#
+        )
+        .bind(session_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        let tools = sqlx::query("SELECT tool_name, calls FROM session_tool_rollups WHERE session_id = $1")
+            .bind(session_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        // A session nothing countable was stored for yet has no rollup row
+        let mut summary = row.as_ref().map(rollup_from_row).unwrap_or_default();
+        summary.session_id = session_id.to_string();
+        summary.tool_usage = tools
+            .iter()
+            .map(|row| (row.get("tool_name"), row.get::<i64, _>("calls") as u64))
+            .collect();
+        summary.last_updated = match &row {
+            Some(row) => session.updated_at.max(row.get("updated_at")),
+            None => session.updated_at,
+        };
+        Ok(Some(summary))
+    }
+
+    async fn update_session(
+        &self,
+        session_id: Uuid,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
+        sqlx::query("UPDATE sessions SET end_time = $1, end_reason = $2, updated_at = $3 WHERE id = $4")
+            .bind(end_time)
+            .bind(end_time.map(|_| SessionEndReason::Completed.as_str()))
+            .bind(Utc::now())
+            .bind(session_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| self.write_error(e))?;
+
+        Ok(())
+    }
+
+    async fn end_idle_sessions(&self, idle_since: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        self.ensure_writable()?;
+
+        // GREATEST skips the NULLs of sessions without logs or metrics
+        let result = sqlx::query(
+            r#"
+            WITH activity AS (
+                SELECT s.id, GREATEST(
+                    s.start_time,
+                    (SELECT MAX(timestamp) FROM logs WHERE session_id = s.id),
+                    (SELECT MAX(timestamp) FROM metrics WHERE session_id = s.id)
+                ) AS last_event
+                FROM sessions s
+                WHERE s.end_time IS NULL
+            )
+            UPDATE sessions
+            SET end_time = activity.last_event, end_reason = $1, updated_at = $2
+            FROM activity
+            WHERE sessions.id = activity.id AND activity.last_event < $3
+            "#
+        )
+        .bind(SessionEndReason::Terminated.as_str())
+        .bind(Utc::now())
+        .bind(idle_since)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn increment_command_count(&self, session_id: Uuid) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
+        sqlx::query("UPDATE sessions SET command_count = command_count + 1, updated_at = $1 WHERE id = $2")
+            .bind(Utc::now())
+            .bind(session_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| self.write_error(e))?;
+
+        Ok(())
+    }
+
+    async fn list_sessions(
+        &self,
+        user_id: Option<&str>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<SessionRecord>, DatabaseError> {
+        let filter = SessionFilter { user_id: user_id.map(str::to_string), ..SessionFilter::default() };
+        self.list_sessions_sorted(&filter, SessionSort::default(), limit, offset).await
+    }
+
+    async fn list_sessions_sorted(
+        &self,
+        filter: &SessionFilter,
+        sort: SessionSort,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<SessionRecord>, DatabaseError> {
+        // ORDER BY is built only from fixed fragments, never from user input
+        let sort_expr = match sort.key {
+            SessionSortKey::StartTime => "start_time",
+            SessionSortKey::Duration => "(COALESCE(end_time, NOW()) - start_time)",
+            SessionSortKey::CommandCount => "command_count",
+        };
+        let direction = match sort.order {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+
+        let mut query = QueryBuilder::new("SELECT id, user_id, start_time, end_time, end_reason, command_count, created_at, updated_at FROM sessions");
+        push_session_filter(&mut query, filter);
+        query
+            .push(format!(" ORDER BY {} {}, id {} LIMIT ", sort_expr, direction, direction))
+            .push_bind(limit as i64)
+            .push(" OFFSET ")
+            .push_bind(offset as i64);
+
+        let rows = query
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(session_from_row).collect()
+    }
+
+    async fn count_sessions(&self, filter: &SessionFilter) -> Result<u64, DatabaseError> {
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM sessions");
+        push_session_filter(&mut query, filter);
+        let count: i64 = query
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(count as u64)
+    }
+
+    async fn search_sessions(&self, query: &str, limit: u32) -> Result<Vec<SessionRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, user_id, start_time, end_time, end_reason, command_count, created_at, updated_at
+            FROM sessions s
+            WHERE s.user_id ILIKE $1 ESCAPE '\'
+               OR EXISTS (
+                   SELECT 1 FROM logs l, jsonb_each_text(l.attributes) a
+                   WHERE l.session_id = s.id AND LOWER(a.value) = LOWER($2)
+               )
+               OR EXISTS (
+                   SELECT 1 FROM metrics m, jsonb_each_text(m.labels) a
+                   WHERE m.session_id = s.id AND LOWER(a.value) = LOWER($2)
+               )
+            ORDER BY s.start_time DESC, s.id DESC
+            LIMIT $3
+            "#
+        )
+        .bind(contains_pattern(query))
+        .bind(query)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(session_from_row).collect()
+    }
+
+    async fn list_ended_sessions(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<SessionRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, user_id, start_time, end_time, end_reason, command_count, created_at, updated_at
+            FROM sessions
+            WHERE end_time IS NOT NULL
+              AND start_time >= $1
+              AND start_time <= $2
+            ORDER BY start_time, id
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(session_from_row).collect()
+    }
+
+    async fn store_metric(&self, metric: &MetricRecord) -> Result<bool, DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut tx = self.pool
+            .begin()
+            .await
+            .map_err(|e| self.write_error(e))?;
+        // A retried export; the point and its attributes are already stored
+        if !self.insert_metric(&mut tx, metric).await? {
+            return Ok(false);
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| self.write_error(e))?;
+
+        Ok(true)
+    }
+
+    async fn store_metric_in_session(&self, metric: &MetricRecord, user_id: &str, start: DateTime<Utc>) -> Result<bool, DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut tx = self.pool
+            .begin()
+            .await
+            .map_err(|e| self.write_error(e))?;
+        if let Some(session_id) = metric.session_id {
+            self.upsert_session_in(&mut tx, session_id, user_id, start).await?;
+        }
+        let inserted = self.insert_metric(&mut tx, metric).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| self.write_error(e))?;
+
+        Ok(inserted)
+    }
+
+    async fn get_metrics(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        metric_name: Option<&str>,
+    ) -> Result<Vec<MetricRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, name, timestamp, value, labels, unit, description, service, created_at FROM metrics
+            WHERE ($1::TIMESTAMPTZ IS NULL OR timestamp >= $1)
+              AND ($2::TIMESTAMPTZ IS NULL OR timestamp <= $2)
+              AND ($3::TEXT IS NULL OR name = $3)
+            ORDER BY timestamp DESC
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(metric_name)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    async fn get_scoped_metrics(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+        scope: &MetricScope,
+    ) -> Result<Vec<MetricRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, name, timestamp, value, labels, unit, description, service, created_at FROM metrics
+            WHERE timestamp >= $1
+              AND timestamp <= $2
+              AND ($3::TEXT IS NULL OR name = $3)
+              AND ($4::TEXT IS NULL OR labels->>'user.email' = $4
+                   OR session_id IN (SELECT id FROM sessions WHERE user_id = $4))
+              AND ($5::TEXT IS NULL OR labels->>'organization.id' = $5)
+              AND ($6::TEXT IS NULL OR service = $6)
+            ORDER BY timestamp DESC
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(metric_name)
+        .bind(scope.user_email.as_deref())
+        .bind(scope.organization_id.as_deref())
+        .bind(scope.service.as_deref())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    fn stream_metrics(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        filter: MetricFilter,
+    ) -> BoxStream<'static, Result<MetricRecord, DatabaseError>> {
+        let pool = self.pool.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        let label_match = if self.index_attributes {
+            "id IN (SELECT metric_id FROM metric_attributes WHERE key = $4 AND value = $5)"
+        } else {
+            // Without the side table every row's JSON has to be scanned
+            "labels->>$4 = $5"
+        };
+        let label_key = filter.label.as_ref().map(|(key, _)| key.clone());
+        let sql = format!(
+            r#"
+            SELECT id, session_id, name, timestamp, value, labels, unit, description, service, created_at FROM metrics
+            WHERE timestamp >= $1
+              AND timestamp <= $2
+              AND ($3::TEXT IS NULL OR name = $3)
+              AND ($4::TEXT IS NULL OR {})
+              AND ($6::TEXT IS NULL OR service = $6)
+            ORDER BY timestamp, id
+            "#,
+            label_match
+        );
+
+        // The query runs on its own task; the bounded channel holds it back to the consumer's pace
+        tokio::spawn(async move {
+            let mut rows = sqlx::query(&sql)
+                .bind(start_time)
+                .bind(end_time)
+                .bind(filter.metric_name.as_deref())
+                .bind(label_key.as_deref())
+                .bind(filter.label.as_ref().map(|(_, value)| value.as_str()))
+                .bind(filter.service.as_deref())
+                .fetch(&pool);
+
+            while let Some(row) = rows.next().await {
+                let metric = row
+                    .map_err(|e| DatabaseError::Query(e.to_string()))
+                    .and_then(|row| metric_from_row(&row));
+                let failed = metric.is_err();
+                // A send error means the consumer went away
+                if tx.send(metric).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|metric| (metric, rx)) }).boxed()
+    }
+
+    async fn metric_label_keys(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+    ) -> Result<Vec<String>, DatabaseError> {
+        sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT l.key
+            FROM metrics m, jsonb_each_text(m.labels) l
+            WHERE m.timestamp >= $1
+              AND m.timestamp <= $2
+              AND ($3::TEXT IS NULL OR m.name = $3)
+            ORDER BY l.key
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(metric_name)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))
+    }
+
+    async fn get_metrics_by_label(
+        &self,
+        key: &str,
+        value: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+    ) -> Result<Vec<MetricRecord>, DatabaseError> {
+        let sql = if self.index_attributes {
+            r#"
+            SELECT m.id, m.session_id, m.name, m.timestamp, m.value, m.labels, m.unit, m.description, m.service, m.created_at
+            FROM metric_attributes a
+            JOIN metrics m ON m.id = a.metric_id
+            WHERE a.key = $1 AND a.value = $2
+              AND m.timestamp >= $3
+              AND m.timestamp <= $4
+              AND ($5::TEXT IS NULL OR m.name = $5)
+            ORDER BY m.timestamp DESC
+            "#
+        } else {
+            // Without the side table every row's JSON has to be scanned
+            r#"
+            SELECT id, session_id, name, timestamp, value, labels, unit, description, service, created_at FROM metrics
+            WHERE labels->>$1 = $2
+              AND timestamp >= $3
+              AND timestamp <= $4
+              AND ($5::TEXT IS NULL OR name = $5)
+            ORDER BY timestamp DESC
+            "#
+        };
+
+        let rows = sqlx::query(sql)
+            .bind(key)
+            .bind(value)
+            .bind(start_time)
+            .bind(end_time)
+            .bind(metric_name)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    async fn get_latest_metrics(&self) -> Result<Vec<MetricRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT DISTINCT ON (name, labels) id, session_id, name, timestamp, value, labels, unit, description, service, created_at
+            FROM metrics
+            ORDER BY name, labels, timestamp DESC, created_at DESC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    async fn get_series_totals(&self) -> Result<Vec<MetricRecord>, DatabaseError> {
+        // The window sum covers the whole series before DISTINCT ON keeps its latest row
+        let rows = sqlx::query(
+            r#"
+            SELECT DISTINCT ON (name, labels) id, session_id, name, timestamp,
+                SUM(value) OVER (PARTITION BY name, labels) AS value, labels, unit, description, service, created_at
+            FROM metrics
+            ORDER BY name, labels, timestamp DESC, created_at DESC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    async fn series_total(&self, metric: &MetricRecord, since: Option<DateTime<Utc>>) -> Result<Option<SeriesTotal>, DatabaseError> {
+        // Labels are stored as JSON with sorted keys, so equal label sets compare equal
+        let labels: BTreeMap<_, _> = metric.labels.iter().collect();
+        // The window sum is taken over every matching row before LIMIT keeps the latest
+        let row = sqlx::query(
+            r#"
+            SELECT timestamp, value, SUM(value) OVER () AS total
+            FROM metrics
+            WHERE name = $1 AND session_id IS NOT DISTINCT FROM $2 AND labels = $3 AND timestamp >= $4
+            ORDER BY timestamp DESC, created_at DESC
+            LIMIT 1
+            "#
+        )
+        .bind(&metric.name)
+        .bind(metric.session_id)
+        .bind(Json(&labels))
+        .bind(since.unwrap_or(DateTime::UNIX_EPOCH))
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(row.map(|row| SeriesTotal {
+            total: row.get("total"),
+            timestamp: row.get("timestamp"),
+            last_value: row.get("value"),
+        }))
+    }
+
+    async fn cost_buckets(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        bucket: TimeBucket,
+        scope: &MetricScope,
+    ) -> Result<Vec<CostBucket>, DatabaseError> {
+        // The bucket field is one of these fixed names, never user input; weeks start on Monday.
+        // Token types are read like classify_metric: `type`, else `token_type`, untyped is input
+        let field = match bucket {
+            TimeBucket::Hour => "hour",
+            TimeBucket::Day => "day",
+            TimeBucket::Week => "week",
+        };
+        let sql = format!(
+            r#"
+            SELECT date_trunc('{}', timestamp, 'UTC') AS bucket,
+                COALESCE(SUM(CASE WHEN name = 'claude_code.cost.usage' THEN value ELSE 0 END), 0) AS cost_usd,
+                COALESCE(SUM(CASE WHEN name = 'claude_code.token.usage' AND COALESCE(labels->>'type', labels->>'token_type', '') NOT IN ('output', 'cache_creation', 'cache_read') THEN value ELSE 0 END), 0) AS input_tokens,
+                COALESCE(SUM(CASE WHEN name = 'claude_code.token.usage' AND COALESCE(labels->>'type', labels->>'token_type') = 'output' THEN value ELSE 0 END), 0) AS output_tokens,
+                COALESCE(SUM(CASE WHEN name = 'claude_code.token.usage' AND COALESCE(labels->>'type', labels->>'token_type') = 'cache_creation' THEN value ELSE 0 END), 0) AS cache_creation_tokens,
+                COALESCE(SUM(CASE WHEN name = 'claude_code.token.usage' AND COALESCE(labels->>'type', labels->>'token_type') = 'cache_read' THEN value ELSE 0 END), 0) AS cache_read_tokens,
+                COUNT(DISTINCT session_id) AS sessions
+            FROM metrics
+            WHERE name IN ('claude_code.cost.usage', 'claude_code.token.usage')
+              AND timestamp >= $1
+              AND timestamp <= $2
+              AND ($3::TEXT IS NULL OR labels->>'user.email' = $3
+                   OR session_id IN (SELECT id FROM sessions WHERE user_id = $3))
+              AND ($4::TEXT IS NULL OR labels->>'organization.id' = $4)
+              AND ($5::TEXT IS NULL OR service = $5)
+            GROUP BY bucket
+            ORDER BY bucket
+            "#,
+            field
+        );
+
+        let rows = sqlx::query(&sql)
+            .bind(start_time)
+            .bind(end_time)
+            .bind(scope.user_email.as_deref())
+            .bind(scope.organization_id.as_deref())
+            .bind(scope.service.as_deref())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(rows
+            .iter()
+            .map(|row| CostBucket {
+                start: row.get("bucket"),
+                cost_usd: row.get("cost_usd"),
+                input_tokens: row.get::<f64, _>("input_tokens") as u64,
+                output_tokens: row.get::<f64, _>("output_tokens") as u64,
+                cache_creation_tokens: row.get::<f64, _>("cache_creation_tokens") as u64,
+                cache_read_tokens: row.get::<f64, _>("cache_read_tokens") as u64,
+                sessions: row.get::<i64, _>("sessions") as u64,
+            })
+            .collect())
+    }
+
+    async fn metric_stats(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<MetricStats>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT name, MAX(unit) AS unit, MAX(description) AS description,
+                   COUNT(*) AS count, SUM(value) AS sum, MIN(value) AS min, MAX(value) AS max, AVG(value) AS avg
+            FROM metrics
+            WHERE timestamp >= $1 AND timestamp <= $2
+            GROUP BY name
+            ORDER BY name
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(rows
+            .iter()
+            .map(|row| MetricStats {
+                name: row.get("name"),
+                unit: row.get("unit"),
+                description: row.get("description"),
+                count: row.get::<i64, _>("count") as u64,
+                sum: row.get("sum"),
+                min: row.get("min"),
+                max: row.get("max"),
+                avg: row.get("avg"),
+            })
+            .collect())
+    }
+
+    async fn metric_buckets(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+        width_secs: i64,
+    ) -> Result<Vec<MetricBucket>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT name, (FLOOR(EXTRACT(EPOCH FROM timestamp) / $4) * $4)::BIGINT AS bucket,
+                   MAX(unit) AS unit, MAX(description) AS description, COUNT(*) AS count, SUM(value) AS sum, MIN(value) AS min, MAX(value) AS max
+            FROM metrics
+            WHERE timestamp >= $1 AND timestamp <= $2
+              AND ($3::TEXT IS NULL OR name = $3)
+            GROUP BY name, bucket
+            ORDER BY name, bucket
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(metric_name)
+        .bind(width_secs)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter()
+            .map(|row| {
+                let bucket: i64 = row.get("bucket");
+                Ok(MetricBucket {
+                    name: row.get("name"),
+                    unit: row.get("unit"),
+                    description: row.get("description"),
+                    start: DateTime::from_timestamp(bucket, 0)
+                        .ok_or_else(|| DatabaseError::InvalidData(format!("Invalid bucket timestamp: {}", bucket)))?,
+                    count: row.get::<i64, _>("count") as u64,
+                    sum: row.get("sum"),
+                    min: row.get("min"),
+                    max: row.get("max"),
+                })
+            })
+            .collect()
+    }
+
+    async fn facets(&self, start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> Result<Facets, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT 'metric_name' AS facet, name AS value, COUNT(*) AS count
+            FROM metrics WHERE timestamp >= $1 AND timestamp <= $2
+            GROUP BY 2
+            UNION ALL
+            SELECT 'model', labels->>'model', COUNT(*)
+            FROM metrics WHERE timestamp >= $1 AND timestamp <= $2 AND labels->>'model' IS NOT NULL
+            GROUP BY 2
+            UNION ALL
+            SELECT 'user_email', labels->>'user.email', COUNT(*)
+            FROM metrics WHERE timestamp >= $1 AND timestamp <= $2 AND labels->>'user.email' IS NOT NULL
+            GROUP BY 2
+            UNION ALL
+            SELECT 'tool_name', COALESCE(attributes->>'tool_name', 'unknown'), COUNT(*)
+            FROM logs WHERE timestamp >= $1 AND timestamp <= $2 AND message = 'tool_result'
+            GROUP BY 2
+            ORDER BY facet, count DESC, value
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        let mut facets = Facets::default();
+        for row in &rows {
+            facets.push(row.get("facet"), row.get("value"), row.get("count"));
+        }
+        Ok(facets)
+    }
+
+    async fn store_trace(&self, trace: &TraceRecord) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO traces (id, session_id, trace_id, span_id, parent_span_id, name, start_time, end_time, duration_ns, attributes, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#
+        )
+        .bind(trace.id)
+        .bind(trace.session_id)
+        .bind(&trace.trace_id)
+        .bind(&trace.span_id)
+        .bind(trace.parent_span_id.as_ref())
+        .bind(&trace.name)
+        .bind(trace.start_time)
+        .bind(trace.end_time)
+        .bind(trace.duration_ns as i64)
+        .bind(Json(&trace.attributes))
+        .bind(trace.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        Ok(())
+    }
+
+    async fn get_traces(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        trace_id: Option<&str>,
+    ) -> Result<Vec<TraceRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, trace_id, span_id, parent_span_id, name, start_time, end_time, duration_ns, attributes, created_at
+            FROM traces
+            WHERE ($1::TIMESTAMPTZ IS NULL OR start_time >= $1)
+              AND ($2::TIMESTAMPTZ IS NULL OR start_time <= $2)
+              AND ($3::TEXT IS NULL OR trace_id = $3)
+            ORDER BY start_time ASC, span_id ASC
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(trace_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(trace_from_row).collect()
+    }
+
+    async fn list_traces(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<TraceSummary>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT t.trace_id, t.span_count, t.start_time, t.end_time,
+                   root.name AS root_name, root.duration_ns AS root_duration_ns
+            FROM (
+                SELECT trace_id, COUNT(*) AS span_count, MIN(start_time) AS start_time, MAX(end_time) AS end_time
+                FROM traces
+                WHERE ($1::TIMESTAMPTZ IS NULL OR start_time >= $1)
+                  AND ($2::TIMESTAMPTZ IS NULL OR start_time <= $2)
+                GROUP BY trace_id
+            ) t
+            LEFT JOIN LATERAL (
+                SELECT r.name, r.duration_ns FROM traces r
+                WHERE r.trace_id = t.trace_id AND r.parent_span_id IS NULL
+                ORDER BY r.start_time
+                LIMIT 1
+            ) root ON TRUE
+            ORDER BY t.start_time DESC, t.trace_id
+            LIMIT $3
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(rows
+            .iter()
+            .map(|row| TraceSummary {
+                trace_id: row.get("trace_id"),
+                span_count: row.get::<i64, _>("span_count") as u64,
+                start_time: row.get("start_time"),
+                end_time: row.get("end_time"),
+                root_name: row.get("root_name"),
+                root_duration_ns: row.get::<Option<i64>, _>("root_duration_ns").map(|ns| ns as u64),
+            })
+            .collect())
+    }
+
+    async fn store_log(&self, log: &LogRecord) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut tx = self.pool
+            .begin()
+            .await
+            .map_err(|e| self.write_error(e))?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO logs (id, session_id, timestamp, level, message, attributes, created_at, service)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#
+        )
+        .bind(log.id)
+        .bind(log.session_id)
+        .bind(log.timestamp)
+        .bind(&log.level)
+        .bind(&log.message)
+        .bind(Json(&log.attributes))
+        .bind(log.created_at)
+        .bind(&log.service)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        if let Some((session_id, delta)) = log.rollup() {
+            self.add_to_rollup(&mut tx, session_id, &delta, log.timestamp).await?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| self.write_error(e))?;
+
+        Ok(())
+    }
+
+    async fn get_logs(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        level: Option<&str>,
+    ) -> Result<Vec<LogRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, timestamp, level, message, attributes, service, created_at
+            FROM logs
+            WHERE ($1::TIMESTAMPTZ IS NULL OR timestamp >= $1)
+              AND ($2::TIMESTAMPTZ IS NULL OR timestamp <= $2)
+              AND ($3::TEXT IS NULL OR LOWER(level) = LOWER($3))
+            ORDER BY timestamp DESC
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(level)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(log_from_row).collect()
+    }
+
+    async fn get_logs_for_session(&self, session_id: Uuid) -> Result<Vec<LogRecord>, DatabaseError> {
+        // Insertion order breaks ties between events stamped the same instant
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, timestamp, level, message, attributes, service, created_at
+            FROM logs
+            WHERE session_id = $1
+            ORDER BY timestamp ASC, created_at ASC
+            "#
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(log_from_row).collect()
+    }
+
+    async fn list_logs(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        level: Option<&str>,
+        service: Option<&str>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<LogRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, timestamp, level, message, attributes, service, created_at
+            FROM logs
+            WHERE ($1::TIMESTAMPTZ IS NULL OR timestamp >= $1)
+              AND ($2::TIMESTAMPTZ IS NULL OR timestamp <= $2)
+              AND ($3::TEXT IS NULL OR LOWER(level) = LOWER($3))
+              AND ($6::TEXT IS NULL OR service = $6)
+            ORDER BY timestamp DESC, id DESC
+            LIMIT $4 OFFSET $5
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(level)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .bind(service)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(log_from_row).collect()
+    }
+
+    async fn count_logs(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        level: Option<&str>,
+        service: Option<&str>,
+    ) -> Result<u64, DatabaseError> {
+        let count: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM logs
+            WHERE ($1::TIMESTAMPTZ IS NULL OR timestamp >= $1)
+              AND ($2::TIMESTAMPTZ IS NULL OR timestamp <= $2)
+              AND ($3::TEXT IS NULL OR LOWER(level) = LOWER($3))
+              AND ($4::TEXT IS NULL OR service = $4)
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(level)
+        .bind(service)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(count as u64)
+    }
+
+    async fn search_logs(
+        &self,
+        query: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<LogRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, timestamp, level, message, attributes, service, created_at
+            FROM logs l
+            WHERE ($2::TIMESTAMPTZ IS NULL OR l.timestamp >= $2)
+              AND ($3::TIMESTAMPTZ IS NULL OR l.timestamp <= $3)
+              AND (
+                  l.message ILIKE $1 ESCAPE '\'
+                  OR EXISTS (SELECT 1 FROM jsonb_each_text(l.attributes) a WHERE a.value ILIKE $1 ESCAPE '\')
+              )
+            ORDER BY l.timestamp DESC, l.id DESC
+            LIMIT $4
+            "#
+        )
+        .bind(contains_pattern(query))
+        .bind(start_time)
+        .bind(end_time)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(log_from_row).collect()
+    }
+
+    async fn set_budget(&self, user_id: &str, monthly_budget_usd: f64) -> Result<BudgetRecord, DatabaseError> {
+        self.ensure_writable()?;
+
+        let budget = BudgetRecord { user_id: user_id.to_string(), monthly_budget_usd, updated_at: Utc::now() };
+        sqlx::query(
+            r#"
+            INSERT INTO budgets (user_id, monthly_budget_usd, updated_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE SET monthly_budget_usd = EXCLUDED.monthly_budget_usd, updated_at = EXCLUDED.updated_at
+            "#
+        )
+        .bind(&budget.user_id)
+        .bind(budget.monthly_budget_usd)
+        .bind(budget.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        Ok(budget)
+    }
+
+    async fn get_budget(&self, user_id: &str) -> Result<Option<BudgetRecord>, DatabaseError> {
+        let row = sqlx::query("SELECT user_id, monthly_budget_usd, updated_at FROM budgets WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(row.map(|row| BudgetRecord {
+            user_id: row.get("user_id"),
+            monthly_budget_usd: row.get("monthly_budget_usd"),
+            updated_at: row.get("updated_at"),
+        }))
+    }
+
+    async fn delete_older_than(&self, cutoff: DateTime<Utc>) -> Result<PurgeCounts, DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut tx = self.pool.begin().await.map_err(|e| self.write_error(e))?;
+        let mut counts = PurgeCounts::default();
+
+        // Telemetry first so the session delete doesn't cascade into rows counted here
+        for (table, column, count) in [
+            ("metrics", "timestamp", &mut counts.metrics),
+            ("logs", "timestamp", &mut counts.logs),
+            ("traces", "start_time", &mut counts.traces),
+        ] {
+            let sql = format!("DELETE FROM {} WHERE {} < $1", table, column);
+            *count = sqlx::query(&sql)
+                .bind(cutoff)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| self.write_error(e))?
+                .rows_affected();
+        }
+
+        // Sessions that are still open are kept however old they are
+        counts.sessions = sqlx::query("DELETE FROM sessions WHERE end_time IS NOT NULL AND end_time < $1")
+            .bind(cutoff)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| self.write_error(e))?
+            .rows_affected();
+
+        tx.commit().await.map_err(|e| self.write_error(e))?;
+        Ok(counts)
+    }
+
+    async fn delete_session(&self, session_id: Uuid) -> Result<Option<PurgeCounts>, DatabaseError> {
+        let counts = self.delete_sessions_where("id = $1::UUID", session_id.to_string(), false).await?;
+        Ok((counts.sessions > 0).then_some(counts))
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<PurgeCounts, DatabaseError> {
+        self.delete_sessions_where("user_id = $1", user_id.to_string(), true).await
+    }
+}
+
+// Appends a WHERE clause for every set field of `filter`, values bound as parameters
+fn push_session_filter<'a>(query: &mut QueryBuilder<'a, Postgres>, filter: &'a SessionFilter) {
+    query.push(" WHERE TRUE");
+    if let Some(user_id) = &filter.user_id {
+        query.push(" AND user_id = ").push_bind(user_id.as_str());
+    }
+    if let Some(start_time) = filter.start_time {
+        query.push(" AND start_time >= ").push_bind(start_time);
+    }
+    if let Some(end_time) = filter.end_time {
+        query.push(" AND start_time <= ").push_bind(end_time);
+    }
+    if let Some(min_command_count) = filter.min_command_count {
+        query.push(" AND command_count >= ").push_bind(min_command_count as i64);
+    }
+    match filter.status {
+        Some(SessionState::Active) => query.push(" AND end_time IS NULL"),
+        // Sessions ended before end_reason was recorded count as completed
+        Some(SessionState::Completed) => query.push(" AND end_time IS NOT NULL AND COALESCE(end_reason, 'completed') = 'completed'"),
+        Some(SessionState::Terminated) => query.push(" AND end_reason = 'terminated'"),
+        None => query,
+    };
+}
+
+// Totals of a `session_rollups` row; tool usage lives in `session_tool_rollups`
+fn rollup_from_row(row: &PgRow) -> SessionSummary {
+    let count = |column: &str| row.get::<i64, _>(column) as u64;
+    SessionSummary {
+        total_tokens_input: count("tokens_input"),
+        total_tokens_output: count("tokens_output"),
+        total_tokens_cache_creation: count("tokens_cache_creation"),
+        total_tokens_cache_read: count("tokens_cache_read"),
+        total_cost: row.get("cost"),
+        total_commits: count("commits"),
+        total_pull_requests: count("pull_requests"),
+        lines_added: count("lines_added"),
+        lines_removed: count("lines_removed"),
+        api_requests: count("api_requests"),
+        api_failures: count("api_failures"),
+        ..SessionSummary::default()
+    }
+}
+
+fn session_from_row(row: &PgRow) -> Result<SessionRecord, DatabaseError> {
+    Ok(SessionRecord {
+        id: row.get("id"),
+        user_id: row.get("user_id"),
+        start_time: row.get("start_time"),
+        end_time: row.get("end_time"),
+        end_reason: row.get::<Option<&str>, _>("end_reason").and_then(SessionEndReason::parse),
+        command_count: row.get::<i64, _>("command_count") as u64,
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    })
+}
+
+fn trace_from_row(row: &PgRow) -> Result<TraceRecord, DatabaseError> {
+    let Json(attributes) = row
+        .try_get::<Json<HashMap<String, String>>, _>("attributes")
+        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+
+    Ok(TraceRecord {
+        id: row.get("id"),
+        session_id: row.get("session_id"),
+        trace_id: row.get("trace_id"),
+        span_id: row.get("span_id"),
+        parent_span_id: row.get("parent_span_id"),
+        name: row.get("name"),
+        start_time: row.get("start_time"),
+        end_time: row.get("end_time"),
+        duration_ns: row.get::<i64, _>("duration_ns") as u64,
+        attributes,
+        created_at: row.get("created_at"),
+    })
+}
+
+// ILIKE pattern matching `query` anywhere, with its own wildcards taken literally
+fn contains_pattern(query: &str) -> String {
+    let escaped = query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn log_from_row(row: &PgRow) -> Result<LogRecord, DatabaseError> {
+    let Json(attributes) = row
+        .try_get::<Json<HashMap<String, String>>, _>("attributes")
+        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+
+    Ok(LogRecord {
+        id: row.get("id"),
+        session_id: row.get("session_id"),
+        timestamp: row.get("timestamp"),
+        level: row.get("level"),
+        message: row.get("message"),
+        attributes,
+        service: row.get("service"),
+        created_at: row.get("created_at"),
+    })
+}
+
+fn metric_from_row(row: &PgRow) -> Result<MetricRecord, DatabaseError> {
+    let Json(labels) = row
+        .try_get::<Json<HashMap<String, String>>, _>("labels")
+        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+
+    Ok(MetricRecord {
+        id: row.get("id"),
+        session_id: row.get("session_id"),
+        name: row.get("name"),
+        timestamp: row.get("timestamp"),
+        value: row.get("value"),
+        labels,
+        unit: row.get("unit"),
+        description: row.get("description"),
+        service: row.get("service"),
+        created_at: row.get("created_at"),
+    })
+}
+
+pub async fn init_database(config: &Config) -> Result<Arc<dyn Database>, DatabaseError> {
+    let database_url = config.database_url();
+    tracing::info!("Connecting to Postgres database");
+
+    let db = PostgresDatabase::new(&database_url, config.max_connections)
+        .await?
+        .with_attribute_index(config.index_metric_attributes);
+    tracing::info!("Database pool size: {} connections", db.pool.options().get_max_connections());
+    if db.is_read_only() {
+        tracing::warn!("Skipping migrations on read-only database; serving existing data only");
+    } else {
+        tracing::info!("Running database migrations...");
+        db.migrate().await?;
+        tracing::info!("Database schema at version {}", db.schema_version().await?);
+    }
+    tracing::info!("Database initialized successfully");
+
+    Ok(Arc::new(db))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FacetCount;
+    use chrono::Duration;
+    use sqlx::postgres::PgConnectOptions;
+    use std::str::FromStr;
+
+    // Tests run against a real server and are skipped unless this points at one
+    const TEST_URL_VAR: &str = "CLAUDE_LENS_TEST_POSTGRES_URL";
+
+    /// Migrated database in a fresh schema of the test server, or None when none is configured
+    async fn test_db() -> Option<PostgresDatabase> {
+        let Ok(url) = std::env::var(TEST_URL_VAR) else {
+            eprintln!("{} is not set; skipping Postgres test", TEST_URL_VAR);
+            return None;
+        };
+
+        let schema = format!("test_{}", Uuid::new_v4().simple());
+        let options = PgConnectOptions::from_str(&url).unwrap();
+        let admin = PgPool::connect_with(options.clone()).await.unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&admin).await.unwrap();
+        admin.close().await;
+
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect_with(options.options([("search_path", schema.as_str())]))
+            .await
+            .unwrap();
+        let db = PostgresDatabase::with_pool(pool).await.unwrap();
+        db.migrate().await.unwrap();
+        Some(db)
+    }
+
+    fn metric(session_id: Option<Uuid>, name: &str, value: f64, labels: &[(&str, &str)], timestamp: DateTime<Utc>) -> MetricRecord {
+        MetricRecord {
+            id: Uuid::new_v4(),
+            session_id,
+            name: name.to_string(),
+            timestamp,
+            value,
+            labels: labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn log(session_id: Option<Uuid>, message: &str, attributes: &[(&str, &str)], timestamp: DateTime<Utc>) -> LogRecord {
+        LogRecord {
+            id: Uuid::new_v4(),
+            session_id,
+            timestamp,
+            level: "INFO".to_string(),
+            message: message.to_string(),
+            attributes: attributes.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate_twice_is_a_no_op() {
+        let Some(db) = test_db().await else { return };
+        let latest = MIGRATIONS.last().unwrap().version;
+        assert_eq!(db.schema_version().await.unwrap(), latest);
+
+        db.migrate().await.unwrap();
+        assert_eq!(db.schema_version().await.unwrap(), latest);
+        assert!(!db.is_read_only());
+
+        db.ping().await.unwrap();
+        let health = db.check_health().await.unwrap();
+        assert!(health.size_bytes > 0);
+        assert_eq!(health.table_rows.len(), HEALTH_TABLES.len());
+        assert!(health.table_rows.values().all(|rows| *rows == 0));
+        assert!(db.pool_stats().size >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_and_summary_round_trip() {
+        let Some(db) = test_db().await else { return };
+        let id = db.create_session("alice@example.com").await.unwrap();
+        db.increment_command_count(id).await.unwrap();
+        let now = Utc::now();
+        for record in [
+            metric(Some(id), "claude_code.token.usage", 1200.0, &[("type", "input")], now),
+            metric(Some(id), "claude_code.token.usage", 300.0, &[("type", "output")], now),
+            metric(Some(id), "claude_code.cost.usage", 0.42, &[("model", "claude-sonnet-4")], now),
+            metric(Some(id), "claude_code.token.usage", 70.0, &[("token_type", "cache_read")], now),
+            metric(Some(id), "claude_code.token.usage", 5.0, &[], now),
+        ] {
+            db.store_metric(&record).await.unwrap();
+        }
+        db.store_log(&log(Some(id), "tool_result", &[("tool_name", "Bash")], now)).await.unwrap();
+
+        let session = db.get_session(id).await.unwrap().unwrap();
+        assert_eq!(session.user_id, "alice@example.com");
+        assert_eq!(session.command_count, 1);
+        assert_eq!(db.count_sessions(&SessionFilter::for_user("alice@example.com")).await.unwrap(), 1);
+        assert_eq!(db.count_sessions(&SessionFilter::for_user("bob@example.com")).await.unwrap(), 0);
+        let active = SessionFilter { status: Some(SessionState::Active), min_command_count: Some(1), ..SessionFilter::default() };
+        assert_eq!(db.list_sessions_sorted(&active, SessionSort::default(), 10, 0).await.unwrap().len(), 1);
+        let completed = SessionFilter { status: Some(SessionState::Completed), ..SessionFilter::default() };
+        assert_eq!(db.count_sessions(&completed).await.unwrap(), 0);
+
+        let summary = db.get_session_rollup(id).await.unwrap().unwrap();
+        assert_eq!(summary.session_id, id.to_string());
+        assert_eq!((summary.total_tokens_output, summary.total_tokens_cache_read), (300, 70));
+        assert_eq!(summary.tool_usage["Bash"], 1);
+
+        // The rollup backfilled on upgrade matches the one kept on ingest
+        let as_json = |summary: Option<SessionSummary>| serde_json::to_value(summary.unwrap()).unwrap();
+        db.pool
+            .execute(
+                "DROP TABLE session_tool_rollups; DROP TABLE session_rollups; \
+                 DROP INDEX idx_metrics_service; DROP INDEX idx_logs_service; \
+                 ALTER TABLE metrics DROP COLUMN service; ALTER TABLE logs DROP COLUMN service; \
+                 DELETE FROM schema_migrations WHERE version >= 6",
+            )
+            .await
+            .unwrap();
+        db.migrate().await.unwrap();
+        assert_eq!(as_json(db.get_session_rollup(id).await.unwrap()), as_json(Some(summary)));
+        assert!(db.get_session_rollup(Uuid::new_v4()).await.unwrap().is_none());
+        assert_eq!(db.get_logs_for_session(id).await.unwrap().len(), 1);
+
+        // Label values are searched case-insensitively, user ids by substring
+        assert_eq!(db.search_sessions("CLAUDE-SONNET-4", 10).await.unwrap().len(), 1);
+        assert_eq!(db.search_sessions("alice@", 10).await.unwrap().len(), 1);
+        assert!(db.search_sessions("%", 10).await.unwrap().is_empty());
+
+        // The reaper ends the session at its latest event
+        assert_eq!(db.end_idle_sessions(now - Duration::minutes(1)).await.unwrap(), 0);
+        assert_eq!(db.end_idle_sessions(now + Duration::minutes(1)).await.unwrap(), 1);
+        let session = db.get_session(id).await.unwrap().unwrap();
+        assert_eq!(session.end_reason, Some(SessionEndReason::Terminated));
+        assert_eq!(session.end_time.map(|end| end.timestamp_micros()), Some(now.timestamp_micros()));
+        let terminated = SessionFilter { status: Some(SessionState::Terminated), ..SessionFilter::default() };
+        assert_eq!(db.count_sessions(&terminated).await.unwrap(), 1);
+        assert_eq!(db.count_sessions(&completed).await.unwrap(), 0);
+        // Telemetry after that end reopens it
+        db.upsert_session(id, "alice@example.com", now + Duration::minutes(1)).await.unwrap();
+        let session = db.get_session(id).await.unwrap().unwrap();
+        assert_eq!((session.end_time, session.end_reason), (None, None));
+
+        let counts = db.delete_session(id).await.unwrap();
+        assert_eq!(counts, Some(PurgeCounts { metrics: 5, logs: 1, traces: 0, sessions: 1, budgets: 0 }));
+        assert_eq!(db.delete_session(id).await.unwrap(), None);
+        assert_eq!(db.delete_user_sessions("alice@example.com").await.unwrap(), PurgeCounts::default());
+    }
+
+    #[tokio::test]
+    async fn test_metric_queries_match_sqlite_semantics() {
+        let Some(db) = test_db().await else { return };
+        let session_id = db.create_session("alice@example.com").await.unwrap();
+        let start = Utc::now() - Duration::hours(3);
+        for (value, hours) in [(1.0, 0), (2.0, 1), (4.0, 2)] {
+            let record = metric(Some(session_id), "claude_code.cost.usage", value, &[("model", "opus")], start + Duration::hours(hours));
+            db.store_metric(&record).await.unwrap();
+        }
+        db.store_metric(&metric(None, "claude_code.token.usage", 500.0, &[("type", "input")], start)).await.unwrap();
+        // A retried point gets a fresh id but the same dedupe key
+        let retry = metric(Some(session_id), "claude_code.cost.usage", 9.0, &[("model", "opus")], start);
+        assert!(!db.store_metric(&retry).await.unwrap());
+        let end = Utc::now();
+
+        let metrics = db.get_metrics(Some(start), Some(end), Some("claude_code.cost.usage")).await.unwrap();
+        assert_eq!(metrics.iter().map(|m| m.value).collect::<Vec<_>>(), [4.0, 2.0, 1.0]);
+        assert_eq!(metrics[0].labels["model"], "opus");
+
+        let latest = db.get_latest_metrics().await.unwrap();
+        assert_eq!(latest.len(), 2);
+        assert_eq!(latest.iter().find(|m| m.name == "claude_code.cost.usage").unwrap().value, 4.0);
+        let totals = db.get_series_totals().await.unwrap();
+        assert_eq!(totals.iter().find(|m| m.name == "claude_code.cost.usage").unwrap().value, 7.0);
+
+        assert_eq!(db.metric_label_keys(start, end, None).await.unwrap(), ["model", "type"]);
+        assert_eq!(db.get_metrics_by_label("model", "opus", start, end, None).await.unwrap().len(), 3);
+
+        let streamed: Vec<_> = db.stream_metrics(start, end, MetricFilter { metric_name: Some("claude_code.cost.usage".to_string()), ..MetricFilter::default() }).collect().await;
+        assert_eq!(streamed.into_iter().map(|m| m.unwrap().value).collect::<Vec<_>>(), [1.0, 2.0, 4.0]);
+        let filter = MetricFilter { label: Some(("model".to_string(), "opus".to_string())), ..MetricFilter::default() };
+        assert_eq!(db.stream_metrics(start, end, filter).collect::<Vec<_>>().await.len(), 3);
+
+        let buckets = db.cost_buckets(start, end, TimeBucket::Hour, &MetricScope::default()).await.unwrap();
+        assert_eq!(buckets.iter().map(|b| b.cost_usd).sum::<f64>(), 7.0);
+        assert_eq!(buckets.iter().map(|b| b.input_tokens).sum::<u64>(), 500);
+        let scope = MetricScope { user_email: Some("alice@example.com".to_string()), ..MetricScope::default() };
+        let alice = db.cost_buckets(start, end, TimeBucket::Week, &scope).await.unwrap();
+        assert_eq!(alice.iter().map(|b| b.input_tokens).sum::<u64>(), 0);
+        assert_eq!(alice.iter().map(|b| b.sessions).sum::<u64>(), alice.len() as u64);
+
+        let stats = db.metric_stats(start, end).await.unwrap();
+        let cost = stats.iter().find(|s| s.name == "claude_code.cost.usage").unwrap();
+        assert_eq!((cost.count, cost.sum, cost.min, cost.max), (3, 7.0, 1.0, 4.0));
+
+        let buckets = db.metric_buckets(start, end, Some("claude_code.cost.usage"), 86_400 * 365).await.unwrap();
+        assert_eq!(buckets.iter().map(|b| b.count).sum::<u64>(), 3);
+        assert!(buckets.iter().all(|b| b.start.timestamp() % (86_400 * 365) == 0));
+
+        let facets = db.facets(start, end).await.unwrap();
+        let counts = |facets: &[FacetCount]| facets.iter().map(|f| (f.value.clone(), f.count)).collect::<Vec<_>>();
+        assert_eq!(
+            counts(&facets.metric_names),
+            [("claude_code.cost.usage".to_string(), 3), ("claude_code.token.usage".to_string(), 1)]
+        );
+        assert_eq!(counts(&facets.models), [("opus".to_string(), 3)]);
+        assert!(facets.user_emails.is_empty() && facets.tool_names.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_logs_traces_budgets_and_retention() {
+        let Some(db) = test_db().await else { return };
+        let now = Utc::now();
+        let old = now - Duration::days(40);
+        db.store_log(&log(None, "api_request_failed", &[("error", "Overloaded: 529")], now)).await.unwrap();
+        db.store_log(&log(None, "tool_result", &[("tool_name", "Bash")], old)).await.unwrap();
+
+        assert_eq!(db.count_logs(None, None, Some("info"), None).await.unwrap(), 2);
+        assert_eq!(db.list_logs(None, None, None, None, 1, 0).await.unwrap()[0].message, "api_request_failed");
+        assert_eq!(db.search_logs("overloaded", None, None, 10).await.unwrap().len(), 1);
+        assert!(db.search_logs("tool_name", None, None, 10).await.unwrap().is_empty());
+
+        for (span_id, parent) in [("a", None), ("b", Some("a"))] {
+            db.store_trace(&TraceRecord {
+                id: Uuid::new_v4(),
+                session_id: None,
+                trace_id: "trace-1".to_string(),
+                span_id: span_id.to_string(),
+                parent_span_id: parent.map(str::to_string),
+                name: format!("span-{}", span_id),
+                start_time: now,
+                end_time: now + Duration::seconds(1),
+                duration_ns: 1_000_000_000,
+                attributes: HashMap::from([("tool_name".to_string(), "Bash".to_string())]),
+                created_at: now,
+            })
+            .await
+            .unwrap();
+        }
+        let traces = db.list_traces(None, None, 10).await.unwrap();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].span_count, 2);
+        assert_eq!(traces[0].root_name.as_deref(), Some("span-a"));
+        assert_eq!(db.get_traces(None, None, Some("trace-1")).await.unwrap()[1].attributes["tool_name"], "Bash");
+
+        db.set_budget("alice@example.com", 10.0).await.unwrap();
+        db.set_budget("alice@example.com", 25.0).await.unwrap();
+        assert_eq!(db.get_budget("alice@example.com").await.unwrap().unwrap().monthly_budget_usd, 25.0);
+        assert!(db.get_budget("bob@example.com").await.unwrap().is_none());
+
+        let counts = db.delete_older_than(now - Duration::days(30)).await.unwrap();
+        assert_eq!((counts.logs, counts.traces), (1, 0));
+        assert_eq!(db.count_logs(None, None, None, None).await.unwrap(), 1);
+    }
+}
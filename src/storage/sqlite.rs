@@ -1,31 +1,51 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
+use futures_util::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use serde_json;
-use sqlx::{sqlite::SqlitePool, Row};
-use std::{collections::HashMap, sync::Arc};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow, SqliteSynchronous},
+    QueryBuilder, Row, Sqlite, SqliteConnection,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use uuid::Uuid;
 
+use crate::config::Config;
+use crate::otel::SessionSummary;
 use super::{
-    Database, DatabaseError, LogRecord, MetricRecord, SessionRecord, TraceRecord,
+    BudgetRecord, CostBucket, Database, DatabaseError, DatabaseHealth, Facets, LogRecord, MetricBucket, MetricFilter, MetricRecord, MetricScope, MetricStats, PoolStats, PurgeCounts, SessionEndReason, SessionFilter, SessionRecord,
+    SeriesTotal, SessionSort, SessionSortKey, SessionState, SortOrder, TimeBucket, TraceRecord, TraceSummary, HEALTH_TABLES,
 };
 
-pub struct SqliteDatabase {
-    pool: SqlitePool,
-}
+// How long a connection waits on a locked database before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
-impl SqliteDatabase {
-    pub async fn new(database_url: &str) -> Result<Self, DatabaseError> {
-        let pool = SqlitePool::connect(database_url)
-            .await
-            .map_err(|e| DatabaseError::Connection(e.to_string()))?;
+// Rows read ahead of a slow stream consumer
+const STREAM_BUFFER: usize = 256;
 
-        Ok(Self { pool })
-    }
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sql: &'static str,
+}
 
-    pub async fn migrate(&self) -> Result<(), DatabaseError> {
-        // Run the initial migration manually for now
-        // TODO: Use sqlx::migrate!() once migration files are properly set up
-        let migration_sql = r#"
+// Applied in order and never edited once released; add a new version instead.
+// Version 1 uses IF NOT EXISTS so databases created before versioning adopt it in place.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: r#"
         -- Claude Scope Database Schema
         -- Initial migration for storing OpenTelemetry data
 
@@ -59,6 +79,17 @@ impl SqliteDatabase {
         CREATE INDEX IF NOT EXISTS idx_metrics_timestamp ON metrics(timestamp);
         CREATE INDEX IF NOT EXISTS idx_metrics_session_id ON metrics(session_id);
 
+        -- Metric attributes: one row per label, only written when attribute indexing is enabled
+        CREATE TABLE IF NOT EXISTS metric_attributes (
+            metric_id TEXT NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (metric_id, key),
+            FOREIGN KEY (metric_id) REFERENCES metrics(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_metric_attributes_key_value ON metric_attributes(key, value);
+
         -- Traces table: stores OpenTelemetry trace/span data
         CREATE TABLE IF NOT EXISTS traces (
             id TEXT PRIMARY KEY,
@@ -95,20 +126,525 @@ impl SqliteDatabase {
         CREATE INDEX IF NOT EXISTS idx_logs_timestamp ON logs(timestamp);
         CREATE INDEX IF NOT EXISTS idx_logs_level ON logs(level);
         CREATE INDEX IF NOT EXISTS idx_logs_session_id ON logs(session_id);
-        "#;
+        "#,
+    },
+    Migration {
+        version: 2,
+        description: "metric unit and description",
+        sql: r#"
+        ALTER TABLE metrics ADD COLUMN unit TEXT NULL;
+        ALTER TABLE metrics ADD COLUMN description TEXT NULL;
+        "#,
+    },
+    Migration {
+        version: 3,
+        description: "per-user budgets",
+        sql: r#"
+        CREATE TABLE budgets (
+            user_id TEXT PRIMARY KEY,
+            monthly_budget_usd REAL NOT NULL,
+            updated_at DATETIME NOT NULL
+        );
+        "#,
+    },
+    Migration {
+        version: 4,
+        description: "session end reason",
+        sql: r#"
+        ALTER TABLE sessions ADD COLUMN end_reason TEXT NULL;
+        "#,
+    },
+    Migration {
+        version: 5,
+        description: "metric dedupe key",
+        sql: r#"
+        ALTER TABLE metrics ADD COLUMN dedupe_key TEXT NULL;
+        CREATE UNIQUE INDEX idx_metrics_dedupe_key ON metrics(dedupe_key);
+        "#,
+    },
+    Migration {
+        version: 6,
+        description: "session rollups",
+        sql: r#"
+        CREATE TABLE session_rollups (
+            session_id TEXT PRIMARY KEY REFERENCES sessions(id) ON DELETE CASCADE,
+            tokens_input INTEGER NOT NULL DEFAULT 0,
+            tokens_output INTEGER NOT NULL DEFAULT 0,
+            tokens_cache_creation INTEGER NOT NULL DEFAULT 0,
+            tokens_cache_read INTEGER NOT NULL DEFAULT 0,
+            cost REAL NOT NULL DEFAULT 0,
+            commits INTEGER NOT NULL DEFAULT 0,
+            pull_requests INTEGER NOT NULL DEFAULT 0,
+            lines_added INTEGER NOT NULL DEFAULT 0,
+            lines_removed INTEGER NOT NULL DEFAULT 0,
+            api_requests INTEGER NOT NULL DEFAULT 0,
+            api_failures INTEGER NOT NULL DEFAULT 0,
+            updated_at DATETIME NOT NULL
+        );
+
+        CREATE TABLE session_tool_rollups (
+            session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+            tool_name TEXT NOT NULL,
+            calls INTEGER NOT NULL,
+            PRIMARY KEY (session_id, tool_name)
+        );
+
+        -- Backfill from existing rows, classifying them like classify_metric / classify_event
+        INSERT INTO session_rollups (
+            session_id, tokens_input, tokens_output, tokens_cache_creation, tokens_cache_read, cost,
+            commits, pull_requests, lines_added, lines_removed, api_requests, api_failures, updated_at
+        )
+        SELECT m.session_id,
+               SUM(CASE WHEN m.name = 'claude_code.token.usage'
+                         AND COALESCE(json_extract(m.labels, '$.type'), json_extract(m.labels, '$.token_type'), '')
+                             NOT IN ('output', 'cache_creation', 'cache_read')
+                        THEN CAST(m.value AS INTEGER) ELSE 0 END),
+               SUM(CASE WHEN m.name = 'claude_code.token.usage'
+                         AND COALESCE(json_extract(m.labels, '$.type'), json_extract(m.labels, '$.token_type')) = 'output'
+                        THEN CAST(m.value AS INTEGER) ELSE 0 END),
+               SUM(CASE WHEN m.name = 'claude_code.token.usage'
+                         AND COALESCE(json_extract(m.labels, '$.type'), json_extract(m.labels, '$.token_type')) = 'cache_creation'
+                        THEN CAST(m.value AS INTEGER) ELSE 0 END),
+               SUM(CASE WHEN m.name = 'claude_code.token.usage'
+                         AND COALESCE(json_extract(m.labels, '$.type'), json_extract(m.labels, '$.token_type')) = 'cache_read'
+                        THEN CAST(m.value AS INTEGER) ELSE 0 END),
+               TOTAL(CASE WHEN m.name = 'claude_code.cost.usage' THEN m.value ELSE 0 END),
+               SUM(CASE WHEN m.name = 'claude_code.commit.count' THEN CAST(m.value AS INTEGER) ELSE 0 END),
+               SUM(CASE WHEN m.name = 'claude_code.pull_request.count' THEN CAST(m.value AS INTEGER) ELSE 0 END),
+               SUM(CASE WHEN m.name = 'claude_code.lines_of_code.count'
+                         AND COALESCE(json_extract(m.labels, '$.type'), json_extract(m.labels, '$.change_type'), '') != 'removed'
+                        THEN CAST(m.value AS INTEGER) ELSE 0 END),
+               SUM(CASE WHEN m.name = 'claude_code.lines_of_code.count'
+                         AND COALESCE(json_extract(m.labels, '$.type'), json_extract(m.labels, '$.change_type')) = 'removed'
+                        THEN CAST(m.value AS INTEGER) ELSE 0 END),
+               0,
+               0,
+               MAX(m.timestamp)
+        FROM metrics m
+        JOIN sessions s ON s.id = m.session_id
+        GROUP BY m.session_id;
+
+        INSERT INTO session_rollups (session_id, api_requests, api_failures, updated_at)
+        SELECT l.session_id, SUM(l.message = 'api_request'), SUM(l.message = 'api_request_failed'), MAX(l.timestamp)
+        FROM logs l
+        JOIN sessions s ON s.id = l.session_id
+        WHERE l.message IN ('api_request', 'api_request_failed')
+        GROUP BY l.session_id
+        ON CONFLICT (session_id) DO UPDATE SET
+            api_requests = excluded.api_requests,
+            api_failures = excluded.api_failures,
+            updated_at = MAX(updated_at, excluded.updated_at);
+
+        INSERT INTO session_tool_rollups (session_id, tool_name, calls)
+        SELECT l.session_id, COALESCE(json_extract(l.attributes, '$.tool_name'), 'unknown'), COUNT(*)
+        FROM logs l
+        JOIN sessions s ON s.id = l.session_id
+        WHERE l.message = 'tool_result'
+        GROUP BY 1, 2;
+        "#,
+    },
+    Migration {
+        version: 7,
+        description: "service column",
+        sql: r#"
+        ALTER TABLE metrics ADD COLUMN service TEXT NULL;
+        ALTER TABLE logs ADD COLUMN service TEXT NULL;
+        UPDATE metrics SET service = json_extract(labels, '$."service.name"');
+        UPDATE logs SET service = json_extract(attributes, '$."service.name"');
+        CREATE INDEX idx_metrics_service ON metrics(service);
+        CREATE INDEX idx_logs_service ON logs(service);
+        "#,
+    },
+];
+
+pub struct SqliteDatabase {
+    pool: SqlitePool,
+    index_attributes: bool,
+    read_only: AtomicBool,
+}
+
+impl SqliteDatabase {
+    pub async fn new(database_url: &str, extensions: &[String], max_connections: u32) -> Result<Self, DatabaseError> {
+        // WAL lets reads proceed while ingestion writes; NORMAL sync is still crash-safe under WAL.
+        // Foreign keys must be on per connection for the ON DELETE CASCADE clauses to apply.
+        let mut options = SqliteConnectOptions::from_str(database_url)
+            .map_err(|e| DatabaseError::Connection(e.to_string()))?
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .foreign_keys(true)
+            .busy_timeout(BUSY_TIMEOUT);
+
+        for extension in extensions {
+            options = options.extension(extension.clone());
+        }
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await
+            .map_err(|e| DatabaseError::Connection(e.to_string()))?;
+
+        let db = Self {
+            pool,
+            index_attributes: false,
+            read_only: AtomicBool::new(false),
+        };
+        if !db.probe_writable().await {
+            tracing::warn!("Database is read-only; ingestion is disabled");
+            db.read_only.store(true, Ordering::Relaxed);
+        }
+
+        Ok(db)
+    }
+
+    // Rewriting the header's user_version is the cheapest write that touches the file
+    async fn probe_writable(&self) -> bool {
+        let Ok(version) = sqlx::query_scalar::<_, i64>("PRAGMA user_version")
+            .fetch_one(&self.pool)
+            .await
+        else {
+            return false;
+        };
 
-        sqlx::query(migration_sql)
+        sqlx::query(&format!("PRAGMA user_version = {}", version))
             .execute(&self.pool)
             .await
-            .map_err(|e| DatabaseError::Migration(e.to_string()))?;
-        
+            .is_ok()
+    }
+
+    fn ensure_writable(&self) -> Result<(), DatabaseError> {
+        if self.read_only.load(Ordering::Relaxed) {
+            Err(DatabaseError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Read-only media or a full disk switches the database into read-only mode
+    fn write_error(&self, error: sqlx::Error) -> DatabaseError {
+        const SQLITE_READONLY: i32 = 8;
+        const SQLITE_FULL: i32 = 13;
+
+        let primary_code = match &error {
+            sqlx::Error::Database(db_error) => db_error
+                .code()
+                .and_then(|code| code.parse::<i32>().ok())
+                .map(|code| code & 0xff),
+            _ => None,
+        };
+
+        match primary_code {
+            Some(SQLITE_READONLY) | Some(SQLITE_FULL) => {
+                if !self.read_only.swap(true, Ordering::Relaxed) {
+                    tracing::warn!("Database write failed ({}); switching to read-only mode", error);
+                }
+                DatabaseError::ReadOnly
+            }
+            _ => DatabaseError::Query(error.to_string()),
+        }
+    }
+
+    // Sessions matching `filter` (with `value` bound to ?1) and their telemetry, counted table
+    // by table since rows removed through ON DELETE CASCADE don't show up in rows_affected.
+    // With `by_user`, session-less records whose user.email is `value` go as well.
+    async fn delete_sessions_where(&self, filter: &str, value: String, by_user: bool) -> Result<PurgeCounts, DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut tx = self.pool.begin().await.map_err(|e| self.write_error(e))?;
+        let mut counts = PurgeCounts::default();
+
+        for (table, attributes, count) in [
+            ("metrics", "labels", &mut counts.metrics),
+            ("logs", "attributes", &mut counts.logs),
+            ("traces", "attributes", &mut counts.traces),
+        ] {
+            let mut sql = format!("DELETE FROM {} WHERE session_id IN (SELECT id FROM sessions WHERE {})", table, filter);
+            if by_user {
+                sql.push_str(&format!(" OR (session_id IS NULL AND json_extract({}, '$.\"user.email\"') = ?1)", attributes));
+            }
+            *count = sqlx::query(&sql)
+                .bind(&value)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| self.write_error(e))?
+                .rows_affected();
+        }
+
+        counts.sessions = sqlx::query(&format!("DELETE FROM sessions WHERE {}", filter))
+            .bind(&value)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| self.write_error(e))?
+            .rows_affected();
+
+        // The budget is the user's data too, not telemetry of a session
+        if by_user {
+            counts.budgets = sqlx::query("DELETE FROM budgets WHERE user_id = ?1")
+                .bind(&value)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| self.write_error(e))?
+                .rows_affected();
+        }
+
+        tx.commit().await.map_err(|e| self.write_error(e))?;
+        Ok(counts)
+    }
+
+    // Insert or merge a session row as `upsert_session` does, inside the caller's transaction.
+    // Telemetry after the idle reaper ended the session reopens it; closed sessions stay closed
+    async fn upsert_session_in(
+        &self,
+        conn: &mut SqliteConnection,
+        id: Uuid,
+        user_id: &str,
+        start: DateTime<Utc>,
+    ) -> Result<(), DatabaseError> {
+        let now = Utc::now();
+
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, start_time, command_count, created_at, updated_at)
+            VALUES (?1, ?2, ?3, 0, ?4, ?5)
+            ON CONFLICT(id) DO UPDATE SET
+                user_id = CASE WHEN sessions.user_id = 'unknown' THEN excluded.user_id ELSE sessions.user_id END,
+                start_time = MIN(sessions.start_time, excluded.start_time),
+                end_time = CASE WHEN sessions.end_reason = 'terminated' AND excluded.start_time > sessions.end_time THEN NULL ELSE sessions.end_time END,
+                end_reason = CASE WHEN sessions.end_reason = 'terminated' AND excluded.start_time > sessions.end_time THEN NULL ELSE sessions.end_reason END
+            "#
+        )
+        .bind(id.to_string())
+        .bind(user_id)
+        .bind(start)
+        .bind(now)
+        .bind(now)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        Ok(())
+    }
+
+    // Insert one point with its attributes and rollup inside the caller's transaction;
+    // false when a retried export already stored it
+    async fn insert_metric(&self, conn: &mut SqliteConnection, metric: &MetricRecord) -> Result<bool, DatabaseError> {
+        // Sorted keys keep the JSON identical for identical label sets
+        let labels: BTreeMap<_, _> = metric.labels.iter().collect();
+        let labels_json = serde_json::to_string(&labels)
+            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO metrics (id, session_id, name, timestamp, value, labels, unit, description, created_at, dedupe_key, service)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            ON CONFLICT (dedupe_key) DO NOTHING
+            "#
+        )
+        .bind(metric.id.to_string())
+        .bind(metric.session_id.map(|id| id.to_string()))
+        .bind(&metric.name)
+        .bind(metric.timestamp)
+        .bind(metric.value)
+        .bind(labels_json)
+        .bind(&metric.unit)
+        .bind(&metric.description)
+        .bind(metric.created_at)
+        .bind(metric.dedupe_key())
+        .bind(&metric.service)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| self.write_error(e))?;
+        if inserted.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        if let Some((session_id, delta)) = metric.rollup() {
+            self.add_to_rollup(&mut *conn, session_id, &delta, metric.timestamp).await?;
+        }
+
+        if self.index_attributes {
+            for (key, value) in &labels {
+                sqlx::query("INSERT INTO metric_attributes (metric_id, key, value) VALUES (?1, ?2, ?3)")
+                    .bind(metric.id.to_string())
+                    .bind(key.as_str())
+                    .bind(value.as_str())
+                    .execute(&mut *conn)
+                    .await
+                    .map_err(|e| self.write_error(e))?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Add what one stored point or event contributes to its session's running totals,
+    // inside the transaction that stored it
+    async fn add_to_rollup(
+        &self,
+        conn: &mut SqliteConnection,
+        session_id: Uuid,
+        delta: &SessionSummary,
+        at: DateTime<Utc>,
+    ) -> Result<(), DatabaseError> {
+        let id = session_id.to_string();
+        sqlx::query(
+            r#"
+            INSERT INTO session_rollups (
+                session_id, tokens_input, tokens_output, tokens_cache_creation, tokens_cache_read, cost,
+                commits, pull_requests, lines_added, lines_removed, api_requests, api_failures, updated_at
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+            ON CONFLICT (session_id) DO UPDATE SET
+                tokens_input = session_rollups.tokens_input + excluded.tokens_input,
+                tokens_output = session_rollups.tokens_output + excluded.tokens_output,
+                tokens_cache_creation = session_rollups.tokens_cache_creation + excluded.tokens_cache_creation,
+                tokens_cache_read = session_rollups.tokens_cache_read + excluded.tokens_cache_read,
+                cost = session_rollups.cost + excluded.cost,
+                commits = session_rollups.commits + excluded.commits,
+                pull_requests = session_rollups.pull_requests + excluded.pull_requests,
+                lines_added = session_rollups.lines_added + excluded.lines_added,
+                lines_removed = session_rollups.lines_removed + excluded.lines_removed,
+                api_requests = session_rollups.api_requests + excluded.api_requests,
+                api_failures = session_rollups.api_failures + excluded.api_failures,
+                updated_at = MAX(session_rollups.updated_at, excluded.updated_at)
+            "#
+        )
+        .bind(&id)
+        .bind(delta.total_tokens_input as i64)
+        .bind(delta.total_tokens_output as i64)
+        .bind(delta.total_tokens_cache_creation as i64)
+        .bind(delta.total_tokens_cache_read as i64)
+        .bind(delta.total_cost)
+        .bind(delta.total_commits as i64)
+        .bind(delta.total_pull_requests as i64)
+        .bind(delta.lines_added as i64)
+        .bind(delta.lines_removed as i64)
+        .bind(delta.api_requests as i64)
+        .bind(delta.api_failures as i64)
+        .bind(at)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        for (tool_name, calls) in &delta.tool_usage {
+            sqlx::query(
+                r#"
+                INSERT INTO session_tool_rollups (session_id, tool_name, calls) VALUES (?1, ?2, ?3)
+                ON CONFLICT (session_id, tool_name) DO UPDATE SET calls = session_tool_rollups.calls + excluded.calls
+                "#
+            )
+            .bind(&id)
+            .bind(tool_name)
+            .bind(*calls as i64)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| self.write_error(e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Populate `metric_attributes` on ingest and filter labels through it
+    pub fn with_attribute_index(mut self, enabled: bool) -> Self {
+        self.index_attributes = enabled;
+        self
+    }
+
+    /// Apply every migration newer than the recorded schema version, each in its own transaction
+    pub async fn migrate(&self) -> Result<(), DatabaseError> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at DATETIME NOT NULL
+            )
+            "#
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+
+        let current = self.schema_version().await?;
+        for migration in MIGRATIONS.iter().filter(|migration| migration.version > current) {
+            let mut tx = self.pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+
+            sqlx::query(migration.sql)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::Migration(format!("version {}: {}", migration.version, e)))?;
+
+            sqlx::query("INSERT INTO schema_migrations (version, description, applied_at) VALUES (?1, ?2, ?3)")
+                .bind(migration.version)
+                .bind(migration.description)
+                .bind(Utc::now())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+            tracing::info!("Applied migration {}: {}", migration.version, migration.description);
+        }
+
         Ok(())
     }
+
+    /// Highest applied migration, 0 for a database that predates versioning
+    pub async fn schema_version(&self) -> Result<i64, DatabaseError> {
+        sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_migrations")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Migration(e.to_string()))
+    }
 }
 
 #[async_trait]
 impl Database for SqliteDatabase {
+    fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats { size: self.pool.size(), idle: self.pool.num_idle() as u32 }
+    }
+
+    async fn ping(&self) -> Result<(), DatabaseError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Connection(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn check_health(&self) -> Result<DatabaseHealth, DatabaseError> {
+        self.ping().await?;
+
+        // Pages in use, excluding a WAL not yet checkpointed
+        let size_bytes: i64 = sqlx::query_scalar("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        let mut table_rows = BTreeMap::new();
+        for table in HEALTH_TABLES {
+            let rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::Query(e.to_string()))?;
+            table_rows.insert(table.to_string(), rows as u64);
+        }
+
+        Ok(DatabaseHealth { size_bytes: size_bytes as u64, table_rows })
+    }
+
     async fn create_session(&self, user_id: &str) -> Result<Uuid, DatabaseError> {
+        self.ensure_writable()?;
+
         let id = Uuid::new_v4();
         let now = Utc::now();
 
@@ -125,13 +661,20 @@ impl Database for SqliteDatabase {
         .bind(now)
         .execute(&self.pool)
         .await
-        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+        .map_err(|e| self.write_error(e))?;
 
         Ok(id)
     }
 
+    async fn upsert_session(&self, id: Uuid, user_id: &str, start: DateTime<Utc>) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut conn = self.pool.acquire().await.map_err(|e| self.write_error(e))?;
+        self.upsert_session_in(&mut conn, id, user_id, start).await
+    }
+
     async fn get_session(&self, session_id: Uuid) -> Result<Option<SessionRecord>, DatabaseError> {
-        let row = sqlx::query("SELECT id, user_id, start_time, end_time, command_count, created_at, updated_at FROM sessions WHERE id = ?1")
+        let row = sqlx::query("SELECT id, user_id, start_time, end_time, end_reason, command_count, created_at, updated_at FROM sessions WHERE id = ?1")
             .bind(session_id.to_string())
             .fetch_optional(&self.pool)
             .await
@@ -144,6 +687,7 @@ impl Database for SqliteDatabase {
                 user_id: row.get("user_id"),
                 start_time: row.get("start_time"),
                 end_time: row.get("end_time"),
+                end_reason: row.get::<Option<&str>, _>("end_reason").and_then(SessionEndReason::parse),
                 command_count: row.get::<i64, _>("command_count") as u64,
                 created_at: row.get("created_at"),
                 updated_at: row.get("updated_at"),
@@ -152,20 +696,105 @@ impl Database for SqliteDatabase {
         }
     }
 
+    async fn get_session_rollup(&self, session_id: Uuid) -> Result<Option<SessionSummary>, DatabaseError> {
+        let Some(session) = self.get_session(session_id).await? else {
+            return Ok(None);
+        };
+        let id = session_id.to_string();
+
+        let row = sqlx::query(
+            r#"
+            SELECT tokens_input, tokens_output, tokens_cache_creation, tokens_cache_read, cost, commits, pull_requests, lines_added, lines_removed, api_requests, api_failures, updated_at
+            FROM session_rollups
+            WHERE session_id = ?1
+            "#
+        )
+        .bind(&id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        let tools = sqlx::query("SELECT tool_name, calls FROM session_tool_rollups WHERE session_id = ?1")
+            .bind(&id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        // A session nothing countable was stored for yet has no rollup row
+        let mut summary = row.as_ref().map(rollup_from_row).unwrap_or_default();
+        summary.session_id = id;
+        summary.tool_usage = tools
+            .iter()
+            .map(|row| (row.get("tool_name"), row.get::<i64, _>("calls") as u64))
+            .collect();
+        summary.last_updated = match &row {
+            Some(row) => session.updated_at.max(row.get("updated_at")),
+            None => session.updated_at,
+        };
+        Ok(Some(summary))
+    }
+
     async fn update_session(
         &self,
         session_id: Uuid,
         end_time: Option<DateTime<Utc>>,
     ) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
         let now = Utc::now();
 
-        sqlx::query("UPDATE sessions SET end_time = ?1, updated_at = ?2 WHERE id = ?3")
+        sqlx::query("UPDATE sessions SET end_time = ?1, end_reason = ?2, updated_at = ?3 WHERE id = ?4")
             .bind(end_time)
+            .bind(end_time.map(|_| SessionEndReason::Completed.as_str()))
             .bind(now)
             .bind(session_id.to_string())
             .execute(&self.pool)
             .await
-            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+            .map_err(|e| self.write_error(e))?;
+
+        Ok(())
+    }
+
+    async fn end_idle_sessions(&self, idle_since: DateTime<Utc>) -> Result<u64, DatabaseError> {
+        self.ensure_writable()?;
+
+        // Timestamps are stored in one text format, so MAX() picks the latest
+        let result = sqlx::query(
+            r#"
+            WITH activity AS (
+                SELECT s.id, MAX(
+                    s.start_time,
+                    COALESCE((SELECT MAX(timestamp) FROM logs WHERE session_id = s.id), s.start_time),
+                    COALESCE((SELECT MAX(timestamp) FROM metrics WHERE session_id = s.id), s.start_time)
+                ) AS last_event
+                FROM sessions s
+                WHERE s.end_time IS NULL
+            )
+            UPDATE sessions
+            SET end_time = activity.last_event, end_reason = ?1, updated_at = ?2
+            FROM activity
+            WHERE sessions.id = activity.id AND activity.last_event < ?3
+            "#
+        )
+        .bind(SessionEndReason::Terminated.as_str())
+        .bind(Utc::now())
+        .bind(idle_since)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn increment_command_count(&self, session_id: Uuid) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
+        sqlx::query("UPDATE sessions SET command_count = command_count + 1, updated_at = ?1 WHERE id = ?2")
+            .bind(Utc::now())
+            .bind(session_id.to_string())
+            .execute(&self.pool)
+            .await
+            .map_err(|e| self.write_error(e))?;
 
         Ok(())
     }
@@ -176,101 +805,594 @@ impl Database for SqliteDatabase {
         limit: u32,
         offset: u32,
     ) -> Result<Vec<SessionRecord>, DatabaseError> {
-        let rows = if let Some(uid) = user_id {
-            sqlx::query("SELECT id, user_id, start_time, end_time, command_count, created_at, updated_at FROM sessions WHERE user_id = ?1 ORDER BY start_time DESC LIMIT ?2 OFFSET ?3")
-                .bind(uid)
-                .bind(limit as i64)
-                .bind(offset as i64)
-                .fetch_all(&self.pool)
-                .await
-        } else {
-            sqlx::query("SELECT id, user_id, start_time, end_time, command_count, created_at, updated_at FROM sessions ORDER BY start_time DESC LIMIT ?1 OFFSET ?2")
-                .bind(limit as i64)
-                .bind(offset as i64)
-                .fetch_all(&self.pool)
-                .await
+        let filter = SessionFilter { user_id: user_id.map(str::to_string), ..SessionFilter::default() };
+        self.list_sessions_sorted(&filter, SessionSort::default(), limit, offset).await
+    }
+
+    async fn list_sessions_sorted(
+        &self,
+        filter: &SessionFilter,
+        sort: SessionSort,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<SessionRecord>, DatabaseError> {
+        // ORDER BY is built only from fixed fragments, never from user input
+        let sort_expr = match sort.key {
+            SessionSortKey::StartTime => "start_time",
+            SessionSortKey::Duration => {
+                "(julianday(COALESCE(end_time, strftime('%Y-%m-%dT%H:%M:%f', 'now'))) - julianday(start_time))"
+            }
+            SessionSortKey::CommandCount => "command_count",
+        };
+        let direction = match sort.order {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
         };
 
-        let rows = rows.map_err(|e| DatabaseError::Query(e.to_string()))?;
+        let mut query = QueryBuilder::new("SELECT id, user_id, start_time, end_time, end_reason, command_count, created_at, updated_at FROM sessions");
+        push_session_filter(&mut query, filter);
+        query
+            .push(format!(" ORDER BY {} {}, id {} LIMIT ", sort_expr, direction, direction))
+            .push_bind(limit as i64)
+            .push(" OFFSET ")
+            .push_bind(offset as i64);
 
-        let mut sessions = Vec::new();
-        for row in rows {
-            sessions.push(SessionRecord {
-                id: Uuid::parse_str(row.get("id"))
-                    .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
-                user_id: row.get("user_id"),
-                start_time: row.get("start_time"),
-                end_time: row.get("end_time"),
-                command_count: row.get::<i64, _>("command_count") as u64,
-                created_at: row.get("created_at"),
-                updated_at: row.get("updated_at"),
-            });
-        }
+        let rows = query
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
 
-        Ok(sessions)
+        rows.iter().map(session_from_row).collect()
     }
 
-    async fn store_metric(&self, metric: &MetricRecord) -> Result<(), DatabaseError> {
-        let labels_json = serde_json::to_string(&metric.labels)
-            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+    async fn count_sessions(&self, filter: &SessionFilter) -> Result<u64, DatabaseError> {
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM sessions");
+        push_session_filter(&mut query, filter);
+        let count: i64 = query
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
 
-        sqlx::query(
+        Ok(count as u64)
+    }
+
+    async fn search_sessions(&self, query: &str, limit: u32) -> Result<Vec<SessionRecord>, DatabaseError> {
+        let rows = sqlx::query(
             r#"
-            INSERT INTO metrics (id, session_id, name, timestamp, value, labels, created_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            SELECT id, user_id, start_time, end_time, end_reason, command_count, created_at, updated_at
+            FROM sessions s
+            WHERE s.user_id LIKE ?1 ESCAPE '\'
+               OR EXISTS (
+                   SELECT 1 FROM logs l, json_each(l.attributes) a
+                   WHERE l.session_id = s.id AND a.value = ?2 COLLATE NOCASE
+               )
+               OR EXISTS (
+                   SELECT 1 FROM metrics m, json_each(m.labels) a
+                   WHERE m.session_id = s.id AND a.value = ?2 COLLATE NOCASE
+               )
+            ORDER BY s.start_time DESC, s.id DESC
+            LIMIT ?3
             "#
         )
-        .bind(metric.id.to_string())
-        .bind(metric.session_id.map(|id| id.to_string()))
-        .bind(&metric.name)
-        .bind(metric.timestamp)
-        .bind(metric.value)
-        .bind(labels_json)
-        .bind(metric.created_at)
-        .execute(&self.pool)
+        .bind(contains_pattern(query))
+        .bind(query)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
         .await
         .map_err(|e| DatabaseError::Query(e.to_string()))?;
 
-        Ok(())
+        rows.iter().map(session_from_row).collect()
     }
 
-    async fn get_metrics(
+    async fn list_ended_sessions(
         &self,
-        _start_time: Option<DateTime<Utc>>,
-        _end_time: Option<DateTime<Utc>>,
-        _metric_name: Option<&str>,
-    ) -> Result<Vec<MetricRecord>, DatabaseError> {
-        // This is a simplified query - in practice, you'd want to build dynamic WHERE clauses
-        let rows = sqlx::query("SELECT id, session_id, name, timestamp, value, labels, created_at FROM metrics ORDER BY timestamp DESC")
-            .fetch_all(&self.pool)
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<SessionRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, user_id, start_time, end_time, end_reason, command_count, created_at, updated_at
+            FROM sessions
+            WHERE end_time IS NOT NULL
+              AND start_time >= ?1
+              AND start_time <= ?2
+            ORDER BY start_time, id
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(session_from_row).collect()
+    }
+
+    async fn store_metric(&self, metric: &MetricRecord) -> Result<bool, DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut tx = self.pool
+            .begin()
             .await
-            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+            .map_err(|e| self.write_error(e))?;
+        // A retried export; the point and its attributes are already stored
+        if !self.insert_metric(&mut tx, metric).await? {
+            return Ok(false);
+        }
 
-        let mut metrics = Vec::new();
-        for row in rows {
-            let labels_str: String = row.get("labels");
-            let labels: HashMap<String, String> = serde_json::from_str(&labels_str)
-                .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+        tx.commit()
+            .await
+            .map_err(|e| self.write_error(e))?;
 
-            metrics.push(MetricRecord {
-                id: Uuid::parse_str(row.get("id"))
-                    .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
-                session_id: row.get::<Option<String>, _>("session_id")
-                    .map(|s| Uuid::parse_str(&s))
-                    .transpose()
-                    .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
-                name: row.get("name"),
-                timestamp: row.get("timestamp"),
-                value: row.get("value"),
-                labels,
-                created_at: row.get("created_at"),
-            });
+        Ok(true)
+    }
+
+    async fn store_metric_in_session(&self, metric: &MetricRecord, user_id: &str, start: DateTime<Utc>) -> Result<bool, DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut tx = self.pool
+            .begin()
+            .await
+            .map_err(|e| self.write_error(e))?;
+        if let Some(session_id) = metric.session_id {
+            self.upsert_session_in(&mut tx, session_id, user_id, start).await?;
+        }
+        let inserted = self.insert_metric(&mut tx, metric).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| self.write_error(e))?;
+
+        Ok(inserted)
+    }
+
+    async fn get_metrics(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        metric_name: Option<&str>,
+    ) -> Result<Vec<MetricRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, name, timestamp, value, labels, unit, description, service, created_at FROM metrics
+            WHERE (?1 IS NULL OR timestamp >= ?1)
+              AND (?2 IS NULL OR timestamp <= ?2)
+              AND (?3 IS NULL OR name = ?3)
+            ORDER BY timestamp DESC
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(metric_name)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    async fn get_scoped_metrics(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+        scope: &MetricScope,
+    ) -> Result<Vec<MetricRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, name, timestamp, value, labels, unit, description, service, created_at FROM metrics
+            WHERE timestamp >= ?1
+              AND timestamp <= ?2
+              AND (?3 IS NULL OR name = ?3)
+              AND (?4 IS NULL OR json_extract(labels, '$."user.email"') = ?4
+                   OR session_id IN (SELECT id FROM sessions WHERE user_id = ?4))
+              AND (?5 IS NULL OR json_extract(labels, '$."organization.id"') = ?5)
+              AND (?6 IS NULL OR service = ?6)
+            ORDER BY timestamp DESC
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(metric_name)
+        .bind(scope.user_email.as_deref())
+        .bind(scope.organization_id.as_deref())
+        .bind(scope.service.as_deref())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    fn stream_metrics(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        filter: MetricFilter,
+    ) -> BoxStream<'static, Result<MetricRecord, DatabaseError>> {
+        let pool = self.pool.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        // Without the side table every row's JSON has to be scanned, keyed by a JSON path
+        let label_match = if self.index_attributes {
+            "id IN (SELECT metric_id FROM metric_attributes WHERE key = ?4 AND value = ?5)"
+        } else {
+            "json_extract(labels, ?4) = ?5"
+        };
+        let label_key = filter.label.as_ref().map(|(key, _)| {
+            if self.index_attributes { key.clone() } else { format!("$.\"{}\"", key.replace('"', "\\\"")) }
+        });
+        let sql = format!(
+            r#"
+            SELECT id, session_id, name, timestamp, value, labels, unit, description, service, created_at FROM metrics
+            WHERE timestamp >= ?1
+              AND timestamp <= ?2
+              AND (?3 IS NULL OR name = ?3)
+              AND (?4 IS NULL OR {})
+              AND (?6 IS NULL OR service = ?6)
+            ORDER BY timestamp, id
+            "#,
+            label_match
+        );
+
+        // The query runs on its own task; the bounded channel holds it back to the consumer's pace
+        tokio::spawn(async move {
+            let mut rows = sqlx::query(&sql)
+                .bind(start_time)
+                .bind(end_time)
+                .bind(filter.metric_name.as_deref())
+                .bind(label_key.as_deref())
+                .bind(filter.label.as_ref().map(|(_, value)| value.as_str()))
+                .bind(filter.service.as_deref())
+                .fetch(&pool);
+
+            while let Some(row) = rows.next().await {
+                let metric = row
+                    .map_err(|e| DatabaseError::Query(e.to_string()))
+                    .and_then(|row| metric_from_row(&row));
+                let failed = metric.is_err();
+                // A send error means the consumer went away
+                if tx.send(metric).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|metric| (metric, rx)) }).boxed()
+    }
+
+    async fn metric_label_keys(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+    ) -> Result<Vec<String>, DatabaseError> {
+        sqlx::query_scalar(
+            r#"
+            SELECT DISTINCT l.key
+            FROM metrics m, json_each(m.labels) l
+            WHERE m.timestamp >= ?1
+              AND m.timestamp <= ?2
+              AND (?3 IS NULL OR m.name = ?3)
+            ORDER BY l.key
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(metric_name)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))
+    }
+
+    async fn get_metrics_by_label(
+        &self,
+        key: &str,
+        value: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+    ) -> Result<Vec<MetricRecord>, DatabaseError> {
+        let rows = if self.index_attributes {
+            sqlx::query(
+                r#"
+                SELECT m.id, m.session_id, m.name, m.timestamp, m.value, m.labels, m.unit, m.description, m.service, m.created_at
+                FROM metric_attributes a
+                JOIN metrics m ON m.id = a.metric_id
+                WHERE a.key = ?1 AND a.value = ?2
+                  AND m.timestamp >= ?3
+                  AND m.timestamp <= ?4
+                  AND (?5 IS NULL OR m.name = ?5)
+                ORDER BY m.timestamp DESC
+                "#
+            )
+            .bind(key)
+            .bind(value)
+            .bind(start_time)
+            .bind(end_time)
+            .bind(metric_name)
+            .fetch_all(&self.pool)
+            .await
+        } else {
+            // Without the side table every row's JSON has to be scanned
+            let path = format!("$.\"{}\"", key.replace('"', "\\\""));
+            sqlx::query(
+                r#"
+                SELECT id, session_id, name, timestamp, value, labels, unit, description, service, created_at FROM metrics
+                WHERE json_extract(labels, ?1) = ?2
+                  AND timestamp >= ?3
+                  AND timestamp <= ?4
+                  AND (?5 IS NULL OR name = ?5)
+                ORDER BY timestamp DESC
+                "#
+            )
+            .bind(path)
+            .bind(value)
+            .bind(start_time)
+            .bind(end_time)
+            .bind(metric_name)
+            .fetch_all(&self.pool)
+            .await
         }
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    async fn get_latest_metrics(&self) -> Result<Vec<MetricRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, name, timestamp, value, labels, unit, description, service, created_at FROM (
+                SELECT *, ROW_NUMBER() OVER (PARTITION BY name, labels ORDER BY timestamp DESC, created_at DESC) AS rn
+                FROM metrics
+            )
+            WHERE rn = 1
+            ORDER BY name, labels
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    async fn get_series_totals(&self) -> Result<Vec<MetricRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, name, timestamp, total AS value, labels, unit, description, service, created_at FROM (
+                SELECT *,
+                    ROW_NUMBER() OVER (PARTITION BY name, labels ORDER BY timestamp DESC, created_at DESC) AS rn,
+                    TOTAL(value) OVER (PARTITION BY name, labels) AS total
+                FROM metrics
+            )
+            WHERE rn = 1
+            ORDER BY name, labels
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(metric_from_row).collect()
+    }
+
+    async fn series_total(&self, metric: &MetricRecord, since: Option<DateTime<Utc>>) -> Result<Option<SeriesTotal>, DatabaseError> {
+        // Labels are stored as JSON with sorted keys, so equal label sets compare equal
+        let labels: BTreeMap<_, _> = metric.labels.iter().collect();
+        let labels_json = serde_json::to_string(&labels)
+            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+        // The window sum is taken over every matching row before LIMIT keeps the latest
+        let row = sqlx::query(
+            r#"
+            SELECT timestamp, value, SUM(value) OVER () AS total
+            FROM metrics
+            WHERE name = ?1 AND session_id IS ?2 AND labels = ?3 AND timestamp >= ?4
+            ORDER BY timestamp DESC, created_at DESC
+            LIMIT 1
+            "#
+        )
+        .bind(&metric.name)
+        .bind(metric.session_id.map(|id| id.to_string()))
+        .bind(labels_json)
+        .bind(since.unwrap_or(DateTime::UNIX_EPOCH))
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(row.map(|row| SeriesTotal {
+            total: row.get("total"),
+            timestamp: row.get("timestamp"),
+            last_value: row.get("value"),
+        }))
+    }
+
+    async fn cost_buckets(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        bucket: TimeBucket,
+        scope: &MetricScope,
+    ) -> Result<Vec<CostBucket>, DatabaseError> {
+        // The bucket expression is one of these fixed fragments, never user input.
+        // TOTAL rather than SUM so columns are always REAL, even for all-zero buckets.
+        // Token types are read like classify_metric: `type`, else `token_type`, untyped is input
+        let bucket_expr = match bucket {
+            TimeBucket::Hour => "strftime('%Y-%m-%dT%H:00:00Z', timestamp)",
+            TimeBucket::Day => "strftime('%Y-%m-%dT00:00:00Z', timestamp)",
+            // 'weekday 0' moves forward to Sunday, so six days back is that week's Monday
+            TimeBucket::Week => "strftime('%Y-%m-%dT00:00:00Z', timestamp, 'weekday 0', '-6 days')",
+        };
+        let sql = format!(
+            r#"
+            SELECT {} AS bucket,
+                TOTAL(CASE WHEN name = 'claude_code.cost.usage' THEN value ELSE 0 END) AS cost_usd,
+                TOTAL(CASE WHEN name = 'claude_code.token.usage' AND COALESCE(json_extract(labels, '$.type'), json_extract(labels, '$.token_type'), '') NOT IN ('output', 'cache_creation', 'cache_read') THEN value ELSE 0 END) AS input_tokens,
+                TOTAL(CASE WHEN name = 'claude_code.token.usage' AND COALESCE(json_extract(labels, '$.type'), json_extract(labels, '$.token_type')) = 'output' THEN value ELSE 0 END) AS output_tokens,
+                TOTAL(CASE WHEN name = 'claude_code.token.usage' AND COALESCE(json_extract(labels, '$.type'), json_extract(labels, '$.token_type')) = 'cache_creation' THEN value ELSE 0 END) AS cache_creation_tokens,
+                TOTAL(CASE WHEN name = 'claude_code.token.usage' AND COALESCE(json_extract(labels, '$.type'), json_extract(labels, '$.token_type')) = 'cache_read' THEN value ELSE 0 END) AS cache_read_tokens,
+                COUNT(DISTINCT session_id) AS sessions
+            FROM metrics
+            WHERE name IN ('claude_code.cost.usage', 'claude_code.token.usage')
+              AND timestamp >= ?1
+              AND timestamp <= ?2
+              AND (?3 IS NULL OR json_extract(labels, '$."user.email"') = ?3
+                   OR session_id IN (SELECT id FROM sessions WHERE user_id = ?3))
+              AND (?4 IS NULL OR json_extract(labels, '$."organization.id"') = ?4)
+              AND (?5 IS NULL OR service = ?5)
+            GROUP BY bucket
+            ORDER BY bucket
+            "#,
+            bucket_expr
+        );
+
+        let rows = sqlx::query(&sql)
+            .bind(start_time)
+            .bind(end_time)
+            .bind(scope.user_email.as_deref())
+            .bind(scope.organization_id.as_deref())
+            .bind(scope.service.as_deref())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter()
+            .map(|row| {
+                let start: String = row.get("bucket");
+                Ok(CostBucket {
+                    start: DateTime::parse_from_rfc3339(&start)
+                        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?
+                        .with_timezone(&Utc),
+                    cost_usd: row.get("cost_usd"),
+                    input_tokens: row.get::<f64, _>("input_tokens") as u64,
+                    output_tokens: row.get::<f64, _>("output_tokens") as u64,
+                    cache_creation_tokens: row.get::<f64, _>("cache_creation_tokens") as u64,
+                    cache_read_tokens: row.get::<f64, _>("cache_read_tokens") as u64,
+                    sessions: row.get::<i64, _>("sessions") as u64,
+                })
+            })
+            .collect()
+    }
+
+    async fn metric_stats(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<MetricStats>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT name, MAX(unit) AS unit, MAX(description) AS description,
+                   COUNT(*) AS count, TOTAL(value) AS sum, MIN(value) AS min, MAX(value) AS max, AVG(value) AS avg
+            FROM metrics
+            WHERE timestamp >= ?1 AND timestamp <= ?2
+            GROUP BY name
+            ORDER BY name
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        // MIN/MAX keep the stored column type, which is REAL, so they decode as f64
+        Ok(rows
+            .iter()
+            .map(|row| MetricStats {
+                name: row.get("name"),
+                unit: row.get("unit"),
+                description: row.get("description"),
+                count: row.get::<i64, _>("count") as u64,
+                sum: row.get("sum"),
+                min: row.get("min"),
+                max: row.get("max"),
+                avg: row.get("avg"),
+            })
+            .collect())
+    }
+
+    async fn metric_buckets(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        metric_name: Option<&str>,
+        width_secs: i64,
+    ) -> Result<Vec<MetricBucket>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT name, CAST(strftime('%s', timestamp) AS INTEGER) / ?4 * ?4 AS bucket,
+                   MAX(unit) AS unit, MAX(description) AS description, COUNT(*) AS count, TOTAL(value) AS sum, MIN(value) AS min, MAX(value) AS max
+            FROM metrics
+            WHERE timestamp >= ?1 AND timestamp <= ?2
+              AND (?3 IS NULL OR name = ?3)
+            GROUP BY name, bucket
+            ORDER BY name, bucket
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(metric_name)
+        .bind(width_secs)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter()
+            .map(|row| {
+                let bucket: i64 = row.get("bucket");
+                Ok(MetricBucket {
+                    name: row.get("name"),
+                    unit: row.get("unit"),
+                    description: row.get("description"),
+                    start: DateTime::from_timestamp(bucket, 0)
+                        .ok_or_else(|| DatabaseError::InvalidData(format!("Invalid bucket timestamp: {}", bucket)))?,
+                    count: row.get::<i64, _>("count") as u64,
+                    sum: row.get("sum"),
+                    min: row.get("min"),
+                    max: row.get("max"),
+                })
+            })
+            .collect()
+    }
+
+    async fn facets(&self, start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> Result<Facets, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT 'metric_name' AS facet, name AS value, COUNT(*) AS count
+            FROM metrics WHERE timestamp >= ?1 AND timestamp <= ?2
+            GROUP BY 2
+            UNION ALL
+            SELECT 'model', json_extract(labels, '$.model'), COUNT(*)
+            FROM metrics WHERE timestamp >= ?1 AND timestamp <= ?2 AND json_extract(labels, '$.model') IS NOT NULL
+            GROUP BY 2
+            UNION ALL
+            SELECT 'user_email', json_extract(labels, '$."user.email"'), COUNT(*)
+            FROM metrics WHERE timestamp >= ?1 AND timestamp <= ?2 AND json_extract(labels, '$."user.email"') IS NOT NULL
+            GROUP BY 2
+            UNION ALL
+            SELECT 'tool_name', COALESCE(json_extract(attributes, '$.tool_name'), 'unknown'), COUNT(*)
+            FROM logs WHERE timestamp >= ?1 AND timestamp <= ?2 AND message = 'tool_result'
+            GROUP BY 2
+            ORDER BY facet, count DESC, value
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
 
-        Ok(metrics)
+        let mut facets = Facets::default();
+        for row in &rows {
+            facets.push(row.get("facet"), row.get("value"), row.get("count"));
+        }
+        Ok(facets)
     }
 
     async fn store_trace(&self, trace: &TraceRecord) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
         let attributes_json = serde_json::to_string(&trace.attributes)
             .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
 
@@ -293,29 +1415,96 @@ impl Database for SqliteDatabase {
         .bind(trace.created_at)
         .execute(&self.pool)
         .await
-        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+        .map_err(|e| self.write_error(e))?;
 
         Ok(())
     }
 
     async fn get_traces(
         &self,
-        _start_time: Option<DateTime<Utc>>,
-        _end_time: Option<DateTime<Utc>>,
-        _trace_id: Option<&str>,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        trace_id: Option<&str>,
     ) -> Result<Vec<TraceRecord>, DatabaseError> {
-        // TODO: Implement trace retrieval with filtering
-        Ok(vec![])
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, trace_id, span_id, parent_span_id, name, start_time, end_time, duration_ns, attributes, created_at
+            FROM traces
+            WHERE (?1 IS NULL OR start_time >= ?1)
+              AND (?2 IS NULL OR start_time <= ?2)
+              AND (?3 IS NULL OR trace_id = ?3)
+            ORDER BY start_time ASC, span_id ASC
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(trace_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(trace_from_row).collect()
+    }
+
+    async fn list_traces(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<TraceSummary>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT t.trace_id, COUNT(*) AS span_count, MIN(t.start_time) AS start_time, MAX(t.end_time) AS end_time,
+                   root.name AS root_name, root.duration_ns AS root_duration_ns
+            FROM traces t
+            LEFT JOIN traces root ON root.id = (
+                SELECT r.id FROM traces r
+                WHERE r.trace_id = t.trace_id AND r.parent_span_id IS NULL
+                ORDER BY r.start_time
+                LIMIT 1
+            )
+            WHERE (?1 IS NULL OR t.start_time >= ?1)
+              AND (?2 IS NULL OR t.start_time <= ?2)
+            GROUP BY t.trace_id
+            ORDER BY MIN(t.start_time) DESC, t.trace_id
+            LIMIT ?3
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(rows
+            .iter()
+            .map(|row| TraceSummary {
+                trace_id: row.get("trace_id"),
+                span_count: row.get::<i64, _>("span_count") as u64,
+                start_time: row.get("start_time"),
+                end_time: row.get("end_time"),
+                root_name: row.get("root_name"),
+                root_duration_ns: row.get::<Option<i64>, _>("root_duration_ns").map(|ns| ns as u64),
+            })
+            .collect())
     }
 
     async fn store_log(&self, log: &LogRecord) -> Result<(), DatabaseError> {
+        self.ensure_writable()?;
+
         let attributes_json = serde_json::to_string(&log.attributes)
             .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
 
+        let mut tx = self.pool
+            .begin()
+            .await
+            .map_err(|e| self.write_error(e))?;
+
         sqlx::query(
             r#"
-            INSERT INTO logs (id, session_id, timestamp, level, message, attributes, created_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            INSERT INTO logs (id, session_id, timestamp, level, message, attributes, created_at, service)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
             "#
         )
         .bind(log.id.to_string())
@@ -325,46 +1514,1023 @@ impl Database for SqliteDatabase {
         .bind(&log.message)
         .bind(attributes_json)
         .bind(log.created_at)
-        .execute(&self.pool)
+        .bind(&log.service)
+        .execute(&mut *tx)
         .await
-        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+        .map_err(|e| self.write_error(e))?;
+
+        if let Some((session_id, delta)) = log.rollup() {
+            self.add_to_rollup(&mut tx, session_id, &delta, log.timestamp).await?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| self.write_error(e))?;
 
         Ok(())
     }
 
     async fn get_logs(
         &self,
-        _start_time: Option<DateTime<Utc>>,
-        _end_time: Option<DateTime<Utc>>,
-        _level: Option<&str>,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        level: Option<&str>,
+    ) -> Result<Vec<LogRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, timestamp, level, message, attributes, service, created_at
+            FROM logs
+            WHERE (?1 IS NULL OR timestamp >= ?1)
+              AND (?2 IS NULL OR timestamp <= ?2)
+              AND (?3 IS NULL OR level = ?3 COLLATE NOCASE)
+            ORDER BY timestamp DESC
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(level)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(log_from_row).collect()
+    }
+
+    async fn get_logs_for_session(&self, session_id: Uuid) -> Result<Vec<LogRecord>, DatabaseError> {
+        // Insertion order breaks ties between events stamped the same instant
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, timestamp, level, message, attributes, service, created_at
+            FROM logs
+            WHERE session_id = ?1
+            ORDER BY timestamp ASC, created_at ASC
+            "#
+        )
+        .bind(session_id.to_string())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(log_from_row).collect()
+    }
+
+    async fn list_logs(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        level: Option<&str>,
+        service: Option<&str>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<LogRecord>, DatabaseError> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, timestamp, level, message, attributes, service, created_at
+            FROM logs
+            WHERE (?1 IS NULL OR timestamp >= ?1)
+              AND (?2 IS NULL OR timestamp <= ?2)
+              AND (?3 IS NULL OR level = ?3 COLLATE NOCASE)
+              AND (?6 IS NULL OR service = ?6)
+            ORDER BY timestamp DESC, id DESC
+            LIMIT ?4 OFFSET ?5
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(level)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .bind(service)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(log_from_row).collect()
+    }
+
+    async fn count_logs(
+        &self,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        level: Option<&str>,
+        service: Option<&str>,
+    ) -> Result<u64, DatabaseError> {
+        let count: i64 = sqlx::query_scalar(
+            r#"
+            SELECT COUNT(*) FROM logs
+            WHERE (?1 IS NULL OR timestamp >= ?1)
+              AND (?2 IS NULL OR timestamp <= ?2)
+              AND (?3 IS NULL OR level = ?3 COLLATE NOCASE)
+              AND (?4 IS NULL OR service = ?4)
+            "#
+        )
+        .bind(start_time)
+        .bind(end_time)
+        .bind(level)
+        .bind(service)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(count as u64)
+    }
+
+    async fn search_logs(
+        &self,
+        query: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: u32,
     ) -> Result<Vec<LogRecord>, DatabaseError> {
-        // TODO: Implement log retrieval with filtering
-        Ok(vec![])
+        let rows = sqlx::query(
+            r#"
+            SELECT id, session_id, timestamp, level, message, attributes, service, created_at
+            FROM logs l
+            WHERE (?2 IS NULL OR l.timestamp >= ?2)
+              AND (?3 IS NULL OR l.timestamp <= ?3)
+              AND (
+                  l.message LIKE ?1 ESCAPE '\'
+                  OR EXISTS (SELECT 1 FROM json_each(l.attributes) a WHERE a.value LIKE ?1 ESCAPE '\')
+              )
+            ORDER BY l.timestamp DESC, l.id DESC
+            LIMIT ?4
+            "#
+        )
+        .bind(contains_pattern(query))
+        .bind(start_time)
+        .bind(end_time)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        rows.iter().map(log_from_row).collect()
+    }
+
+    async fn set_budget(&self, user_id: &str, monthly_budget_usd: f64) -> Result<BudgetRecord, DatabaseError> {
+        self.ensure_writable()?;
+
+        let budget = BudgetRecord { user_id: user_id.to_string(), monthly_budget_usd, updated_at: Utc::now() };
+        sqlx::query(
+            r#"
+            INSERT INTO budgets (user_id, monthly_budget_usd, updated_at)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT(user_id) DO UPDATE SET monthly_budget_usd = excluded.monthly_budget_usd, updated_at = excluded.updated_at
+            "#
+        )
+        .bind(&budget.user_id)
+        .bind(budget.monthly_budget_usd)
+        .bind(budget.updated_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| self.write_error(e))?;
+
+        Ok(budget)
+    }
+
+    async fn get_budget(&self, user_id: &str) -> Result<Option<BudgetRecord>, DatabaseError> {
+        let row = sqlx::query("SELECT user_id, monthly_budget_usd, updated_at FROM budgets WHERE user_id = ?1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::Query(e.to_string()))?;
+
+        Ok(row.map(|row| BudgetRecord {
+            user_id: row.get("user_id"),
+            monthly_budget_usd: row.get("monthly_budget_usd"),
+            updated_at: row.get("updated_at"),
+        }))
+    }
+
+    async fn delete_older_than(&self, cutoff: DateTime<Utc>) -> Result<PurgeCounts, DatabaseError> {
+        self.ensure_writable()?;
+
+        let mut tx = self.pool.begin().await.map_err(|e| self.write_error(e))?;
+        let mut counts = PurgeCounts::default();
+
+        // Telemetry first so the session delete doesn't cascade into rows counted here
+        // (metric_attributes rows go with their metrics through the foreign key)
+        for (table, column, count) in [
+            ("metrics", "timestamp", &mut counts.metrics),
+            ("logs", "timestamp", &mut counts.logs),
+            ("traces", "start_time", &mut counts.traces),
+        ] {
+            let sql = format!("DELETE FROM {} WHERE {} < ?1", table, column);
+            *count = sqlx::query(&sql)
+                .bind(cutoff)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| self.write_error(e))?
+                .rows_affected();
+        }
+
+        // Sessions that are still open are kept however old they are
+        counts.sessions = sqlx::query("DELETE FROM sessions WHERE end_time IS NOT NULL AND end_time < ?1")
+            .bind(cutoff)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| self.write_error(e))?
+            .rows_affected();
+
+        tx.commit().await.map_err(|e| self.write_error(e))?;
+        Ok(counts)
+    }
+
+    async fn delete_session(&self, session_id: Uuid) -> Result<Option<PurgeCounts>, DatabaseError> {
+        let counts = self.delete_sessions_where("id = ?1", session_id.to_string(), false).await?;
+        Ok((counts.sessions > 0).then_some(counts))
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<PurgeCounts, DatabaseError> {
+        self.delete_sessions_where("user_id = ?1", user_id.to_string(), true).await
+    }
+}
+
+// Appends a WHERE clause for every set field of `filter`, values bound as parameters
+fn push_session_filter<'a>(query: &mut QueryBuilder<'a, Sqlite>, filter: &'a SessionFilter) {
+    query.push(" WHERE TRUE");
+    if let Some(user_id) = &filter.user_id {
+        query.push(" AND user_id = ").push_bind(user_id.as_str());
+    }
+    if let Some(start_time) = filter.start_time {
+        query.push(" AND start_time >= ").push_bind(start_time);
+    }
+    if let Some(end_time) = filter.end_time {
+        query.push(" AND start_time <= ").push_bind(end_time);
+    }
+    if let Some(min_command_count) = filter.min_command_count {
+        query.push(" AND command_count >= ").push_bind(min_command_count as i64);
+    }
+    match filter.status {
+        Some(SessionState::Active) => query.push(" AND end_time IS NULL"),
+        // Sessions ended before end_reason was recorded count as completed
+        Some(SessionState::Completed) => query.push(" AND end_time IS NOT NULL AND COALESCE(end_reason, 'completed') = 'completed'"),
+        Some(SessionState::Terminated) => query.push(" AND end_reason = 'terminated'"),
+        None => query,
+    };
+}
+
+fn session_from_row(row: &SqliteRow) -> Result<SessionRecord, DatabaseError> {
+    Ok(SessionRecord {
+        id: Uuid::parse_str(row.get("id"))
+            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
+        user_id: row.get("user_id"),
+        start_time: row.get("start_time"),
+        end_time: row.get("end_time"),
+        end_reason: row.get::<Option<&str>, _>("end_reason").and_then(SessionEndReason::parse),
+        command_count: row.get::<i64, _>("command_count") as u64,
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    })
+}
+
+// Totals of a `session_rollups` row; tool usage lives in `session_tool_rollups`
+fn rollup_from_row(row: &SqliteRow) -> SessionSummary {
+    let count = |column: &str| row.get::<i64, _>(column) as u64;
+    SessionSummary {
+        total_tokens_input: count("tokens_input"),
+        total_tokens_output: count("tokens_output"),
+        total_tokens_cache_creation: count("tokens_cache_creation"),
+        total_tokens_cache_read: count("tokens_cache_read"),
+        total_cost: row.get("cost"),
+        total_commits: count("commits"),
+        total_pull_requests: count("pull_requests"),
+        lines_added: count("lines_added"),
+        lines_removed: count("lines_removed"),
+        api_requests: count("api_requests"),
+        api_failures: count("api_failures"),
+        ..SessionSummary::default()
     }
 }
 
-pub async fn init_database(database_path: &str) -> Result<Arc<dyn Database>, DatabaseError> {
+fn trace_from_row(row: &SqliteRow) -> Result<TraceRecord, DatabaseError> {
+    let attributes_str: String = row.get("attributes");
+    let attributes: HashMap<String, String> = serde_json::from_str(&attributes_str)
+        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+
+    Ok(TraceRecord {
+        id: Uuid::parse_str(row.get("id"))
+            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
+        session_id: row.get::<Option<String>, _>("session_id")
+            .map(|s| Uuid::parse_str(&s))
+            .transpose()
+            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
+        trace_id: row.get("trace_id"),
+        span_id: row.get("span_id"),
+        parent_span_id: row.get("parent_span_id"),
+        name: row.get("name"),
+        start_time: row.get("start_time"),
+        end_time: row.get("end_time"),
+        duration_ns: row.get::<i64, _>("duration_ns") as u64,
+        attributes,
+        created_at: row.get("created_at"),
+    })
+}
+
+// LIKE pattern matching `query` anywhere, with its own wildcards taken literally
+fn contains_pattern(query: &str) -> String {
+    let escaped = query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn log_from_row(row: &SqliteRow) -> Result<LogRecord, DatabaseError> {
+    let attributes_str: String = row.get("attributes");
+    let attributes: HashMap<String, String> = serde_json::from_str(&attributes_str)
+        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+
+    Ok(LogRecord {
+        id: Uuid::parse_str(row.get("id"))
+            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
+        session_id: row.get::<Option<String>, _>("session_id")
+            .map(|s| Uuid::parse_str(&s))
+            .transpose()
+            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
+        timestamp: row.get("timestamp"),
+        level: row.get("level"),
+        message: row.get("message"),
+        attributes,
+        service: row.get("service"),
+        created_at: row.get("created_at"),
+    })
+}
+
+fn metric_from_row(row: &SqliteRow) -> Result<MetricRecord, DatabaseError> {
+    let labels_str: String = row.get("labels");
+    let labels: HashMap<String, String> = serde_json::from_str(&labels_str)
+        .map_err(|e| DatabaseError::InvalidData(e.to_string()))?;
+
+    Ok(MetricRecord {
+        id: Uuid::parse_str(row.get("id"))
+            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
+        session_id: row.get::<Option<String>, _>("session_id")
+            .map(|s| Uuid::parse_str(&s))
+            .transpose()
+            .map_err(|e| DatabaseError::InvalidData(e.to_string()))?,
+        name: row.get("name"),
+        timestamp: row.get("timestamp"),
+        value: row.get("value"),
+        labels,
+        unit: row.get("unit"),
+        description: row.get("description"),
+        service: row.get("service"),
+        created_at: row.get("created_at"),
+    })
+}
+
+pub async fn init_database(config: &Config) -> Result<Arc<dyn Database>, DatabaseError> {
     use std::path::Path;
     
-    // Ensure the parent directory exists
-    if let Some(parent) = Path::new(database_path).parent() {
-        if !parent.exists() {
-            std::fs::create_dir_all(parent)
-                .map_err(|e| DatabaseError::Connection(format!(
-                    "Failed to create database directory {}: {}", 
-                    parent.display(), 
-                    e
-                )))?;
+    // Ensure the parent directory exists when connecting via the plain path
+    if config.database_url.is_none() {
+        if let Some(parent) = Path::new(&config.database_path).parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| DatabaseError::Connection(format!(
+                        "Failed to create database directory {}: {}", 
+                        parent.display(), 
+                        e
+                    )))?;
+            }
         }
     }
     
-    let database_url = format!("sqlite:{}?mode=rwc", database_path);
-    tracing::info!("Connecting to database at: {}", database_path);
+    let database_url = config.database_url();
+    tracing::info!("Connecting to database at: {}", database_url);
     
-    let db = SqliteDatabase::new(&database_url).await?;
-    tracing::info!("Running database migrations...");
-    db.migrate().await?;
+    let db = SqliteDatabase::new(&database_url, &config.sqlite_extensions, config.max_connections)
+        .await?
+        .with_attribute_index(config.index_metric_attributes);
+    tracing::info!("Database pool size: {} connections", db.pool.options().get_max_connections());
+    if db.is_read_only() {
+        tracing::warn!("Skipping migrations on read-only database; serving existing data only");
+    } else {
+        tracing::info!("Running database migrations...");
+        db.migrate().await?;
+        tracing::info!("Database schema at version {}", db.schema_version().await?);
+    }
     tracing::info!("Database initialized successfully");
     
     Ok(Arc::new(db))
-}
\ No newline at end of file
+}
+
+/// Fresh migrated database in a temporary directory, kept alive by the returned guard
+#[cfg(test)]
+pub(crate) async fn test_database() -> (tempfile::TempDir, Arc<dyn Database>) {
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display());
+    let db = SqliteDatabase::new(&url, &[], 5).await.unwrap();
+    db.migrate().await.unwrap();
+    (dir, Arc::new(db))
+}
+
+/// Migrated database whose pool is already closed, so every query fails
+#[cfg(test)]
+pub(crate) async fn unavailable_database() -> (tempfile::TempDir, Arc<dyn Database>) {
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display());
+    let db = SqliteDatabase::new(&url, &[], 5).await.unwrap();
+    db.migrate().await.unwrap();
+    db.pool.close().await;
+    (dir, Arc::new(db))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    async fn test_db() -> (tempfile::TempDir, SqliteDatabase) {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display());
+        let db = SqliteDatabase::new(&url, &[], 5).await.unwrap();
+        db.migrate().await.unwrap();
+        (dir, db)
+    }
+
+    #[tokio::test]
+    async fn test_migrate_twice_is_a_no_op() {
+        let (_dir, db) = test_db().await;
+        let latest = MIGRATIONS.last().unwrap().version;
+        assert_eq!(db.schema_version().await.unwrap(), latest);
+
+        let applied_at: Vec<(i64, String)> = sqlx::query_as("SELECT version, applied_at FROM schema_migrations ORDER BY version")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(applied_at.iter().map(|(version, _)| *version).collect::<Vec<_>>(), (1..=latest).collect::<Vec<_>>());
+
+        // A second run applies nothing: no new rows, no ALTER TABLE failing on existing columns
+        db.migrate().await.unwrap();
+        let again: Vec<(i64, String)> = sqlx::query_as("SELECT version, applied_at FROM schema_migrations ORDER BY version")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(again, applied_at);
+        assert_eq!(db.schema_version().await.unwrap(), latest);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_adds_metric_metadata_columns_to_old_databases() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite:{}?mode=rwc", dir.path().join("test.db").display());
+        let db = SqliteDatabase::new(&url, &[], 5).await.unwrap();
+        // The metrics table as first released, before unit and description
+        sqlx::query(
+            r#"
+            CREATE TABLE metrics (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NULL,
+                name TEXT NOT NULL,
+                timestamp DATETIME NOT NULL,
+                value REAL NOT NULL,
+                labels TEXT NOT NULL,
+                created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+            "#
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO metrics (id, name, timestamp, value, labels) VALUES (?1, 'claude_code.cost.usage', ?2, 0.5, '{}')")
+            .bind(Uuid::new_v4().to_string())
+            .bind(Utc::now())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        db.migrate().await.unwrap();
+        // Running again is a no-op
+        db.migrate().await.unwrap();
+
+        let metrics = db.get_metrics(None, None, None).await.unwrap();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].unit, None);
+        assert_eq!(metrics[0].description, None);
+    }
+
+    #[tokio::test]
+    async fn test_rollup_backfill_matches_the_rollup_kept_on_ingest() {
+        let (_dir, db) = test_db().await;
+        let session_id = db.create_session("dev@example.com").await.unwrap();
+        let events_only = db.create_session("ops@example.com").await.unwrap();
+        let now = Utc::now();
+        for (offset, (name, value, labels)) in [
+            ("claude_code.token.usage", 1200.0, vec![("type", "input")]),
+            ("claude_code.token.usage", 300.0, vec![("type", "output")]),
+            ("claude_code.token.usage", 40.0, vec![("type", "cache_creation")]),
+            ("claude_code.cost.usage", 0.25, vec![("model", "opus")]),
+            ("claude_code.lines_of_code.count", 12.0, vec![("type", "removed")]),
+            ("claude_code.pull_request.count", 1.0, vec![]),
+            // Older spellings and untyped points count like classify_metric reads them
+            ("claude_code.token.usage", 70.0, vec![("token_type", "cache_read")]),
+            ("claude_code.token.usage", 5.0, vec![]),
+            ("claude_code.lines_of_code.count", 8.0, vec![("change_type", "removed")]),
+        ].into_iter().enumerate() {
+            let at = now + Duration::seconds(offset as i64);
+            db.store_metric(&MetricRecord { session_id: Some(session_id), ..metric(name, value, at, &labels) }).await.unwrap();
+        }
+        for (session, message, tool_name) in [
+            (session_id, "tool_result", Some("Edit")),
+            (events_only, "tool_result", None),
+            (events_only, "api_request", None),
+        ] {
+            db.store_log(&LogRecord {
+                id: Uuid::new_v4(),
+                session_id: Some(session),
+                timestamp: now,
+                level: "INFO".to_string(),
+                message: message.to_string(),
+                attributes: tool_name.map(|tool| HashMap::from([("tool_name".to_string(), tool.to_string())])).unwrap_or_default(),
+                service: None,
+                created_at: now,
+            }).await.unwrap();
+        }
+
+        let as_json = |summary: Option<SessionSummary>| serde_json::to_value(summary.unwrap()).unwrap();
+        let kept = as_json(db.get_session_rollup(session_id).await.unwrap());
+        assert_eq!(kept["total_tokens_cache_read"], 70);
+        assert_eq!(kept["lines_removed"], 20);
+        assert_eq!(kept["tool_usage"]["Edit"], 1);
+        let events_kept = as_json(db.get_session_rollup(events_only).await.unwrap());
+        assert_eq!(events_kept["api_requests"], 1);
+
+        // Rebuilding the rollups from the stored rows, as an upgrade does, gives the same totals;
+        // the later migrations are undone too so they re-apply on top
+        for statement in [
+            "DROP TABLE session_tool_rollups",
+            "DROP TABLE session_rollups",
+            "DROP INDEX idx_metrics_service",
+            "DROP INDEX idx_logs_service",
+            "ALTER TABLE metrics DROP COLUMN service",
+            "ALTER TABLE logs DROP COLUMN service",
+            "DELETE FROM schema_migrations WHERE version >= 6",
+        ] {
+            sqlx::query(statement).execute(&db.pool).await.unwrap();
+        }
+        db.migrate().await.unwrap();
+        assert_eq!(as_json(db.get_session_rollup(session_id).await.unwrap()), kept);
+        assert_eq!(as_json(db.get_session_rollup(events_only).await.unwrap()), events_kept);
+        assert!(db.get_session_rollup(Uuid::new_v4()).await.unwrap().is_none());
+    }
+
+    async fn insert_session(
+        db: &SqliteDatabase,
+        user_id: &str,
+        start_time: DateTime<Utc>,
+        end_time: Option<DateTime<Utc>>,
+        command_count: i64,
+    ) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO sessions (id, user_id, start_time, end_time, command_count) VALUES (?1, ?2, ?3, ?4, ?5)")
+            .bind(id.to_string())
+            .bind(user_id)
+            .bind(start_time)
+            .bind(end_time)
+            .bind(command_count)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn sorted_ids(db: &SqliteDatabase, key: SessionSortKey, order: SortOrder) -> Vec<Uuid> {
+        db.list_sessions_sorted(&SessionFilter::default(), SessionSort { key, order }, 10, 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_cost_buckets_read_token_types_like_the_classifier() {
+        let (_dir, db) = test_db().await;
+        let at = Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap();
+        for (value, labels) in [
+            (100.0, vec![("type", "input")]),
+            (10.0, vec![]),
+            (20.0, vec![("token_type", "output")]),
+            (30.0, vec![("token_type", "cache_read")]),
+            (40.0, vec![("type", "cache_creation"), ("token_type", "output")]),
+        ] {
+            db.store_metric(&metric("claude_code.token.usage", value, at, &labels)).await.unwrap();
+        }
+
+        let buckets = db.cost_buckets(at - Duration::hours(1), at + Duration::hours(1), TimeBucket::Day, &MetricScope::default()).await.unwrap();
+        let bucket = &buckets[0];
+        assert_eq!(
+            (bucket.input_tokens, bucket.output_tokens, bucket.cache_read_tokens, bucket.cache_creation_tokens),
+            (110, 20, 30, 40)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_database_url_runs_migrations() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            database_url: Some(format!(
+                "sqlite:{}?mode=rwc&cache=shared",
+                dir.path().join("custom.db").display()
+            )),
+            ..Config::default()
+        };
+
+        let db = init_database(&config).await.unwrap();
+        let id = db.create_session("user@example.com").await.unwrap();
+        let session = db.get_session(id).await.unwrap().unwrap();
+        assert_eq!(session.user_id, "user@example.com");
+        assert!(dir.path().join("custom.db").exists());
+    }
+
+    #[tokio::test]
+    async fn test_pool_enables_wal_on_every_connection() {
+        let (_dir, db) = test_db().await;
+        assert_eq!(db.pool.options().get_max_connections(), 5);
+
+        // Hold several connections at once so more than one gets opened
+        let mut connections = Vec::new();
+        for _ in 0..3 {
+            connections.push(db.pool.acquire().await.unwrap());
+        }
+        for conn in &mut connections {
+            let mode: String = sqlx::query_scalar("PRAGMA journal_mode").fetch_one(&mut **conn).await.unwrap();
+            assert_eq!(mode, "wal");
+            let synchronous: i64 = sqlx::query_scalar("PRAGMA synchronous").fetch_one(&mut **conn).await.unwrap();
+            assert_eq!(synchronous, 1);
+            let foreign_keys: i64 = sqlx::query_scalar("PRAGMA foreign_keys").fetch_one(&mut **conn).await.unwrap();
+            assert_eq!(foreign_keys, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writes_and_reads_do_not_lock() {
+        let (_dir, db) = test_db().await;
+        let db = Arc::new(db);
+        let session_id = db.create_session("user@example.com").await.unwrap();
+
+        let mut tasks = Vec::new();
+        for worker in 0..8 {
+            let db = db.clone();
+            tasks.push(tokio::spawn(async move {
+                for i in 0..25 {
+                    let metric = MetricRecord {
+                        id: Uuid::new_v4(),
+                        session_id: Some(session_id),
+                        name: "claude_code.token.usage".to_string(),
+                        timestamp: Utc::now(),
+                        value: (worker * 100 + i) as f64,
+                        labels: HashMap::from([("type".to_string(), "input".to_string())]),
+                        unit: None,
+                        description: None,
+                        service: None,
+                        created_at: Utc::now(),
+                    };
+                    db.store_metric(&metric).await?;
+                    db.get_metrics(None, None, Some("claude_code.token.usage")).await?;
+                    db.count_logs(None, None, None, None).await?;
+                }
+                Ok::<_, DatabaseError>(())
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        let metrics = db.get_metrics(None, None, None).await.unwrap();
+        assert_eq!(metrics.len(), 8 * 25);
+    }
+
+    #[tokio::test]
+    async fn test_list_sessions_sorted_by_each_key() {
+        let (_dir, db) = test_db().await;
+        let now = Utc::now();
+
+        // oldest start, longest duration, fewest commands
+        let a = insert_session(&db, "a", now - Duration::hours(3), Some(now), 1).await;
+        // middle start, shortest duration, most commands
+        let b = insert_session(&db, "b", now - Duration::hours(2), Some(now - Duration::hours(2) + Duration::minutes(5)), 9).await;
+        // newest start, middle duration, middle commands
+        let c = insert_session(&db, "c", now - Duration::hours(1), Some(now - Duration::minutes(30)), 4).await;
+
+        assert_eq!(sorted_ids(&db, SessionSortKey::StartTime, SortOrder::Asc).await, vec![a, b, c]);
+        assert_eq!(sorted_ids(&db, SessionSortKey::StartTime, SortOrder::Desc).await, vec![c, b, a]);
+        assert_eq!(sorted_ids(&db, SessionSortKey::Duration, SortOrder::Asc).await, vec![b, c, a]);
+        assert_eq!(sorted_ids(&db, SessionSortKey::Duration, SortOrder::Desc).await, vec![a, c, b]);
+        assert_eq!(sorted_ids(&db, SessionSortKey::CommandCount, SortOrder::Asc).await, vec![a, c, b]);
+        assert_eq!(sorted_ids(&db, SessionSortKey::CommandCount, SortOrder::Desc).await, vec![b, c, a]);
+    }
+
+    #[tokio::test]
+    async fn test_count_sessions_respects_user_filter() {
+        let (_dir, db) = test_db().await;
+        let now = Utc::now();
+        insert_session(&db, "a", now, None, 0).await;
+        insert_session(&db, "a", now, None, 0).await;
+        insert_session(&db, "b", now, None, 0).await;
+
+        assert_eq!(db.count_sessions(&SessionFilter::default()).await.unwrap(), 3);
+        assert_eq!(db.count_sessions(&SessionFilter::for_user("a")).await.unwrap(), 2);
+        assert_eq!(db.count_sessions(&SessionFilter::for_user("missing")).await.unwrap(), 0);
+    }
+
+    fn metric(name: &str, value: f64, timestamp: DateTime<Utc>, labels: &[(&str, &str)]) -> MetricRecord {
+        MetricRecord {
+            id: Uuid::new_v4(),
+            session_id: None,
+            name: name.to_string(),
+            timestamp,
+            value,
+            labels: labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            unit: None,
+            description: None,
+            service: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_latest_metrics_returns_one_point_per_series() {
+        let (_dir, db) = test_db().await;
+        let now = Utc::now();
+        let labels = [("type", "input"), ("model", "sonnet")];
+
+        db.store_metric(&metric("claude_code.token.usage", 10.0, now - Duration::minutes(2), &labels)).await.unwrap();
+        db.store_metric(&metric("claude_code.token.usage", 30.0, now, &labels)).await.unwrap();
+        db.store_metric(&metric("claude_code.token.usage", 5.0, now, &[("type", "output")])).await.unwrap();
+
+        let latest = db.get_latest_metrics().await.unwrap();
+        assert_eq!(latest.len(), 2);
+        let input = latest.iter().find(|m| m.labels.get("type").map(String::as_str) == Some("input")).unwrap();
+        assert_eq!(input.value, 30.0);
+
+        let totals = db.get_series_totals().await.unwrap();
+        assert_eq!(totals.len(), 2);
+        let input = totals.iter().find(|m| m.labels.get("type").map(String::as_str) == Some("input")).unwrap();
+        assert_eq!((input.value, input.timestamp), (40.0, now));
+    }
+
+    #[tokio::test]
+    async fn test_get_metrics_honors_filters() {
+        let (_dir, db) = test_db().await;
+        let now = Utc::now();
+
+        db.store_metric(&metric("claude_code.cost.usage", 1.0, now - Duration::days(3), &[])).await.unwrap();
+        db.store_metric(&metric("claude_code.cost.usage", 2.0, now - Duration::hours(1), &[])).await.unwrap();
+        db.store_metric(&metric("claude_code.token.usage", 3.0, now - Duration::hours(1), &[])).await.unwrap();
+
+        let all = db.get_metrics(None, None, None).await.unwrap();
+        assert_eq!(all.len(), 3);
+
+        let recent_cost = db
+            .get_metrics(Some(now - Duration::days(1)), Some(now), Some("claude_code.cost.usage"))
+            .await
+            .unwrap();
+        assert_eq!(recent_cost.len(), 1);
+        assert_eq!(recent_cost[0].value, 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_attribute_index_filters_arbitrary_labels() {
+        let (_dir, db) = test_db().await;
+        let db = db.with_attribute_index(true);
+        let now = Utc::now();
+        let labels = [("model", "sonnet"), ("terminal.type", "vscode"), ("user.email", "a@example.com")];
+        db.store_metric(&metric("claude_code.cost.usage", 1.0, now, &labels)).await.unwrap();
+        db.store_metric(&metric("claude_code.cost.usage", 2.0, now, &[("terminal.type", "iterm")])).await.unwrap();
+        // Matches the label, but falls before the window
+        db.store_metric(&metric("claude_code.cost.usage", 3.0, now - Duration::days(2), &[("terminal.type", "vscode")])).await.unwrap();
+
+        let indexed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM metric_attributes")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(indexed, 5);
+
+        let (start, end) = (now - Duration::days(1), now);
+        let found = db.get_metrics_by_label("terminal.type", "vscode", start, end, None).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].value, 1.0);
+        assert_eq!(found[0].labels["user.email"], "a@example.com");
+
+        let other_name = db.get_metrics_by_label("terminal.type", "vscode", start, end, Some("claude_code.token.usage")).await.unwrap();
+        assert!(other_name.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_label_filter_without_attribute_index() {
+        let (_dir, db) = test_db().await;
+        let now = Utc::now();
+        db.store_metric(&metric("claude_code.cost.usage", 1.0, now, &[("terminal.type", "vscode")])).await.unwrap();
+        db.store_metric(&metric("claude_code.cost.usage", 3.0, now - Duration::days(2), &[("terminal.type", "vscode")])).await.unwrap();
+
+        let indexed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM metric_attributes")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(indexed, 0);
+
+        let found = db.get_metrics_by_label("terminal.type", "vscode", now - Duration::days(1), now, None).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].value, 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_database_serves_reads_and_rejects_writes() {
+        let (dir, db) = test_db().await;
+        assert!(!db.is_read_only());
+        let session_id = db.create_session("alice").await.unwrap();
+        db.store_metric(&metric("claude_code.cost.usage", 1.0, Utc::now(), &[])).await.unwrap();
+        db.pool.close().await;
+
+        let url = format!("sqlite:{}?mode=ro", dir.path().join("test.db").display());
+        let db = SqliteDatabase::new(&url, &[], 5).await.unwrap();
+        assert!(db.is_read_only());
+
+        assert!(db.get_session(session_id).await.unwrap().is_some());
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 1);
+
+        let write = db.store_metric(&metric("claude_code.cost.usage", 2.0, Utc::now(), &[])).await;
+        assert!(matches!(write, Err(DatabaseError::ReadOnly)));
+        assert!(matches!(db.create_session("bob").await, Err(DatabaseError::ReadOnly)));
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_switches_to_read_only() {
+        let (dir, db) = test_db().await;
+        db.pool.close().await;
+
+        // Opened writable, but the file becomes unwritable afterwards
+        let url = format!("sqlite:{}?mode=ro", dir.path().join("test.db").display());
+        let db = SqliteDatabase::new(&url, &[], 5).await.unwrap();
+        db.read_only.store(false, Ordering::Relaxed);
+
+        let write = db.create_session("alice").await;
+        assert!(matches!(write, Err(DatabaseError::ReadOnly)));
+        assert!(db.is_read_only());
+    }
+
+    #[tokio::test]
+    async fn test_search_sessions_matches_user_and_event_attributes() {
+        let (_dir, db) = test_db().await;
+        let now = Utc::now();
+        let bash_failure = insert_session(&db, "alice@example.com", now - Duration::hours(3), None, 0).await;
+        let tagged = insert_session(&db, "bob@example.com", now - Duration::hours(2), None, 0).await;
+        let unrelated = insert_session(&db, "carol@example.com", now - Duration::hours(1), None, 0).await;
+
+        for (session_id, attributes) in [
+            (bash_failure, vec![("tool_name", "Bash"), ("error", "exit code 127")]),
+            (unrelated, vec![("tool_name", "Read")]),
+        ] {
+            db.store_log(&LogRecord {
+                id: Uuid::new_v4(),
+                session_id: Some(session_id),
+                timestamp: now,
+                level: "INFO".to_string(),
+                message: "claude_code.tool_result".to_string(),
+                attributes: attributes.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+                service: None,
+                created_at: now,
+            }).await.unwrap();
+        }
+        let mut tagged_metric = metric("claude_code.cost.usage", 1.0, now, &[("tag", "release-prep")]);
+        tagged_metric.session_id = Some(tagged);
+        db.store_metric(&tagged_metric).await.unwrap();
+
+        let ids = |sessions: Vec<SessionRecord>| sessions.into_iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids(db.search_sessions("bash", 10).await.unwrap()), vec![bash_failure]);
+        assert_eq!(ids(db.search_sessions("exit code 127", 10).await.unwrap()), vec![bash_failure]);
+        assert_eq!(ids(db.search_sessions("release-prep", 10).await.unwrap()), vec![tagged]);
+        // User ids match on substrings, newest session first
+        assert_eq!(ids(db.search_sessions("example.com", 10).await.unwrap()), vec![unrelated, tagged, bash_failure]);
+        assert!(db.search_sessions("%", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_older_than_keeps_recent_rows() {
+        let (_dir, db) = test_db().await;
+        let now = Utc::now();
+        let cutoff = now - Duration::days(30);
+        let old = now - Duration::days(45);
+
+        let ended_long_ago = insert_session(&db, "alice", old, Some(old + Duration::hours(1)), 0).await;
+        let still_open = insert_session(&db, "bob", old, None, 0).await;
+        let recent = insert_session(&db, "carol", now - Duration::days(1), Some(now), 0).await;
+
+        db.store_metric(&metric("claude_code.cost.usage", 1.0, old, &[])).await.unwrap();
+        db.store_metric(&metric("claude_code.cost.usage", 2.0, now, &[])).await.unwrap();
+        for timestamp in [old, now] {
+            db.store_log(&LogRecord {
+                id: Uuid::new_v4(),
+                session_id: None,
+                timestamp,
+                level: "INFO".to_string(),
+                message: "claude_code.user_prompt".to_string(),
+                attributes: HashMap::new(),
+                service: None,
+                created_at: now,
+            }).await.unwrap();
+            db.store_trace(&TraceRecord {
+                id: Uuid::new_v4(),
+                session_id: None,
+                trace_id: "trace".to_string(),
+                span_id: timestamp.timestamp().to_string(),
+                parent_span_id: None,
+                name: "span".to_string(),
+                start_time: timestamp,
+                end_time: timestamp,
+                duration_ns: 0,
+                attributes: HashMap::new(),
+                created_at: now,
+            }).await.unwrap();
+        }
+
+        let counts = db.delete_older_than(cutoff).await.unwrap();
+
+        assert_eq!(counts, PurgeCounts { metrics: 1, logs: 1, traces: 1, sessions: 1, budgets: 0 });
+        let metrics = db.get_metrics(None, None, None).await.unwrap();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].value, 2.0);
+        assert_eq!(db.get_traces(None, None, None).await.unwrap().len(), 1);
+        assert!(db.get_session(ended_long_ago).await.unwrap().is_none());
+        assert!(db.get_session(still_open).await.unwrap().is_some());
+        assert!(db.get_session(recent).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_delete_session_counts_its_telemetry() {
+        let (_dir, db) = test_db().await;
+        let now = Utc::now();
+        let doomed = insert_session(&db, "alice@example.com", now, None, 0).await;
+        let second = insert_session(&db, "alice@example.com", now, None, 0).await;
+        let kept = insert_session(&db, "bob@example.com", now, None, 0).await;
+
+        for (offset, session) in [doomed, doomed, second, kept].into_iter().enumerate() {
+            let at = now + Duration::seconds(offset as i64);
+            db.store_metric(&MetricRecord { session_id: Some(session), ..metric("claude_code.cost.usage", 1.0, at, &[]) }).await.unwrap();
+        }
+        db.store_log(&LogRecord {
+            id: Uuid::new_v4(),
+            session_id: Some(doomed),
+            timestamp: now,
+            level: "INFO".to_string(),
+            message: "claude_code.user_prompt".to_string(),
+            attributes: HashMap::new(),
+            service: None,
+            created_at: now,
+        }).await.unwrap();
+        db.store_trace(&TraceRecord {
+            id: Uuid::new_v4(),
+            session_id: Some(doomed),
+            trace_id: "trace".to_string(),
+            span_id: "span".to_string(),
+            parent_span_id: None,
+            name: "span".to_string(),
+            start_time: now,
+            end_time: now,
+            duration_ns: 0,
+            attributes: HashMap::new(),
+            created_at: now,
+        }).await.unwrap();
+
+        let counts = db.delete_session(doomed).await.unwrap();
+        assert_eq!(counts, Some(PurgeCounts { metrics: 2, logs: 1, traces: 1, sessions: 1, budgets: 0 }));
+        assert_eq!(db.delete_session(doomed).await.unwrap(), None);
+
+        db.set_budget("alice@example.com", 100.0).await.unwrap();
+        db.set_budget("bob@example.com", 50.0).await.unwrap();
+        let counts = db.delete_user_sessions("alice@example.com").await.unwrap();
+        assert_eq!(counts, PurgeCounts { metrics: 1, logs: 0, traces: 0, sessions: 1, budgets: 1 });
+        assert!(db.get_budget("alice@example.com").await.unwrap().is_none());
+        assert!(db.get_budget("bob@example.com").await.unwrap().is_some());
+        assert!(db.get_session(kept).await.unwrap().is_some());
+        assert_eq!(db.get_metrics(None, None, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_telemetry_after_the_idle_reaper_reopens_the_session() {
+        let (_dir, db) = test_db().await;
+        let start = Utc::now() - Duration::hours(2);
+        let (resumed, closed) = (Uuid::new_v4(), Uuid::new_v4());
+        for id in [resumed, closed] {
+            db.upsert_session(id, "alice@example.com", start).await.unwrap();
+        }
+        db.update_session(closed, Some(start + Duration::minutes(5))).await.unwrap();
+        assert_eq!(db.end_idle_sessions(start + Duration::hours(1)).await.unwrap(), 1);
+
+        // Late data from before the session was reaped leaves it ended
+        db.upsert_session(resumed, "alice@example.com", start).await.unwrap();
+        let session = db.get_session(resumed).await.unwrap().unwrap();
+        assert_eq!(session.end_reason, Some(SessionEndReason::Terminated));
+
+        // Telemetry after the pause reopens it; an explicitly closed session stays closed
+        let later = start + Duration::minutes(90);
+        for id in [resumed, closed] {
+            db.upsert_session(id, "alice@example.com", later).await.unwrap();
+        }
+        let session = db.get_session(resumed).await.unwrap().unwrap();
+        assert_eq!((session.end_time, session.end_reason), (None, None));
+        assert_eq!(session.start_time, start);
+        let session = db.get_session(closed).await.unwrap().unwrap();
+        assert_eq!(session.end_reason, Some(SessionEndReason::Completed));
+    }
+}
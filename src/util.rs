@@ -0,0 +1,41 @@
+// Small parsers shared by the API modules
+use chrono::Duration;
+
+/// Length of a relative range such as "12h", "3d" or "2w": a positive whole number
+/// followed by `h` (hours), `d` (days) or `w` (weeks)
+pub fn parse_range(range: &str) -> Option<Duration> {
+    let unit = range.chars().last()?;
+    let count = &range[..range.len() - unit.len_utf8()];
+    if count.is_empty() || !count.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let count: i64 = count.parse().ok().filter(|count| *count > 0)?;
+
+    match unit {
+        'h' => Duration::try_hours(count),
+        'd' => Duration::try_days(count),
+        'w' => Duration::try_weeks(count),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range_accepts_any_count_of_hours_days_or_weeks() {
+        assert_eq!(parse_range("12h"), Some(Duration::hours(12)));
+        assert_eq!(parse_range("3d"), Some(Duration::days(3)));
+        assert_eq!(parse_range("2w"), Some(Duration::weeks(2)));
+        assert_eq!(parse_range("1h"), Some(Duration::hours(1)));
+        assert_eq!(parse_range("90d"), Some(Duration::days(90)));
+    }
+
+    #[test]
+    fn test_parse_range_rejects_malformed_input() {
+        for range in ["5x", "", "h", "0d", "-3d", "+3d", "1.5h", "24H", " 3d", "3 d", "99999999999999999999w", "é"] {
+            assert_eq!(parse_range(range), None, "{:?}", range);
+        }
+    }
+}
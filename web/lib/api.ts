@@ -10,6 +10,9 @@ export interface MetricPoint {
   name: string
   value: number
   labels: Record<string, string>
+  unit: string | null
+  description: string | null
+  service: string | null
 }
 
 export interface MetricsOverview {
@@ -29,6 +32,7 @@ export interface ToolUsage {
 
 export interface TimelineData {
   range: string
+  bucket_seconds: number
   points: MetricPoint[]
   summary: TimelineSummary
 }
@@ -56,6 +60,23 @@ export interface SessionToolUsage {
   usage_count: number
 }
 
+export interface SessionTotals {
+  session_id: string
+  total_tokens_input: number
+  total_tokens_output: number
+  total_tokens_cache_creation: number
+  total_tokens_cache_read: number
+  total_cost: number
+  total_commits: number
+  total_pull_requests: number
+  lines_added: number
+  lines_removed: number
+  tool_usage: Record<string, number>
+  api_requests: number
+  api_failures: number
+  last_updated: string
+}
+
 export interface SessionsResponse {
   sessions: SessionData[]
   total_count: number
@@ -101,6 +122,7 @@ export interface ToolUsageStats {
   usage_count: number
   success_rate: number
   avg_duration_ms: number
+  duration_available: boolean
   percentage: number
   color: string
 }
@@ -304,7 +326,7 @@ export class ApiClient {
     return this.request(`/sessions/${id}`)
   }
 
-  async getSessionMetrics(id: string): Promise<ApiResponse<MetricPoint[]>> {
+  async getSessionMetrics(id: string): Promise<ApiResponse<SessionTotals>> {
     return this.request(`/sessions/${id}/metrics`)
   }
 